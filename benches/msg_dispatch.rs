@@ -0,0 +1,74 @@
+// The hot path of a busy group: every received message is delivered to several subscribers and handlers,
+// and every command encodes a request. Run with `cargo bench --bench msg_dispatch`.
+//
+// - deliver_*: one message to SUBSCRIBERS subscribers, as a deep copy each versus the shared Arc in Event
+// - encode_*: a send_text request into a fresh Vec per command versus the reused buffer of run_cmd()
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use prost::Message;
+use std::sync::Arc;
+use wechat_bot::wechatferry::{proto, Event, WxMsg};
+
+const SUBSCRIBERS: usize = 8;
+
+// sizes close to a text message in a large group
+fn group_msg() -> WxMsg {
+    WxMsg {
+        is_group: true,
+        id: 7_366_192_734_155_622_401,
+        r#type: 1,
+        ts: 1_723_000_000,
+        roomid: "12345678901@chatroom".into(),
+        content: "今天的会议改到下午三点，请大家准时参加。".repeat(8),
+        sender: "wxid_abcdefghijklmn".into(),
+        sign: "0123456789abcdef0123456789abcdef".into(),
+        xml: format!(
+            "<msgsource><atuserlist>{}</atuserlist><membercount>500</membercount></msgsource>",
+            "wxid_x,".repeat(60)
+        ),
+        ..Default::default()
+    }
+}
+
+fn bench_deliver(c: &mut Criterion) {
+    let msg = group_msg();
+    c.bench_function("deliver_cloned_wxmsg", |b| {
+        b.iter(|| {
+            for _ in 0..SUBSCRIBERS {
+                black_box(msg.clone());
+            }
+        })
+    });
+    let event = Event::MsgReceived(Arc::new(msg));
+    c.bench_function("deliver_shared_event", |b| {
+        b.iter(|| {
+            for _ in 0..SUBSCRIBERS {
+                black_box(event.clone());
+            }
+        })
+    });
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let text =
+        proto::TextMsg { msg: "收到，谢谢".into(), receiver: "12345678901@chatroom".into(), aters: String::new() };
+    let request =
+        proto::Request { func: proto::Functions::FuncSendTxt.into(), msg: Some(proto::request::Msg::Txt(text)) };
+    c.bench_function("encode_fresh_vec", |b| {
+        b.iter(|| {
+            let mut buf = Vec::with_capacity(request.encoded_len());
+            request.encode(&mut buf).unwrap();
+            black_box(buf);
+        })
+    });
+    let mut scratch = Vec::new();
+    c.bench_function("encode_reused_vec", |b| {
+        b.iter(|| {
+            scratch.clear();
+            request.encode(&mut scratch).unwrap();
+            black_box(&scratch);
+        })
+    });
+}
+
+criterion_group!(benches, bench_deliver, bench_encode);
+criterion_main!(benches);
@@ -3,39 +3,55 @@ use std::{env, fs, path::PathBuf};
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     const PROTOC_PATH: &str = "protoc-27.3-win64";
     const WCF_PATH: &str = "wcf-v39.2.4";
+    const SERVICE_PROTO: &str = "proto/wcf_service.proto";
     let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
     println!("cargo::rerun-if-changed={}", WCF_PATH);
+    println!("cargo::rerun-if-changed={}", SERVICE_PROTO);
 
-    // copy dll to out dir
-    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
-    let target_dir = out_dir.ancestors().nth(3).unwrap().to_path_buf();
-    let dlls = vec!["sdk.dll", "spy.dll", "spy_debug.dll"];
-    let dll_dir = PathBuf::from(&manifest_dir).join(WCF_PATH).canonicalize().unwrap();
-    for dll in dlls {
-        let src_path = dll_dir.join(dll);
-        let dest_path = target_dir.join(dll);
-        if let Err(e) = fs::copy(&src_path, &dest_path) {
-            println!("cargo:warning=failed to copy {:?} to {:?}, error={}", src_path, dest_path, e);
+    // copy dll to out dir, only needed when the real sdk is built for windows
+    let target_windows = env::var("CARGO_CFG_TARGET_OS").is_ok_and(|os| os == "windows");
+    if target_windows && env::var_os("CARGO_FEATURE_REAL_SDK").is_some() {
+        let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+        let target_dir = out_dir.ancestors().nth(3).unwrap().to_path_buf();
+        let dlls = vec!["sdk.dll", "spy.dll", "spy_debug.dll"];
+        let dll_dir = PathBuf::from(&manifest_dir).join(WCF_PATH).canonicalize().unwrap();
+        for dll in dlls {
+            let src_path = dll_dir.join(dll);
+            let dest_path = target_dir.join(dll);
+            if let Err(e) = fs::copy(&src_path, &dest_path) {
+                println!("cargo:warning=failed to copy {:?} to {:?}, error={}", src_path, dest_path, e);
+            }
         }
     }
 
-    // configure protobuf tools
-    let protobuf_location = PathBuf::from(&manifest_dir).join(PROTOC_PATH).canonicalize().unwrap();
-    let protoc = protobuf_location.join("bin/protoc.exe").canonicalize().unwrap();
-    let protoc_include = protobuf_location.join("include").canonicalize().unwrap();
-    env::set_var("PROTOBUF_LOCATION", protobuf_location.to_str().unwrap());
-    env::set_var("PROTOC", protoc.to_str().unwrap());
-    env::set_var("PROTOC_INCLUDE", protoc_include.to_str().unwrap());
+    // configure protobuf tools, the bundled protoc only runs on windows hosts, others use PROTOC or protoc in PATH
+    if cfg!(windows) {
+        let protobuf_location = PathBuf::from(&manifest_dir).join(PROTOC_PATH).canonicalize().unwrap();
+        let protoc = protobuf_location.join("bin/protoc.exe").canonicalize().unwrap();
+        let protoc_include = protobuf_location.join("include").canonicalize().unwrap();
+        env::set_var("PROTOBUF_LOCATION", protobuf_location.to_str().unwrap());
+        env::set_var("PROTOC", protoc.to_str().unwrap());
+        env::set_var("PROTOC_INCLUDE", protoc_include.to_str().unwrap());
+    }
 
     // build wcf proto
     let wcf_protos = format!("{}/proto", WCF_PATH);
     let wcf_proto = format!("{}/wcf.proto", &wcf_protos);
     let roomdata_proto = format!("{}/roomdata.proto", &wcf_protos);
+    let mut protos = vec![wcf_proto.as_str(), roomdata_proto.as_str()];
+    // the gRPC service is only generated for GrpcServer
+    let grpc_server = env::var_os("CARGO_FEATURE_GRPC_SERVER").is_some();
+    if grpc_server {
+        protos.push(SERVICE_PROTO);
+    }
     tonic_build::configure()
         .build_client(true)
-        .build_server(false)
+        .build_server(grpc_server)
         .type_attribute("wcf.Functions", "#[allow(clippy::enum_variant_names)]")
-        .compile(&[wcf_proto.as_str(), roomdata_proto.as_str()], &[wcf_protos.as_str()])
+        .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+        // raw column values, e.g. protobuf encoded RoomData, kept as bytes instead of a list of numbers
+        .field_attribute("wcf.DbField.content", "#[serde(with = \"serde_bytes\")]")
+        .compile(&protos, &[wcf_protos.as_str(), "proto"])
         .unwrap();
 
     Ok(())
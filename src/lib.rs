@@ -0,0 +1,13 @@
+//! WeChat-Bot 的库入口，基于 WeChatFerry 提供微信机器人的基础接口。
+//!
+//! 命令行工具见 `main.rs`，作为库使用的方式：
+//!
+//! ```ignore
+//! use wechat_bot::wechatferry;
+//!
+//! let _cleanup = wechatferry::init(10086, false, true)?;
+//! wechatferry::connect_cmd_socket()?;
+//! wechatferry::send_text("hello".into(), "filehelper".into(), "".into())?;
+//! ```
+
+pub mod wechatferry;
@@ -1,61 +1,343 @@
-use anyhow::Result;
-use std::time::Duration;
+use anyhow::{bail, Result};
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+use wechat_bot::wechatferry::{
+    self, CleanupHandler, Config, DbValue, Event, Message, SendResult, UserInfo, Verdict, WxMsg,
+};
 
-mod wechatferry;
+mod repl;
 
-fn main() -> Result<()> {
-    env_logger::init();
-
-    // 注册回调函数，参考 wcf::Event
-    wechatferry::register_event_callback(|event| {
-        println!("received event: {:?}", event);
-        // 注意，回调函数有可能是当前线程回调，也有可能是接收线程回调，不能在此函数中做复杂操作，否则可能死锁。
-        // 如果希望通过回调执行复杂操作，请使用 channel 通知其他线程执行。
-    });
-    // auto_clean 为 true 时，返回值必须保留，否则会被自动清理
-    let _cleanup = wechatferry::init(10086, true, true)?;
-    // 显式连接 command socket 后才可以调用下列测试函数
+/// 基于 WeChatFerry 的微信机器人，可以用来检查环境是否配置正确
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// TOML 配置文件，见 README
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// cmd socket 的端口，覆盖配置文件中的 wcf.port
+    #[arg(long, global = true)]
+    port: Option<u16>,
+    /// 以 json 输出，每行一个对象
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 接收并打印消息，Ctrl-C 退出
+    Listen {
+        /// 接收多少秒后退出，不指定时一直接收
+        #[arg(long)]
+        seconds: Option<u64>,
+    },
+    /// 发送消息
+    #[command(subcommand)]
+    Send(SendCommand),
+    /// 联系人
+    #[command(subcommand)]
+    Contacts(ContactsCommand),
+    /// 群
+    #[command(subcommand)]
+    Rooms(RoomsCommand),
+    /// 数据库
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// 打印版本检查、登录状态和当前账号，微信版本不匹配时不连接 wcf
+    Status,
+    /// 交互式执行命令，输入 help 查看可用的命令
+    Repl,
+}
+
+#[derive(Args)]
+struct Receiver {
+    /// 接收者的 wxid 或群 id
+    #[arg(long)]
+    to: String,
+}
+
+#[derive(Subcommand)]
+enum SendCommand {
+    /// 发送文本
+    Text {
+        #[command(flatten)]
+        receiver: Receiver,
+        #[arg(long)]
+        msg: String,
+        /// 群消息中要 @ 的 wxid，以逗号分隔
+        #[arg(long, value_delimiter = ',')]
+        at: Vec<String>,
+    },
+    /// 发送图片
+    Image {
+        #[command(flatten)]
+        receiver: Receiver,
+        #[arg(long)]
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ContactsCommand {
+    /// 列出所有联系人
+    List,
+}
+
+#[derive(Subcommand)]
+enum RoomsCommand {
+    /// 列出群成员
+    Members { roomid: String },
+}
+
+#[derive(Subcommand)]
+enum DbCommand {
+    /// 执行 SQL 查询
+    Query {
+        #[arg(long, default_value = "MicroMsg.db")]
+        db: String,
+        #[arg(long)]
+        sql: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Status {
+    port: u16,
+    wcf_version: String,
+    supported_wechat_version: String,
+    installed_wechat_version: Option<String>,
+    logged_in: bool,
+    user: Option<UserInfo>,
+}
+
+// inits and connects the cmd socket, dropping the handler uninits
+fn connect(config: &Config) -> Result<CleanupHandler> {
+    let cleanup = wechatferry::init_with_options(config.wcf.port, config.init_options())?;
     wechatferry::connect_cmd_socket()?;
+    Ok(cleanup)
+}
 
-    // 测试所需变量，按实际情况设置
-    // let wxid = ""; // your wxid
-    // let chatroom_name = ""; // your chatroom name
-    // let db_name = "MicroMsg.db";
-    // let image_file = ""; // your image file here
-
-    // 测试函数，按需启用
-    println!("is_login={}", wechatferry::is_login()?);
-    // println!("get_self_wx_id={:?}", wcf::get_self_wx_id()?);
-    // println!("get_user_info={:?}", wcf::get_user_info()?);
-    // println!("get_contacts={:?}", wcf::get_contacts()?);
-    // println!("db_names={:?}", wcf::get_db_names()?);
-    // println!("db_tables={:?}", wcf::get_db_tables(db_name.into())?);
-    // println!("query_all_contact_info={:?}", wcf::query_all_contact_info()?);
-    // println!("query_contact_info={:?}", wcf::query_contact_info(wxid.into())?);
-    // println!("query_chat_room_info={:?}", wcf::query_chat_room_info(chatroom_name.into())?);
-    // let sql = "SELECT ChatRoomName FROM ChatRoom";
-    // println!("exec_db_query={:?}", wcf::exec_db_query("MicroMsg.db".into(), sql.into())?);
-
-    // let send_result = wcf::send_text("Are you ok?".into(), wxid.into(), "".into())?;
-    // println!("send_result={}", send_result);
-    // let send_result = wcf::send_image(image_file.into(), wxid.into())?;
-    // println!("send_result={}", send_result);
+// display width of a cell, CJK characters take two columns
+fn width(s: &str) -> usize {
+    s.chars().map(|c| if c as u32 >= 0x1100 { 2 } else { 1 }).sum()
+}
 
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| width(header)).collect();
+    for row in rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(width(cell));
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> =
+            cells.iter().zip(&widths).map(|(cell, w)| format!("{}{}", cell, " ".repeat(w - width(cell)))).collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(headers.to_vec());
+    for row in rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
+
+fn format_msg(msg: &WxMsg) -> String {
+    Message::from(msg.clone()).to_string()
+}
+
+fn print_msg(msg: &WxMsg, json: bool) -> Result<()> {
+    if json {
+        return print_json(msg);
+    }
+    println!("{}", format_msg(msg));
+    Ok(())
+}
+
+fn db_json(value: &DbValue) -> serde_json::Value {
+    match value {
+        DbValue::Text(text) => text.clone().into(),
+        DbValue::Integer(value) => (*value).into(),
+        DbValue::Real(value) => (*value).into(),
+        DbValue::Blob(bytes) => format!("<{} bytes>", bytes.len()).into(),
+        DbValue::Null => serde_json::Value::Null,
+    }
+}
+
+fn db_cell(value: &DbValue) -> String {
+    match value {
+        DbValue::Text(text) => text.clone(),
+        DbValue::Integer(value) => value.to_string(),
+        DbValue::Real(value) => value.to_string(),
+        DbValue::Blob(bytes) => format!("<{} bytes>", bytes.len()),
+        DbValue::Null => "NULL".into(),
+    }
+}
+
+fn check_sent(result: SendResult, json: bool) -> Result<()> {
+    if json {
+        print_json(&result)?;
+    } else {
+        println!("success={} status={}", result.success, result.status);
+    }
+    if !result.success {
+        bail!("send failed, status={}", result.status);
+    }
+    Ok(())
+}
+
+fn listen(seconds: Option<u64>, json: bool) -> Result<()> {
+    // shut down in order on Ctrl-C, otherwise wcf stays injected
+    wechatferry::install_shutdown_handler()?;
+    let events = wechatferry::subscribe();
     wechatferry::enable_listen()?;
-    println!("waiting 60s to receive msg...");
-    std::thread::sleep(Duration::from_secs(60)); // check received msg in callback
+    let deadline = seconds.map(|seconds| Instant::now() + Duration::from_secs(seconds));
+    while deadline.is_none_or(|deadline| Instant::now() < deadline) {
+        let timeout = match deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => Duration::from_secs(1),
+        };
+        match events.recv_timeout(timeout) {
+            Ok(Event::MsgReceived(msg)) => print_msg(&msg, json)?,
+            Ok(Event::MsgSocketDisconnected) => bail!("msg socket disconnected"),
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => bail!("event subscription closed"),
+        }
+    }
     wechatferry::disable_listen()?;
+    Ok(())
+}
 
-    // println!("get_msg_types={:?}", wcf::get_msg_types()?);
+fn status(config: &Config, json: bool) -> Result<()> {
+    // checked before init, which refuses a mismatched wechat, so the mismatch can still be shown
+    let report = wechatferry::check_compatibility()?;
+    let cleanup = match report.verdict {
+        Verdict::Mismatch => None,
+        _ => Some(connect(config)?),
+    };
+    let logged_in = cleanup.is_some() && wechatferry::is_login()?;
+    let user = if logged_in { wechatferry::get_user_info()? } else { None };
+    let status = Status {
+        port: config.wcf.port,
+        wcf_version: report.wcf_version.clone(),
+        supported_wechat_version: report.supported_wechat_version.clone(),
+        installed_wechat_version: report.installed_wechat_version.clone(),
+        logged_in,
+        user,
+    };
+    if json {
+        return print_json(&status);
+    }
+    println!("{}", report);
+    println!("port={} logged_in={}", status.port, status.logged_in);
+    if let Some(user) = &status.user {
+        println!("{}", user);
+    }
+    Ok(())
+}
 
-    // Note: these functions are not tested, but I think they should work
-    // send_file, send_xml, send_emotion
-    // accept_new_friend, add_chatroom_members, inv_chatroom_members, del_chatroom_members
-    // decrypt_image, recv_transfer, refresh_pyq, attach_msg, get_audio_msg
-    // send_rich_text, send_pat_msg, exec_ocr, forward_msg
+fn run(cli: Cli) -> Result<()> {
+    let mut config = match &cli.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if let Some(port) = cli.port {
+        config.wcf.port = port;
+    }
+    wechatferry::apply_config(&config)?;
+    let json = cli.json;
+    // status connects by itself after checking the wechat version
+    let _cleanup = match cli.command {
+        Command::Status => None,
+        _ => Some(connect(&config)?),
+    };
 
-    // wcf::uninit(); // auto_clean 为 false 时，需要显式调用 uninit()
-    println!("waiting 5s to ensure msg socket closed...");
-    std::thread::sleep(Duration::from_secs(5)); // msg socket 可能尚未关闭，等 5s
+    match cli.command {
+        Command::Listen { seconds } => listen(seconds, json)?,
+        Command::Send(SendCommand::Text { receiver, msg, at }) => {
+            check_sent(wechatferry::send_text(msg, receiver.to, at.join(","))?, json)?
+        }
+        Command::Send(SendCommand::Image { receiver, file }) => {
+            check_sent(wechatferry::send_image(file, receiver.to)?, json)?
+        }
+        Command::Contacts(ContactsCommand::List) => {
+            let contacts = wechatferry::query_all_contact_info()?;
+            if json {
+                return contacts.iter().try_for_each(print_json);
+            }
+            let rows: Vec<Vec<String>> = contacts
+                .into_iter()
+                .map(|contact| {
+                    vec![
+                        contact.wxid,
+                        contact.alias.unwrap_or_default(),
+                        contact.nick_name.unwrap_or_default(),
+                        contact.remark.unwrap_or_default(),
+                    ]
+                })
+                .collect();
+            print_table(&["WXID", "ALIAS", "NICKNAME", "REMARK"], &rows);
+        }
+        Command::Rooms(RoomsCommand::Members { roomid }) => {
+            let members = wechatferry::get_room_members(roomid)?;
+            if json {
+                return members.iter().try_for_each(print_json);
+            }
+            let rows: Vec<Vec<String>> = members
+                .into_iter()
+                .map(|member| {
+                    vec![
+                        member.wxid,
+                        member.room_nickname.unwrap_or_default(),
+                        member.contact_name.unwrap_or_default(),
+                        if member.is_owner { "owner".into() } else { String::new() },
+                    ]
+                })
+                .collect();
+            print_table(&["WXID", "ROOM NICKNAME", "NAME", "ROLE"], &rows);
+        }
+        Command::Db(DbCommand::Query { db, sql }) => {
+            let rows = wechatferry::exec_db_query(db, sql)?;
+            let decoded: Vec<Vec<(String, DbValue)>> = rows
+                .into_iter()
+                .map(|row| {
+                    row.fields
+                        .into_iter()
+                        .map(|field| (field.column, DbValue::decode(field.r#type, field.content)))
+                        .collect()
+                })
+                .collect();
+            if json {
+                let objects = decoded.iter().map(|row| {
+                    row.iter()
+                        .map(|(column, value)| (column.clone(), db_json(value)))
+                        .collect::<serde_json::Map<_, _>>()
+                });
+                return objects.into_iter().try_for_each(|object| print_json(&object));
+            }
+            let Some(first) = decoded.first() else {
+                println!("no rows");
+                return Ok(());
+            };
+            let headers: Vec<&str> = first.iter().map(|(column, _)| column.as_str()).collect();
+            let rows: Vec<Vec<String>> =
+                decoded.iter().map(|row| row.iter().map(|(_, value)| db_cell(value)).collect()).collect();
+            print_table(&headers, &rows);
+        }
+        Command::Status => status(&config, json)?,
+        Command::Repl => repl::run()?,
+    }
     Ok(())
 }
+
+fn main() -> Result<()> {
+    wechatferry::init_tracing();
+    run(Cli::parse())
+}
@@ -0,0 +1,153 @@
+// `wechat-bot repl`, keeps one session open and runs commands typed by the user.
+//
+// Errors of a command are printed and the loop goes on. The session is uninited when run() returns, which covers
+// quit, Ctrl-D and Ctrl-C at the prompt, Ctrl-C while a command runs is left to install_shutdown_handler().
+use anyhow::{bail, Result};
+use rustyline::error::ReadlineError;
+use rustyline::{DefaultEditor, ExternalPrinter};
+use std::thread;
+use wechat_bot::wechatferry::{self, ContactInfo, DbValue, Event};
+
+use crate::{db_cell, format_msg, print_table};
+
+const HELP: &str = "\
+commands:
+  send <wxid> <text>    send a text message
+  contacts [query]      list contacts whose wxid, alias, nickname or remark contains query
+  rooms                 list chat rooms
+  room <roomid>         show the owner, members and announcement of a chat room
+  info <wxid>           show the contact info returned by wcf as json
+  sql <db> <query>      run a query, e.g. sql MicroMsg.db SELECT * FROM Contact LIMIT 5
+  listen on|off         print received messages inline
+  status                show the login state and the current account
+  help                  show this help
+  quit                  uninit and exit";
+
+pub fn run() -> Result<()> {
+    wechatferry::install_shutdown_handler()?;
+    let mut editor = DefaultEditor::new()?;
+    start_printer(&mut editor)?;
+    println!("{}", HELP);
+    loop {
+        let line = match editor.readline("wcf> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+        let (command, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let result = match command {
+            "quit" | "exit" => break,
+            "help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            "listen" => listen(args.trim()),
+            _ => execute(command, args.trim()),
+        };
+        if let Err(e) = result {
+            println!("error: {:#}", e);
+        }
+    }
+    if wechatferry::is_listening() {
+        wechatferry::disable_listen()?;
+    }
+    Ok(())
+}
+
+// prints received messages above the prompt, they only arrive after listen on
+fn start_printer(editor: &mut DefaultEditor) -> Result<()> {
+    let mut printer = editor.create_external_printer()?;
+    let events = wechatferry::subscribe();
+    thread::Builder::new().name("repl-printer".into()).spawn(move || {
+        for event in events {
+            if let Event::MsgReceived(msg) = event {
+                if printer.print(format_msg(&msg)).is_err() {
+                    break;
+                }
+            }
+        }
+    })?;
+    Ok(())
+}
+
+fn listen(args: &str) -> Result<()> {
+    match args {
+        "on" => {
+            let status = wechatferry::enable_listen()?;
+            println!("{:?}", status);
+        }
+        "off" => {
+            wechatferry::disable_listen()?;
+        }
+        _ => bail!("usage: listen on|off"),
+    }
+    Ok(())
+}
+
+fn contact_matches(contact: &ContactInfo, query: &str) -> bool {
+    let fields = [&contact.alias, &contact.nick_name, &contact.remark];
+    contact.wxid.contains(query) || fields.iter().any(|field| field.as_deref().is_some_and(|f| f.contains(query)))
+}
+
+fn execute(command: &str, args: &str) -> Result<()> {
+    match command {
+        "send" => {
+            let Some((wxid, text)) = args.split_once(char::is_whitespace) else {
+                bail!("usage: send <wxid> <text>");
+            };
+            let result = wechatferry::send_text(text.trim().into(), wxid.into(), String::new())?;
+            println!("{:?}", result);
+        }
+        "contacts" | "rooms" => {
+            let rooms = command == "rooms";
+            let rows: Vec<Vec<String>> = wechatferry::query_all_contact_info()?
+                .into_iter()
+                .filter(|contact| contact.wxid.ends_with("@chatroom") == rooms && contact_matches(contact, args))
+                .map(|contact| {
+                    vec![contact.wxid, contact.nick_name.unwrap_or_default(), contact.remark.unwrap_or_default()]
+                })
+                .collect();
+            print_table(&["WXID", "NICKNAME", "REMARK"], &rows);
+        }
+        "room" => match wechatferry::query_chat_room_info(args.into())? {
+            Some(room) => println!("{:#}", room),
+            None => println!("not found"),
+        },
+        "info" => match wechatferry::get_contact_info(args.into())? {
+            Some(contact) => println!("{}", wechatferry::to_pretty_json(&contact)?),
+            None => println!("not found"),
+        },
+        "sql" => {
+            let Some((db, sql)) = args.split_once(char::is_whitespace) else {
+                bail!("usage: sql <db> <query>");
+            };
+            let rows = wechatferry::exec_db_query(db.into(), sql.trim().into())?;
+            let Some(first) = rows.first() else {
+                println!("no rows");
+                return Ok(());
+            };
+            let headers: Vec<&str> = first.fields.iter().map(|field| field.column.as_str()).collect();
+            let cells: Vec<Vec<String>> = rows
+                .iter()
+                .map(|row| {
+                    let values = row.fields.iter().map(|field| DbValue::decode(field.r#type, field.content.clone()));
+                    values.map(|value| db_cell(&value)).collect()
+                })
+                .collect();
+            print_table(&headers, &cells);
+        }
+        "status" => {
+            println!("logged_in={}", wechatferry::is_login()?);
+            if let Some(user) = wechatferry::get_user_info()? {
+                println!("{:#}", user);
+            }
+        }
+        _ => bail!("unknown command {:?}, type help for the list of commands", command),
+    }
+    Ok(())
+}
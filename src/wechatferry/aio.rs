@@ -0,0 +1,266 @@
+//! tokio 下使用的异步接口，需要开启 async feature。
+//!
+//! 命令仍通过 cmd socket 同步收发，在 tokio 的 blocking 线程池中执行，不会阻塞异步任务。
+//! 等待中的 future 被 drop 时，已发出的命令仍会在后台执行完并丢弃结果，cmd socket 的状态不受影响，
+//! 之后的命令可以正常执行。需要在 tokio runtime 中调用。
+
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+
+use super::error::{Result, WcfError};
+use super::proto;
+use super::{
+    DbRow, DbTable, Event, ListenStatus, RpcContact, RpcContacts, SendResult, UserInfo, WcfClient,
+    DEFAULT_SUBSCRIBER_CAPACITY,
+};
+
+/// 事件的异步订阅，见 `AsyncWcfClient::events()`，也可以作为 `Stream<Item = Event>` 使用
+pub struct EventStream {
+    receiver: mpsc::Receiver<Event>,
+}
+
+impl EventStream {
+    /// 等待下一个事件，客户端被销毁后返回 None
+    pub async fn next_event(&mut self) -> Option<Event> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// WcfClient 的异步封装，clone 后共享同一个客户端
+#[derive(Clone)]
+pub struct AsyncWcfClient {
+    client: WcfClient,
+}
+
+impl Default for AsyncWcfClient {
+    /// 封装默认客户端
+    fn default() -> Self {
+        AsyncWcfClient::new(super::default_client().clone())
+    }
+}
+
+impl AsyncWcfClient {
+    pub fn new(client: WcfClient) -> Self {
+        AsyncWcfClient { client }
+    }
+
+    /// 封装的同步客户端，可以用于 init() 等本身很快的函数
+    pub fn client(&self) -> &WcfClient {
+        &self.client
+    }
+
+    /// 在 blocking 线程池中执行任意同步函数，用于没有异步版本的函数
+    pub async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&WcfClient) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let client = self.client.clone();
+        match tokio::task::spawn_blocking(move || f(&client)).await {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            // only when the runtime is shutting down
+            Err(_) => Err(WcfError::Cancelled),
+        }
+    }
+
+    /// 订阅事件，队列长度为 DEFAULT_SUBSCRIBER_CAPACITY，队列满时新事件会被丢弃。EventStream 被 drop 后自动取消订阅
+    pub fn events(&self) -> EventStream {
+        self.events_with_capacity(DEFAULT_SUBSCRIBER_CAPACITY)
+    }
+
+    /// 同 events()，指定队列长度
+    pub fn events_with_capacity(&self, capacity: usize) -> EventStream {
+        EventStream { receiver: self.client.subscribe_async(capacity) }
+    }
+
+    pub async fn is_login(&self) -> Result<bool> {
+        self.run(|client| client.is_login()).await
+    }
+
+    pub async fn wait_for_login(&self, timeout: Duration, poll_interval: Duration) -> Result<UserInfo> {
+        self.run(move |client| client.wait_for_login(timeout, poll_interval)).await
+    }
+
+    pub async fn get_self_wx_id(&self) -> Result<Option<String>> {
+        self.run(|client| client.get_self_wx_id()).await
+    }
+
+    pub async fn get_user_info(&self) -> Result<Option<UserInfo>> {
+        self.run(|client| client.get_user_info()).await
+    }
+
+    pub async fn get_contacts(&self) -> Result<Option<RpcContacts>> {
+        self.run(|client| client.get_contacts()).await
+    }
+
+    pub async fn get_contact_info(&self, wxid: String) -> Result<Option<RpcContact>> {
+        self.run(move |client| client.get_contact_info(wxid)).await
+    }
+
+    pub async fn get_db_names(&self) -> Result<Vec<String>> {
+        self.run(|client| client.get_db_names()).await
+    }
+
+    pub async fn get_db_tables(&self, db: String) -> Result<Vec<DbTable>> {
+        self.run(move |client| client.get_db_tables(db)).await
+    }
+
+    pub async fn exec_db_query(&self, db: String, sql: String) -> Result<Vec<DbRow>> {
+        self.run(move |client| client.exec_db_query(db, sql)).await
+    }
+
+    pub async fn send_text(&self, msg: String, receiver: String, aters: String) -> Result<SendResult> {
+        self.run(move |client| client.send_text(msg, receiver, aters)).await
+    }
+
+    pub async fn send_image(&self, path: PathBuf, receiver: String) -> Result<SendResult> {
+        self.run(move |client| client.send_image(path, receiver)).await
+    }
+
+    pub async fn send_file(&self, path: PathBuf, receiver: String) -> Result<SendResult> {
+        self.run(move |client| client.send_file(path, receiver)).await
+    }
+
+    pub async fn send_emotion(&self, path: PathBuf, receiver: String) -> Result<SendResult> {
+        self.run(move |client| client.send_emotion(path, receiver)).await
+    }
+
+    pub async fn send_rich_text(&self, richtext: proto::RichText) -> Result<SendResult> {
+        self.run(move |client| client.send_rich_text(richtext)).await
+    }
+
+    pub async fn send_pat_msg(&self, roomid: String, wxid: String) -> Result<SendResult> {
+        self.run(move |client| client.send_pat_msg(roomid, wxid)).await
+    }
+
+    pub async fn forward_msg(&self, id: u64, receiver: String) -> Result<SendResult> {
+        self.run(move |client| client.forward_msg(id, receiver)).await
+    }
+
+    pub async fn revoke_msg(&self, id: u64) -> Result<bool> {
+        self.run(move |client| client.revoke_msg(id)).await
+    }
+
+    pub async fn exec_raw(&self, func: proto::Functions, msg: Option<proto::request::Msg>) -> Result<proto::Response> {
+        self.run(move |client| client.exec_raw(func, msg)).await
+    }
+
+    pub async fn enable_listen(&self) -> Result<ListenStatus> {
+        self.run(|client| client.enable_listen()).await
+    }
+
+    pub async fn disable_listen(&self) -> Result<bool> {
+        self.run(|client| client.disable_listen()).await
+    }
+
+    /// 同 `WcfClient::shutdown()`，等待期间不阻塞异步任务
+    pub async fn shutdown(&self) {
+        let shutdown = |client: &WcfClient| {
+            client.shutdown();
+            Ok(())
+        };
+        let _ = self.run(shutdown).await;
+    }
+}
+
+/// 订阅默认客户端的事件，参考 [`AsyncWcfClient::events`]
+pub fn events() -> EventStream {
+    AsyncWcfClient::default().events()
+}
+
+pub async fn is_login() -> Result<bool> {
+    AsyncWcfClient::default().is_login().await
+}
+
+pub async fn wait_for_login(timeout: Duration, poll_interval: Duration) -> Result<UserInfo> {
+    AsyncWcfClient::default().wait_for_login(timeout, poll_interval).await
+}
+
+pub async fn get_self_wx_id() -> Result<Option<String>> {
+    AsyncWcfClient::default().get_self_wx_id().await
+}
+
+pub async fn get_user_info() -> Result<Option<UserInfo>> {
+    AsyncWcfClient::default().get_user_info().await
+}
+
+pub async fn get_contacts() -> Result<Option<RpcContacts>> {
+    AsyncWcfClient::default().get_contacts().await
+}
+
+pub async fn get_contact_info(wxid: String) -> Result<Option<RpcContact>> {
+    AsyncWcfClient::default().get_contact_info(wxid).await
+}
+
+pub async fn get_db_names() -> Result<Vec<String>> {
+    AsyncWcfClient::default().get_db_names().await
+}
+
+pub async fn get_db_tables(db: String) -> Result<Vec<DbTable>> {
+    AsyncWcfClient::default().get_db_tables(db).await
+}
+
+pub async fn exec_db_query(db: String, sql: String) -> Result<Vec<DbRow>> {
+    AsyncWcfClient::default().exec_db_query(db, sql).await
+}
+
+pub async fn send_text(msg: String, receiver: String, aters: String) -> Result<SendResult> {
+    AsyncWcfClient::default().send_text(msg, receiver, aters).await
+}
+
+pub async fn send_image(path: PathBuf, receiver: String) -> Result<SendResult> {
+    AsyncWcfClient::default().send_image(path, receiver).await
+}
+
+pub async fn send_file(path: PathBuf, receiver: String) -> Result<SendResult> {
+    AsyncWcfClient::default().send_file(path, receiver).await
+}
+
+pub async fn send_emotion(path: PathBuf, receiver: String) -> Result<SendResult> {
+    AsyncWcfClient::default().send_emotion(path, receiver).await
+}
+
+pub async fn send_rich_text(richtext: proto::RichText) -> Result<SendResult> {
+    AsyncWcfClient::default().send_rich_text(richtext).await
+}
+
+pub async fn send_pat_msg(roomid: String, wxid: String) -> Result<SendResult> {
+    AsyncWcfClient::default().send_pat_msg(roomid, wxid).await
+}
+
+pub async fn forward_msg(id: u64, receiver: String) -> Result<SendResult> {
+    AsyncWcfClient::default().forward_msg(id, receiver).await
+}
+
+pub async fn revoke_msg(id: u64) -> Result<bool> {
+    AsyncWcfClient::default().revoke_msg(id).await
+}
+
+pub async fn exec_raw(func: proto::Functions, msg: Option<proto::request::Msg>) -> Result<proto::Response> {
+    AsyncWcfClient::default().exec_raw(func, msg).await
+}
+
+pub async fn enable_listen() -> Result<ListenStatus> {
+    AsyncWcfClient::default().enable_listen().await
+}
+
+pub async fn disable_listen() -> Result<bool> {
+    AsyncWcfClient::default().disable_listen().await
+}
+
+pub async fn shutdown() {
+    AsyncWcfClient::default().shutdown().await
+}
@@ -0,0 +1,253 @@
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, trace, warn};
+
+use super::error::Result;
+use super::events::HandlerId;
+use super::{AppMsg, ContactCache, Message, MsgType, RevokeNotice, WcfClient};
+
+/// 撤回的消息转发到哪里
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RevokeDestination {
+    /// 发回撤回消息的会话
+    SameChat,
+    /// 发到指定的会话（wxid 或群 id），例如只有管理员在的审计群
+    Chat(String),
+}
+
+/// AntiRevoke 的选项
+#[derive(Clone, Debug)]
+pub struct AntiRevokeOptions {
+    /// 最多保留最近多少条消息
+    pub window: usize,
+    /// 保留多长时间内的消息，微信只能撤回 2 分钟内的消息
+    pub max_age: Duration,
+    pub destination: RevokeDestination,
+    /// 保存图片、视频、文件的目录，消息移出窗口时删除
+    pub media_dir: PathBuf,
+    /// 下载一个图片、视频或文件的超时时间，超时后撤回时只发送占位文字
+    pub download_timeout: Duration,
+    /// 等待处理的消息数，超过时丢弃新消息
+    pub queue_capacity: usize,
+}
+
+impl Default for AntiRevokeOptions {
+    fn default() -> Self {
+        AntiRevokeOptions {
+            window: 500,
+            max_age: Duration::from_secs(180),
+            destination: RevokeDestination::SameChat,
+            media_dir: std::env::temp_dir().join("wcf-anti-revoke"),
+            download_timeout: Duration::from_secs(30),
+            queue_capacity: 256,
+        }
+    }
+}
+
+struct Captured {
+    msg: Message,
+    media: Option<PathBuf>,
+    received_at: Instant,
+}
+
+// recent messages by id, the oldest first
+#[derive(Default)]
+struct Window {
+    ids: VecDeque<u64>,
+    messages: HashMap<u64, Captured>,
+}
+
+impl Window {
+    fn push(&mut self, captured: Captured, options: &AntiRevokeOptions) {
+        self.ids.push_back(captured.msg.id());
+        self.messages.insert(captured.msg.id(), captured);
+        while let Some(&id) = self.ids.front() {
+            let expired =
+                self.messages.get(&id).is_none_or(|captured| captured.received_at.elapsed() > options.max_age);
+            if !expired && self.ids.len() <= options.window {
+                break;
+            }
+            self.ids.pop_front();
+            if let Some(media) = self.messages.remove(&id).and_then(|captured| captured.media) {
+                let _ = fs::remove_file(media);
+            }
+        }
+    }
+
+    fn get(&self, id: u64) -> Option<&Captured> {
+        self.messages.get(&id)
+    }
+
+    fn clear(&mut self) {
+        for media in self.messages.drain().filter_map(|(_, captured)| captured.media) {
+            let _ = fs::remove_file(media);
+        }
+        self.ids.clear();
+    }
+}
+
+enum Job {
+    Capture(Message),
+    Revoke(Message, RevokeNotice),
+}
+
+struct Running {
+    handler: HandlerId,
+    worker: JoinHandle<()>,
+}
+
+/// 防撤回：保留最近收到的消息，收到撤回通知时把原消息以 "X 撤回了: ..." 发回原会话或指定的会话。
+///
+/// 默认不开启，需要创建后调用 start()。文本保存在内存中，图片、视频、文件在收到时下载到 media_dir，撤回时通过
+/// send_image()、send_file() 重新发送，未能及时下载的只发送占位文字。下载和发送在独立的 wcf-anti-revoke 线程中进行，
+/// 不会阻塞接收和事件分发；自己撤回的消息不处理
+pub struct AntiRevoke {
+    client: WcfClient,
+    options: AntiRevokeOptions,
+    running: Mutex<Option<Running>>,
+}
+
+impl AntiRevoke {
+    pub fn new(client: WcfClient, options: AntiRevokeOptions) -> Self {
+        AntiRevoke { client, options, running: Mutex::new(None) }
+    }
+
+    /// 开始保存消息和处理撤回通知，已经开始时不做任何事
+    pub fn start(&self) -> Result<()> {
+        let mut running = self.running.lock();
+        if running.is_some() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.options.media_dir)?;
+        let (sender, receiver) = mpsc::sync_channel::<Job>(self.options.queue_capacity.max(1));
+        let (client, options) = (self.client.clone(), self.options.clone());
+        let worker = thread::Builder::new().name("wcf-anti-revoke".into()).spawn(move || {
+            let contacts = ContactCache::new(client.clone());
+            let mut window = Window::default();
+            for job in receiver {
+                match job {
+                    Job::Capture(msg) => {
+                        let media = Self::download(&client, &msg, &options);
+                        window.push(Captured { msg, media, received_at: Instant::now() }, &options);
+                    }
+                    Job::Revoke(notice_msg, notice) => {
+                        let captured = window.get(notice.revoked_msg_id);
+                        if let Err(e) = Self::repost(&client, &contacts, &options, &notice_msg, &notice, captured) {
+                            error!("failed to repost revoked message {}, error={}", notice.revoked_msg_id, e);
+                        }
+                    }
+                }
+            }
+            window.clear();
+        })?;
+        let handler = self.on_message(sender);
+        *running = Some(Running { handler, worker });
+        Ok(())
+    }
+
+    fn on_message(&self, sender: SyncSender<Job>) -> HandlerId {
+        self.client.on_message(move |msg| {
+            if msg.is_self {
+                return;
+            }
+            // system messages are skipped without copying them
+            let job = match RevokeNotice::parse(msg) {
+                Some(notice) => Job::Revoke(Message::from(msg.clone()), notice),
+                None if matches!(MsgType::from(msg.r#type as i32), MsgType::System | MsgType::SysNotice) => return,
+                None => Job::Capture(Message::from(msg.clone())),
+            };
+            match sender.try_send(job) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => warn!("anti-revoke queue is full, dropped a message"),
+                Err(TrySendError::Disconnected(_)) => trace!("anti-revoke stopped"),
+            }
+        })
+    }
+
+    // saves the media of msg into media_dir, None for text or when the download fails
+    fn download(client: &WcfClient, msg: &Message, options: &AntiRevokeOptions) -> Option<PathBuf> {
+        let wx_msg = msg.as_wx_msg();
+        let (dir, timeout) = (options.media_dir.as_path(), options.download_timeout);
+        let result = match msg.msg_type() {
+            MsgType::Image => client.download_image(wx_msg, dir, timeout),
+            MsgType::Video => client.download_attachment(wx_msg, Some(dir), timeout, |_| {}),
+            MsgType::App if matches!(msg.app_msg(), Ok(Some(AppMsg::File { .. }))) => {
+                client.download_attachment(wx_msg, Some(dir), timeout, |_| {})
+            }
+            _ => return None,
+        };
+        result.inspect_err(|e| debug!("failed to save media of msg {}, error={}", msg.id(), e)).ok()
+    }
+
+    fn repost(
+        client: &WcfClient,
+        contacts: &ContactCache,
+        options: &AntiRevokeOptions,
+        notice_msg: &Message,
+        notice: &RevokeNotice,
+        captured: Option<&Captured>,
+    ) -> Result<()> {
+        let chat = notice_msg.room_id().unwrap_or(notice_msg.sender()).to_string();
+        let receiver = match &options.destination {
+            RevokeDestination::SameChat => chat.clone(),
+            RevokeDestination::Chat(receiver) => receiver.clone(),
+        };
+        let who = match captured {
+            Some(captured) => contacts.display_name(captured.msg.sender())?,
+            // e.g. "张三" 撤回了一条消息
+            None => notice.replace_text.split(" 撤回了").next().unwrap_or_default().trim_matches('"').to_string(),
+        };
+        let prefix = match &options.destination {
+            RevokeDestination::SameChat => format!("{} 撤回了", who),
+            RevokeDestination::Chat(_) => format!("{} 在 {} 撤回了", who, contacts.display_name(&chat)?),
+        };
+        let Some(captured) = captured else {
+            // older than the window, the message database still has text messages
+            let text = match client.lookup_revoked_message(notice.revoked_msg_id)? {
+                Some(original) => format!("{}: {}", prefix, original.content),
+                None => format!("{}一条消息，未能保存内容", prefix),
+            };
+            client.send_text(text, receiver, String::new())?;
+            return Ok(());
+        };
+        let msg_type = captured.msg.msg_type();
+        // the file stays in media_dir until the message leaves the window, wechat reads it after the call returns
+        match &captured.media {
+            Some(media) => {
+                client.send_text(format!("{}[{}]:", prefix, msg_type), receiver.clone(), String::new())?;
+                match msg_type {
+                    MsgType::Image => client.send_image(media.clone(), receiver)?,
+                    _ => client.send_file(media.clone(), receiver)?,
+                };
+            }
+            None => {
+                let text = match captured.msg.text() {
+                    Some(text) => format!("{}: {}", prefix, text),
+                    None => format!("{}[{}]，未能保存内容", prefix, msg_type),
+                };
+                client.send_text(text, receiver, String::new())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 停止处理，等待队列中已有的消息处理完，删除保存的媒体文件
+    pub fn stop(&self) {
+        if let Some(running) = self.running.lock().take() {
+            // removing the handler drops the sender, which ends the worker loop
+            self.client.remove_handler(running.handler);
+            let _ = running.worker.join();
+        }
+    }
+}
+
+impl Drop for AntiRevoke {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
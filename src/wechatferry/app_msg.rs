@@ -0,0 +1,235 @@
+use roxmltree::{Document, Node};
+use serde::{Deserialize, Serialize};
+
+use super::error::Result;
+
+/// 转账的状态，对应 wcpayinfo 中的 paysubtype
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferDirection {
+    /// 对方转给自己，待收款，可以调用 recv_transfer() 收款
+    Incoming,
+    /// 已收款
+    Accepted,
+    /// 已退还
+    Refunded,
+    Other(i32),
+}
+
+impl From<i32> for TransferDirection {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => TransferDirection::Incoming,
+            3 => TransferDirection::Accepted,
+            4 => TransferDirection::Refunded,
+            _ => TransferDirection::Other(value),
+        }
+    }
+}
+
+/// type 49 消息（WxMsg.content）解析后的内容
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AppMsg {
+    Link {
+        title: String,
+        desc: String,
+        url: String,
+        thumb_url: String,
+    },
+    File {
+        name: String,
+        size: u64,
+        md5: String,
+    },
+    /// transferid、transcationid 即 recv_transfer() 的参数，wxid 为消息的发送者
+    Transfer {
+        /// 金额描述，例如 "￥0.01"
+        amount: String,
+        transferid: String,
+        transcationid: String,
+        direction: TransferDirection,
+    },
+    QuotedReply {
+        text: String,
+        quoted_msg_id: u64,
+        quoted_text: String,
+    },
+    MiniProgram {
+        appid: String,
+        title: String,
+        page_path: String,
+    },
+    /// 其他类型，保留原始 xml
+    Other(String),
+}
+
+// appmsg/type values
+const APP_MSG_LINK: i32 = 5;
+const APP_MSG_FILE: i32 = 6;
+const APP_MSG_MINI_PROGRAM: i32 = 33;
+const APP_MSG_MINI_PROGRAM_CARD: i32 = 36;
+const APP_MSG_QUOTED_REPLY: i32 = 57;
+const APP_MSG_TRANSFER: i32 = 2000;
+
+// text of the child at `path`, empty if missing
+fn child_text(node: Node, path: &[&str]) -> String {
+    let mut node = node;
+    for name in path {
+        match node.children().find(|child| child.has_tag_name(*name)) {
+            Some(child) => node = child,
+            None => return String::new(),
+        }
+    }
+    node.text().unwrap_or_default().trim().to_string()
+}
+
+impl AppMsg {
+    /// 解析 type 49 消息的 content，无法识别的类型返回 `AppMsg::Other`
+    pub fn parse(content: &str) -> Result<AppMsg> {
+        let doc = Document::parse(content.trim())?;
+        let root = doc.root_element();
+        let appmsg = match root.children().find(|child| child.has_tag_name("appmsg")) {
+            Some(appmsg) => appmsg,
+            None => return Ok(AppMsg::Other(content.to_string())),
+        };
+        let text = |path: &[&str]| child_text(appmsg, path);
+        let app_msg = match text(&["type"]).parse().unwrap_or_default() {
+            APP_MSG_LINK => AppMsg::Link {
+                title: text(&["title"]),
+                desc: text(&["des"]),
+                url: text(&["url"]),
+                thumb_url: text(&["thumburl"]),
+            },
+            APP_MSG_FILE => AppMsg::File {
+                name: text(&["title"]),
+                size: text(&["appattach", "totallen"]).parse().unwrap_or_default(),
+                md5: text(&["md5"]),
+            },
+            APP_MSG_TRANSFER => AppMsg::Transfer {
+                amount: text(&["wcpayinfo", "feedesc"]),
+                transferid: text(&["wcpayinfo", "transferid"]),
+                transcationid: text(&["wcpayinfo", "transcationid"]),
+                direction: text(&["wcpayinfo", "paysubtype"]).parse::<i32>().unwrap_or_default().into(),
+            },
+            APP_MSG_QUOTED_REPLY => AppMsg::QuotedReply {
+                text: text(&["title"]),
+                quoted_msg_id: text(&["refermsg", "svrid"]).parse().unwrap_or_default(),
+                quoted_text: text(&["refermsg", "content"]),
+            },
+            APP_MSG_MINI_PROGRAM | APP_MSG_MINI_PROGRAM_CARD => {
+                let appid = Some(text(&["weappinfo", "appid"])).filter(|appid| !appid.is_empty());
+                AppMsg::MiniProgram {
+                    appid: appid.unwrap_or_else(|| appmsg.attribute("appid").unwrap_or_default().to_string()),
+                    title: text(&["title"]),
+                    page_path: text(&["weappinfo", "pagepath"]),
+                }
+            }
+            _ => AppMsg::Other(content.to_string()),
+        };
+        Ok(app_msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link() {
+        let xml = r#"<?xml version="1.0"?>
+<msg>
+    <appmsg appid="" sdkver="0">
+        <title>标题 &amp; 副标题</title>
+        <des>摘要</des>
+        <type>5</type>
+        <url>https://example.com/a?b=1&amp;c=2</url>
+        <thumburl>https://example.com/thumb.jpg</thumburl>
+    </appmsg>
+</msg>"#;
+        let expected = AppMsg::Link {
+            title: "标题 & 副标题".into(),
+            desc: "摘要".into(),
+            url: "https://example.com/a?b=1&c=2".into(),
+            thumb_url: "https://example.com/thumb.jpg".into(),
+        };
+        assert_eq!(AppMsg::parse(xml).unwrap(), expected);
+    }
+
+    #[test]
+    fn file() {
+        let xml = r#"<msg>
+    <appmsg appid="" sdkver="0">
+        <title>报告.pdf</title>
+        <type>6</type>
+        <appattach>
+            <totallen>123456</totallen>
+            <fileext>pdf</fileext>
+        </appattach>
+        <md5>0123456789abcdef0123456789abcdef</md5>
+    </appmsg>
+</msg>"#;
+        let expected =
+            AppMsg::File { name: "报告.pdf".into(), size: 123456, md5: "0123456789abcdef0123456789abcdef".into() };
+        assert_eq!(AppMsg::parse(xml).unwrap(), expected);
+    }
+
+    #[test]
+    fn quoted_reply() {
+        let xml = r#"<msg>
+    <appmsg appid="" sdkver="0">
+        <title>同意</title>
+        <type>57</type>
+        <refermsg>
+            <type>1</type>
+            <svrid>1234567890123456789</svrid>
+            <fromusr>123@chatroom</fromusr>
+            <chatusr>wxid_a</chatusr>
+            <displayname>张三</displayname>
+            <content>明天开会吗</content>
+        </refermsg>
+    </appmsg>
+</msg>"#;
+        let expected = AppMsg::QuotedReply {
+            text: "同意".into(),
+            quoted_msg_id: 1234567890123456789,
+            quoted_text: "明天开会吗".into(),
+        };
+        assert_eq!(AppMsg::parse(xml).unwrap(), expected);
+    }
+
+    #[test]
+    fn mini_program() {
+        let xml = r#"<msg>
+    <appmsg appid="wx1234567890" sdkver="0">
+        <title>点外卖</title>
+        <type>33</type>
+        <weappinfo>
+            <pagepath><![CDATA[pages/index/index.html?from=share]]></pagepath>
+            <appid>wxabcdef</appid>
+        </weappinfo>
+    </appmsg>
+</msg>"#;
+        let expected = AppMsg::MiniProgram {
+            appid: "wxabcdef".into(),
+            title: "点外卖".into(),
+            page_path: "pages/index/index.html?from=share".into(),
+        };
+        assert_eq!(AppMsg::parse(xml).unwrap(), expected);
+
+        // the appid attribute when weappinfo has none
+        let xml = r#"<msg><appmsg appid="wx1234567890"><title>卡片</title><type>36</type></appmsg></msg>"#;
+        assert!(matches!(AppMsg::parse(xml).unwrap(), AppMsg::MiniProgram { appid, .. } if appid == "wx1234567890"));
+    }
+
+    #[test]
+    fn unknown_type_is_other() {
+        let xml = r#"<msg><appmsg><title>音乐</title><type>3</type></appmsg></msg>"#;
+        assert_eq!(AppMsg::parse(xml).unwrap(), AppMsg::Other(xml.into()));
+    }
+
+    #[test]
+    fn malformed_xml_is_err() {
+        assert!(AppMsg::parse("<msg><appmsg><type>5</type></msg>").is_err());
+        assert!(AppMsg::parse("not xml").is_err());
+        assert!(AppMsg::parse("").is_err());
+    }
+}
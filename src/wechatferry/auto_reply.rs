@@ -0,0 +1,231 @@
+use parking_lot::Mutex;
+#[cfg(feature = "regex")]
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, trace};
+
+use super::error::Result;
+#[cfg(feature = "regex")]
+use super::error::WcfError;
+use super::events::HandlerId;
+use super::{Mention, Message, WcfClient};
+
+type MatchFn = Arc<dyn Fn(&Message) -> bool + Send + Sync>;
+type ReplyFn = Arc<dyn Fn(&Message) -> Option<String> + Send + Sync>;
+
+/// 自动回复规则匹配的条件，只匹配文本消息的内容
+#[derive(Clone)]
+pub enum Matcher {
+    /// 内容（去掉首尾空白）等于
+    Exact(String),
+    Contains(String),
+    /// 需要开启 regex feature
+    #[cfg(feature = "regex")]
+    Regex(Regex),
+    Custom(MatchFn),
+}
+
+impl Matcher {
+    /// 编译正则表达式，无效时返回 `WcfError::InvalidArgument`
+    #[cfg(feature = "regex")]
+    pub fn regex(pattern: &str) -> Result<Matcher> {
+        let regex = Regex::new(pattern).map_err(|e| WcfError::InvalidArgument(format!("invalid regex: {}", e)))?;
+        Ok(Matcher::Regex(regex))
+    }
+
+    pub fn custom<F>(f: F) -> Matcher
+    where
+        F: Fn(&Message) -> bool + Send + Sync + 'static,
+    {
+        Matcher::Custom(Arc::new(f))
+    }
+
+    pub fn matches(&self, msg: &Message) -> bool {
+        match (self, msg.text()) {
+            (Matcher::Custom(f), _) => f(msg),
+            (_, None) => false,
+            (Matcher::Exact(exact), Some(text)) => text.trim() == exact,
+            (Matcher::Contains(keyword), Some(text)) => text.contains(keyword.as_str()),
+            #[cfg(feature = "regex")]
+            (Matcher::Regex(regex), Some(text)) => regex.is_match(text),
+        }
+    }
+}
+
+/// 匹配后回复的内容
+#[derive(Clone)]
+pub enum Reply {
+    Text(String),
+    /// 模板，{sender} 替换为发送者 wxid，{room} 替换为群 id（私聊时为空），{text} 替换为收到的内容
+    Template(String),
+    /// 返回 None 时不回复
+    Custom(ReplyFn),
+}
+
+impl Reply {
+    pub fn custom<F>(f: F) -> Reply
+    where
+        F: Fn(&Message) -> Option<String> + Send + Sync + 'static,
+    {
+        Reply::Custom(Arc::new(f))
+    }
+
+    pub fn render(&self, msg: &Message) -> Option<String> {
+        match self {
+            Reply::Text(text) => Some(text.clone()),
+            Reply::Template(template) => Some(
+                template
+                    .replace("{sender}", msg.sender())
+                    .replace("{room}", msg.room_id().unwrap_or_default())
+                    .replace("{text}", msg.text().unwrap_or_default()),
+            ),
+            Reply::Custom(f) => f(msg),
+        }
+    }
+}
+
+/// 一条自动回复规则
+#[derive(Clone)]
+pub struct ReplyRule {
+    pub matcher: Matcher,
+    pub reply: Reply,
+    /// 优先级高的规则先匹配，相同时按添加顺序
+    pub priority: i32,
+    /// 同一个会话中两次回复的最小间隔，冷却期间匹配到时不回复
+    pub cooldown: Duration,
+    /// 群聊中回复时 @ 发送者
+    pub mention_sender: bool,
+}
+
+impl ReplyRule {
+    pub fn new(matcher: Matcher, reply: Reply) -> Self {
+        ReplyRule { matcher, reply, priority: 0, cooldown: Duration::ZERO, mention_sender: false }
+    }
+}
+
+/// add_rule() 返回的 id，用于删除规则
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RuleId(u64);
+
+struct RuleEntry {
+    id: RuleId,
+    rule: ReplyRule,
+    // chat id -> last reply time
+    last_replied: HashMap<String, Instant>,
+}
+
+#[derive(Default)]
+struct Rules {
+    // sorted by priority, highest first, insertion order kept for equal priorities
+    entries: Vec<RuleEntry>,
+    next_id: u64,
+}
+
+/// 关键词自动回复，start() 后处理收到的文本消息，按优先级匹配规则，只回复第一条匹配的规则。
+///
+/// 自己发送的消息不会触发回复；规则可以在运行中随时添加、删除
+pub struct AutoReply {
+    client: WcfClient,
+    rules: Arc<Mutex<Rules>>,
+    handler: Mutex<Option<HandlerId>>,
+}
+
+impl AutoReply {
+    pub fn new(client: WcfClient) -> Self {
+        AutoReply { client, rules: Arc::default(), handler: Mutex::new(None) }
+    }
+
+    /// 添加优先级为 0、没有冷却时间的规则
+    pub fn add_rule(&self, matcher: Matcher, reply: Reply) -> RuleId {
+        self.add(ReplyRule::new(matcher, reply))
+    }
+
+    pub fn add(&self, rule: ReplyRule) -> RuleId {
+        let mut rules = self.rules.lock();
+        let id = RuleId(rules.next_id);
+        rules.next_id += 1;
+        let index = rules.entries.partition_point(|entry| entry.rule.priority >= rule.priority);
+        rules.entries.insert(index, RuleEntry { id, rule, last_replied: HashMap::new() });
+        id
+    }
+
+    /// 删除规则，规则不存在时返回 false
+    pub fn remove_rule(&self, id: RuleId) -> bool {
+        let mut rules = self.rules.lock();
+        let len = rules.entries.len();
+        rules.entries.retain(|entry| entry.id != id);
+        rules.entries.len() != len
+    }
+
+    /// 按匹配顺序返回所有规则的 id
+    pub fn rule_ids(&self) -> Vec<RuleId> {
+        self.rules.lock().entries.iter().map(|entry| entry.id).collect()
+    }
+
+    /// 开始处理收到的消息，已经开始时不做任何事
+    pub fn start(&self) {
+        let mut handler = self.handler.lock();
+        if handler.is_some() {
+            return;
+        }
+        let (client, rules) = (self.client.clone(), self.rules.clone());
+        *handler = Some(self.client.on_message(move |msg| {
+            let msg = Message::from(msg.clone());
+            if let Err(e) = Self::reply_to(&client, &rules, &msg) {
+                error!("failed to auto reply, msg_id={}, error={}", msg.id(), e);
+            }
+        }));
+    }
+
+    /// 停止处理收到的消息，规则保留，可以再次 start()
+    pub fn stop(&self) {
+        if let Some(id) = self.handler.lock().take() {
+            self.client.remove_handler(id);
+        }
+    }
+
+    /// 按规则回复一条消息，不需要 start()，返回是否匹配到了规则
+    pub fn handle(&self, msg: &Message) -> Result<bool> {
+        Self::reply_to(&self.client, &self.rules, msg)
+    }
+
+    fn reply_to(client: &WcfClient, rules: &Mutex<Rules>, msg: &Message) -> Result<bool> {
+        if msg.is_self() {
+            return Ok(false);
+        }
+        let chat = msg.room_id().unwrap_or(msg.sender()).to_string();
+        let rule = {
+            let mut rules = rules.lock();
+            let entry = match rules.entries.iter_mut().find(|entry| entry.rule.matcher.matches(msg)) {
+                Some(entry) => entry,
+                None => return Ok(false),
+            };
+            let now = Instant::now();
+            if entry.last_replied.get(&chat).is_some_and(|at| now.duration_since(*at) < entry.rule.cooldown) {
+                trace!("auto reply rule cooling down, rule={:?}, chat={}", entry.id, chat);
+                return Ok(true);
+            }
+            entry.last_replied.insert(chat.clone(), now);
+            entry.rule.clone()
+        };
+        // rendered and sent with the rules unlocked, a custom reply may take a while
+        let text = match rule.reply.render(msg) {
+            Some(text) => text,
+            None => return Ok(true),
+        };
+        if rule.mention_sender && msg.is_group() {
+            client.send_text_with_mentions(chat, text, &[Mention::Wxid(msg.sender().to_string())])?;
+        } else {
+            client.send_text(text, chat, String::new())?;
+        }
+        Ok(true)
+    }
+}
+
+impl Drop for AutoReply {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,3108 @@
+use nng::options::{Options, RecvTimeout, SendTimeout};
+use nng::Socket;
+use parking_lot::{Mutex, MutexGuard};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::{Ipv4Addr, TcpListener};
+use std::ops::RangeInclusive;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{debug, debug_span, error, field, info, trace, warn};
+
+use super::compat::{self, Verdict};
+use super::dedup::RecentIds;
+use super::error::{Result, WcfError};
+use super::events::{
+    panic_message, ConnectionChange, EventHub, EventQueueConfig, HandlerId, DEFAULT_SUBSCRIBER_CAPACITY,
+};
+use super::export::{self, CsvWriter, ExportRow, ExportWriter, ExportedMsg};
+use super::friend_policy::{self, FriendAutomation};
+use super::history::MsgDbMap;
+use super::humanize::{self, HumanizeOptions};
+use super::listen_filter::AccessLists;
+use super::loader::{DllSdkLoader, SdkLoader, SpyVariant};
+#[cfg(feature = "process")]
+use super::process;
+use super::rate_limit::{RateLimitConfig, RateLimitMode, RateLimiter};
+use super::stats::{StatsCounters, WcfStats};
+use super::welcome::{self, Welcomes};
+#[cfg(feature = "config")]
+use super::Config;
+use super::{db_value, download, history, metrics, proto, search, sql, validate};
+use super::{
+    AppMsg, ChatRoom, ChatRoomMember, ContactCache, ContactInfo, ContactKind, Ctx, DbMessage, DbRow, DbTable, Event,
+    ExportFormat, ExportSummary, FriendPolicy, FriendRequest, LinkCard, ListenFilter, Mention, MessageFilter, MsgType,
+    OcrMsg, Pipeline, RichText, RoomEvent, RpcContact, RpcContacts, SearchHit, SearchOptions, SendResult, TimeRange,
+    TransferInfo, TransferPolicy, TypedDbRow, UserInfo, WelcomeConfig, WxMsg,
+};
+
+const RECV_TIMEOUT: Duration = Duration::from_millis(5000);
+const SEND_TIMEOUT: Duration = Duration::from_millis(5000);
+// rows per page in query_all_contact_info()
+const CONTACT_PAGE_SIZE: usize = 1000;
+// msg socket keeps its own timeout, the receive loop just retries on it
+const MSG_RECV_TIMEOUT: Duration = Duration::from_millis(5000);
+// the spy may start listening on the msg port a moment after FUNC_ENABLE_RECV_TXT returns
+const MSG_CONNECT_TIMEOUT: Duration = Duration::from_millis(3000);
+const MSG_CONNECT_INTERVAL: Duration = Duration::from_millis(100);
+// a large request, e.g. a long xml, is not worth keeping as the scratch buffer
+const MAX_KEPT_ENCODE_BUF: usize = 64 * 1024;
+// identical MsgDecodeError or UnsupportedMsg events are sent at most once per interval
+const MSG_ERROR_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+/// 重复消息过滤默认记录的消息 id 数，见 `set_dedup()`
+pub const DEFAULT_DEDUP_CAPACITY: usize = 4096;
+/// `disable_listen()` 等待接收线程退出的默认时间
+pub const DEFAULT_LISTEN_STOP_TIMEOUT: Duration = Duration::from_millis(3000);
+/// `shutdown()` 每一步的默认等待时间
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(3000);
+
+/// cmd socket 的收发超时
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CmdTimeouts {
+    pub send_timeout: Duration,
+    pub recv_timeout: Duration,
+}
+
+impl Default for CmdTimeouts {
+    fn default() -> Self {
+        CmdTimeouts { send_timeout: SEND_TIMEOUT, recv_timeout: RECV_TIMEOUT }
+    }
+}
+
+/// `init_with_options()` 的参数
+#[derive(Clone, Debug)]
+pub struct InitOptions {
+    /// 是否开启 sdk 调试模式
+    pub debug: bool,
+    /// 为 true 时，返回的 CleanupHandler 销毁时自动调用 uninit()
+    pub auto_clean: bool,
+    /// cmd socket 的收发超时，之后也可以通过 set_cmd_timeouts() 修改
+    pub cmd_timeouts: CmdTimeouts,
+    /// sdk.dll 的路径，可以是文件或其所在目录，None 时读取环境变量 WCF_SDK_PATH，都没有则从默认搜索路径加载
+    pub sdk_path: Option<PathBuf>,
+    /// 为 true 且微信没有运行时，先启动微信，需要开启 process feature；之后的步骤失败时会结束启动的微信
+    pub launch_wechat: bool,
+    /// WeChat.exe 或其所在目录，None 时从注册表中的安装路径和默认安装目录查找
+    pub wechat_path: Option<PathBuf>,
+    /// 启动微信后等待其窗口出现的最长时间，超时后继续 init
+    pub launch_wait: Duration,
+    /// 为 true 时，init 前先检查 port 和 port + 1 是否空闲，被占用时返回 `WcfError::PortInUse`，
+    /// 使用 MockWcfServer 时不要开启，它已经监听了这两个端口
+    pub check_ports: bool,
+    /// 为 true 时跳过微信版本检查，版本不一致时也注入，见 check_compatibility()
+    pub force: bool,
+    /// 命令超时后的重试策略，None 时不修改 set_retry_policy() 的设置，默认不重试
+    pub retry_policy: Option<RetryPolicy>,
+}
+
+impl Default for InitOptions {
+    fn default() -> Self {
+        InitOptions {
+            debug: false,
+            auto_clean: true,
+            cmd_timeouts: CmdTimeouts::default(),
+            sdk_path: None,
+            launch_wechat: false,
+            wechat_path: None,
+            launch_wait: Duration::from_secs(30),
+            check_ports: false,
+            force: false,
+            retry_policy: None,
+        }
+    }
+}
+
+/// cmd socket 出错后的自动重连策略，重连间隔按指数退避增长
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    /// 最多尝试重连的次数
+    pub max_attempts: u32,
+    /// 第一次重连前的等待时间
+    pub initial_backoff: Duration,
+    /// 重连等待时间的上限
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// 命令超时后在同一个 cmd socket 上重试的策略，重试都超时后才断开 cmd socket，再按 ReconnectPolicy 重连。
+///
+/// 只重试 functions 中的函数，应当只包含重复执行没有副作用的查询，发送消息等函数重试可能导致重复发送
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// 最多执行的次数，包括第一次
+    pub max_attempts: u32,
+    /// 第一次重试前的等待时间，之后每次翻倍
+    pub backoff: Duration,
+    /// 允许重试的函数
+    pub functions: HashSet<proto::Functions>,
+}
+
+impl RetryPolicy {
+    /// 可以安全重试的查询类函数，即 Default 中的 functions
+    pub fn idempotent_functions() -> HashSet<proto::Functions> {
+        use proto::Functions;
+        HashSet::from([
+            Functions::FuncIsLogin,
+            Functions::FuncGetSelfWxid,
+            Functions::FuncGetMsgTypes,
+            Functions::FuncGetContacts,
+            Functions::FuncGetDbNames,
+            Functions::FuncGetDbTables,
+            Functions::FuncGetUserInfo,
+            Functions::FuncExecDbQuery,
+            Functions::FuncGetContactInfo,
+        ])
+    }
+
+    fn allows(&self, func: i32) -> bool {
+        proto::Functions::try_from(func).is_ok_and(|function| self.functions.contains(&function))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(200),
+            functions: RetryPolicy::idempotent_functions(),
+        }
+    }
+}
+
+/// `enable_listen()` 的结果
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListenStatus {
+    /// 已连接 msg socket 并启动了接收线程
+    Started,
+    /// 接收线程已在运行，本次调用没有做任何事
+    AlreadyListening,
+}
+
+/// 客户端当前的连接状态，见 `WcfClient::state()`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WcfState {
+    /// 是否已调用 init() 且尚未 uninit()
+    pub sdk_inited: bool,
+    pub cmd_port: Option<u16>,
+    /// cmd socket 是否已连接
+    pub cmd_connected: bool,
+    /// 接收线程是否在运行
+    pub listening: bool,
+    /// 已开启远端消息接收时的 msg 端口
+    pub msg_port: Option<u16>,
+}
+
+/// 消息接收的统计，见 `WcfClient::listen_stats()`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListenStats {
+    /// 开启 set_dedup() 后丢弃的重复消息数
+    pub duplicates_dropped: u64,
+    /// 被 set_listen_filter() 过滤掉的消息数
+    pub filtered: u64,
+}
+
+/// `broadcast_text()` 等群发接口的参数
+#[derive(Clone, Debug, Default)]
+pub struct BroadcastOptions {
+    /// 每两次发送之间额外等待的时间，速率限制仍然生效
+    pub delay: Duration,
+    /// 遇到第一个失败（出错或 success 为 false）后停止，之后的接收者不会出现在结果中
+    pub stop_on_error: bool,
+    /// 不发送，只检查接收者是否在联系人中，存在的返回 status 为 0、success 为 true 的结果
+    pub dry_run: bool,
+}
+
+pub struct CleanupHandler {
+    client: WcfClient,
+    auto_clean: bool,
+    // the init() it belongs to, a handler left from before reinitialize() does nothing
+    generation: u64,
+    launched_wechat: Option<Child>,
+    port: u16,
+    // created by attach(), dropping it detaches instead of destroying the sdk
+    attached: bool,
+}
+
+impl CleanupHandler {
+    /// init 使用的 cmd socket 端口，msg socket 为其后一个端口，init_auto_port() 时为选中的端口
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// 是否由 attach() 返回，此时销毁时调用 detach()，不会关闭微信中的 wcf
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    /// InitOptions.launch_wechat 为 true 时 init() 启动的微信进程，微信原本就在运行时为 None。
+    ///
+    /// 销毁时不会结束该进程，需要时可以在退出前自行 kill()
+    pub fn launched_wechat(&mut self) -> Option<&mut Child> {
+        self.launched_wechat.as_mut()
+    }
+
+    /// 取出 init() 启动的微信进程，参考 launched_wechat()
+    pub fn take_launched_wechat(&mut self) -> Option<Child> {
+        self.launched_wechat.take()
+    }
+}
+
+impl Drop for CleanupHandler {
+    fn drop(&mut self) {
+        if !self.auto_clean || self.client.state.init_generation.load(Ordering::SeqCst) != self.generation {
+            return;
+        }
+        match self.attached {
+            true => self.client.detach(),
+            false => self.client.shutdown(),
+        }
+    }
+}
+
+// the running receive thread, closing `socket` makes it quit at once
+struct MsgThread {
+    socket: Socket,
+    handle: JoinHandle<()>,
+}
+
+// the running health check thread, dropping `stop` makes it quit
+struct HealthCheck {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+// a connected cmd socket, `serial` tells a pending exchange whether it has been replaced meanwhile
+struct CmdSocket {
+    serial: u64,
+    port: u16,
+    socket: Socket,
+}
+
+#[derive(Default)]
+struct ClientState {
+    // set in init(), and unset in uninit()
+    cmd_port: Mutex<u16>,
+    // connect as caller requests, disconnect when uninit() or error happens
+    // only locked briefly, never across a blocking exchange, so it can always be closed
+    cmd_socket: Mutex<Option<CmdSocket>>,
+    // bumped whenever cmd_socket is stored or taken
+    cmd_socket_serial: AtomicU64,
+    // serializes request/response pairs, Pair1 cannot match a response to its request
+    cmd_exchange: Mutex<()>,
+    // set in enable_listen(), and unset in disable_listen()
+    msg_port: Mutex<u16>,
+    // the running receive thread, set in enable_listen(), and joined in disable_listen()
+    msg_thread: Mutex<Option<MsgThread>>,
+    events: EventHub,
+    // None means no auto reconnect, set in set_cmd_reconnect()
+    reconnect_policy: Mutex<Option<ReconnectPolicy>>,
+    // None means no retry, set in set_retry_policy() or init()
+    retry_policy: Mutex<Option<RetryPolicy>>,
+    // responses to timed out requests which were retried, they may still arrive on the current cmd socket
+    stale_responses: AtomicU64,
+    // applied to cmd socket on connect, and to the connected one in set_cmd_timeouts()
+    cmd_timeouts: Mutex<CmdTimeouts>,
+    // true after connect_cmd_socket(), false after disconnect_cmd_socket(), unlike cmd_socket it stays true on errors
+    cmd_wanted: AtomicBool,
+    health_check: Mutex<Option<HealthCheck>>,
+    // None means DllSdkLoader, set in with_loader()
+    loader: Option<Arc<dyn SdkLoader>>,
+    // filled by resolve_msg_dbs()
+    msg_db_map: Mutex<MsgDbMap>,
+    // None means no dedup, set in set_dedup()
+    dedup: Mutex<Option<RecentIds>>,
+    duplicates_dropped: AtomicU64,
+    // replaced as a whole in set_listen_filter(), so the receive thread never sees a half updated filter
+    listen_filter: Mutex<Arc<ListenFilter>>,
+    filtered: AtomicU64,
+    // held across a whole access list update and its save, set in persist_access_lists()
+    access_list_path: Mutex<Option<PathBuf>>,
+    // checked before every send, unlimited until set_rate_limit()
+    rate_limiter: Mutex<RateLimiter>,
+    // None means sending at once, set in set_humanize()
+    humanize: Mutex<Option<HumanizeOptions>>,
+    // auto_accept is false until set_transfer_policy()
+    transfer_policy: Mutex<TransferPolicy>,
+    // transferids already accepted or ignored, a transfer message may be pushed again after reconnecting
+    seen_transfers: Mutex<HashSet<String>>,
+    // auto_accept is false until set_friend_policy()
+    friend_policy: Mutex<FriendPolicy>,
+    friend_automation: Mutex<FriendAutomation>,
+    // welcome configs and the joins waiting for their delay, see set_welcome()
+    welcomes: Mutex<Welcomes>,
+    // the on_message() handler feeding the pipeline, set in set_pipeline()
+    pipeline_handler: Mutex<Option<HandlerId>>,
+    // see stats(), the uptime is set in init() and unset in uninit()
+    stats: StatsCounters,
+    // held during shutdown(), a concurrent call returns at once instead of waiting
+    shutdown: Mutex<()>,
+    // bumped on every successful init(), see CleanupHandler
+    init_generation: AtomicU64,
+    // port and options of the last successful init(), used by reinitialize()
+    last_init: Mutex<Option<(u16, InitOptions)>>,
+    // set in attach(), uninit() never destroys an sdk it didn't init
+    attached: AtomicBool,
+}
+
+/// 一个 wcf 客户端，独立持有 cmd socket、msg 端口和事件回调。
+///
+/// 可以在同一进程内创建多个客户端分别连接不同端口，各自的接收线程互不影响。
+/// `WcfClient` 可以廉价地 clone，clone 出的实例共享同一份状态。
+#[derive(Clone, Default)]
+pub struct WcfClient {
+    state: Arc<ClientState>,
+    // per-call timeouts, see with_cmd_timeouts()
+    timeouts_override: Option<CmdTimeouts>,
+}
+
+thread_local! {
+    // reused by run_cmd() for encoding requests
+    static ENCODE_BUF: Cell<Vec<u8>> = const { Cell::new(Vec::new()) };
+}
+
+fn exchange_message(socket: &Socket, msg: nng::Message) -> Result<nng::Message> {
+    socket.send(msg).map_err(|(_, e)| e)?;
+    Ok(socket.recv()?)
+}
+
+// e.g. "FUNC_SEND_TXT", used in logs and metrics
+pub(crate) fn function_name(func: i32) -> &'static str {
+    proto::Functions::try_from(func).map_or("UNKNOWN", |function| function.as_str_name())
+}
+
+// send_* and forward_msg, counted as sends in stats and metrics
+pub(crate) fn is_send_function(func: i32) -> bool {
+    use proto::Functions;
+    matches!(
+        Functions::try_from(func),
+        Ok(Functions::FuncSendTxt
+            | Functions::FuncSendImg
+            | Functions::FuncSendFile
+            | Functions::FuncSendXml
+            | Functions::FuncSendEmotion
+            | Functions::FuncSendRichTxt
+            | Functions::FuncSendPatMsg
+            | Functions::FuncForwardMsg)
+    )
+}
+
+fn get_response_status_as_bool(response: &proto::Response) -> bool {
+    match response.msg {
+        Some(proto::response::Msg::Status(status)) => 1 == status,
+        _ => false,
+    }
+}
+
+// lower is better, `query` is already lowercased
+fn search_rank(contact: &ContactInfo, query: &str) -> u8 {
+    let names = [
+        &contact.nick_name,
+        &contact.alias,
+        &contact.py_initial,
+        &contact.quan_pin,
+        &contact.remark_py_initial,
+        &contact.remark_quan_pin,
+    ];
+    let names = || names.iter().filter_map(|name| name.as_deref()).map(str::to_lowercase);
+    if contact.remark.as_deref().is_some_and(|remark| remark.to_lowercase() == query) {
+        0
+    } else if names().any(|name| name == query) {
+        1
+    } else if contact
+        .remark
+        .iter()
+        .map(|remark| remark.to_lowercase())
+        .chain(names())
+        .any(|name| name.starts_with(query))
+    {
+        2
+    } else {
+        3
+    }
+}
+
+fn set_socket_timeouts(socket: &Socket, timeouts: &CmdTimeouts) -> Result<()> {
+    socket.set_opt::<RecvTimeout>(Some(timeouts.recv_timeout))?;
+    socket.set_opt::<SendTimeout>(Some(timeouts.send_timeout))?;
+    Ok(())
+}
+
+// an unknown version is let through, the check can't tell whether it works
+fn check_wechat_version() -> Result<()> {
+    let report = compat::check_compatibility()?;
+    match report.verdict {
+        Verdict::Compatible => Ok(()),
+        Verdict::Unknown => {
+            warn!("{}", report);
+            Ok(())
+        }
+        Verdict::Mismatch => Err(WcfError::IncompatibleWeChat {
+            installed: report.installed_wechat_version.unwrap_or_default(),
+            supported: report.supported_wechat_version,
+            wcf: report.wcf_version,
+        }),
+    }
+}
+
+// the spy listens on all interfaces, so probe the same way
+fn check_port_pair(port: u16) -> Result<()> {
+    let msg_port =
+        port.checked_add(1).ok_or_else(|| WcfError::InvalidArgument(format!("no msg port after port {}", port)))?;
+    for port in [port, msg_port] {
+        if TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).is_err() {
+            return Err(WcfError::PortInUse(port));
+        }
+    }
+    Ok(())
+}
+
+fn connect_socket(port: u16, timeouts: &CmdTimeouts) -> Result<Socket> {
+    let socket = Socket::new(nng::Protocol::Pair1)?;
+    set_socket_timeouts(&socket, timeouts)?;
+    let url = format!("tcp://127.0.0.1:{}", port);
+    socket.dial(&url)?;
+    Ok(socket)
+}
+
+// retries until the spy listens, nng dials synchronously so a refused connection fails at once
+fn connect_msg_socket(port: u16) -> Result<Socket> {
+    let timeouts = CmdTimeouts { recv_timeout: MSG_RECV_TIMEOUT, ..Default::default() };
+    let deadline = Instant::now() + MSG_CONNECT_TIMEOUT;
+    loop {
+        match connect_socket(port, &timeouts) {
+            Ok(socket) => return Ok(socket),
+            Err(WcfError::Socket(source)) if Instant::now() >= deadline => {
+                return Err(WcfError::MsgSocketConnect { port, source })
+            }
+            Err(WcfError::Socket(e)) => trace!("msg socket not ready on port {}, error={}", port, e),
+            Err(e) => return Err(e),
+        }
+        thread::sleep(MSG_CONNECT_INTERVAL);
+    }
+}
+
+// a wechat launched by init_with_options() is not left running when a later step fails
+fn kill_launched_wechat(mut child: Child) {
+    warn!("init failed, killing the launched wechat, pid={}", child.id());
+    if let Err(e) = child.kill().and_then(|_| child.wait()) {
+        warn!("failed to kill the launched wechat, pid={}, error={}", child.id(), e);
+    }
+}
+
+impl WcfClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 使用指定的 loader 创建客户端，init()/uninit() 通过它加载和调用 sdk
+    pub fn with_loader<L: SdkLoader + 'static>(loader: L) -> Self {
+        let state = ClientState { loader: Some(Arc::new(loader)), ..Default::default() };
+        WcfClient { state: Arc::new(state), timeouts_override: None }
+    }
+
+    fn loader(&self) -> &dyn SdkLoader {
+        self.state.loader.as_deref().unwrap_or(&DllSdkLoader)
+    }
+
+    fn exchange_message_via_cmd_socket(&self, func: i32, buf: &[u8]) -> Result<nng::Message> {
+        let _exchange = self.state.cmd_exchange.lock();
+        let (serial, port, socket) = match self.state.cmd_socket.lock().as_ref() {
+            Some(cmd) => (cmd.serial, cmd.port, cmd.socket.clone()),
+            None => return Err(WcfError::SocketDisconnected),
+        };
+        let error = match self.exchange_message_with_retries(&socket, func, buf) {
+            Ok(msg) => return Ok(msg),
+            Err(e) => e,
+        };
+        if !self.drop_cmd_socket(serial) {
+            // already disconnected by another thread, e.g. during shutdown, so never reconnect
+            return Err(error);
+        }
+        error!("failed to send or receive, error={:?}, disconnect cmd_socket", error);
+
+        let policy = match self.state.reconnect_policy.lock().clone() {
+            Some(policy) => policy,
+            None => return Err(error),
+        };
+        let (serial, socket) = self.reconnect_cmd_socket(port, &policy)?;
+        // retry the failed command only once
+        let retry_result = self.exchange_message_with_timeouts(&socket, func, buf);
+        if let Err(e) = retry_result.as_ref() {
+            error!("failed to retry after reconnect, error={:?}, disconnect cmd_socket", e);
+            self.drop_cmd_socket(serial);
+        }
+        retry_result
+    }
+
+    // timeouts of functions allowed by the retry policy are retried on the same socket before giving it up
+    fn exchange_message_with_retries(&self, socket: &Socket, func: i32, buf: &[u8]) -> Result<nng::Message> {
+        let policy = self.state.retry_policy.lock().clone().filter(|policy| policy.allows(func));
+        let Some(policy) = policy else {
+            return self.exchange_message_with_timeouts(socket, func, buf);
+        };
+        let mut backoff = policy.backoff;
+        let mut attempt = 1;
+        loop {
+            match self.exchange_message_with_timeouts(socket, func, buf) {
+                Err(WcfError::Timeout) if attempt < policy.max_attempts => {
+                    warn!("command timed out, retry {}/{} in {:?}", attempt, policy.max_attempts - 1, backoff);
+                    self.state.stale_responses.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // must be called with cmd_exchange locked, so the override never leaks into other commands
+    fn exchange_message_with_timeouts(&self, socket: &Socket, func: i32, buf: &[u8]) -> Result<nng::Message> {
+        let timeouts = match self.timeouts_override {
+            Some(timeouts) => timeouts,
+            None => return self.exchange_message_skipping_stale(socket, func, buf),
+        };
+        set_socket_timeouts(socket, &timeouts)?;
+        let result = self.exchange_message_skipping_stale(socket, func, buf);
+        let _ = set_socket_timeouts(socket, &self.state.cmd_timeouts.lock());
+        result
+    }
+
+    // a late response to a retried request is taken for the answer if it has the same function,
+    // the count stays right then as the actual answer becomes the stale one
+    fn exchange_message_skipping_stale(&self, socket: &Socket, func: i32, buf: &[u8]) -> Result<nng::Message> {
+        let mut msg = exchange_message(socket, nng::Message::from(buf))?;
+        while self.state.stale_responses.load(Ordering::SeqCst) > 0 {
+            match proto::Response::decode(msg.as_slice()) {
+                Ok(response) if response.func != func => {
+                    self.state.stale_responses.fetch_sub(1, Ordering::SeqCst);
+                    trace!("skipped stale response of {}", function_name(response.func));
+                    msg = socket.recv()?;
+                }
+                _ => break,
+            }
+        }
+        Ok(msg)
+    }
+
+    fn store_cmd_socket(&self, cmd_socket_option: &mut Option<CmdSocket>, port: u16, socket: Socket) -> u64 {
+        let serial = self.state.cmd_socket_serial.fetch_add(1, Ordering::SeqCst) + 1;
+        // late responses only arrive on the socket they were requested on
+        self.state.stale_responses.store(0, Ordering::SeqCst);
+        *cmd_socket_option = Some(CmdSocket { serial, port, socket });
+        serial
+    }
+
+    fn take_cmd_socket(&self, cmd_socket_option: &mut Option<CmdSocket>) -> Option<CmdSocket> {
+        self.state.cmd_socket_serial.fetch_add(1, Ordering::SeqCst);
+        cmd_socket_option.take()
+    }
+
+    // drop cmd_socket only if it's still the one with `serial`, returns whether it was dropped
+    fn drop_cmd_socket(&self, serial: u64) -> bool {
+        let cmd_socket = {
+            let mut cmd_socket_option = self.state.cmd_socket.lock();
+            match cmd_socket_option.as_ref() {
+                Some(cmd) if cmd.serial == serial => self.take_cmd_socket(&mut cmd_socket_option),
+                _ => return false,
+            }
+        };
+        if let Some(cmd) = cmd_socket {
+            cmd.socket.close();
+        }
+        self.send_event(Event::CmdSocketDisconnected);
+        true
+    }
+
+    fn reconnect_cmd_socket(&self, port: u16, policy: &ReconnectPolicy) -> Result<(u64, Socket)> {
+        // any connect or disconnect from other threads bumps the serial and cancels reconnecting
+        let expected_serial = self.state.cmd_socket_serial.load(Ordering::SeqCst);
+        let mut backoff = policy.initial_backoff;
+        for attempt in 1..=policy.max_attempts {
+            std::thread::sleep(backoff);
+            if self.state.cmd_socket_serial.load(Ordering::SeqCst) != expected_serial {
+                return Err(WcfError::SocketDisconnected);
+            }
+            let timeouts = *self.state.cmd_timeouts.lock();
+            match connect_socket(port, &timeouts) {
+                Ok(socket) => {
+                    let serial = {
+                        let mut cmd_socket_option = self.state.cmd_socket.lock();
+                        if self.state.cmd_socket_serial.load(Ordering::SeqCst) != expected_serial {
+                            socket.close();
+                            return Err(WcfError::SocketDisconnected);
+                        }
+                        self.store_cmd_socket(&mut cmd_socket_option, port, socket.clone())
+                    };
+                    trace!("cmd_socket reconnected after {} attempt(s)", attempt);
+                    self.state.stats.record_reconnect();
+                    metrics::socket_reconnected();
+                    self.send_event(Event::CmdSocketConnected);
+                    return Ok((serial, socket));
+                }
+                Err(e) => {
+                    warn!("failed to reconnect cmd_socket, attempt {}/{}, error={}", attempt, policy.max_attempts, e)
+                }
+            }
+            backoff = (backoff * 2).min(policy.max_backoff);
+        }
+        Err(WcfError::ReconnectFailed(policy.max_attempts))
+    }
+
+    pub(crate) fn run_cmd(&self, func: i32, msg: Option<proto::request::Msg>) -> Result<proto::Response> {
+        let req = proto::Request { func, msg };
+        // taken rather than borrowed, a callback run while exchanging may issue a nested command
+        let mut buf = ENCODE_BUF.take();
+        buf.clear();
+        req.encode(&mut buf)?;
+        let result = self.run_encoded_cmd(func, &buf);
+        if buf.capacity() <= MAX_KEPT_ENCODE_BUF {
+            ENCODE_BUF.set(buf);
+        }
+        result
+    }
+
+    fn run_encoded_cmd(&self, func: i32, buf: &[u8]) -> Result<proto::Response> {
+        // errors logged while exchanging, e.g. reconnects, carry the function in their span
+        let span = debug_span!("run_cmd", func = function_name(func), elapsed_ms = field::Empty);
+        let _entered = span.enter();
+        let started = Instant::now();
+        let result = self
+            .exchange_message_via_cmd_socket(func, buf)
+            .and_then(|msg_recv| Ok(proto::Response::decode(msg_recv.as_slice())?));
+        let elapsed = started.elapsed();
+        span.record("elapsed_ms", elapsed.as_millis() as u64);
+        if let Err(e) = &result {
+            debug!("command failed, error={}", e);
+        }
+        self.state.stats.record_cmd(func, elapsed, &result);
+        metrics::cmd_finished(func, elapsed, &result);
+        result
+    }
+
+    /// 直接执行一个 wcf 函数，用于本库尚未封装的函数，例如新版本 wcf 增加的函数，请求和响应的格式见 wcf.proto。
+    ///
+    /// 与其他命令一样经过 cmd socket，可以在任意线程中并发调用，命令会按顺序逐个执行，调用阻塞到收到响应或超时，
+    /// 同样受自动重连和 with_cmd_timeouts() 的影响，但不经过发送速率限制。
+    /// 不会更新客户端的状态，因此不能用于 FUNC_ENABLE_RECV_TXT 和 FUNC_DISABLE_RECV_TXT，请使用 enable_listen() 等。
+    ///
+    /// 响应无法解码时返回 `WcfError::DecodeError`，远端没有返回 msg 时返回 `WcfError::EmptyResponse`
+    pub fn exec_raw(&self, func: proto::Functions, msg: Option<proto::request::Msg>) -> Result<proto::Response> {
+        use proto::Functions;
+        if matches!(func, Functions::FuncReserved | Functions::FuncEnableRecvTxt | Functions::FuncDisableRecvTxt) {
+            return Err(WcfError::InvalidArgument(format!("{} can't be executed directly", func.as_str_name())));
+        }
+        let response = self.run_cmd(func.into(), msg)?;
+        if response.msg.is_none() {
+            return Err(WcfError::EmptyResponse(func.as_str_name().into()));
+        }
+        Ok(response)
+    }
+
+    pub(crate) fn send_event(&self, event: Event) {
+        self.state.stats.record_event();
+        self.state.events.dispatch(event);
+    }
+
+    fn health_check_thread(&self, interval: Duration, stop: Receiver<()>) {
+        trace!("health_check_thread()");
+        let mut consecutive_failures = 0;
+        while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+            if !self.state.cmd_wanted.load(Ordering::SeqCst) {
+                continue; // disconnected as user requested, nothing to check
+            }
+            // goes through the same serialized path as user commands
+            match self.is_login() {
+                Ok(_) if consecutive_failures > 0 => {
+                    consecutive_failures = 0;
+                    self.send_event(Event::HealthCheckRecovered);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    consecutive_failures += 1;
+                    warn!("health check failed {} time(s), error={:?}", consecutive_failures, e);
+                    self.send_event(Event::HealthCheckFailed { consecutive_failures });
+                }
+            }
+        }
+    }
+
+    // everything a received message goes through before becoming MsgReceived
+    fn dispatch_msg(&self, msg: WxMsg) {
+        if let Some(recent) = self.state.dedup.lock().as_mut() {
+            if !recent.insert(msg.id) {
+                trace!("dropped duplicate msg, id={}", msg.id);
+                self.state.duplicates_dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+        let filter = self.state.listen_filter.lock().clone();
+        if !filter.accepts(&msg) {
+            trace!("filtered msg, id={}, sender={}, roomid={}", msg.id, msg.sender, msg.roomid);
+            self.state.filtered.fetch_add(1, Ordering::Relaxed);
+            if filter.report_filtered {
+                self.send_event(Event::MsgFiltered(Arc::new(msg)));
+            }
+            return;
+        }
+        self.auto_accept_transfer(&msg);
+        self.auto_accept_friend(&msg);
+        self.welcome_new_members(&msg);
+        self.send_event(Event::MsgReceived(Arc::new(msg)));
+    }
+
+    fn auto_accept_friend(&self, msg: &WxMsg) {
+        // greetings of accepted requests are still sent if auto accept was turned off in between
+        if friend_policy::is_friend_added(msg) {
+            if let Some(greeting) = self.state.friend_automation.lock().take_pending_greeting(&msg.sender) {
+                self.send_friend_greeting(msg.sender.clone(), greeting);
+            }
+            return;
+        }
+        let policy = self.state.friend_policy.lock().clone();
+        if !policy.auto_accept {
+            return;
+        }
+        let request = match FriendRequest::parse(msg) {
+            Some(request) => request,
+            None => return,
+        };
+        if self.state.friend_automation.lock().is_accepted(&request.v3) {
+            trace!("friend request already accepted, wxid={}", request.wxid);
+            return;
+        }
+        if let Err(reason) = policy.check(&request) {
+            self.send_event(Event::FriendRequestRejected { request, reason });
+            return;
+        }
+        if !self.state.friend_automation.lock().try_accept(&request.v3, policy.max_per_hour) {
+            self.send_event(Event::FriendRequestRateLimited(request));
+            return;
+        }
+        let reason = match self.accept_new_friend(request.v3.clone(), request.v4.clone(), request.scene) {
+            Ok(true) => {
+                if let Some(greeting) = policy.greeting {
+                    self.state.friend_automation.lock().add_pending_greeting(request.wxid.clone(), greeting);
+                }
+                self.send_event(Event::FriendRequestAccepted(request));
+                return;
+            }
+            Ok(false) => "accept_new_friend failed".to_string(),
+            Err(e) => format!("accept_new_friend failed: {}", e),
+        };
+        self.state.friend_automation.lock().undo_accept(&request.v3);
+        self.send_event(Event::FriendRequestRejected { request, reason });
+    }
+
+    fn send_friend_greeting(&self, wxid: String, greeting: String) {
+        self.spawn_send("wcf-friend-greeting", move |client| {
+            client.send_text(greeting, wxid, String::new())?;
+            Ok(())
+        });
+    }
+
+    fn welcome_new_members(&self, msg: &WxMsg) {
+        let (inviter, members) = match RoomEvent::parse(msg) {
+            Some(RoomEvent::MemberJoined { inviter, members }) => (inviter, members),
+            _ => return,
+        };
+        let mut welcomes = self.state.welcomes.lock();
+        let delay = match welcomes.get(&msg.roomid) {
+            Some(config) => config.delay,
+            None => return,
+        };
+        // later joins within the delay are merged into the message already scheduled
+        if !welcomes.add_joined(&msg.roomid, inviter, members) {
+            return;
+        }
+        drop(welcomes);
+        let room_id = msg.roomid.clone();
+        self.spawn_send("wcf-welcome", move |client| {
+            thread::sleep(delay);
+            client.send_welcome(room_id)
+        });
+    }
+
+    fn send_welcome(&self, room_id: String) -> Result<()> {
+        let (config, joined) = {
+            let mut welcomes = self.state.welcomes.lock();
+            (welcomes.get(&room_id).cloned(), welcomes.take_joined(&room_id))
+        };
+        // the config may have been removed during the delay
+        let (config, (names, inviters)) = match (config, joined) {
+            (Some(config), Some(joined)) => (config, joined),
+            _ => return Ok(()),
+        };
+        let room = self.query_contact_info(room_id.clone())?;
+        let room_name = room.as_ref().map_or(room_id.as_str(), ContactInfo::display_name);
+        let text = config.render(&names, room_name, &inviters);
+        if config.mention_new_member {
+            let members = self.get_room_members(room_id.clone())?;
+            let mentions: Vec<_> = welcome::member_wxids(&members, &names).into_iter().map(Mention::Wxid).collect();
+            if !mentions.is_empty() {
+                self.send_text_with_mentions(room_id, text, &mentions)?;
+                return Ok(());
+            }
+        }
+        self.send_text(text, room_id, String::new())?;
+        Ok(())
+    }
+
+    // spawns an internal thread, a panic inside is logged and reported as InternalThreadPanicked
+    pub(crate) fn spawn_thread<F>(&self, name: &'static str, f: F) -> std::io::Result<JoinHandle<()>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let client = self.clone();
+        thread::Builder::new().name(name.into()).spawn(move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                error!("thread {} panicked: {}", name, panic_message(payload.as_ref()));
+                client.send_event(Event::InternalThreadPanicked(name));
+            }
+        })
+    }
+
+    // runs `send` on its own thread, so the rate limiter or humanize delay never holds up receiving
+    fn spawn_send<F>(&self, name: &'static str, send: F)
+    where
+        F: FnOnce(&WcfClient) -> Result<()> + Send + 'static,
+    {
+        let client = self.clone();
+        let spawned = self.spawn_thread(name, move || {
+            if let Err(e) = send(&client) {
+                error!("failed to send, thread={}, error={}", name, e);
+            }
+        });
+        if let Err(e) = spawned {
+            error!("failed to spawn send thread, name={}, error={}", name, e);
+        }
+    }
+
+    // runs on the receive thread, transfers are rare enough that the extra round trip does not matter
+    fn auto_accept_transfer(&self, msg: &WxMsg) {
+        let policy = self.state.transfer_policy.lock().clone();
+        if !policy.auto_accept {
+            return;
+        }
+        let transfer = match TransferInfo::parse(msg) {
+            Some(transfer) => transfer,
+            None => return,
+        };
+        if !self.state.seen_transfers.lock().insert(transfer.transferid.clone()) {
+            trace!("transfer already handled, transferid={}", transfer.transferid);
+            return;
+        }
+        if let Err(reason) = policy.check(&transfer) {
+            self.send_event(Event::TransferIgnored { transfer, reason });
+            return;
+        }
+        let (wxid, transferid, transcationid) =
+            (transfer.wxid.clone(), transfer.transferid.clone(), transfer.transcationid.clone());
+        match self.recv_transfer(wxid, transferid, transcationid) {
+            Ok(true) => self.send_event(Event::TransferAccepted(transfer)),
+            Ok(false) => self.send_event(Event::TransferIgnored { transfer, reason: "recv_transfer failed".into() }),
+            Err(e) => {
+                error!("failed to accept transfer, transferid={}, error={}", transfer.transferid, e);
+                self.send_event(Event::TransferIgnored { transfer, reason: format!("recv_transfer failed: {}", e) });
+            }
+        }
+    }
+
+    // the socket is connected by enable_listen(), MsgSocketConnected is sent there too
+    fn recv_msg_thread(&self, socket: Socket) {
+        trace!("recv_msg_thread()");
+        // when each kind of error was last reported as an event
+        let mut reported: HashMap<String, Instant> = HashMap::new();
+        let mut should_report = |key: String| {
+            let now = Instant::now();
+            let due = reported.get(&key).is_none_or(|last| now.duration_since(*last) >= MSG_ERROR_REPORT_INTERVAL);
+            if due {
+                reported.insert(key, now);
+            }
+            due
+        };
+        loop {
+            match socket.recv() {
+                Ok(mut msg) => {
+                    let response = match proto::Response::decode(msg.as_slice()) {
+                        Ok(resp) => resp,
+                        Err(e) => {
+                            error!("received invalid msg, error={}", e);
+                            self.state.stats.record_decode_error();
+                            let error = e.to_string();
+                            if should_report(error.clone()) {
+                                self.send_event(Event::MsgDecodeError { bytes_len: msg.len(), error });
+                            }
+                            continue;
+                        }
+                    };
+                    msg.clear();
+                    match response.msg {
+                        Some(proto::response::Msg::Wxmsg(msg)) => {
+                            // events queued for this msg keep the span, so handler errors can be traced back to it
+                            let span = debug_span!("recv_msg", id = msg.id, sender = %msg.sender, roomid = %msg.roomid);
+                            let _entered = span.enter();
+                            self.state.stats.record_msg(msg.r#type);
+                            metrics::msg_received(msg.r#type);
+                            self.dispatch_msg(msg);
+                        }
+                        Some(other) => {
+                            warn!("received unsupported msg, response.msg={:?}", other);
+                            self.state.stats.record_unsupported_msg();
+                            // the variant name, e.g. "Status"
+                            let kind = format!("{:?}", other).split('(').next().unwrap_or_default().to_string();
+                            if should_report(kind) {
+                                self.send_event(Event::UnsupportedMsg(other));
+                            }
+                        }
+                        None => trace!("received empty msg, func={}", function_name(response.func)),
+                    }
+                }
+                Err(nng::Error::TimedOut) => {}
+                Err(nng::Error::Closed) => {
+                    trace!("disabled receiving as user requested, now closing");
+                    break;
+                }
+                Err(e) => {
+                    error!("recv error! now closing, e={}", e);
+                    break;
+                }
+            }
+        }
+        socket.close();
+        self.send_event(Event::MsgSocketDisconnected);
+    }
+
+    pub fn register_event_callback<F>(&self, callback: F)
+    where
+        F: FnMut(Event) + Send + 'static,
+    {
+        self.state.events.set_callback(Some(Arc::new(Mutex::new(callback))));
+    }
+
+    pub fn unregister_event_callback(&self) {
+        self.state.events.set_callback(None);
+    }
+
+    /// 订阅事件，返回的 Receiver 会收到之后发生的所有事件，可以在自己的线程中循环读取
+    ///
+    /// 队列长度为 DEFAULT_SUBSCRIBER_CAPACITY，队列满时新事件会被丢弃，不会阻塞接收线程。
+    /// Receiver 被 drop 后自动取消订阅。
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.subscribe_with_capacity(DEFAULT_SUBSCRIBER_CAPACITY)
+    }
+
+    /// 同 subscribe()，指定队列长度
+    pub fn subscribe_with_capacity(&self, capacity: usize) -> Receiver<Event> {
+        self.state.events.subscribe(capacity)
+    }
+
+    // same as subscribe(), for aio::EventStream
+    #[cfg(feature = "async")]
+    pub(crate) fn subscribe_async(&self, capacity: usize) -> tokio::sync::mpsc::Receiver<Event> {
+        self.state.events.subscribe_async(capacity)
+    }
+
+    /// 注册只处理 MsgReceived 的函数，可以注册多个，按注册顺序调用
+    pub fn on_message<F>(&self, handler: F) -> HandlerId
+    where
+        F: FnMut(&WxMsg) + Send + 'static,
+    {
+        self.state.events.on_message(handler)
+    }
+
+    /// 注册只处理 socket 连接状态变化的函数，可以注册多个，按注册顺序调用
+    pub fn on_connection_change<F>(&self, handler: F) -> HandlerId
+    where
+        F: FnMut(ConnectionChange) + Send + 'static,
+    {
+        self.state.events.on_connection_change(handler)
+    }
+
+    /// 事件默认在独立的 wcf-dispatch 线程中按顺序分发，回调中可以安全地调用 wcf 函数。
+    /// 设为 true 时改为在产生事件的线程中同步分发，此时回调中不能做复杂操作，否则可能死锁。
+    pub fn set_sync_dispatch(&self, sync_dispatch: bool) {
+        self.state.events.set_sync_dispatch(sync_dispatch)
+    }
+
+    /// 设置等待 wcf-dispatch 线程分发的事件队列，默认长度为 DEFAULT_EVENT_QUEUE_CAPACITY，队列满时阻塞产生事件的线程。
+    ///
+    /// 应在 enable_listen() 之前设置，之后设置时只影响新产生的事件。回调中产生的事件不会阻塞，
+    /// 队列达到 80% 时发出 `Event::QueueHighWatermark`，丢弃事件后发出 `Event::EventsDropped`
+    pub fn set_event_queue(&self, config: EventQueueConfig) {
+        self.state.events.set_queue_config(config)
+    }
+
+    pub fn event_queue(&self) -> EventQueueConfig {
+        self.state.events.queue_config()
+    }
+
+    /// 按配置设置 cmd socket 超时、事件队列、接收过滤、去重、发送速率限制和欢迎消息。
+    ///
+    /// init 的参数见 `Config::init_options()`，`[webhook]` 需要自行通过 `Config::webhook_config()` 创建 WebhookForwarder
+    #[cfg(feature = "config")]
+    pub fn apply_config(&self, config: &Config) -> Result<()> {
+        self.set_cmd_timeouts(config.cmd_timeouts())?;
+        self.set_event_queue(config.event_queue());
+        self.set_listen_filter(config.listen_filter());
+        if let Some(capacity) = config.listen.dedup {
+            self.set_dedup(capacity);
+        }
+        self.set_rate_limit(config.rate_limit());
+        for (room_id, welcome) in config.welcomes() {
+            self.set_welcome(room_id, welcome);
+        }
+        #[cfg(not(feature = "http"))]
+        if config.webhook.is_some() {
+            warn!("[webhook] in config is ignored, it requires the http feature");
+        }
+        Ok(())
+    }
+
+    /// 等待已产生的事件全部分发完成，在回调中调用时直接返回
+    pub fn flush_events(&self) {
+        self.state.events.flush()
+    }
+
+    /// 注销 on_message() 等注册的函数，不影响 register_event_callback() 注册的回调
+    pub fn remove_handler(&self, id: HandlerId) -> bool {
+        self.state.events.remove_handler(id)
+    }
+
+    pub fn init(&self, port: u16, debug: bool, auto_clean: bool) -> Result<CleanupHandler> {
+        self.init_with_options(port, InitOptions { debug, auto_clean, ..Default::default() })
+    }
+
+    pub fn init_with_options(&self, port: u16, options: InitOptions) -> Result<CleanupHandler> {
+        trace!("init_with_options()");
+        let InitOptions {
+            debug,
+            auto_clean,
+            cmd_timeouts,
+            sdk_path,
+            launch_wechat,
+            wechat_path,
+            launch_wait,
+            check_ports,
+            force,
+            retry_policy,
+        } = options.clone();
+        if *self.state.cmd_port.lock() != 0 {
+            return Err(WcfError::AlreadyInited);
+        }
+        if !force {
+            check_wechat_version()?;
+        }
+        if check_ports {
+            check_port_pair(port)?;
+        }
+        let launched_wechat = match launch_wechat {
+            true => self.launch_wechat(wechat_path.as_deref(), launch_wait)?,
+            false => None,
+        };
+        let (spy, mut cmd_port) = match self.load_and_init_sdk(port, debug, sdk_path.as_deref()) {
+            Ok(inited) => inited,
+            Err(e) => {
+                if let Some(child) = launched_wechat {
+                    kill_launched_wechat(child);
+                }
+                return Err(e);
+            }
+        };
+        *cmd_port = port;
+        self.state.stats.set_inited(true);
+        *self.state.cmd_timeouts.lock() = cmd_timeouts;
+        if retry_policy.is_some() {
+            *self.state.retry_policy.lock() = retry_policy;
+        }
+        *self.state.last_init.lock() = Some((port, options));
+        let generation = self.state.init_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.send_event(Event::SdkInited(port, spy));
+        self.state.attached.store(false, Ordering::SeqCst);
+        Ok(CleanupHandler { client: self.clone(), auto_clean, generation, launched_wechat, port, attached: false })
+    }
+
+    /// 连接微信中已经注入的 wcf，例如 bot 重启而微信一直在运行时，不加载 sdk.dll，也不再次注入。
+    ///
+    /// 记录 cmd 端口后连接 cmd socket，并通过 is_login() 确认 wcf 可用，失败时恢复为未 init() 的状态并返回错误。
+    /// 返回的 CleanupHandler 销毁时调用 detach()，uninit() 和 shutdown() 也不会关闭不是由 init() 注入的 wcf
+    pub fn attach(&self, port: u16) -> Result<CleanupHandler> {
+        trace!("attach({})", port);
+        {
+            let mut cmd_port = self.state.cmd_port.lock();
+            if *cmd_port != 0 {
+                return Err(WcfError::AlreadyInited);
+            }
+            *cmd_port = port;
+            self.state.attached.store(true, Ordering::SeqCst);
+        }
+        if let Err(e) = self.connect_cmd_socket().and_then(|_| self.is_login()) {
+            warn!("no wcf to attach on port {}, error={}", port, e);
+            self.disconnect_cmd_socket();
+            *self.state.cmd_port.lock() = 0;
+            self.state.attached.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+        self.state.stats.set_inited(true);
+        // reinitialize() injects again after wechat restarted, the attached wcf is gone then
+        *self.state.last_init.lock() = Some((port, InitOptions::default()));
+        let generation = self.state.init_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.send_event(Event::SdkAttached(port));
+        info!("attached to wcf on port {}", port);
+        Ok(CleanupHandler {
+            client: self.clone(),
+            auto_clean: true,
+            generation,
+            launched_wechat: None,
+            port,
+            attached: true,
+        })
+    }
+
+    /// 断开 attach() 的连接：同 shutdown() 的顺序关闭消息接收和 cmd socket，但不调用 WxDestroySDK，
+    /// 微信中的 wcf 继续运行，之后可以再次 attach()。不是 attach() 的连接时同 shutdown()
+    pub fn detach(&self) {
+        self.shutdown_with_timeout(DEFAULT_SHUTDOWN_TIMEOUT);
+    }
+
+    /// spy 的日志文件 `logs/wcf.txt`，在 sdk.dll 所在目录下，可以用于查看 spy 的输出，debug 为 true 时更详细。
+    ///
+    /// sdk.dll 未加载、使用其他 SdkLoader 或文件尚不存在时返回 None
+    pub fn spy_log_path(&self) -> Option<PathBuf> {
+        let path = self.loader().sdk_dir()?.join("logs").join("wcf.txt");
+        path.is_file().then_some(path)
+    }
+
+    /// 在 ports 范围内按顺序查找 port 和 port + 1 都空闲的端口，使用找到的第一个 init，
+    /// 选中的端口可以通过返回的 `CleanupHandler::port()` 或 `Event::SdkInited` 获得。
+    ///
+    /// 总是检查端口，忽略 options.check_ports，范围内没有空闲端口时返回 `WcfError::NoFreePort`
+    pub fn init_auto_port(&self, ports: RangeInclusive<u16>, options: InitOptions) -> Result<CleanupHandler> {
+        trace!("init_auto_port({:?})", ports);
+        let options = InitOptions { check_ports: true, ..options };
+        for port in ports.clone() {
+            // another process may take the port between the check and init, then try the next one
+            match self.init_with_options(port, options.clone()) {
+                Err(WcfError::PortInUse(taken)) => trace!("port {} is in use, skipped {}", taken, port),
+                result => return result,
+            }
+        }
+        Err(WcfError::NoFreePort { start: *ports.start(), end: *ports.end() })
+    }
+
+    // loads sdk.dll and calls WxInitSDK, the cmd_port lock is kept so no other init() runs in between
+    fn load_and_init_sdk(
+        &self,
+        port: u16,
+        debug: bool,
+        sdk_path: Option<&Path>,
+    ) -> Result<(SpyVariant, MutexGuard<'_, u16>)> {
+        if self.loader().load(sdk_path)? {
+            self.send_event(Event::SdkDllLoaded);
+        }
+        // sdk.dll injects the spy dll next to it, without which WxInitSDK only returns an opaque error
+        let spy = SpyVariant::from_debug(debug);
+        if let Some(spy_path) = self.loader().sdk_dir().map(|dir| dir.join(spy.file_name())) {
+            if !spy_path.is_file() {
+                return Err(WcfError::SpyDllNotFound(spy_path));
+            }
+            debug!("injecting {:?}", spy_path);
+        }
+        let cmd_port = self.state.cmd_port.lock();
+        if *cmd_port != 0 {
+            return Err(WcfError::AlreadyInited);
+        }
+        let init_sdk_result = self.loader().init_sdk(debug, port as i32)?;
+        if init_sdk_result != 0 {
+            return Err(WcfError::SdkInitFailed(init_sdk_result));
+        }
+        Ok((spy, cmd_port))
+    }
+
+    #[cfg(feature = "process")]
+    fn launch_wechat(&self, path: Option<&Path>, wait: Duration) -> Result<Option<Child>> {
+        process::launch_wechat(path, wait)
+    }
+
+    #[cfg(not(feature = "process"))]
+    fn launch_wechat(&self, _path: Option<&Path>, _wait: Duration) -> Result<Option<Child>> {
+        Err(WcfError::WeChatLaunchFailed("InitOptions.launch_wechat requires the process feature".into()))
+    }
+
+    /// 微信退出后恢复连接：shutdown() 后等待微信进程重新出现，最多等待 wait，
+    /// 再使用上次 init() 的参数重新 init() 并 connect_cmd_socket()，之前开启了消息接收时再 enable_listen()。
+    ///
+    /// 返回新的 CleanupHandler，之前 init() 返回的 CleanupHandler 销毁时不会再关闭客户端。
+    /// 从未 init() 过时返回 `WcfError::NotInited`，等待超时返回 `WcfError::Timeout`
+    #[cfg(feature = "process")]
+    pub fn reinitialize(&self, wait: Duration) -> Result<CleanupHandler> {
+        let (port, options) = self.state.last_init.lock().clone().ok_or(WcfError::NotInited)?;
+        // the receive thread may have quit already when wechat exited, msg_port is still set then
+        let listening = self.is_listening() || self.state().msg_port.is_some();
+        self.shutdown();
+        let deadline = Instant::now() + wait;
+        while !process::is_wechat_running() {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(WcfError::Timeout);
+            }
+            thread::sleep((deadline - now).min(Duration::from_secs(1)));
+        }
+        info!("wechat process found, init again on port {}", port);
+        let cleanup = self.init_with_options(port, options)?;
+        self.connect_cmd_socket()?;
+        if listening {
+            self.enable_listen()?;
+        }
+        Ok(cleanup)
+    }
+
+    pub fn uninit(&self) {
+        self.uninit_with_flush_timeout(None);
+    }
+
+    // flush_timeout limits waiting for the events, None waits until all are delivered
+    fn uninit_with_flush_timeout(&self, flush_timeout: Option<Duration>) {
+        trace!("uninit()");
+        let mut cmd_port = self.state.cmd_port.lock();
+        if *cmd_port == 0 {
+            return; // no need to uninit
+        }
+
+        self.stop_health_check();
+        // disable listen first, it still needs the cmd socket
+        if let Err(e) = self.stop_listen(DEFAULT_LISTEN_STOP_TIMEOUT) {
+            warn!("wcf::uninit(), disable_listen() returned error={:?}", e);
+            *self.state.msg_port.lock() = 0;
+            let _ = self.stop_msg_thread(DEFAULT_LISTEN_STOP_TIMEOUT);
+        }
+        self.disconnect_cmd_socket();
+
+        // an attached wcf was injected by someone else, leave it running in wechat
+        let attached = self.state.attached.swap(false, Ordering::SeqCst);
+        if !attached {
+            match self.loader().destroy_sdk() {
+                Ok(0) => {}
+                Ok(i) => warn!("wcf::uninit(), wx_destroy_sdk() returned result={}", i),
+                Err(e) => warn!("wcf::uninit(), wx_destroy_sdk() returned error={:?}", e),
+            }
+        }
+        *cmd_port = 0;
+        self.state.stats.set_inited(false);
+        self.send_event(if attached { Event::SdkDetached } else { Event::SdkDestroyed });
+        // unlock first, callbacks may read the state while being flushed
+        drop(cmd_port);
+        // all events, including SdkDestroyed, are delivered once uninit() returns
+        if !self.state.events.flush_timeout(flush_timeout) {
+            warn!("events not delivered in {:?} after uninit", flush_timeout);
+        }
+    }
+
+    /// 按顺序关闭客户端：停止健康检查，关闭消息接收并等待接收线程退出，等待已有事件分发完，断开 cmd socket，最后 uninit()。
+    ///
+    /// 每一步最多等待 DEFAULT_SHUTDOWN_TIMEOUT。可以重复调用，未 init() 时不做任何事；
+    /// 可以在事件回调中调用，此时不等待事件分发；其他线程正在 shutdown() 时立即返回。
+    /// auto_clean 为 true 时，CleanupHandler 销毁时会调用此函数
+    pub fn shutdown(&self) {
+        self.shutdown_with_timeout(DEFAULT_SHUTDOWN_TIMEOUT);
+    }
+
+    /// 同 shutdown()，指定每一步的等待时间
+    pub fn shutdown_with_timeout(&self, timeout: Duration) {
+        let Some(_shutdown) = self.state.shutdown.try_lock() else {
+            trace!("shutdown already in progress");
+            return;
+        };
+        if *self.state.cmd_port.lock() == 0 {
+            return;
+        }
+        info!("shutting down");
+        self.stop_health_check();
+        if let Err(e) = self.stop_listen(timeout) {
+            warn!("shutdown(), disable_listen() returned error={:?}", e);
+            *self.state.msg_port.lock() = 0;
+            let _ = self.stop_msg_thread(timeout);
+        }
+        // messages received before the thread stopped reach the handlers while wcf is still usable
+        if !self.state.events.flush_timeout(Some(timeout)) {
+            warn!("events not delivered in {:?}, continue shutting down", timeout);
+        }
+        self.disconnect_cmd_socket();
+        self.uninit_with_flush_timeout(Some(timeout));
+        info!("shut down");
+    }
+
+    /// 安装 Ctrl-C（以及 SIGTERM、关闭控制台窗口等）的处理函数，收到时调用 shutdown() 后退出进程。
+    ///
+    /// 一个进程只能安装一次，重复安装返回 `WcfError::ShutdownHandler`
+    #[cfg(feature = "ctrlc")]
+    pub fn install_shutdown_handler(&self) -> Result<()> {
+        let client = self.clone();
+        ctrlc::set_handler(move || {
+            info!("received termination signal, shutting down");
+            client.shutdown();
+            std::process::exit(130);
+        })?;
+        Ok(())
+    }
+
+    /// 调用 uninit() 后卸载 sdk.dll，下次 init() 时重新加载，可用于更换 dll 版本或从异常状态中恢复。
+    ///
+    /// sdk.dll 在进程内共享，调用前需要确保其他 `WcfClient` 都已 uninit()。
+    pub fn uninit_and_unload(&self) {
+        self.uninit();
+        if self.loader().unload() {
+            self.send_event(Event::SdkDllUnloaded);
+            self.state.events.flush();
+        }
+    }
+
+    pub fn connect_cmd_socket(&self) -> Result<()> {
+        let cmd_port = *self.state.cmd_port.lock();
+        if cmd_port == 0 {
+            return Err(WcfError::NotInited);
+        }
+
+        {
+            let mut cmd_socket = self.state.cmd_socket.lock();
+            if cmd_socket.is_some() {
+                return Err(WcfError::SocketAlreadyConnected);
+            }
+            let timeouts = *self.state.cmd_timeouts.lock();
+            self.store_cmd_socket(&mut cmd_socket, cmd_port, connect_socket(cmd_port, &timeouts)?);
+            self.state.cmd_wanted.store(true, Ordering::SeqCst);
+        }
+        self.send_event(Event::CmdSocketConnected);
+        Ok(())
+    }
+
+    /// 设置 cmd socket 的自动重连策略，None 表示出错后不自动重连（默认）
+    ///
+    /// 开启后，命令收发失败时会按策略重连，重连成功后重试一次失败的命令
+    pub fn set_cmd_reconnect(&self, policy: Option<ReconnectPolicy>) {
+        *self.state.reconnect_policy.lock() = policy;
+    }
+
+    /// 设置命令超时后的重试策略，None 表示不重试（默认），也可以通过 InitOptions.retry_policy 设置
+    pub fn set_retry_policy(&self, policy: Option<RetryPolicy>) {
+        *self.state.retry_policy.lock() = policy;
+    }
+
+    /// 修改 cmd socket 的收发超时，已连接的 socket 立即生效，无需重连
+    pub fn set_cmd_timeouts(&self, timeouts: CmdTimeouts) -> Result<()> {
+        *self.state.cmd_timeouts.lock() = timeouts;
+        match self.state.cmd_socket.lock().as_ref() {
+            Some(cmd) => set_socket_timeouts(&cmd.socket, &timeouts),
+            None => Ok(()),
+        }
+    }
+
+    /// 返回一个使用指定超时执行命令的客户端，只影响通过它发出的命令，例如
+    /// `client.with_cmd_timeouts(timeouts).exec_db_query(db, sql)`
+    pub fn with_cmd_timeouts(&self, timeouts: CmdTimeouts) -> WcfClient {
+        WcfClient { state: self.state.clone(), timeouts_override: Some(timeouts) }
+    }
+
+    /// 断开 cmd socket，正在等待响应的命令会被立即中断并返回错误
+    pub fn disconnect_cmd_socket(&self) {
+        self.state.cmd_wanted.store(false, Ordering::SeqCst);
+        let cmd_socket = self.take_cmd_socket(&mut self.state.cmd_socket.lock());
+        if let Some(cmd) = cmd_socket {
+            cmd.socket.close();
+            self.send_event(Event::CmdSocketDisconnected);
+        }
+    }
+
+    /// 启动健康检查，每隔 interval 通过 cmd socket 发送一次 is_login 命令。
+    ///
+    /// 失败时发出 `Event::HealthCheckFailed`，恢复后发出 `Event::HealthCheckRecovered`。
+    /// 调用 disconnect_cmd_socket() 后暂停检查，直到再次 connect_cmd_socket()。已在运行时按新的间隔重启。
+    pub fn start_health_check(&self, interval: Duration) {
+        self.stop_health_check();
+        let (stop, stop_receiver) = mpsc::channel();
+        let client = self.clone();
+        match self.spawn_thread("wcf-health-check", move || client.health_check_thread(interval, stop_receiver)) {
+            Ok(handle) => *self.state.health_check.lock() = Some(HealthCheck { stop, handle }),
+            Err(e) => error!("failed to spawn health check thread, error={}", e),
+        }
+    }
+
+    /// 停止健康检查，返回之前是否在运行，正在进行的检查会先完成
+    pub fn stop_health_check(&self) -> bool {
+        let health_check = self.state.health_check.lock().take();
+        match health_check {
+            Some(HealthCheck { stop, handle }) => {
+                drop(stop);
+                if handle.thread().id() != thread::current().id() {
+                    let _ = handle.join();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 查询当前的连接状态，只读取本地状态，不会发送命令
+    pub fn state(&self) -> WcfState {
+        let cmd_port = *self.state.cmd_port.lock();
+        let msg_port = *self.state.msg_port.lock();
+        WcfState {
+            sdk_inited: cmd_port != 0,
+            cmd_port: Some(cmd_port).filter(|&port| port != 0),
+            cmd_connected: self.state.cmd_socket.lock().is_some(),
+            listening: self.is_listening(),
+            msg_port: Some(msg_port).filter(|&port| port != 0),
+        }
+    }
+
+    /// 丢弃最近 capacity 条消息中 id 重复的消息，例如 msg socket 重连后重复推送的，0 表示关闭（默认）。
+    ///
+    /// 一般使用 DEFAULT_DEDUP_CAPACITY 即可，修改时会清空已记录的 id
+    pub fn set_dedup(&self, capacity: usize) {
+        *self.state.dedup.lock() = Some(capacity).filter(|&capacity| capacity > 0).map(RecentIds::new);
+    }
+
+    /// 设置接收消息的过滤条件，在 MsgReceived 发出之前应用，可以随时修改，无需重新 enable_listen()
+    pub fn set_listen_filter(&self, filter: ListenFilter) {
+        if let Err(e) = self.update_access_lists(|current| *current = filter) {
+            error!("failed to save access lists, error={}", e);
+        }
+    }
+
+    pub fn listen_filter(&self) -> ListenFilter {
+        ListenFilter::clone(&self.state.listen_filter.lock())
+    }
+
+    // applies `update` to a copy of the filter and swaps it in, then saves the lists if persistence is on
+    fn update_access_lists<T>(&self, update: impl FnOnce(&mut ListenFilter) -> T) -> Result<T> {
+        let path = self.state.access_list_path.lock();
+        let (result, lists) = {
+            let mut current = self.state.listen_filter.lock();
+            let mut filter = ListenFilter::clone(&current);
+            let result = update(&mut filter);
+            let lists = AccessLists::of(&filter);
+            *current = Arc::new(filter);
+            (result, lists)
+        };
+        if let Some(path) = path.as_ref() {
+            lists.save(path)?;
+        }
+        Ok(result)
+    }
+
+    /// 将 wxid 加入黑名单，忽略其私聊和群聊消息，返回是否新加入
+    pub fn blocklist_add(&self, wxid: &str) -> Result<bool> {
+        self.update_access_lists(|filter| filter.blocked_senders.insert(wxid.to_string()))
+    }
+
+    /// 将 wxid 移出黑名单，返回之前是否在黑名单中
+    pub fn blocklist_remove(&self, wxid: &str) -> Result<bool> {
+        self.update_access_lists(|filter| filter.blocked_senders.remove(wxid))
+    }
+
+    pub fn blocklist(&self) -> HashSet<String> {
+        self.state.listen_filter.lock().blocked_senders.clone()
+    }
+
+    /// 只接收这些群的群消息，None 表示接收所有群
+    pub fn allowlist_set_rooms(&self, room_ids: Option<HashSet<String>>) -> Result<()> {
+        self.update_access_lists(|filter| filter.allowed_rooms = room_ids)
+    }
+
+    pub fn allowlist_rooms(&self) -> Option<HashSet<String>> {
+        self.state.listen_filter.lock().allowed_rooms.clone()
+    }
+
+    /// 将黑名单和群白名单保存到 path（json 格式），之后每次修改都会保存；path 已存在时先从中加载
+    pub fn persist_access_lists<P: Into<PathBuf>>(&self, path: P) -> Result<()> {
+        let path = path.into();
+        let mut current_path = self.state.access_list_path.lock();
+        match AccessLists::load(&path)? {
+            Some(lists) => {
+                let mut current = self.state.listen_filter.lock();
+                let mut filter = ListenFilter::clone(&current);
+                lists.apply_to(&mut filter);
+                *current = Arc::new(filter);
+            }
+            None => AccessLists::of(&self.state.listen_filter.lock()).save(&path)?,
+        }
+        *current_path = Some(path);
+        Ok(())
+    }
+
+    /// 客户端的运行统计，包括收发的消息数、命令的平均耗时、事件队列长度等，可以序列化为 json
+    pub fn stats(&self) -> WcfStats {
+        self.state.stats.snapshot(self.state.events.queue_depth(), self.state.events.events_dropped())
+    }
+
+    /// 清零 stats() 中的计数，uptime_secs 不受影响
+    pub fn reset_stats(&self) {
+        self.state.stats.reset();
+        self.state.events.reset_dropped();
+    }
+
+    pub fn listen_stats(&self) -> ListenStats {
+        ListenStats {
+            duplicates_dropped: self.state.duplicates_dropped.load(Ordering::Relaxed),
+            filtered: self.state.filtered.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 设置发送类接口（send_*、forward_msg）的速率限制，会清空已有的计数。
+    ///
+    /// 默认不限制，可以设为 `RateLimitConfig::conservative()`（同一接收者每秒 1 条、合计每分钟 20 条）。
+    /// Block 模式下超过限制时在调用线程中等待，AutoReply、Welcome 等在 wcf-dispatch 线程中发送时会推迟后续事件的分发
+    pub fn set_rate_limit(&self, config: RateLimitConfig) {
+        *self.state.rate_limiter.lock() = RateLimiter::new(config);
+    }
+
+    pub fn rate_limit(&self) -> RateLimitConfig {
+        self.state.rate_limiter.lock().config().clone()
+    }
+
+    /// 设置处理收到消息的管道，收到的每条消息（MsgReceived）都会依次经过它的各层，None 表示取消。
+    ///
+    /// 管道和其他 on_message() 注册的函数一样在事件分发线程中运行，新设置的管道替换旧的
+    pub fn set_pipeline(&self, pipeline: Option<Pipeline>) {
+        let mut handler = self.state.pipeline_handler.lock();
+        if let Some(id) = handler.take() {
+            self.remove_handler(id);
+        }
+        if let Some(pipeline) = pipeline {
+            let client = self.clone();
+            *handler = Some(self.on_message(move |msg| {
+                let mut ctx = Ctx::new(client.clone());
+                pipeline.run(super::Message::from(msg.clone()), &mut ctx);
+            }));
+        }
+    }
+
+    /// 设置自动收款策略，开启后收到对方的转账时按策略调用 recv_transfer()，并发出 `Event::TransferAccepted` 或
+    /// `Event::TransferIgnored`。同一笔转账（transferid）只处理一次，收款、退还通知会被忽略
+    pub fn set_transfer_policy(&self, policy: TransferPolicy) {
+        *self.state.transfer_policy.lock() = policy;
+    }
+
+    pub fn transfer_policy(&self) -> TransferPolicy {
+        self.state.transfer_policy.lock().clone()
+    }
+
+    /// 设置自动通过好友申请的策略，开启后收到好友申请（type 37）时按策略调用 accept_new_friend()，并发出
+    /// `Event::FriendRequestAccepted`、`Event::FriendRequestRejected` 或 `Event::FriendRequestRateLimited`。
+    ///
+    /// 同一个申请（v3）只通过一次；设置了 greeting 时，收到 "你已添加了xxx" 的系统消息后发送给新好友
+    pub fn set_friend_policy(&self, policy: FriendPolicy) {
+        *self.state.friend_policy.lock() = policy;
+    }
+
+    pub fn friend_policy(&self) -> FriendPolicy {
+        self.state.friend_policy.lock().clone()
+    }
+
+    /// 设置新成员入群时发送的欢迎消息，room_id 为 None 时对所有没有单独设置的群生效，可以随时修改。
+    ///
+    /// 收到入群的系统消息后等待 config.delay，期间入群的成员合并为一条消息发送
+    pub fn set_welcome(&self, room_id: Option<String>, config: WelcomeConfig) {
+        self.state.welcomes.lock().set(room_id, Some(config));
+    }
+
+    /// 取消欢迎消息，room_id 为 None 时取消对所有群的设置，单独设置过的群不受影响
+    pub fn remove_welcome(&self, room_id: Option<String>) {
+        self.state.welcomes.lock().set(room_id, None);
+    }
+
+    /// room_id 群实际使用的欢迎消息设置
+    pub fn welcome(&self, room_id: &str) -> Option<WelcomeConfig> {
+        self.state.welcomes.lock().get(room_id).cloned()
+    }
+
+    /// 开启后所有发送类接口在发送前随机等待 min_delay 到 max_delay，None 表示关闭（默认）
+    pub fn set_humanize(&self, options: Option<HumanizeOptions>) {
+        *self.state.humanize.lock() = options;
+    }
+
+    // waits for the global humanize delay and the rate limit before sending to receiver
+    fn acquire_send(&self, receiver: &str) -> Result<()> {
+        let humanize = self.state.humanize.lock().clone();
+        self.acquire_send_with(receiver, humanize.as_ref())
+    }
+
+    fn acquire_send_with(&self, receiver: &str, humanize: Option<&HumanizeOptions>) -> Result<()> {
+        if let Some(options) = humanize {
+            options.sleep(options.jitter())?;
+        }
+        loop {
+            let (wait, mode) = {
+                let mut limiter = self.state.rate_limiter.lock();
+                match limiter.try_acquire(receiver, Instant::now()) {
+                    Ok(()) => return Ok(()),
+                    Err(wait) => (wait, limiter.config().mode),
+                }
+            };
+            match mode {
+                RateLimitMode::Reject => return Err(WcfError::RateLimited { retry_after: wait }),
+                RateLimitMode::Block => {
+                    trace!("rate limited, receiver={}, wait={:?}", receiver, wait);
+                    match humanize {
+                        // so that cancelling also interrupts the rate limit wait
+                        Some(options) => options.sleep(wait)?,
+                        None => thread::sleep(wait),
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn is_login(&self) -> Result<bool> {
+        let response = self.run_cmd(proto::Functions::FuncIsLogin.into(), None)?;
+        Ok(get_response_status_as_bool(&response))
+    }
+
+    pub fn get_self_wx_id(&self) -> Result<Option<String>> {
+        let response = self.run_cmd(proto::Functions::FuncGetSelfWxid.into(), None)?;
+        match response.msg {
+            Some(proto::response::Msg::Str(wx_id)) => Ok(Some(wx_id)),
+            _ => Ok(None),
+        }
+    }
+
+    /// 每隔 poll_interval 查询一次是否已登录，登录后发出 `Event::LoggedIn` 并返回用户信息。
+    ///
+    /// 超时返回 `WcfError::LoginTimeout`，等待期间其他线程调用 uninit() 时返回 `WcfError::NotInited`。
+    pub fn wait_for_login(&self, timeout: Duration, poll_interval: Duration) -> Result<UserInfo> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.is_login() {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(_) if *self.state.cmd_port.lock() == 0 => return Err(WcfError::NotInited),
+                Err(e) => return Err(e),
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(WcfError::LoginTimeout);
+            }
+            if !self.sleep_while_inited(poll_interval.min(deadline - now)) {
+                return Err(WcfError::NotInited);
+            }
+        }
+        let user_info = self
+            .get_user_info()?
+            .ok_or_else(|| WcfError::RemoteRejected("no user info returned after login".into()))?;
+        self.send_event(Event::LoggedIn(user_info.clone()));
+        Ok(user_info)
+    }
+
+    // sleep in short slices so uninit() from another thread is noticed soon, returns false if uninited
+    fn sleep_while_inited(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        loop {
+            if *self.state.cmd_port.lock() == 0 {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::sleep((deadline - now).min(Duration::from_millis(100)));
+        }
+    }
+
+    pub fn get_user_info(&self) -> Result<Option<UserInfo>> {
+        let response = self.run_cmd(proto::Functions::FuncGetUserInfo.into(), None)?;
+        match response.msg {
+            Some(proto::response::Msg::Ui(user_info)) => Ok(Some(user_info.into())),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn get_contacts(&self) -> Result<Option<RpcContacts>> {
+        let response = self.run_cmd(proto::Functions::FuncGetContacts.into(), None)?;
+        match response.msg {
+            Some(proto::response::Msg::Contacts(contacts)) => Ok(Some(contacts)),
+            _ => Ok(None),
+        }
+    }
+
+    /// 分页查询，避免联系人很多时单个响应过大而超时
+    pub fn query_all_contact_info(&self) -> Result<Vec<ContactInfo>> {
+        let sql = "SELECT * FROM Contact \
+            LEFT JOIN ContactHeadImgUrl \
+            ON Contact.UserName = ContactHeadImgUrl.usrName \
+            ORDER BY Contact.rowid";
+        let mut contacts = Vec::new();
+        for page in self.exec_db_query_paged("MicroMsg.db".into(), sql.into(), CONTACT_PAGE_SIZE) {
+            contacts.extend(page?.into_iter().map(ContactInfo::from));
+        }
+        Ok(contacts)
+    }
+
+    fn list_contacts_of_kind(&self, kind: ContactKind) -> Result<Vec<ContactInfo>> {
+        let contacts = self.query_all_contact_info()?;
+        Ok(contacts.into_iter().filter(|contact| contact.kind() == kind).collect())
+    }
+
+    /// 获取好友，不包括群聊、公众号和系统账号
+    pub fn list_friends(&self) -> Result<Vec<ContactInfo>> {
+        self.list_contacts_of_kind(ContactKind::Friend)
+    }
+
+    /// 获取通讯录中的群聊
+    pub fn list_chatrooms(&self) -> Result<Vec<ContactInfo>> {
+        self.list_contacts_of_kind(ContactKind::ChatRoom)
+    }
+
+    /// 获取关注的公众号
+    pub fn list_official_accounts(&self) -> Result<Vec<ContactInfo>> {
+        self.list_contacts_of_kind(ContactKind::OfficialAccount)
+    }
+
+    pub fn query_contact_info(&self, wxid: String) -> Result<Option<ContactInfo>> {
+        let sql = "SELECT * FROM Contact \
+            LEFT JOIN ContactHeadImgUrl \
+            ON Contact.UserName = ContactHeadImgUrl.usrName \
+            WHERE Contact.UserName = ?";
+        let rows = self.exec_db_query_params("MicroMsg.db".into(), sql, &[&wxid])?;
+        Ok(rows.into_iter().next().map(|row| row.into()))
+    }
+
+    pub fn query_chat_room_info(&self, wxid: String) -> Result<Option<ChatRoom>> {
+        let sql = "SELECT ChatRoom.ChatRoomName AS ChatRoomName, \
+            ChatRoom.RoomData AS RoomData, \
+            ContactHeadImgUrl.smallHeadImgUrl AS smallHeadImgUrl, \
+            ChatRoomInfo.Announcement AS Announcement, \
+            ChatRoom.Reserved2 AS Owner \
+            FROM ChatRoom \
+            LEFT JOIN ContactHeadImgUrl \
+            ON ChatRoom.ChatRoomName = ContactHeadImgUrl.usrName \
+            LEFT JOIN ChatRoomInfo \
+            ON ChatRoom.ChatRoomName = ChatRoomInfo.ChatRoomName \
+            WHERE ChatRoom.ChatRoomName = ?";
+        let rows = self.exec_db_query_params("MicroMsg.db".into(), sql, &[&wxid])?;
+        Ok(rows.into_iter().next().map(|row| row.into()))
+    }
+
+    /// 按昵称、备注、微信号和拼音（例如 "zs"、"zhangsan"）搜索联系人，不区分大小写
+    ///
+    /// 结果按匹配程度排序：备注完全一致、其他字段完全一致、前缀匹配、包含
+    pub fn search_contacts(&self, query: &str) -> Result<Vec<ContactInfo>> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Err(WcfError::InvalidArgument("empty contact search query".into()));
+        }
+        let columns = ["NickName", "Remark", "Alias", "PYInitial", "QuanPin", "RemarkPYInitial", "RemarkQuanPin"];
+        let conditions: Vec<String> =
+            columns.iter().map(|column| format!("Contact.{} LIKE ? ESCAPE '\\'", column)).collect();
+        let sql = format!(
+            "SELECT * FROM Contact \
+            LEFT JOIN ContactHeadImgUrl \
+            ON Contact.UserName = ContactHeadImgUrl.usrName \
+            WHERE {}",
+            conditions.join(" OR ")
+        );
+        let pattern = format!("%{}%", sql::escape_like(query));
+        let params = vec![pattern.as_str(); columns.len()];
+        let rows = self.exec_db_query_params("MicroMsg.db".into(), &sql, &params)?;
+        let mut contacts: Vec<ContactInfo> = rows.into_iter().map(|row| row.into()).collect();
+        let query = query.to_lowercase();
+        contacts.sort_by_key(|contact| search_rank(contact, &query));
+        Ok(contacts)
+    }
+
+    /// 获取群成员及其群昵称、备注或昵称，联系人信息通过一次查询批量获取
+    pub fn get_room_members(&self, room_id: String) -> Result<Vec<ChatRoomMember>> {
+        let room = match self.query_chat_room_info(room_id)? {
+            Some(room) => room,
+            None => return Ok(vec![]),
+        };
+        let members = room.room_data.members;
+        if members.is_empty() {
+            return Ok(vec![]);
+        }
+        let wxids: Vec<&str> = members.iter().map(|member| member.wxid.as_str()).collect();
+        let sql = format!(
+            "SELECT UserName, NickName, Remark FROM Contact WHERE UserName IN ({})",
+            vec!["?"; wxids.len()].join(", ")
+        );
+        let mut contact_names = HashMap::new();
+        for contact in self.exec_db_query_params("MicroMsg.db".into(), &sql, &wxids)? {
+            let contact = ContactInfo::from(contact);
+            if let Some(name) = contact.remark.or(contact.nick_name) {
+                contact_names.insert(contact.wxid, name);
+            }
+        }
+        Ok(members
+            .iter()
+            .map(|member| ChatRoomMember {
+                room_nickname: Some(member.name.clone()).filter(|name| !name.is_empty()),
+                contact_name: contact_names.get(&member.wxid).cloned(),
+                is_owner: room.room_owner.as_ref() == Some(&member.wxid),
+                wxid: member.wxid.clone(),
+            })
+            .collect())
+    }
+
+    /// 获取群主 wxid，群不存在时返回 None
+    pub fn get_room_owner(&self, room_id: String) -> Result<Option<String>> {
+        Ok(self.query_chat_room_info(room_id)?.and_then(|room| room.room_owner))
+    }
+
+    pub fn get_db_names(&self) -> Result<Vec<String>> {
+        let response = self.run_cmd(proto::Functions::FuncGetDbNames.into(), None)?;
+        match response.msg {
+            Some(proto::response::Msg::Dbs(dbs)) => Ok(dbs.names),
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// 在 MSG0.db、MSG1.db …… 中查找 id 为 msg_id 的原始消息，例如 RevokeNotice.revoked_msg_id。
+    ///
+    /// 找不到，或原始消息是图片、语音等内容无法直接使用的媒体消息时返回 None
+    pub fn lookup_revoked_message(&self, msg_id: u64) -> Result<Option<DbMessage>> {
+        let sql = format!("SELECT {} FROM MSG WHERE MsgSvrID = {}", history::MSG_COLUMNS, msg_id);
+        for db in history::msg_db_names(self.get_db_names()?) {
+            if let Some(row) = self.exec_db_query(db, sql.clone())?.into_iter().next() {
+                return Ok(Some(DbMessage::from(row)).filter(|msg| msg.is_textual()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// 查询聊天记录，按时间从新到旧排列。
+    ///
+    /// 消息分散在 MSG0.db、MSG1.db …… 中，会查询每个分库后合并
+    pub fn query_messages(&self, filter: &MessageFilter) -> Result<Vec<DbMessage>> {
+        if filter.limit == Some(0) {
+            return Ok(vec![]);
+        }
+        let sql = filter.to_sql()?;
+        let shards = match &filter.talker {
+            Some(talker) => self.resolve_msg_dbs(talker)?,
+            None => history::msg_db_names(self.get_db_names()?),
+        };
+        let mut messages = Vec::new();
+        for db in shards {
+            messages.extend(self.exec_db_query(db, sql.clone())?.into_iter().map(DbMessage::from));
+        }
+        Ok(filter.merge(messages))
+    }
+
+    /// 把会话的聊天记录按时间从旧到新导出到 out，逐页读取、逐条写入，不会一次加载全部消息。
+    ///
+    /// 发送者的名字来自联系人缓存，图片在 `ExportFormat::Html` 中链接到本地路径（相对于 WeChat Files 目录）；
+    /// 语音、视频、表情等无法导出内容的媒体消息会被跳过，计入 `ExportSummary.skipped`
+    pub fn export_chat(
+        &self,
+        talker: &str,
+        range: Option<TimeRange>,
+        format: ExportFormat,
+        out: &Path,
+    ) -> Result<ExportSummary> {
+        let sql = export::export_sql(talker, range)?;
+        let self_wxid = self.get_self_wx_id()?.unwrap_or_default();
+        let contacts = ContactCache::new(self.clone());
+        let mut writer = ExportWriter::create(out, format, &contacts.display_name(talker)?)?;
+        let mut summary = ExportSummary::default();
+        // shards are numbered oldest first, resolve_msg_dbs() returns them newest first
+        for db in self.resolve_msg_dbs(talker)?.into_iter().rev() {
+            for page in self.exec_db_query_paged(db, sql.clone(), export::EXPORT_PAGE_SIZE) {
+                for row in page? {
+                    let row = ExportRow::from(row);
+                    let sender = row.sender_wxid(&self_wxid);
+                    let ExportRow { msg, image, .. } = row;
+                    let image = image.filter(|_| msg.msg_type() == MsgType::Image);
+                    if !msg.is_textual() && image.is_none() {
+                        summary.skipped += 1;
+                        continue;
+                    }
+                    writer.write(&ExportedMsg {
+                        id: msg.msg_svr_id,
+                        time: export::format_time(msg.create_time),
+                        sender_name: contacts.display_name(&sender)?,
+                        sender,
+                        is_self: msg.is_sender,
+                        msg_type: msg.msg_type,
+                        content: if image.is_some() { String::new() } else { msg.content },
+                        image,
+                    })?;
+                    summary.exported += 1;
+                }
+            }
+        }
+        writer.finish()?;
+        Ok(summary)
+    }
+
+    /// 在 MSG0.db、MSG1.db …… 中搜索文本消息，query 按空白分为多个词（引号中的内容作为一个词），
+    /// 包含所有词的消息才会返回，英文不区分大小写。
+    ///
+    /// 结果按时间从新到旧排列，最多 options.limit 条；query 中没有词时返回 `WcfError::InvalidArgument`。
+    /// 开启 store feature 时也可以用 `MessageStore::search()` 搜索本地保存的消息
+    pub fn search_messages(&self, query: &str, options: &SearchOptions) -> Result<Vec<SearchHit>> {
+        let terms = search::parse_terms(query)?;
+        let sql = search::msg_db_sql(&terms, options)?;
+        let shards = match &options.talker {
+            Some(talker) => self.resolve_msg_dbs(talker)?,
+            None => history::msg_db_names(self.get_db_names()?),
+        };
+        let self_wxid = self.get_self_wx_id()?.unwrap_or_default();
+        let mut hits = Vec::new();
+        for db in shards {
+            for row in self.exec_db_query(db, sql.clone())? {
+                let row = ExportRow::from(row);
+                let sender = row.sender_wxid(&self_wxid);
+                let msg = row.msg;
+                hits.push(SearchHit {
+                    msg_id: msg.msg_svr_id,
+                    talker: msg.talker,
+                    sender,
+                    is_self: msg.is_sender,
+                    create_time: msg.create_time,
+                    snippet: search::snippet(&msg.content, &terms),
+                    content: msg.content,
+                });
+            }
+        }
+        Ok(search::merge(hits, options.limit))
+    }
+
+    /// 搜索结果前后各 n 条同一会话中的消息，包括该消息本身，按时间从旧到新排列。
+    ///
+    /// 只在该消息所在的分库中查找，消息不存在时返回 `WcfError::NotFound`
+    pub fn search_context(&self, hit: &SearchHit, n: usize) -> Result<Vec<DbMessage>> {
+        let find = format!("SELECT {} FROM MSG WHERE MsgSvrID = {}", history::MSG_COLUMNS, hit.msg_id);
+        let talker = sql::bind_params("StrTalker = ?", &[&hit.talker])?;
+        for db in self.resolve_msg_dbs(&hit.talker)? {
+            let Some(row) = self.exec_db_query(db.clone(), find.clone())?.into_iter().next() else {
+                continue;
+            };
+            // localId grows with each message stored in the shard
+            let local_id = DbMessage::from(row).local_id;
+            let before = format!(
+                "SELECT {} FROM MSG WHERE {} AND localId < {} ORDER BY localId DESC LIMIT {}",
+                history::MSG_COLUMNS,
+                talker,
+                local_id,
+                n
+            );
+            let after = format!(
+                "SELECT {} FROM MSG WHERE {} AND localId >= {} ORDER BY localId LIMIT {}",
+                history::MSG_COLUMNS,
+                talker,
+                local_id,
+                n + 1
+            );
+            let mut messages: Vec<DbMessage> =
+                self.exec_db_query(db.clone(), before)?.into_iter().map(DbMessage::from).collect();
+            messages.reverse();
+            messages.extend(self.exec_db_query(db, after)?.into_iter().map(DbMessage::from));
+            return Ok(messages);
+        }
+        Err(WcfError::NotFound(format!("message {}", hit.msg_id)))
+    }
+
+    /// 把所有联系人导出为 CSV（UTF-8 BOM），包括 wxid、微信号、昵称、备注、类型和头像地址，返回导出的行数。
+    ///
+    /// 每写入 CSV_PROGRESS_INTERVAL 行以已写入的行数调用 progress，结束时再以总行数调用一次
+    pub fn export_contacts_csv<F>(&self, path: &Path, mut progress: F) -> Result<u64>
+    where
+        F: FnMut(u64),
+    {
+        let headers = ["wxid", "alias", "nickname", "remark", "kind", "small_head_url", "big_head_url"];
+        let mut writer = CsvWriter::create(path, &headers)?;
+        for contact in self.query_all_contact_info()? {
+            let fields = [
+                &contact.wxid,
+                contact.alias.as_deref().unwrap_or_default(),
+                contact.nick_name.as_deref().unwrap_or_default(),
+                contact.remark.as_deref().unwrap_or_default(),
+                &format!("{:?}", contact.kind()),
+                contact.small_head_url.as_deref().unwrap_or_default(),
+                contact.big_head_url.as_deref().unwrap_or_default(),
+            ];
+            writer.write_row(&fields, &mut progress)?;
+        }
+        writer.finish(&mut progress)
+    }
+
+    /// 把所有群导出为 CSV（UTF-8 BOM），包括群 id、群名、备注、成员数、群主和头像地址，返回导出的行数。
+    ///
+    /// progress 的调用方式与 export_contacts_csv() 相同
+    pub fn export_rooms_csv<F>(&self, path: &Path, mut progress: F) -> Result<u64>
+    where
+        F: FnMut(u64),
+    {
+        let contacts: HashMap<String, ContactInfo> =
+            self.query_all_contact_info()?.into_iter().map(|contact| (contact.wxid.clone(), contact)).collect();
+        let name = |wxid: &str| contacts.get(wxid).map_or(wxid, ContactInfo::display_name).to_string();
+        let sql = "SELECT ChatRoom.ChatRoomName AS ChatRoomName, \
+            ChatRoom.RoomData AS RoomData, \
+            ContactHeadImgUrl.smallHeadImgUrl AS smallHeadImgUrl, \
+            ChatRoom.Reserved2 AS Owner \
+            FROM ChatRoom \
+            LEFT JOIN ContactHeadImgUrl \
+            ON ChatRoom.ChatRoomName = ContactHeadImgUrl.usrName \
+            ORDER BY ChatRoom.rowid";
+        let headers = ["room_id", "name", "remark", "member_count", "owner", "owner_name", "head_url"];
+        let mut writer = CsvWriter::create(path, &headers)?;
+        for page in self.exec_db_query_paged("MicroMsg.db".into(), sql.into(), CONTACT_PAGE_SIZE) {
+            for room in page?.into_iter().map(ChatRoom::from) {
+                let contact = contacts.get(&room.room_id);
+                let owner = room.room_owner.unwrap_or_default();
+                let fields = [
+                    &room.room_id,
+                    contact.and_then(|contact| contact.nick_name.as_deref()).unwrap_or_default(),
+                    contact.and_then(|contact| contact.remark.as_deref()).unwrap_or_default(),
+                    &room.room_data.members.len().to_string(),
+                    &owner,
+                    &if owner.is_empty() { String::new() } else { name(&owner) },
+                    room.room_head_img_url.as_deref().unwrap_or_default(),
+                ];
+                writer.write_row(&fields, &mut progress)?;
+            }
+        }
+        writer.finish(&mut progress)
+    }
+
+    // whether the shard has messages with talker, via Name2ID if the shard has it, or by probing MSG
+    fn msg_db_has_talker(&self, db: &str, talker: &str) -> Result<bool> {
+        let has_name2id = self.get_db_tables(db.to_string())?.iter().any(|table| table.name == "Name2ID");
+        let sql = if has_name2id {
+            "SELECT 1 FROM Name2ID WHERE UsrName = ? LIMIT 1"
+        } else {
+            "SELECT 1 FROM MSG WHERE StrTalker = ? LIMIT 1"
+        };
+        Ok(!self.exec_db_query_params(db.to_string(), sql, &[talker])?.is_empty())
+    }
+
+    /// 会话的消息所在的所有分库，按编号从新到旧排列，结果会被缓存，出现新的分库时重新查找
+    pub fn resolve_msg_dbs(&self, talker: &str) -> Result<Vec<String>> {
+        let db_names = history::msg_db_names(self.get_db_names()?);
+        if let Some(shards) = self.state.msg_db_map.lock().get(&db_names, talker) {
+            return Ok(shards);
+        }
+        let mut shards = Vec::new();
+        for db in &db_names {
+            if self.msg_db_has_talker(db, talker)? {
+                shards.push(db.clone());
+            }
+        }
+        self.state.msg_db_map.lock().insert(&db_names, talker, shards.clone());
+        Ok(shards)
+    }
+
+    /// 会话最新的消息所在的分库，例如 "MSG3.db"，用于自己编写 sql 查询，没有聊天记录时返回 `WcfError::NotFound`
+    pub fn resolve_msg_db(&self, talker: &str) -> Result<String> {
+        let shards = self.resolve_msg_dbs(talker)?;
+        shards.into_iter().next().ok_or_else(|| WcfError::NotFound(format!("message database of {}", talker)))
+    }
+
+    pub fn get_db_tables(&self, db: String) -> Result<Vec<DbTable>> {
+        let msg = Some(proto::request::Msg::Str(db));
+        let response = self.run_cmd(proto::Functions::FuncGetDbTables.into(), msg)?;
+        match response.msg {
+            Some(proto::response::Msg::Tables(tables)) => Ok(tables.tables),
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// 执行带参数的查询，sql 中的 `?` 依次替换为转义后的 params，避免 SQL 注入
+    pub fn exec_db_query_params(&self, db: String, sql: &str, params: &[&str]) -> Result<Vec<DbRow>> {
+        let sql = sql::bind_params(sql, params)?;
+        self.exec_db_query(db, sql)
+    }
+
+    /// 分页执行 sql，每次返回最多 page_size 行，取完时结束，适用于结果很大的查询。
+    ///
+    /// 通过在 sql 后追加 LIMIT/OFFSET 实现，sql 中已有 LIMIT 时第一项返回 `WcfError::InvalidArgument`；
+    /// sql 应带有 ORDER BY，保证各页之间的顺序稳定
+    pub fn exec_db_query_paged(
+        &self,
+        db: String,
+        sql: String,
+        page_size: usize,
+    ) -> impl Iterator<Item = Result<Vec<DbRow>>> {
+        let client = self.clone();
+        let sql = sql.trim().trim_end_matches(';').to_string();
+        let mut error = if sql::has_limit(&sql) {
+            Some(WcfError::InvalidArgument(format!("sql for paged query already has LIMIT: {}", sql)))
+        } else if page_size == 0 {
+            Some(WcfError::InvalidArgument("page_size must be positive".into()))
+        } else {
+            None
+        };
+        let mut offset = 0;
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if let Some(e) = error.take() {
+                done = true;
+                return Some(Err(e));
+            }
+            if done {
+                return None;
+            }
+            let page_sql = format!("{} LIMIT {} OFFSET {}", sql, page_size, offset);
+            let rows = match client.exec_db_query(db.clone(), page_sql) {
+                Ok(rows) => rows,
+                Err(e) => {
+                    done = true;
+                    return Some(Err(e));
+                }
+            };
+            // a short page is the last one, an empty page ends without being yielded
+            done = rows.len() < page_size;
+            if rows.is_empty() {
+                return None;
+            }
+            offset += rows.len();
+            Some(Ok(rows))
+        })
+    }
+
+    /// 执行 sql，结果中的每个字段按 SQLite 类型解码为 [`DbValue`](super::DbValue)，以列名为键
+    pub fn exec_db_query_typed(&self, db: String, sql: String) -> Result<Vec<TypedDbRow>> {
+        let rows = self.exec_db_query(db, sql)?;
+        Ok(rows.into_iter().map(db_value::typed_row).collect())
+    }
+
+    pub fn exec_db_query(&self, db: String, sql: String) -> Result<Vec<DbRow>> {
+        let db_query_msg = proto::DbQuery { db, sql };
+        let msg = Some(proto::request::Msg::Query(db_query_msg));
+        let response = self.run_cmd(proto::Functions::FuncExecDbQuery.into(), msg)?;
+        match response.msg {
+            Some(proto::response::Msg::Rows(rows)) => Ok(rows.rows),
+            _ => Ok(vec![]),
+        }
+    }
+
+    /**
+     * @param msg:      消息内容（如果是 @ 消息则需要有跟 @ 的人数量相同的 @）
+     * @param receiver: 消息接收人，私聊为 wxid（wxid_xxxxxxxxxxxxxx），群聊为
+     *                  roomid（xxxxxxxxxx@chatroom）
+     * @param aters:    群聊时要 @ 的人（私聊时为空字符串），多个用逗号分隔。@所有人 用
+     *                  notify@all（必须是群主或者管理员才有权限）
+     * @return int
+     * @Description 发送文本消息，receiver 格式不对时返回 `WcfError::InvalidReceiver`
+     * @author Changhua
+     * @example sendText(" Hello @ 某人1 @ 某人2 ", " xxxxxxxx @ chatroom ",
+     * "wxid_xxxxxxxxxxxxx1,wxid_xxxxxxxxxxxxx2");
+     */
+    pub fn send_text(&self, msg: String, receiver: String, aters: String) -> Result<SendResult> {
+        validate::check_receiver(&receiver)?;
+        self.acquire_send(&receiver)?;
+        self.send_text_now(msg, receiver, aters)
+    }
+
+    // send_text() after the delays are done
+    fn send_text_now(&self, msg: String, receiver: String, aters: String) -> Result<SendResult> {
+        let text_msg = proto::TextMsg { msg, receiver, aters };
+        let msg = Some(proto::request::Msg::Txt(text_msg));
+        let response = self.run_cmd(proto::Functions::FuncSendTxt.into(), msg)?;
+        Ok(SendResult::from(&response))
+    }
+
+    // sends to each receiver in turn, see BroadcastOptions
+    fn broadcast<F>(
+        &self,
+        receivers: &[String],
+        options: &BroadcastOptions,
+        send: F,
+    ) -> Result<Vec<(String, Result<SendResult>)>>
+    where
+        F: Fn(&str) -> Result<SendResult>,
+    {
+        let contacts: HashSet<String> = if options.dry_run {
+            self.query_all_contact_info()?.into_iter().map(|contact| contact.wxid).collect()
+        } else {
+            HashSet::new()
+        };
+        let mut results = Vec::with_capacity(receivers.len());
+        for (i, receiver) in receivers.iter().enumerate() {
+            let result = if options.dry_run {
+                if contacts.contains(receiver) {
+                    Ok(SendResult { success: true, status: 0 })
+                } else {
+                    Err(WcfError::NotFound(format!("contact {}", receiver)))
+                }
+            } else {
+                if i > 0 && !options.delay.is_zero() {
+                    thread::sleep(options.delay);
+                }
+                send(receiver)
+            };
+            let failed = !result.as_ref().is_ok_and(|result| result.success);
+            results.push((receiver.clone(), result));
+            if failed && options.stop_on_error {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    /// 依次发送同一条文本给多个接收者，单个失败不影响其他接收者，返回每个接收者的结果。
+    ///
+    /// 只有 dry_run 时查询联系人失败才返回 Err
+    pub fn broadcast_text(
+        &self,
+        msg: &str,
+        receivers: &[String],
+        options: &BroadcastOptions,
+    ) -> Result<Vec<(String, Result<SendResult>)>> {
+        self.broadcast(receivers, options, |receiver| {
+            self.send_text(msg.to_string(), receiver.to_string(), String::new())
+        })
+    }
+
+    /// 依次发送同一张图片给多个接收者，参考 broadcast_text()
+    pub fn broadcast_image(
+        &self,
+        path: &Path,
+        receivers: &[String],
+        options: &BroadcastOptions,
+    ) -> Result<Vec<(String, Result<SendResult>)>> {
+        self.broadcast(receivers, options, |receiver| self.send_image(path.to_path_buf(), receiver.to_string()))
+    }
+
+    /// 依次发送同一个文件给多个接收者，参考 broadcast_text()
+    pub fn broadcast_file(
+        &self,
+        path: &Path,
+        receivers: &[String],
+        options: &BroadcastOptions,
+    ) -> Result<Vec<(String, Result<SendResult>)>> {
+        self.broadcast(receivers, options, |receiver| self.send_file(path.to_path_buf(), receiver.to_string()))
+    }
+
+    /// 模拟人工发送文本：发送前随机等待，文本过长时按 max_part_chars 拆分为多条，间隔 part_interval 发送。
+    ///
+    /// 拆分不会切开 `@名字\u{2005}`，aters 只随包含 @ 的部分发送（都不包含时随第一部分）；options 代替 set_humanize() 的全局设置，
+    /// 被取消时返回 `WcfError::Cancelled`，已发送的部分不会撤回
+    pub fn send_text_humanized(
+        &self,
+        msg: String,
+        receiver: String,
+        aters: String,
+        options: &HumanizeOptions,
+    ) -> Result<Vec<SendResult>> {
+        validate::check_receiver(&receiver)?;
+        let parts = match options.max_part_chars {
+            Some(max_chars) => humanize::split_text(&msg, max_chars),
+            None => vec![msg.as_str()],
+        };
+        // without recognizable mentions, aters goes with the first part
+        let any_mention = parts.iter().any(|part| humanize::has_mention(part));
+        let mut results = Vec::with_capacity(parts.len());
+        for (i, part) in parts.into_iter().enumerate() {
+            if i > 0 {
+                options.sleep(options.part_interval)?;
+            }
+            self.acquire_send_with(&receiver, Some(options))?;
+            let with_aters = humanize::has_mention(part) || (!any_mention && i == 0);
+            let aters = if with_aters { aters.clone() } else { String::new() };
+            results.push(self.send_text_now(part.to_string(), receiver.clone(), aters)?);
+        }
+        Ok(results)
+    }
+
+    /// 发送群聊 @ 消息，自动在 text 前插入 `@名字\u{2005}` 并生成 aters，无需手动拼写。
+    ///
+    /// 名字优先使用群昵称，没有时使用微信昵称；要 @ 的 wxid 不在群中时返回 `WcfError::InvalidArgument`。
+    pub fn send_text_with_mentions(&self, room_id: String, text: String, mentions: &[Mention]) -> Result<SendResult> {
+        let room = self
+            .query_chat_room_info(room_id.clone())?
+            .ok_or_else(|| WcfError::InvalidArgument(format!("chat room not found: {}", room_id)))?;
+        let mut msg = String::new();
+        let mut aters = Vec::with_capacity(mentions.len());
+        for mention in mentions {
+            let (wxid, name) = match mention {
+                Mention::All => ("notify@all".to_string(), "所有人".to_string()),
+                Mention::Wxid(wxid) => {
+                    let member =
+                        room.room_data.members.iter().find(|member| &member.wxid == wxid).ok_or_else(|| {
+                            WcfError::InvalidArgument(format!("{} is not a member of chat room {}", wxid, room_id))
+                        })?;
+                    let name = match member.name.is_empty() {
+                        false => member.name.clone(),
+                        true => {
+                            let contact = self.query_contact_info(wxid.clone())?;
+                            contact.and_then(|contact| contact.nick_name).unwrap_or_else(|| wxid.clone())
+                        }
+                    };
+                    (wxid.clone(), name)
+                }
+            };
+            msg.push_str(&format!("@{}\u{2005}", name));
+            aters.push(wxid);
+        }
+        msg.push_str(&text);
+        self.send_text(msg, room_id, aters.join(","))
+    }
+
+    /// 发送图片，发送前检查文件存在、非空、扩展名为图片格式，以及 receiver 的格式，
+    /// 不符合时返回 `WcfError::InvalidPath` 或 `WcfError::InvalidReceiver`
+    pub fn send_image(&self, path: PathBuf, receiver: String) -> Result<SendResult> {
+        validate::check_file(&path, &validate::IMAGE_EXTENSIONS)?;
+        validate::check_receiver(&receiver)?;
+        self.send_image_unchecked(path, receiver)
+    }
+
+    /// 不做检查的 send_image()，例如接收者 id 格式特殊时
+    pub fn send_image_unchecked(&self, path: PathBuf, receiver: String) -> Result<SendResult> {
+        let path = validate::path_to_string(&path)?;
+        self.acquire_send(&receiver)?;
+        let path_msg = proto::PathMsg { path, receiver };
+        let msg = Some(proto::request::Msg::File(path_msg));
+        let response = self.run_cmd(proto::Functions::FuncSendImg.into(), msg)?;
+        Ok(SendResult::from(&response))
+    }
+
+    /// 发送文件，发送前检查文件存在、非空，以及 receiver 的格式
+    pub fn send_file(&self, path: PathBuf, receiver: String) -> Result<SendResult> {
+        validate::check_file(&path, &[])?;
+        validate::check_receiver(&receiver)?;
+        self.send_file_unchecked(path, receiver)
+    }
+
+    pub fn send_file_unchecked(&self, path: PathBuf, receiver: String) -> Result<SendResult> {
+        let path = validate::path_to_string(&path)?;
+        self.acquire_send(&receiver)?;
+        let path_msg = proto::PathMsg { path, receiver };
+        let msg = Some(proto::request::Msg::File(path_msg));
+        let response = self.run_cmd(proto::Functions::FuncSendFile.into(), msg)?;
+        Ok(SendResult::from(&response))
+    }
+
+    /// 发送 xml 消息，receiver 格式不对时返回 `WcfError::InvalidReceiver`
+    pub fn send_xml(&self, xml: String, path: PathBuf, receiver: String, xml_type: i32) -> Result<SendResult> {
+        validate::check_receiver(&receiver)?;
+        let path = validate::path_to_string(&path)?;
+        self.acquire_send(&receiver)?;
+        let xml_msg = proto::XmlMsg { content: xml, path, receiver, r#type: xml_type };
+        let msg = Some(proto::request::Msg::Xml(xml_msg));
+        let response = self.run_cmd(proto::Functions::FuncSendXml.into(), msg)?;
+        Ok(SendResult::from(&response))
+    }
+
+    /// 发送表情，发送前检查文件存在、非空、扩展名为 gif 或图片格式，以及 receiver 的格式
+    pub fn send_emotion(&self, path: PathBuf, receiver: String) -> Result<SendResult> {
+        validate::check_file(&path, &validate::EMOTION_EXTENSIONS)?;
+        validate::check_receiver(&receiver)?;
+        self.send_emotion_unchecked(path, receiver)
+    }
+
+    pub fn send_emotion_unchecked(&self, path: PathBuf, receiver: String) -> Result<SendResult> {
+        let path = validate::path_to_string(&path)?;
+        self.acquire_send(&receiver)?;
+        let path_msg = proto::PathMsg { path, receiver };
+        let msg = Some(proto::request::Msg::File(path_msg));
+        let response = self.run_cmd(proto::Functions::FuncSendEmotion.into(), msg)?;
+        Ok(SendResult::from(&response))
+    }
+
+    /// 开启消息接收，返回时 msg socket 已连接，接收线程已启动，并已发出 MsgSocketConnected 事件。
+    ///
+    /// 接收线程已在运行时返回 `ListenStatus::AlreadyListening`，不会重复启动。
+    /// msg 端口为 cmd 端口 + 1，与 wcf 一致，wcf 的响应中没有端口，其他端口见 enable_listen_on()。
+    /// 一段时间内连不上 msg socket 时返回 `WcfError::MsgSocketConnect`，此时远端仍在推送，可以重试或 disable_listen()
+    pub fn enable_listen(&self) -> Result<ListenStatus> {
+        self.enable_listen_with_port(None)
+    }
+
+    /// 同 enable_listen()，连接指定的 msg 端口，用于 wcf 的消息端口不是 cmd 端口 + 1 的情况
+    pub fn enable_listen_on(&self, port: u16) -> Result<ListenStatus> {
+        self.enable_listen_with_port(Some(port))
+    }
+
+    fn enable_listen_with_port(&self, port: Option<u16>) -> Result<ListenStatus> {
+        // read before locking msg_port, uninit() locks cmd_port then msg_port
+        let cmd_port = *self.state.cmd_port.lock();
+        let mut msg_port = self.state.msg_port.lock();
+        let mut msg_thread = self.state.msg_thread.lock();
+        if msg_thread.as_ref().is_some_and(|t| !t.handle.is_finished()) {
+            return Ok(ListenStatus::AlreadyListening);
+        }
+        if *msg_port == 0 {
+            // only send command when msg_port not set
+            if cmd_port == 0 {
+                return Err(WcfError::NotInited);
+            }
+            let msg = Some(proto::request::Msg::Flag(true));
+            let response = self.run_cmd(proto::Functions::FuncEnableRecvTxt.into(), msg)?;
+            if response.msg.is_none() {
+                return Err(WcfError::RemoteRejected("failed to enable remote listen service".into()));
+            }
+            *msg_port = cmd_port.wrapping_add(1);
+        }
+        if let Some(port) = port {
+            *msg_port = port;
+        }
+        // connect here, so the caller knows whether messages could flow
+        let socket = connect_msg_socket(*msg_port)?;
+        self.send_event(Event::MsgSocketConnected);
+        let client = self.clone();
+        let thread_socket = socket.clone();
+        let handle = match self.spawn_thread("wcf-msg-recv", move || client.recv_msg_thread(thread_socket)) {
+            Ok(handle) => handle,
+            Err(e) => {
+                socket.close();
+                self.send_event(Event::MsgSocketDisconnected);
+                return Err(e.into());
+            }
+        };
+        *msg_thread = Some(MsgThread { socket, handle });
+        Ok(ListenStatus::Started)
+    }
+
+    /// 接收线程是否在运行
+    pub fn is_listening(&self) -> bool {
+        self.state.msg_thread.lock().as_ref().is_some_and(|t| !t.handle.is_finished())
+    }
+
+    /// 关闭消息接收，返回时接收线程已退出，MsgSocketDisconnected 事件已分发
+    pub fn disable_listen(&self) -> Result<bool> {
+        self.disable_listen_with_timeout(DEFAULT_LISTEN_STOP_TIMEOUT)
+    }
+
+    /// 同 disable_listen()，可以指定等待接收线程退出的时间，超时返回 `WcfError::Timeout`
+    pub fn disable_listen_with_timeout(&self, timeout: Duration) -> Result<bool> {
+        let disabled = self.stop_listen(timeout)?;
+        // MsgSocketDisconnected is queued by the receive thread, wait for it to be delivered
+        self.state.events.flush();
+        Ok(disabled)
+    }
+
+    // disable_listen() without waiting for events, so it's safe to call with cmd_port locked
+    fn stop_listen(&self, timeout: Duration) -> Result<bool> {
+        {
+            let mut msg_port = self.state.msg_port.lock();
+            if *msg_port == 0 {
+                return Ok(false); // no need to disable
+            }
+
+            let response = self.run_cmd(proto::Functions::FuncDisableRecvTxt.into(), None)?;
+            if response.msg.is_none() {
+                return Err(WcfError::RemoteRejected("failed to disable recv, None returned from remote side".into()));
+            }
+            *msg_port = 0;
+        }
+        self.stop_msg_thread(timeout)?;
+        Ok(true)
+    }
+
+    /// 在后台线程中调用 disable_listen()，不阻塞当前线程，无法创建线程时返回 `WcfError::Io`
+    pub fn disable_listen_async(&self) -> Result<JoinHandle<Result<bool>>> {
+        let client = self.clone();
+        let handle = thread::Builder::new().name("wcf-disable-listen".into()).spawn(move || client.disable_listen())?;
+        Ok(handle)
+    }
+
+    fn stop_msg_thread(&self, timeout: Duration) -> Result<()> {
+        let msg_thread = self.state.msg_thread.lock().take();
+        let Some(MsgThread { socket, handle }) = msg_thread else {
+            return Ok(());
+        };
+        socket.close(); // wakes up the pending recv()
+        let deadline = Instant::now() + timeout;
+        while !handle.is_finished() {
+            if Instant::now() >= deadline {
+                warn!("receive thread not exited in {:?}, leave it detached", timeout);
+                return Err(WcfError::Timeout);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = handle.join();
+        Ok(())
+    }
+
+    /**
+     * 获取消息类型，代码中可以直接使用 MsgType
+     * {"47": "石头剪刀布 | 表情图片", "62": "小视频", "43": "视频", "1": "文字", "10002": "撤回消息", "40": "POSSIBLEFRIEND_MSG", "10000": "红包、系统消息", "37": "好友确认", "48": "位置", "42": "名片", "49": "共享实时位置、文件、转账、链接", "3": "图片", "34": "语音", "9999": "SYSNOTICE", "52": "VOIPNOTIFY", "53": "VOIPINVITE", "51": "微信初始化", "50": "VOIPMSG"}
+     */
+    pub fn get_msg_types(&self) -> Result<HashMap<i32, String>> {
+        let response = self.run_cmd(proto::Functions::FuncGetMsgTypes.into(), None)?;
+        match response.msg {
+            Some(proto::response::Msg::Types(msg_types)) => Ok(msg_types.types),
+            _ => Ok(HashMap::default()),
+        }
+    }
+
+    pub fn accept_new_friend(&self, v3: String, v4: String, scene: i32) -> Result<bool> {
+        let msg = Some(proto::request::Msg::V(proto::Verification { v3, v4, scene }));
+        let response = self.run_cmd(proto::Functions::FuncAcceptFriend.into(), msg)?;
+        Ok(get_response_status_as_bool(&response))
+    }
+
+    /* 添加群成员 */
+    pub fn add_chatroom_members(&self, roomid: String, wxids: String) -> Result<bool> {
+        let msg = Some(proto::request::Msg::M(proto::MemberMgmt { roomid, wxids }));
+        let response = self.run_cmd(proto::Functions::FuncAddRoomMembers.into(), msg)?;
+        Ok(get_response_status_as_bool(&response))
+    }
+
+    /* 邀请群成员 */
+    pub fn inv_chatroom_members(&self, roomid: String, wxids: String) -> Result<bool> {
+        let msg = Some(proto::request::Msg::M(proto::MemberMgmt { roomid, wxids }));
+        let response = self.run_cmd(proto::Functions::FuncInvRoomMembers.into(), msg)?;
+        Ok(get_response_status_as_bool(&response))
+    }
+
+    /* 删除群成员 */
+    pub fn del_chatroom_members(&self, roomid: String, wxids: String) -> Result<bool> {
+        let msg = Some(proto::request::Msg::M(proto::MemberMgmt { roomid, wxids }));
+        let response = self.run_cmd(proto::Functions::FuncDelRoomMembers.into(), msg)?;
+        Ok(get_response_status_as_bool(&response))
+    }
+
+    pub fn decrypt_image(&self, src: String, dst: String) -> Result<bool> {
+        let msg = Some(proto::request::Msg::Dec(proto::DecPath { src, dst }));
+        let response = self.run_cmd(proto::Functions::FuncDecryptImage.into(), msg)?;
+        Ok(get_response_status_as_bool(&response))
+    }
+
+    pub fn recv_transfer(&self, wxid: String, transferid: String, transcationid: String) -> Result<bool> {
+        let (tfid, taid) = (transferid, transcationid);
+        let msg = Some(proto::request::Msg::Tf(proto::Transfer { wxid, tfid, taid }));
+        let response = self.run_cmd(proto::Functions::FuncRecvTransfer.into(), msg)?;
+        Ok(get_response_status_as_bool(&response))
+    }
+
+    /** 刷新朋友圈 */
+    pub fn refresh_pyq(&self, id: u64) -> Result<bool> {
+        let msg = Some(proto::request::Msg::Ui64(id));
+        let response = self.run_cmd(proto::Functions::FuncRefreshPyq.into(), msg)?;
+        Ok(get_response_status_as_bool(&response))
+    }
+
+    /** 保存附件 */
+    pub fn attach_msg(&self, id: u64, thumb: String, extra: String) -> Result<bool> {
+        let msg = Some(proto::request::Msg::Att(proto::AttachMsg { id, thumb, extra }));
+        let response = self.run_cmd(proto::Functions::FuncDownloadAttach.into(), msg)?;
+        Ok(get_response_status_as_bool(&response))
+    }
+
+    /** 通过 wxid 查询联系人信息，联系人不存在时返回 None */
+    pub fn get_contact_info(&self, wxid: String) -> Result<Option<RpcContact>> {
+        let msg = Some(proto::request::Msg::Str(wxid));
+        let response = self.run_cmd(proto::Functions::FuncGetContactInfo.into(), msg)?;
+        match response.msg {
+            Some(proto::response::Msg::Contacts(contacts)) => Ok(contacts.contacts.into_iter().next()),
+            _ => Ok(None),
+        }
+    }
+
+    /** 撤回自己发送的消息，id 为消息 id */
+    pub fn revoke_msg(&self, id: u64) -> Result<bool> {
+        let msg = Some(proto::request::Msg::Ui64(id));
+        let response = self.run_cmd(proto::Functions::FuncRevokeMsg.into(), msg)?;
+        Ok(get_response_status_as_bool(&response))
+    }
+
+    /** 未登录时刷新登录二维码，返回二维码的内容，已登录时返回 None */
+    pub fn refresh_qrcode(&self) -> Result<Option<String>> {
+        let response = self.run_cmd(proto::Functions::FuncRefreshQrcode.into(), None)?;
+        match response.msg {
+            Some(proto::response::Msg::Str(qrcode)) if !qrcode.is_empty() => Ok(Some(qrcode)),
+            _ => Ok(None),
+        }
+    }
+
+    // decrypts src into dir, the spy answers with the path of the decrypted file, or empty if not ready yet
+    fn decrypt_image_into(&self, src: &Path, dir: &Path) -> Result<Option<PathBuf>> {
+        let (src, dst) = (validate::path_to_string(src)?, validate::path_to_string(dir)?);
+        let msg = Some(proto::request::Msg::Dec(proto::DecPath { src, dst }));
+        let response = self.run_cmd(proto::Functions::FuncDecryptImage.into(), msg)?;
+        match response.msg {
+            Some(proto::response::Msg::Str(path)) if !path.is_empty() => Ok(Some(PathBuf::from(path))),
+            _ => Ok(None),
+        }
+    }
+
+    /// 下载并解密图片消息，返回 dest_dir 中的图片路径，文件名为 `消息 id.格式`（按文件头判断）。
+    ///
+    /// 图片已下载过（extra 指向的 .dat 已存在）时不再下载；timeout 内未下载或解密完成时返回 `WcfError::DownloadTimeout`，
+    /// 解密出空文件时返回 `WcfError::DownloadFailed`
+    pub fn download_image(&self, msg: &WxMsg, dest_dir: &Path, timeout: Duration) -> Result<PathBuf> {
+        download::check_msg_type(msg, MsgType::Image)?;
+        if msg.extra.is_empty() {
+            return Err(WcfError::DownloadFailed(format!("image message {} has no file path", msg.id)));
+        }
+        let deadline = Instant::now() + timeout;
+        let encrypted = PathBuf::from(&msg.extra);
+        if !download::is_ready(&encrypted) {
+            if !self.attach_msg(msg.id, msg.thumb.clone(), msg.extra.clone())? {
+                return Err(WcfError::DownloadFailed(format!("spy refused to download message {}", msg.id)));
+            }
+            download::wait_for_file(&encrypted, deadline)?;
+        }
+        fs::create_dir_all(dest_dir)?;
+        loop {
+            if let Some(decrypted) = self.decrypt_image_into(&encrypted, dest_dir)? {
+                return download::finish_image(&decrypted, dest_dir, msg.id);
+            }
+            if Instant::now() >= deadline {
+                return Err(WcfError::DownloadTimeout(encrypted));
+            }
+            thread::sleep(download::POLL_INTERVAL);
+        }
+    }
+
+    /// 下载消息中的附件，按消息类型处理：文件（type 49）、视频（type 43）等待 spy 下载完成，图片调用 download_image()，
+    /// 语音调用 download_audio()。
+    ///
+    /// 下载过程中以已下载的字节数调用 progress；dest_dir 不为 None 时将文件复制过去，文件使用原始文件名，
+    /// 否则返回微信保存文件的路径（图片、语音保存在其所在目录）。
+    /// 文件已存在且大小与消息中的一致时不会重复下载，视频的 xml 中没有大小时总是重新下载
+    pub fn download_attachment<F>(
+        &self,
+        msg: &WxMsg,
+        dest_dir: Option<&Path>,
+        timeout: Duration,
+        progress: F,
+    ) -> Result<PathBuf>
+    where
+        F: FnMut(u64),
+    {
+        let saved = PathBuf::from(&msg.extra);
+        let saved_dir = || saved.parent().map(Path::to_path_buf).unwrap_or_default();
+        let (expected_size, file_name) = match MsgType::from(msg.r#type as i32) {
+            MsgType::Image => return self.download_image(msg, dest_dir.unwrap_or(&saved_dir()), timeout),
+            MsgType::Voice => return self.download_audio(msg, dest_dir.unwrap_or(&saved_dir()), timeout),
+            MsgType::Video => {
+                (download::video_size(&msg.content), saved.file_name().map(|name| name.to_string_lossy().into_owned()))
+            }
+            MsgType::App => match AppMsg::parse(&msg.content)? {
+                AppMsg::File { name, size, .. } => (Some(size), Some(name)),
+                _ => return Err(WcfError::InvalidArgument(format!("message {} has no attachment", msg.id))),
+            },
+            actual => return Err(WcfError::UnexpectedMsgType { expected: MsgType::App, actual }),
+        };
+        if msg.extra.is_empty() {
+            return Err(WcfError::DownloadFailed(format!("message {} has no file path", msg.id)));
+        }
+        let deadline = Instant::now() + timeout;
+        let already_saved = expected_size.is_some_and(|size| saved.metadata().is_ok_and(|m| m.len() == size));
+        if !already_saved && !self.attach_msg(msg.id, msg.thumb.clone(), msg.extra.clone())? {
+            return Err(WcfError::DownloadFailed(format!("spy refused to download message {}", msg.id)));
+        }
+        download::wait_for_complete(&saved, expected_size, deadline, progress)?;
+        match (dest_dir, file_name) {
+            (Some(dest_dir), Some(file_name)) => download::copy_into(&saved, dest_dir, &file_name),
+            _ => Ok(saved),
+        }
+    }
+
+    // the spy answers with the path of the saved audio, or empty if not ready yet
+    fn get_audio_msg_path(&self, id: u64, dir: &Path) -> Result<Option<PathBuf>> {
+        let msg = Some(proto::request::Msg::Am(proto::AudioMsg { id, dir: validate::path_to_string(dir)? }));
+        let response = self.run_cmd(proto::Functions::FuncGetAudioMsg.into(), msg)?;
+        match response.msg {
+            Some(proto::response::Msg::Str(path)) if !path.is_empty() => Ok(Some(PathBuf::from(path))),
+            _ => Ok(None),
+        }
+    }
+
+    /// 保存语音消息到 dir，返回文件路径，spy 会将 SILK 转为 mp3，文件名为 `消息 id.mp3`。
+    ///
+    /// 语音刚收到时常常还不能保存，会按指数退避重试到 timeout，仍没有非空的文件时返回 `WcfError::DownloadTimeout`；
+    /// 不是语音消息时返回 `WcfError::UnexpectedMsgType`
+    pub fn download_audio(&self, msg: &WxMsg, dir: &Path, timeout: Duration) -> Result<PathBuf> {
+        download::check_msg_type(msg, MsgType::Voice)?;
+        fs::create_dir_all(dir)?;
+        let deadline = Instant::now() + timeout;
+        let expected = dir.join(format!("{}.mp3", msg.id));
+        let mut backoff = download::POLL_INTERVAL;
+        loop {
+            let saved = self.get_audio_msg_path(msg.id, dir)?.unwrap_or_else(|| expected.clone());
+            if download::is_ready(&saved) {
+                return Ok(saved);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(WcfError::DownloadTimeout(expected));
+            }
+            thread::sleep(backoff.min(deadline - now));
+            backoff = (backoff * 2).min(Duration::from_secs(2));
+        }
+    }
+
+    /** 获取语音 */
+    pub fn get_audio_msg(&self, id: u64, dir: String) -> Result<bool> {
+        let msg = Some(proto::request::Msg::Am(proto::AudioMsg { id, dir }));
+        let response = self.run_cmd(proto::Functions::FuncGetAudioMsg.into(), msg)?;
+        Ok(get_response_status_as_bool(&response))
+    }
+
+    /// 发送链接卡片，thumb_url 可以为空，参考 [`LinkCard`]
+    pub fn send_link(&self, receiver: &str, title: &str, desc: &str, url: &str, thumb_url: &str) -> Result<SendResult> {
+        LinkCard::new(title, url).description(desc).thumb_url(thumb_url).send_to_with(self, receiver)
+    }
+
+    /** 发送富文本，receiver 格式不对时返回 `WcfError::InvalidReceiver` */
+    pub fn send_rich_text(&self, richtext: RichText) -> Result<SendResult> {
+        validate::check_receiver(&richtext.receiver)?;
+        self.acquire_send(&richtext.receiver)?;
+        let msg = Some(proto::request::Msg::Rt(richtext));
+        let response = self.run_cmd(proto::Functions::FuncSendRichTxt.into(), msg)?;
+        Ok(SendResult::from(&response))
+    }
+
+    /** 发送拍一拍，roomid 格式不对时返回 `WcfError::InvalidReceiver` */
+    pub fn send_pat_msg(&self, roomid: String, wxid: String) -> Result<SendResult> {
+        validate::check_receiver(&roomid)?;
+        self.acquire_send(&roomid)?;
+        let msg = Some(proto::request::Msg::Pm(proto::PatMsg { roomid, wxid }));
+        let response = self.run_cmd(proto::Functions::FuncSendPatMsg.into(), msg)?;
+        Ok(SendResult::from(&response))
+    }
+
+    /// OCR，发送前检查文件存在且非空，不符合或路径不是 UTF-8 时返回 `WcfError::InvalidPath`
+    pub fn exec_ocr(&self, path: PathBuf) -> Result<Option<OcrMsg>> {
+        validate::check_file(&path, &[])?;
+        let msg = Some(proto::request::Msg::Str(validate::path_to_string(&path)?));
+        let response = self.run_cmd(proto::Functions::FuncExecOcr.into(), msg)?;
+        match response.msg {
+            Some(proto::response::Msg::Ocr(msg)) => Ok(Some(msg)),
+            _ => Ok(None),
+        }
+    }
+
+    /// OCR，spy 第一次识别时经常还没有结果，结果为空或 status 不为 0 时间隔 delay 重试，最多调用 attempts 次。
+    ///
+    /// 返回识别出的文字，所有尝试都没有结果时返回 Ok(None)
+    pub fn ocr_with_retry(&self, path: &Path, attempts: u32, delay: Duration) -> Result<Option<String>> {
+        for attempt in 1..=attempts {
+            if let Some(ocr) = self.exec_ocr(path.to_path_buf())? {
+                let text = ocr.result.trim();
+                if ocr.status == 0 && !text.is_empty() {
+                    return Ok(Some(text.to_string()));
+                }
+                trace!("ocr not ready, attempt={}, status={}, path={:?}", attempt, ocr.status, path);
+            }
+            if attempt < attempts {
+                thread::sleep(delay);
+            }
+        }
+        Ok(None)
+    }
+
+    /// 下载收到的图片到 dest_dir 并识别其中的文字，参考 download_image() 和 ocr_with_retry()
+    pub fn ocr_received_image(
+        &self,
+        msg: &WxMsg,
+        dest_dir: &Path,
+        timeout: Duration,
+        attempts: u32,
+        delay: Duration,
+    ) -> Result<Option<String>> {
+        let image = self.download_image(msg, dest_dir, timeout)?;
+        self.ocr_with_retry(&image, attempts, delay)
+    }
+
+    // whether a message with this MsgSvrID is still in any MSG shard
+    fn msg_exists(&self, id: u64) -> Result<bool> {
+        let sql = format!("SELECT 1 FROM MSG WHERE MsgSvrID = {} LIMIT 1", id);
+        for db in history::msg_db_names(self.get_db_names()?) {
+            if !self.exec_db_query(db, sql.clone())?.is_empty() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// 将消息依次转发给多个接收者，单个失败不影响其他接收者，返回每个接收者的结果。
+    ///
+    /// 先确认原消息仍在消息库中，不在时返回 `WcfError::NotFound`，不会转发给任何人
+    pub fn forward_msg_to_many(&self, id: u64, receivers: &[String]) -> Result<Vec<(String, Result<SendResult>)>> {
+        if !self.msg_exists(id)? {
+            return Err(WcfError::NotFound(format!("message {}", id)));
+        }
+        self.broadcast(receivers, &BroadcastOptions::default(), |receiver| self.forward_msg(id, receiver.to_string()))
+    }
+
+    /// 转发 talker（wxid 或群 id）中最新的一条消息，没有消息时返回 `WcfError::NotFound`
+    pub fn forward_last_from(&self, talker: &str, receiver: String) -> Result<SendResult> {
+        let filter = MessageFilter { talker: Some(talker.to_string()), limit: Some(1), ..Default::default() };
+        match self.query_messages(&filter)?.into_iter().next() {
+            Some(msg) => self.forward_msg(msg.msg_svr_id, receiver),
+            None => Err(WcfError::NotFound(format!("message from {}", talker))),
+        }
+    }
+
+    /** 转发消息 */
+    pub fn forward_msg(&self, id: u64, receiver: String) -> Result<SendResult> {
+        validate::check_receiver(&receiver)?;
+        self.acquire_send(&receiver)?;
+        let msg = Some(proto::request::Msg::Fm(proto::ForwardMsg { id, receiver }));
+        let response = self.run_cmd(proto::Functions::FuncForwardMsg.into(), msg)?;
+        Ok(SendResult::from(&response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_msg(id: u64, sender: &str) -> WxMsg {
+        WxMsg { id, r#type: 1, sender: sender.into(), content: format!("msg {}", id), ..Default::default() }
+    }
+
+    fn received_ids(events: &Receiver<Event>) -> Vec<u64> {
+        events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::MsgReceived(msg) => Some(msg.id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn contact_row(fields: &[(&str, &str)]) -> DbRow {
+        let fields = fields
+            .iter()
+            .map(|(column, value)| proto::DbField {
+                r#type: 3,
+                column: column.to_string(),
+                content: value.as_bytes().into(),
+            })
+            .collect();
+        DbRow { fields }
+    }
+
+    fn ranked(contacts: &[ContactInfo], query: &str) -> Vec<String> {
+        let mut contacts = contacts.to_vec();
+        contacts.sort_by_key(|contact| search_rank(contact, &query.to_lowercase()));
+        contacts.into_iter().map(|contact| contact.wxid).collect()
+    }
+
+    #[test]
+    fn search_rank_orders_fixture_contacts() {
+        let contacts: Vec<ContactInfo> = [
+            contact_row(&[("UserName", "wxid_other"), ("NickName", "李四"), ("PYInitial", "LS"), ("QuanPin", "lisi")]),
+            contact_row(&[("UserName", "wxid_prefix"), ("NickName", "zs_bot"), ("QuanPin", "zhangsanfeng")]),
+            contact_row(&[
+                ("UserName", "wxid_quanpin"),
+                ("NickName", "张三"),
+                ("PYInitial", "ZS"),
+                ("QuanPin", "zhangsan"),
+            ]),
+            contact_row(&[("UserName", "wxid_alias"), ("Alias", "ZS"), ("NickName", "阿三")]),
+            contact_row(&[("UserName", "wxid_nick"), ("NickName", "zs"), ("Remark", "")]),
+            contact_row(&[
+                ("UserName", "wxid_remark"),
+                ("NickName", "老张"),
+                ("Remark", "ZS"),
+                ("RemarkQuanPin", "zs"),
+            ]),
+        ]
+        .into_iter()
+        .map(ContactInfo::from)
+        .collect();
+
+        // exact remark, then exact nickname, alias and PYInitial in their original order, then prefixes
+        assert_eq!(
+            ranked(&contacts, "zs"),
+            ["wxid_remark", "wxid_quanpin", "wxid_alias", "wxid_nick", "wxid_prefix", "wxid_other"]
+        );
+        assert_eq!(search_rank(&contacts[4], "zs"), 1);
+        assert_eq!(search_rank(&contacts[1], "zs"), 2);
+        // exact QuanPin before a longer QuanPin
+        assert_eq!(
+            ranked(&contacts, "ZhangSan"),
+            ["wxid_quanpin", "wxid_prefix", "wxid_other", "wxid_alias", "wxid_nick", "wxid_remark"]
+        );
+        assert_eq!(search_rank(&contacts[2], "zhangsan"), 1);
+        assert_eq!(search_rank(&contacts[0], "zhangsan"), 3);
+    }
+
+    #[test]
+    fn search_contacts_rejects_empty_query() {
+        let client = WcfClient::new();
+        assert!(matches!(client.search_contacts(""), Err(WcfError::InvalidArgument(_))));
+        assert!(matches!(client.search_contacts(" \t"), Err(WcfError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn dedup_drops_repeated_ids() {
+        let client = WcfClient::new();
+        client.set_sync_dispatch(true);
+        let events = client.subscribe();
+
+        // off by default
+        client.dispatch_msg(text_msg(1, "wxid_a"));
+        client.dispatch_msg(text_msg(1, "wxid_a"));
+        assert_eq!(received_ids(&events), vec![1, 1]);
+
+        client.set_dedup(2);
+        for id in [1, 2, 1, 2, 3, 1] {
+            client.dispatch_msg(text_msg(id, "wxid_a"));
+        }
+        // 1 was evicted by 3, so it's delivered again
+        assert_eq!(received_ids(&events), vec![1, 2, 3, 1]);
+        assert_eq!(client.listen_stats().duplicates_dropped, 2);
+    }
+
+    #[test]
+    fn blocklist_changes_while_dispatching() {
+        let client = WcfClient::new();
+        client.set_sync_dispatch(true);
+        let total = 2000;
+        let events = client.subscribe_with_capacity(total as usize * 2 + 10);
+
+        let dispatcher = {
+            let client = client.clone();
+            thread::spawn(move || {
+                for id in 0..total {
+                    client.dispatch_msg(text_msg(id, "wxid_blocked"));
+                    let room_msg =
+                        WxMsg { is_group: true, roomid: "1@chatroom".into(), ..text_msg(id, "wxid_blocked") };
+                    client.dispatch_msg(room_msg);
+                }
+            })
+        };
+        let mutators: Vec<_> = (0..4)
+            .map(|i| {
+                let client = client.clone();
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        client.blocklist_add("wxid_blocked").unwrap();
+                        client.allowlist_set_rooms(Some(HashSet::from([format!("{}@chatroom", i)]))).unwrap();
+                        client.blocklist_remove("wxid_blocked").unwrap();
+                        client.allowlist_set_rooms(None).unwrap();
+                    }
+                })
+            })
+            .collect();
+        dispatcher.join().unwrap();
+        mutators.into_iter().for_each(|handle| handle.join().unwrap());
+
+        // every message was either delivered or filtered, none was lost or counted twice
+        let delivered = received_ids(&events).len() as u64;
+        assert_eq!(delivered + client.listen_stats().filtered, total * 2);
+        assert!(client.blocklist().is_empty());
+        assert_eq!(client.allowlist_rooms(), None);
+
+        // blocked in private and group chats once added
+        client.blocklist_add("wxid_blocked").unwrap();
+        client.dispatch_msg(text_msg(total, "wxid_blocked"));
+        client.dispatch_msg(WxMsg {
+            is_group: true,
+            roomid: "1@chatroom".into(),
+            ..text_msg(total + 1, "wxid_blocked")
+        });
+        client.dispatch_msg(text_msg(total + 2, "wxid_other"));
+        assert_eq!(received_ids(&events), vec![total + 2]);
+    }
+
+    #[test]
+    fn rate_limit_is_opt_in() {
+        let client = WcfClient::new();
+        assert_eq!(client.rate_limit(), RateLimitConfig::unlimited());
+        assert!((0..100).all(|_| client.acquire_send("wxid_a").is_ok()));
+
+        client.set_rate_limit(RateLimitConfig { mode: RateLimitMode::Reject, ..RateLimitConfig::conservative() });
+        assert!(client.acquire_send("wxid_a").is_ok());
+        assert!(matches!(client.acquire_send("wxid_a"), Err(WcfError::RateLimited { .. })));
+    }
+
+    #[test]
+    fn cancel_interrupts_rate_limit_wait() {
+        let client = WcfClient::new();
+        let per_receiver = Some(crate::wechatferry::Rate { count: 1, per: Duration::from_secs(60) });
+        client.set_rate_limit(RateLimitConfig { per_receiver, ..RateLimitConfig::unlimited() });
+        let cancel = Arc::new(AtomicBool::new(false));
+        let options = HumanizeOptions {
+            min_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            cancel: Some(cancel.clone()),
+            ..Default::default()
+        };
+        assert!(client.acquire_send_with("wxid_a", Some(&options)).is_ok());
+
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            cancel.store(true, Ordering::Relaxed);
+        });
+        let start = Instant::now();
+        assert!(matches!(client.acquire_send_with("wxid_a", Some(&options)), Err(WcfError::Cancelled)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn every_send_checks_the_receiver() {
+        let client = WcfClient::new();
+        let invalid =
+            |result: Result<SendResult>| matches!(result, Err(WcfError::InvalidReceiver(r)) if r == "bad receiver");
+        assert!(invalid(client.send_text("hi".into(), "bad receiver".into(), String::new())));
+        assert!(invalid(client.send_xml("<msg/>".into(), PathBuf::new(), "bad receiver".into(), 21)));
+        let richtext = RichText { receiver: "bad receiver".into(), ..Default::default() };
+        assert!(invalid(client.send_rich_text(richtext)));
+        assert!(invalid(client.send_pat_msg("bad receiver".into(), "wxid_a".into())));
+        assert!(invalid(client.forward_msg(1, "bad receiver".into())));
+        let humanized =
+            client.send_text_humanized("hi".into(), "bad receiver".into(), String::new(), &Default::default());
+        assert!(matches!(humanized, Err(WcfError::InvalidReceiver(_))));
+    }
+
+    #[test]
+    fn ocr_rejects_missing_file() {
+        let client = WcfClient::new();
+        let missing = std::env::temp_dir().join("wcf-ocr-missing.png");
+        assert!(matches!(client.exec_ocr(missing.clone()), Err(WcfError::InvalidPath { path, .. }) if path == missing));
+    }
+
+    #[test]
+    #[cfg(feature = "mock-sdk")]
+    fn retry_policy_resends_dropped_query() {
+        use crate::wechatferry::{MockSdkLoader, MockWcfServer};
+        let status = |status| proto::Response { func: 0, msg: Some(proto::response::Msg::Status(status)) };
+        let server = MockWcfServer::start(19500).unwrap();
+        let client = WcfClient::with_loader(MockSdkLoader::default());
+        let cmd_timeouts = CmdTimeouts { recv_timeout: Duration::from_millis(200), ..Default::default() };
+        let retry_policy = RetryPolicy { backoff: Duration::from_millis(10), ..Default::default() };
+        let options = InitOptions {
+            cmd_timeouts,
+            retry_policy: Some(retry_policy),
+            force: true,
+            auto_clean: false,
+            ..Default::default()
+        };
+        let _cleanup = client.init_with_options(server.port(), options).unwrap();
+        client.connect_cmd_socket().unwrap();
+        server.respond(proto::Functions::FuncIsLogin, status(1));
+        server.respond(proto::Functions::FuncSendTxt, status(1));
+
+        // the first request gets no answer, the retry on the same socket does
+        server.drop_next(proto::Functions::FuncIsLogin);
+        assert!(client.is_login().unwrap());
+        assert_eq!(server.requests().len(), 2);
+        assert!(client.state().cmd_connected);
+
+        // sending is not in the policy, a lost request is not sent twice
+        server.drop_next(proto::Functions::FuncSendTxt);
+        let result = client.send_text("hi".into(), "filehelper".into(), String::new());
+        assert!(matches!(result, Err(WcfError::Timeout)));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].func, i32::from(proto::Functions::FuncSendTxt));
+        assert!(!client.state().cmd_connected);
+    }
+}
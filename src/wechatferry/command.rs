@@ -0,0 +1,522 @@
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::{error, trace};
+
+use super::error::Result;
+use super::events::HandlerId;
+use super::{
+    export, ContactCache, Message, MsgType, PermissionLevel, Permissions, SearchHit, SearchOptions, WcfClient,
+};
+
+type CommandFn = Arc<dyn Fn(CommandCtx) -> Result<()> + Send + Sync>;
+
+/// 默认的命令前缀
+pub const DEFAULT_COMMAND_PREFIX: &str = "/";
+
+// hits listed in the reply of the search command
+const SEARCH_COMMAND_HITS: usize = 5;
+
+/// 命令可以在哪里使用
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CommandScope {
+    #[default]
+    Any,
+    GroupOnly,
+    PrivateOnly,
+}
+
+/// 注册命令时的选项
+#[derive(Clone, Debug, Default)]
+pub struct CommandOptions {
+    /// 显示在 help 中的说明
+    pub description: String,
+    pub scope: CommandScope,
+    /// 使用命令所需的最低权限等级，默认 User
+    pub min_level: PermissionLevel,
+}
+
+/// 按空白分割参数，空白包括全角空格，引号（"..."、'...'、“...”）中的内容作为一个参数
+pub fn split_args(args: &str) -> Vec<String> {
+    let mut parsed = vec![];
+    let mut current: Option<String> = None;
+    let mut quote: Option<char> = None;
+    for c in args.chars() {
+        match (quote, c) {
+            (Some(q), _) if q == c => quote = None,
+            (Some(_), _) => current.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                current.get_or_insert_with(String::new);
+            }
+            (None, '“') => {
+                quote = Some('”');
+                current.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => parsed.extend(current.take()),
+            (None, c) => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    // an unterminated quote just runs to the end
+    parsed.extend(current);
+    parsed
+}
+
+/// 命令处理函数的参数
+pub struct CommandCtx {
+    client: WcfClient,
+    permissions: Arc<Permissions>,
+    msg: Message,
+    name: String,
+    args: String,
+}
+
+impl CommandCtx {
+    /// 命令名，不含前缀
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 命令名之后的原始参数，去掉了首尾空白
+    pub fn args(&self) -> &str {
+        &self.args
+    }
+
+    /// 分割后的参数，见 `split_args()`
+    pub fn split_args(&self) -> Vec<String> {
+        split_args(&self.args)
+    }
+
+    pub fn sender(&self) -> &str {
+        self.msg.sender()
+    }
+
+    /// 群聊中的命令返回群 id
+    pub fn room(&self) -> Option<&str> {
+        self.msg.room_id()
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.msg
+    }
+
+    pub fn client(&self) -> &WcfClient {
+        &self.client
+    }
+
+    /// 路由使用的权限设置
+    pub fn permissions(&self) -> &Permissions {
+        &self.permissions
+    }
+
+    /// 回复到命令所在的会话，群聊中发到群里，私聊中发给发送者
+    pub fn reply(&self, text: impl Into<String>) -> Result<()> {
+        let receiver = self.room().unwrap_or(self.sender()).to_string();
+        self.client.send_text(text.into(), receiver, String::new())?;
+        Ok(())
+    }
+}
+
+// what dispatch() does with a parsed command
+enum Action {
+    Run(CommandFn),
+    Reply(String),
+    Ignore,
+}
+
+struct CommandEntry {
+    options: CommandOptions,
+    handler: CommandFn,
+}
+
+struct Commands {
+    prefix: String,
+    // sorted by name, so help lists them in order
+    commands: BTreeMap<String, CommandEntry>,
+    fallback: Option<CommandFn>,
+    permissions: Arc<Permissions>,
+    // None means ignoring denied commands silently
+    denial_reply: Option<String>,
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Commands {
+            prefix: DEFAULT_COMMAND_PREFIX.into(),
+            commands: BTreeMap::new(),
+            fallback: None,
+            permissions: Arc::default(),
+            denial_reply: None,
+        }
+    }
+}
+
+impl Commands {
+    fn help(&self) -> String {
+        let mut help = String::from("可用命令：");
+        for (name, entry) in &self.commands {
+            help.push_str(&format!("\n{}{}", self.prefix, name));
+            if !entry.options.description.is_empty() {
+                help.push_str(&format!(" - {}", entry.options.description));
+            }
+        }
+        help
+    }
+}
+
+/// 命令路由，start() 后把以前缀（默认 "/"）开头的文本消息分发给注册的命令，例如 `/weather 北京`。
+///
+/// 没有注册 help 命令时自动回复所有命令及说明；分发前按 CommandOptions.min_level 检查权限。
+/// 自己发送的消息也会被处理并视为 Owner，方便在手机上控制机器人
+pub struct CommandRouter {
+    client: WcfClient,
+    commands: Arc<Mutex<Commands>>,
+    handler: Mutex<Option<HandlerId>>,
+}
+
+impl CommandRouter {
+    pub fn new(client: WcfClient) -> Self {
+        CommandRouter { client, commands: Arc::default(), handler: Mutex::new(None) }
+    }
+
+    pub fn set_prefix(&self, prefix: impl Into<String>) {
+        self.commands.lock().prefix = prefix.into();
+    }
+
+    /// 设置分发前检查的权限，默认为空的 `Permissions::new()`，即所有人都是 User
+    pub fn set_permissions(&self, permissions: Arc<Permissions>) {
+        self.commands.lock().permissions = permissions;
+    }
+
+    pub fn permissions(&self) -> Arc<Permissions> {
+        self.commands.lock().permissions.clone()
+    }
+
+    /// 权限不足时的回复，None 表示不回复（默认）
+    pub fn set_denial_reply(&self, reply: Option<String>) {
+        self.commands.lock().denial_reply = reply;
+    }
+
+    /// 注册只有 Owner 可以使用的 grant 和 revoke 命令：`/grant wxid admin` 设置权限等级，`/revoke wxid` 恢复为 User
+    pub fn enable_permission_commands(&self) {
+        let owner_only = |description: &str| CommandOptions {
+            description: description.into(),
+            min_level: PermissionLevel::Owner,
+            ..Default::default()
+        };
+        self.command_with("grant", owner_only("设置权限：grant wxid owner|admin|user|banned"), |ctx| {
+            match ctx.split_args().as_slice() {
+                [wxid, level] => {
+                    let level = match level.parse::<PermissionLevel>() {
+                        Ok(level) => level,
+                        Err(_) => return ctx.reply(format!("未知的权限等级：{}", level)),
+                    };
+                    ctx.permissions().set_level(wxid, level)?;
+                    ctx.reply(format!("{} 的权限已设为 {}", wxid, level))
+                }
+                _ => ctx.reply("用法：grant wxid owner|admin|user|banned"),
+            }
+        });
+        self.command_with("revoke", owner_only("恢复为普通用户：revoke wxid"), |ctx| {
+            match ctx.split_args().as_slice() {
+                [wxid] => {
+                    ctx.permissions().remove(wxid)?;
+                    ctx.reply(format!("{} 的权限已恢复为 user", wxid))
+                }
+                _ => ctx.reply("用法：revoke wxid"),
+            }
+        });
+    }
+
+    /// 注册 Admin 可以使用的 search 命令：`/search 关键词 [关键词…]` 搜索所在会话的聊天记录，回复最近的几条及其发送者，
+    /// 关键词以【】标出，规则同 `search_messages()`
+    pub fn enable_search_command(&self) {
+        let options = CommandOptions {
+            description: "搜索本会话的聊天记录：search 关键词 [关键词…]".into(),
+            min_level: PermissionLevel::Admin,
+            ..Default::default()
+        };
+        self.command_with("search", options, |ctx| {
+            if ctx.args().is_empty() {
+                return ctx.reply("用法：search 关键词 [关键词…]");
+            }
+            // earlier searches, including this one, contain the terms too
+            let text = ctx.message().text().unwrap_or_default().trim();
+            let command = &text[..text.find(ctx.name()).unwrap_or_default() + ctx.name().len()];
+            let talker = ctx.room().unwrap_or(ctx.sender()).to_string();
+            let options = SearchOptions { talker: Some(talker), limit: SEARCH_COMMAND_HITS * 2, ..Default::default() };
+            let hits: Vec<SearchHit> = ctx
+                .client()
+                .search_messages(ctx.args(), &options)?
+                .into_iter()
+                .filter(|hit| !hit.content.trim_start().starts_with(command))
+                .take(SEARCH_COMMAND_HITS)
+                .collect();
+            if hits.is_empty() {
+                return ctx.reply("没有找到相关的聊天记录");
+            }
+            let contacts = ContactCache::new(ctx.client().clone());
+            let mut reply = format!("最近的 {} 条：", hits.len());
+            for hit in hits {
+                let name = contacts.display_name(&hit.sender)?;
+                reply += &format!("\n{} {}: {}", export::format_time(hit.create_time), name, hit.snippet);
+            }
+            ctx.reply(reply)
+        });
+    }
+
+    /// 注册命令，同名的命令会被替换
+    pub fn command<F>(&self, name: &str, handler: F)
+    where
+        F: Fn(CommandCtx) -> Result<()> + Send + Sync + 'static,
+    {
+        self.command_with(name, CommandOptions::default(), handler)
+    }
+
+    pub fn command_with<F>(&self, name: &str, options: CommandOptions, handler: F)
+    where
+        F: Fn(CommandCtx) -> Result<()> + Send + Sync + 'static,
+    {
+        let entry = CommandEntry { options, handler: Arc::new(handler) };
+        self.commands.lock().commands.insert(name.to_string(), entry);
+    }
+
+    /// 删除命令，命令不存在时返回 false
+    pub fn remove_command(&self, name: &str) -> bool {
+        self.commands.lock().commands.remove(name).is_some()
+    }
+
+    /// 处理未注册的命令，ctx.name() 为收到的命令名
+    pub fn fallback<F>(&self, handler: F)
+    where
+        F: Fn(CommandCtx) -> Result<()> + Send + Sync + 'static,
+    {
+        self.commands.lock().fallback = Some(Arc::new(handler));
+    }
+
+    /// 开始处理收到的消息，已经开始时不做任何事
+    pub fn start(&self) {
+        let mut handler = self.handler.lock();
+        if handler.is_some() {
+            return;
+        }
+        let (client, commands) = (self.client.clone(), self.commands.clone());
+        *handler = Some(self.client.on_message(move |msg| {
+            // most messages are not commands, check before copying them
+            if MsgType::from(msg.r#type as i32) != MsgType::Text
+                || !msg.content.trim_start().starts_with(commands.lock().prefix.as_str())
+            {
+                return;
+            }
+            if let Err(e) = Self::dispatch(&client, &commands, Message::from(msg.clone())) {
+                error!("command failed, error={}", e);
+            }
+        }));
+    }
+
+    /// 停止处理收到的消息，已注册的命令保留
+    pub fn stop(&self) {
+        if let Some(id) = self.handler.lock().take() {
+            self.client.remove_handler(id);
+        }
+    }
+
+    /// 处理一条消息，不需要 start()，返回是否为命令
+    pub fn handle(&self, msg: Message) -> Result<bool> {
+        Self::dispatch(&self.client, &self.commands, msg)
+    }
+
+    fn dispatch(client: &WcfClient, commands: &Mutex<Commands>, msg: Message) -> Result<bool> {
+        let (ctx, action, min_level, denial_reply) = {
+            let commands = commands.lock();
+            let text = match msg.text().map(str::trim).and_then(|text| text.strip_prefix(commands.prefix.as_str())) {
+                Some(text) => text,
+                None => return Ok(false),
+            };
+            let (name, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+            if name.is_empty() {
+                return Ok(false);
+            }
+            let (action, min_level) = match commands.commands.get(name) {
+                Some(entry) if !Self::in_scope(entry.options.scope, &msg) => {
+                    trace!("command used out of scope, name={}, sender={}", name, msg.sender());
+                    return Ok(true);
+                }
+                Some(entry) => (Action::Run(entry.handler.clone()), entry.options.min_level),
+                None if name == "help" => (Action::Reply(commands.help()), PermissionLevel::User),
+                None => (commands.fallback.clone().map_or(Action::Ignore, Action::Run), PermissionLevel::User),
+            };
+            let (name, args) = (name.to_string(), args.trim().to_string());
+            let ctx = CommandCtx { client: client.clone(), permissions: commands.permissions.clone(), msg, name, args };
+            (ctx, action, min_level, commands.denial_reply.clone())
+        };
+        // handlers run with the commands unlocked, so they can register or remove commands
+        if !ctx.permissions.allows(client, &ctx.msg, min_level)? {
+            trace!("command denied, name={}, sender={}", ctx.name, ctx.sender());
+            if let Some(reply) = denial_reply {
+                ctx.reply(reply)?;
+            }
+            return Ok(true);
+        }
+        match action {
+            Action::Run(handler) => handler(ctx)?,
+            Action::Reply(text) => ctx.reply(text)?,
+            Action::Ignore => trace!("unknown command, name={}", ctx.name),
+        }
+        Ok(true)
+    }
+
+    fn in_scope(scope: CommandScope, msg: &Message) -> bool {
+        match scope {
+            CommandScope::Any => true,
+            CommandScope::GroupOnly => msg.is_group(),
+            CommandScope::PrivateOnly => !msg.is_group(),
+        }
+    }
+}
+
+impl Drop for CommandRouter {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wechatferry::WxMsg;
+
+    fn private(text: &str) -> Message {
+        Message::from(WxMsg { r#type: 1, sender: "wxid_a".into(), content: text.into(), ..Default::default() })
+    }
+
+    fn group(text: &str) -> Message {
+        Message::from(WxMsg { is_group: true, roomid: "123@chatroom".into(), ..private(text).into_wx_msg() })
+    }
+
+    // (name, args) of the commands run
+    type Calls = Arc<Mutex<Vec<(String, String)>>>;
+
+    // a router whose commands record their calls instead of replying
+    fn router() -> (CommandRouter, Calls) {
+        let router = CommandRouter::new(WcfClient::new());
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let record = |calls: &Calls| {
+            let calls = calls.clone();
+            move |ctx: CommandCtx| {
+                calls.lock().push((ctx.name().to_string(), ctx.args().to_string()));
+                Ok(())
+            }
+        };
+        router.command_with(
+            "echo",
+            CommandOptions { description: "原样回复".into(), ..Default::default() },
+            record(&calls),
+        );
+        let group_only = CommandOptions { scope: CommandScope::GroupOnly, ..Default::default() };
+        router.command_with("kick", group_only, record(&calls));
+        let private_only = CommandOptions { scope: CommandScope::PrivateOnly, ..Default::default() };
+        router.command_with("secret", private_only, record(&calls));
+        (router, calls)
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn split_args_quotes() {
+        assert_eq!(split_args(r#"a "b c" d"#), strings(&["a", "b c", "d"]));
+        assert_eq!(split_args("'单 引号' x"), strings(&["单 引号", "x"]));
+        assert_eq!(split_args("“中 文” 参数"), strings(&["中 文", "参数"]));
+        assert_eq!(split_args(r#"say "" end"#), strings(&["say", "", "end"]));
+        assert_eq!(split_args(r#"a"b c"d"#), strings(&["ab cd"]));
+        // the other kind of quote is literal inside a quote
+        assert_eq!(split_args(r#""it's" '"x"'"#), strings(&["it's", r#""x""#]));
+    }
+
+    #[test]
+    fn split_args_whitespace() {
+        assert_eq!(split_args("北京\u{3000}上海\u{3000}\u{3000}广州"), strings(&["北京", "上海", "广州"]));
+        assert_eq!(split_args("  a\t b\nc  "), strings(&["a", "b", "c"]));
+        assert!(split_args(" \u{3000} ").is_empty());
+    }
+
+    #[test]
+    fn split_args_unterminated_quote() {
+        assert_eq!(split_args(r#"a "b c"#), strings(&["a", "b c"]));
+        assert_eq!(split_args("“中 文"), strings(&["中 文"]));
+        assert_eq!(split_args(r#"a ""#), strings(&["a", ""]));
+    }
+
+    #[test]
+    fn dispatch_runs_commands() {
+        let (router, calls) = router();
+        assert!(router.handle(private("/echo  你好  世界 ")).unwrap());
+        assert!(router.handle(private("  /echo")).unwrap());
+        assert!(router.handle(group("/kick wxid_b")).unwrap());
+        assert_eq!(
+            *calls.lock(),
+            [("echo".into(), "你好  世界".into()), ("echo".into(), String::new()), ("kick".into(), "wxid_b".into())]
+        );
+
+        router.set_prefix("！");
+        assert!(!router.handle(private("/echo a")).unwrap());
+        assert!(router.handle(private("！echo\u{3000}b")).unwrap());
+        assert_eq!(calls.lock().last(), Some(&("echo".into(), "b".into())));
+    }
+
+    #[test]
+    fn dispatch_ignores_non_commands() {
+        let (router, calls) = router();
+        assert!(!router.handle(private("echo a")).unwrap());
+        assert!(!router.handle(private("/")).unwrap());
+        assert!(!router.handle(private("/ echo a")).unwrap());
+        let image = Message::from(WxMsg { r#type: 3, content: "/echo a".into(), ..Default::default() });
+        assert!(!router.handle(image).unwrap());
+        // unknown commands without a fallback are still commands
+        assert!(router.handle(private("/unknown")).unwrap());
+        assert!(calls.lock().is_empty());
+    }
+
+    #[test]
+    fn dispatch_checks_scope() {
+        let (router, calls) = router();
+        assert!(router.handle(private("/kick wxid_b")).unwrap());
+        assert!(router.handle(group("/secret")).unwrap());
+        assert!(calls.lock().is_empty());
+        assert!(router.handle(private("/secret")).unwrap());
+        assert_eq!(calls.lock().len(), 1);
+    }
+
+    #[test]
+    fn fallback_gets_unknown_names() {
+        let (router, _) = router();
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let recorded = names.clone();
+        router.fallback(move |ctx| {
+            recorded.lock().push(ctx.name().to_string());
+            Ok(())
+        });
+        assert!(router.handle(private("/天气 北京")).unwrap());
+        assert_eq!(*names.lock(), ["天气"]);
+    }
+
+    #[test]
+    fn auto_help() {
+        let (router, calls) = router();
+        assert_eq!(router.commands.lock().help(), "可用命令：\n/echo - 原样回复\n/kick\n/secret");
+        router.set_prefix("!");
+        router.remove_command("kick");
+        assert_eq!(router.commands.lock().help(), "可用命令：\n!echo - 原样回复\n!secret");
+
+        // a registered help command replaces the automatic one
+        let recorded = calls.clone();
+        router.command("help", move |ctx| {
+            recorded.lock().push((ctx.name().to_string(), ctx.args().to_string()));
+            Ok(())
+        });
+        assert!(router.handle(private("!help echo")).unwrap());
+        assert_eq!(calls.lock().last(), Some(&("help".into(), "echo".into())));
+    }
+}
@@ -0,0 +1,140 @@
+use serde::Serialize;
+use std::fmt;
+
+use super::error::Result;
+
+/// 附带的 WeChatFerry 版本
+pub const WCF_VERSION: &str = "39.2.4";
+/// WCF_VERSION 支持的微信版本，其他版本的微信上发送等功能可能静默失败
+pub const SUPPORTED_WECHAT_VERSION: &str = "3.9.10.27";
+
+/// 版本检查的结论
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Verdict {
+    /// 已安装的微信版本与支持的版本一致
+    Compatible,
+    /// 版本不一致，init() 默认会拒绝注入
+    Mismatch,
+    /// 没有读到已安装的微信版本，例如非 windows 平台或微信未安装
+    Unknown,
+}
+
+/// `check_compatibility()` 的结果
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CompatibilityReport {
+    pub wcf_version: String,
+    pub supported_wechat_version: String,
+    /// 已安装的微信版本，例如 "3.9.10.27"
+    pub installed_wechat_version: Option<String>,
+    pub verdict: Verdict,
+}
+
+impl fmt::Display for CompatibilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let installed = self.installed_wechat_version.as_deref().unwrap_or("unknown");
+        match self.verdict {
+            Verdict::Compatible => write!(f, "wechat {} is supported by wcf {}", installed, self.wcf_version),
+            Verdict::Mismatch => write!(
+                f,
+                "wechat {} is installed, but wcf {} only supports wechat {}, please install that version",
+                installed, self.wcf_version, self.supported_wechat_version
+            ),
+            Verdict::Unknown => write!(
+                f,
+                "wechat version is unknown, wcf {} only supports wechat {}",
+                self.wcf_version, self.supported_wechat_version
+            ),
+        }
+    }
+}
+
+/// 读取已安装的微信版本，并与 SUPPORTED_WECHAT_VERSION 比较。
+///
+/// 版本依次从注册表和安装目录中 WeChatWin.dll 的文件版本中读取，都读不到时结论为 `Verdict::Unknown`
+pub fn check_compatibility() -> Result<CompatibilityReport> {
+    let installed_wechat_version = installed_wechat_version();
+    let verdict = match &installed_wechat_version {
+        Some(version) if version == SUPPORTED_WECHAT_VERSION => Verdict::Compatible,
+        Some(_) => Verdict::Mismatch,
+        None => Verdict::Unknown,
+    };
+    Ok(CompatibilityReport {
+        wcf_version: WCF_VERSION.into(),
+        supported_wechat_version: SUPPORTED_WECHAT_VERSION.into(),
+        installed_wechat_version,
+        verdict,
+    })
+}
+
+#[cfg(windows)]
+fn installed_wechat_version() -> Option<String> {
+    use std::path::PathBuf;
+    use winreg::enums::HKEY_CURRENT_USER;
+    use winreg::RegKey;
+
+    let key = RegKey::predef(HKEY_CURRENT_USER).open_subkey("Software\\Tencent\\WeChat").ok()?;
+    // e.g. 0x63090a1b for 3.9.10.27, the highest nibble is a flag
+    if let Ok(version) = key.get_value::<u32, _>("Version") {
+        return Some(format!(
+            "{}.{}.{}.{}",
+            (version >> 24) & 0x0f,
+            (version >> 16) & 0xff,
+            (version >> 8) & 0xff,
+            version & 0xff
+        ));
+    }
+    // the dll lives in a directory named after the version, e.g. "[3.9.10.27]\WeChatWin.dll"
+    let install_path = PathBuf::from(key.get_value::<String, _>("InstallPath").ok()?);
+    std::fs::read_dir(install_path)
+        .ok()?
+        .filter_map(|entry| file_version(&entry.ok()?.path().join("WeChatWin.dll")))
+        .max_by_key(|(numbers, _)| *numbers)
+        .map(|(_, version)| version)
+}
+
+// not on windows, there is no wechat to check
+#[cfg(not(windows))]
+fn installed_wechat_version() -> Option<String> {
+    None
+}
+
+// the fixed file version of a dll, as numbers for ordering and as text
+#[cfg(windows)]
+fn file_version(path: &std::path::Path) -> Option<([u16; 4], String)> {
+    use std::ffi::c_void;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW, VS_FIXEDFILEINFO,
+    };
+
+    if !path.is_file() {
+        return None;
+    }
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let root = [b'\\' as u16, 0];
+    unsafe {
+        let size = GetFileVersionInfoSizeW(wide.as_ptr(), ptr::null_mut());
+        if size == 0 {
+            return None;
+        }
+        let mut data = vec![0u8; size as usize];
+        if GetFileVersionInfoW(wide.as_ptr(), 0, size, data.as_mut_ptr() as *mut c_void) == 0 {
+            return None;
+        }
+        let mut info: *mut c_void = ptr::null_mut();
+        let mut len = 0;
+        if VerQueryValueW(data.as_ptr() as *const c_void, root.as_ptr(), &mut info, &mut len) == 0 || info.is_null() {
+            return None;
+        }
+        let info = &*(info as *const VS_FIXEDFILEINFO);
+        let numbers = [
+            (info.dwFileVersionMS >> 16) as u16,
+            info.dwFileVersionMS as u16,
+            (info.dwFileVersionLS >> 16) as u16,
+            info.dwFileVersionLS as u16,
+        ];
+        let version = numbers.map(|n| n.to_string()).join(".");
+        Some((numbers, version))
+    }
+}
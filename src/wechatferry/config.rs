@@ -0,0 +1,306 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
+
+use super::error::{Result, WcfError};
+use super::{
+    CmdTimeouts, EventQueueConfig, InitOptions, ListenFilter, MsgType, OverflowPolicy, Rate, RateLimitConfig,
+    RateLimitMode, WelcomeConfig,
+};
+
+/// 机器人的配置，见 `Config::load()`，所有字段都可以省略，省略时使用各设置的默认值
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// `[wcf]`，init 和事件队列的参数
+    pub wcf: WcfSection,
+    /// `[listen]`，接收消息的过滤条件
+    pub listen: ListenSection,
+    /// `[rate_limit]`，发送类接口的速率限制，省略时不限制
+    pub rate_limit: Option<RateLimitSection>,
+    /// `[webhook]`，WebhookForwarder 的参数，需要开启 http feature
+    pub webhook: Option<WebhookSection>,
+    /// `[[welcome]]`，新成员入群时的欢迎消息，可以有多个
+    pub welcome: Vec<WelcomeSection>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WcfSection {
+    /// cmd socket 的端口，msg socket 使用 port + 1，可以被环境变量 WCF_PORT 覆盖
+    pub port: u16,
+    /// 可以被环境变量 WCF_DEBUG 覆盖
+    pub debug: bool,
+    /// 同 InitOptions.sdk_path
+    pub sdk_path: Option<PathBuf>,
+    pub launch_wechat: bool,
+    /// 同 InitOptions.wechat_path，可以被环境变量 WCF_WECHAT_PATH 覆盖
+    pub wechat_path: Option<PathBuf>,
+    pub check_ports: bool,
+    pub force: bool,
+    /// cmd socket 的发送超时，单位为毫秒
+    pub send_timeout_ms: Option<u64>,
+    /// cmd socket 的接收超时，单位为毫秒
+    pub recv_timeout_ms: Option<u64>,
+    /// 事件队列的长度，见 set_event_queue()
+    pub event_queue_capacity: Option<usize>,
+    pub event_queue_overflow: Option<OverflowPolicy>,
+}
+
+impl Default for WcfSection {
+    fn default() -> Self {
+        WcfSection {
+            port: 10086,
+            debug: false,
+            sdk_path: None,
+            launch_wechat: false,
+            wechat_path: None,
+            check_ports: false,
+            force: false,
+            send_timeout_ms: None,
+            recv_timeout_ms: None,
+            event_queue_capacity: None,
+            event_queue_overflow: None,
+        }
+    }
+}
+
+/// 对应 ListenFilter
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ListenSection {
+    pub ignore_self: bool,
+    pub allowed_rooms: Option<Vec<String>>,
+    pub blocked_senders: Vec<String>,
+    /// WxMsg.type 的取值，例如 1 为文本
+    pub allowed_types: Option<Vec<i32>>,
+    pub report_filtered: bool,
+    /// 去重时记住的最近消息数，见 set_dedup()
+    pub dedup: Option<usize>,
+}
+
+/// 对应 Rate，per_secs 内最多 count 次
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct RateSection {
+    pub count: u32,
+    pub per_secs: u64,
+}
+
+/// 对应 RateLimitConfig，省略的字段使用 RateLimitConfig::conservative() 中的值
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitSection {
+    /// 为 true 时不做任何限制，忽略其他字段
+    pub unlimited: bool,
+    pub per_receiver: Option<RateSection>,
+    pub global: Option<RateSection>,
+    pub mode: Option<RateLimitMode>,
+}
+
+/// 对应 WebhookConfig，省略的字段使用 WebhookConfig::new() 中的值
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookSection {
+    /// 可以被环境变量 WCF_WEBHOOK_URL 覆盖
+    pub url: String,
+    pub bearer_token: Option<String>,
+    /// 可以被环境变量 WCF_WEBHOOK_SECRET 覆盖，避免把密钥写在文件中
+    pub secret: Option<String>,
+    pub max_retries: Option<u32>,
+    pub retry_delay_secs: Option<u64>,
+    pub queue_capacity: Option<usize>,
+    pub timeout_secs: Option<u64>,
+}
+
+/// 对应 WelcomeConfig，room 省略时为所有群的默认设置
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WelcomeSection {
+    pub room: Option<String>,
+    pub template: Option<String>,
+    pub mention_new_member: bool,
+    pub delay_secs: Option<u64>,
+}
+
+impl Config {
+    /// 从 TOML 文件加载配置，再用环境变量覆盖，然后检查各字段的取值。
+    ///
+    /// 不认识的字段只打印警告，便于新旧版本共用配置文件。取值不合法时返回 `WcfError::InvalidConfig`，携带字段名和文件路径
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let path = path.as_ref();
+        let load_error = |reason: String| WcfError::ConfigLoad { path: path.to_path_buf(), reason };
+        let text = std::fs::read_to_string(path).map_err(|e| load_error(e.to_string()))?;
+        let mut config: Config = serde_ignored::deserialize(toml::Deserializer::new(&text), |key| {
+            warn!("unknown key {} in config {:?}, ignored", key, path)
+        })
+        .map_err(|e| load_error(e.to_string()))?;
+        config.apply_env(path)?;
+        config.validate(path)?;
+        Ok(config)
+    }
+
+    // WCF_SDK_PATH is read by the loader itself when sdk_path is None
+    fn apply_env(&mut self, path: &Path) -> Result<()> {
+        if let Some(port) = env_var(path, "WCF_PORT")? {
+            self.wcf.port = port;
+        }
+        if let Some(debug) = env_var(path, "WCF_DEBUG")? {
+            self.wcf.debug = debug;
+        }
+        if let Some(wechat_path) = env_var(path, "WCF_WECHAT_PATH")? {
+            self.wcf.wechat_path = Some(wechat_path);
+        }
+        if let Some(url) = env_var(path, "WCF_WEBHOOK_URL")? {
+            self.webhook.get_or_insert_with(WebhookSection::default).url = url;
+        }
+        if let Some(secret) = env_var(path, "WCF_WEBHOOK_SECRET")? {
+            self.webhook.get_or_insert_with(WebhookSection::default).secret = Some(secret);
+        }
+        Ok(())
+    }
+
+    fn validate(&self, path: &Path) -> Result<()> {
+        let invalid = |key: &str, reason: &str| {
+            Err(WcfError::InvalidConfig { path: path.to_path_buf(), key: key.into(), reason: reason.into() })
+        };
+        // the msg socket listens on port + 1
+        if self.wcf.port == 0 || self.wcf.port == u16::MAX {
+            return invalid("wcf.port", "must be between 1 and 65534");
+        }
+        for (key, timeout) in
+            [("wcf.send_timeout_ms", self.wcf.send_timeout_ms), ("wcf.recv_timeout_ms", self.wcf.recv_timeout_ms)]
+        {
+            if timeout == Some(0) {
+                return invalid(key, "must be greater than 0");
+            }
+        }
+        if self.wcf.event_queue_capacity == Some(0) {
+            return invalid("wcf.event_queue_capacity", "must be greater than 0");
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            for (key, rate) in
+                [("rate_limit.per_receiver", rate_limit.per_receiver), ("rate_limit.global", rate_limit.global)]
+            {
+                if rate.is_some_and(|rate| rate.count > 0 && rate.per_secs == 0) {
+                    return invalid(&format!("{}.per_secs", key), "must be greater than 0");
+                }
+            }
+        }
+        if let Some(webhook) = &self.webhook {
+            if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+                return invalid("webhook.url", "must be an http:// or https:// url");
+            }
+        }
+        let mut rooms = HashSet::new();
+        for (i, welcome) in self.welcome.iter().enumerate() {
+            if welcome.template.as_deref().is_some_and(|template| template.trim().is_empty()) {
+                return invalid(&format!("welcome[{}].template", i), "must not be empty");
+            }
+            if !rooms.insert(welcome.room.as_deref()) {
+                return invalid(&format!("welcome[{}].room", i), "is configured more than once");
+            }
+        }
+        Ok(())
+    }
+
+    /// `init_with_options()` 的参数，其他字段为默认值
+    pub fn init_options(&self) -> InitOptions {
+        InitOptions {
+            debug: self.wcf.debug,
+            sdk_path: self.wcf.sdk_path.clone(),
+            launch_wechat: self.wcf.launch_wechat,
+            wechat_path: self.wcf.wechat_path.clone(),
+            check_ports: self.wcf.check_ports,
+            force: self.wcf.force,
+            cmd_timeouts: self.cmd_timeouts(),
+            ..Default::default()
+        }
+    }
+
+    pub fn cmd_timeouts(&self) -> CmdTimeouts {
+        let default = CmdTimeouts::default();
+        CmdTimeouts {
+            send_timeout: self.wcf.send_timeout_ms.map_or(default.send_timeout, Duration::from_millis),
+            recv_timeout: self.wcf.recv_timeout_ms.map_or(default.recv_timeout, Duration::from_millis),
+        }
+    }
+
+    pub fn event_queue(&self) -> EventQueueConfig {
+        let default = EventQueueConfig::default();
+        EventQueueConfig {
+            capacity: self.wcf.event_queue_capacity.unwrap_or(default.capacity),
+            overflow: self.wcf.event_queue_overflow.unwrap_or(default.overflow),
+        }
+    }
+
+    pub fn listen_filter(&self) -> ListenFilter {
+        let listen = &self.listen;
+        ListenFilter {
+            ignore_self: listen.ignore_self,
+            allowed_rooms: listen.allowed_rooms.as_ref().map(|rooms| rooms.iter().cloned().collect()),
+            blocked_senders: listen.blocked_senders.iter().cloned().collect(),
+            allowed_types: listen.allowed_types.as_ref().map(|types| types.iter().map(|&t| MsgType::from(t)).collect()),
+            report_filtered: listen.report_filtered,
+        }
+    }
+
+    pub fn rate_limit(&self) -> RateLimitConfig {
+        let Some(section) = &self.rate_limit else {
+            return RateLimitConfig::unlimited();
+        };
+        let default = RateLimitConfig::conservative();
+        if section.unlimited {
+            return RateLimitConfig::unlimited();
+        }
+        let rate = |rate: RateSection| Rate { count: rate.count, per: Duration::from_secs(rate.per_secs) };
+        RateLimitConfig {
+            per_receiver: section.per_receiver.map(rate).or(default.per_receiver),
+            global: section.global.map(rate).or(default.global),
+            mode: section.mode.unwrap_or(default.mode),
+        }
+    }
+
+    /// 各群的欢迎消息，room 为 None 的是所有群的默认设置
+    pub fn welcomes(&self) -> Vec<(Option<String>, WelcomeConfig)> {
+        let default = WelcomeConfig::default();
+        self.welcome
+            .iter()
+            .map(|section| {
+                let config = WelcomeConfig {
+                    template: section.template.clone().unwrap_or_else(|| default.template.clone()),
+                    mention_new_member: section.mention_new_member,
+                    delay: section.delay_secs.map_or(default.delay, Duration::from_secs),
+                };
+                (section.room.clone(), config)
+            })
+            .collect()
+    }
+
+    /// `[webhook]` 对应的 WebhookConfig，用于创建 WebhookForwarder，没有配置时返回 None
+    #[cfg(feature = "http")]
+    pub fn webhook_config(&self) -> Option<super::WebhookConfig> {
+        let section = self.webhook.as_ref()?;
+        let mut config = super::WebhookConfig::new(section.url.clone());
+        config.bearer_token = section.bearer_token.clone();
+        config.secret = section.secret.clone();
+        config.max_retries = section.max_retries.unwrap_or(config.max_retries);
+        config.retry_delay = section.retry_delay_secs.map_or(config.retry_delay, Duration::from_secs);
+        config.queue_capacity = section.queue_capacity.unwrap_or(config.queue_capacity);
+        config.timeout = section.timeout_secs.map_or(config.timeout, Duration::from_secs);
+        Some(config)
+    }
+}
+
+// parses the variable if it's set, the error names the variable and the config it overrides
+fn env_var<T: std::str::FromStr>(path: &Path, name: &str) -> Result<Option<T>> {
+    match std::env::var(name) {
+        Ok(value) => value.trim().parse().map(Some).map_err(|_| WcfError::InvalidConfig {
+            path: path.to_path_buf(),
+            key: name.into(),
+            reason: format!("invalid value {:?}", value),
+        }),
+        Err(_) => Ok(None),
+    }
+}
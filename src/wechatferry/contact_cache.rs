@@ -0,0 +1,83 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::error::Result;
+use super::{ContactInfo, WcfClient};
+
+/// `ContactCache` 默认的过期时间
+pub const DEFAULT_CONTACT_CACHE_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Default)]
+struct CacheState {
+    contacts: HashMap<String, ContactInfo>,
+    // set after a bulk load, all contacts are reloaded once it expires
+    loaded_at: Option<Instant>,
+}
+
+/// 联系人缓存，第一次使用时通过 query_all_contact_info() 批量加载，过期后重新加载，可以在多个线程中共享
+pub struct ContactCache {
+    client: WcfClient,
+    ttl: Duration,
+    state: Mutex<CacheState>,
+}
+
+impl ContactCache {
+    pub fn new(client: WcfClient) -> Self {
+        Self::with_ttl(client, DEFAULT_CONTACT_CACHE_TTL)
+    }
+
+    /// ttl 为缓存的过期时间，过期后下次使用时重新加载，改名等变化在此之后才会生效
+    pub fn with_ttl(client: WcfClient, ttl: Duration) -> Self {
+        ContactCache { client, ttl, state: Mutex::new(CacheState::default()) }
+    }
+
+    fn is_fresh(&self, state: &CacheState) -> bool {
+        state.loaded_at.is_some_and(|loaded_at| loaded_at.elapsed() < self.ttl)
+    }
+
+    /// 查询联系人，缓存中没有时单独查询一次，例如刚添加的好友
+    pub fn get(&self, wxid: &str) -> Result<Option<ContactInfo>> {
+        let fresh = {
+            let state = self.state.lock();
+            if let Some(contact) = state.contacts.get(wxid).filter(|_| self.is_fresh(&state)) {
+                return Ok(Some(contact.clone()));
+            }
+            self.is_fresh(&state)
+        };
+        if !fresh {
+            self.refresh_all()?;
+            if let Some(contact) = self.state.lock().contacts.get(wxid) {
+                return Ok(Some(contact.clone()));
+            }
+        }
+        let contact = self.client.query_contact_info(wxid.to_string())?;
+        if let Some(contact) = contact.as_ref() {
+            self.state.lock().contacts.insert(wxid.to_string(), contact.clone());
+        }
+        Ok(contact)
+    }
+
+    /// 显示的名字，依次使用备注、昵称、微信号，都没有时返回 wxid
+    pub fn display_name(&self, wxid: &str) -> Result<String> {
+        Ok(match self.get(wxid)? {
+            Some(contact) => contact.display_name().to_string(),
+            None => wxid.to_string(),
+        })
+    }
+
+    /// 移除一个联系人的缓存，下次使用时单独查询
+    pub fn invalidate(&self, wxid: &str) {
+        self.state.lock().contacts.remove(wxid);
+    }
+
+    /// 立即重新加载全部联系人
+    pub fn refresh_all(&self) -> Result<()> {
+        // query outside the lock, other threads keep reading the old entries meanwhile
+        let contacts = self.client.query_all_contact_info()?;
+        let mut state = self.state.lock();
+        state.contacts = contacts.into_iter().map(|contact| (contact.wxid.clone(), contact)).collect();
+        state.loaded_at = Some(Instant::now());
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use roxmltree::Document;
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use super::error::Result;
+use super::{ContactInfo, MsgType, WcfClient, WxMsg};
+
+// scene of adding a friend from a shared contact card
+const SCENE_CONTACT_CARD: i32 = 17;
+
+/// 名片消息（type 42），个人名片和公众号名片都可以解析
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContactCard {
+    /// 名片对应的用户，已是好友时为 wxid，否则为 v3（v3_xxx@stranger），公众号为 gh_xxx
+    pub username: String,
+    pub nickname: String,
+    /// 微信号
+    pub alias: String,
+    pub province: String,
+    pub city: String,
+    /// 头像，优先使用大头像
+    pub avatar_url: String,
+    /// antispamticket，添加好友时作为 v4
+    pub ticket: String,
+    /// 是否公众号名片
+    pub is_official: bool,
+}
+
+impl ContactCard {
+    /// 解析名片消息，不是 type 42 或 xml 无法解析时返回 None
+    pub fn parse(msg: &WxMsg) -> Option<ContactCard> {
+        if MsgType::from(msg.r#type as i32) != MsgType::Card {
+            return None;
+        }
+        // group messages may be prefixed with "roomid:\n"
+        let xml = &msg.content[msg.content.find('<')?..];
+        let doc = match Document::parse(xml) {
+            Ok(doc) => doc,
+            Err(e) => {
+                trace!("invalid contact card xml, error={}, content={}", e, msg.content);
+                return None;
+            }
+        };
+        let root = doc.root_element();
+        let attr = |name| root.attribute(name).unwrap_or_default().trim().to_string();
+        let username = attr("username");
+        if username.is_empty() {
+            trace!("contact card without username, content={}", msg.content);
+            return None;
+        }
+        let avatar_url = Some(attr("bigheadimgurl")).filter(|url| !url.is_empty());
+        // official accounts carry certflag and brand attributes, personal cards don't
+        let certified = root.attribute("certflag").and_then(|flag| flag.parse::<i32>().ok()).unwrap_or_default() != 0;
+        Some(ContactCard {
+            is_official: username.starts_with("gh_") || certified,
+            username,
+            nickname: attr("nickname"),
+            alias: attr("alias"),
+            province: attr("province"),
+            city: attr("city"),
+            avatar_url: avatar_url.unwrap_or_else(|| attr("smallheadimgurl")),
+            ticket: attr("antispamticket"),
+        })
+    }
+
+    /// 通过 accept_new_friend() 添加名片中的用户为好友
+    pub fn add_friend_with(&self, client: &WcfClient) -> Result<bool> {
+        client.accept_new_friend(self.username.clone(), self.ticket.clone(), SCENE_CONTACT_CARD)
+    }
+
+    /// 查询名片中用户的联系人信息，不是好友时通常返回 None
+    pub fn query_contact_with(&self, client: &WcfClient) -> Result<Option<ContactInfo>> {
+        client.query_contact_info(self.username.clone())
+    }
+
+    /// 同 add_friend_with()，使用默认客户端
+    pub fn add_friend(&self) -> Result<bool> {
+        self.add_friend_with(super::default_client())
+    }
+
+    /// 同 query_contact_with()，使用默认客户端
+    pub fn query_contact(&self) -> Result<Option<ContactInfo>> {
+        self.query_contact_with(super::default_client())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card_msg(content: &str) -> WxMsg {
+        WxMsg { r#type: 42, content: content.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn personal_card() {
+        let content = r#"<?xml version="1.0"?>
+<msg bigheadimgurl="https://wx.qlogo.cn/big/0" smallheadimgurl="https://wx.qlogo.cn/small/132" username="v3_020b3826fd0301000000000000@stranger" nickname="张三 &amp; 李四" fullpy="zhangsan" shortpy="" alias="zhangsan_88" imagestatus="3" scene="17" province="广东" city="深圳" sign="" sex="1" certflag="0" certinfo="" brandIconUrl="" brandHomeUrl="" brandSubscriptConfigUrl="" brandFlags="0" regionCode="CN_Guangdong_Shenzhen" antispamticket="v4_000b708f0b040000010000000000" />"#;
+        let expected = ContactCard {
+            username: "v3_020b3826fd0301000000000000@stranger".into(),
+            nickname: "张三 & 李四".into(),
+            alias: "zhangsan_88".into(),
+            province: "广东".into(),
+            city: "深圳".into(),
+            avatar_url: "https://wx.qlogo.cn/big/0".into(),
+            ticket: "v4_000b708f0b040000010000000000".into(),
+            is_official: false,
+        };
+        assert_eq!(ContactCard::parse(&card_msg(content)), Some(expected));
+    }
+
+    #[test]
+    fn official_card_in_group() {
+        let content =
+            "123@chatroom:\n<msg smallheadimgurl=\"https://wx.qlogo.cn/small/0\" username=\"gh_1234567890ab\" \
+                       nickname=\"公众号\" certflag=\"24\" />";
+        let card = ContactCard::parse(&card_msg(content)).unwrap();
+        assert_eq!(card.username, "gh_1234567890ab");
+        assert_eq!(card.avatar_url, "https://wx.qlogo.cn/small/0");
+        assert!(card.is_official);
+    }
+
+    #[test]
+    fn not_a_card() {
+        assert_eq!(ContactCard::parse(&card_msg("<msg nickname=\"no username\" />")), None);
+        assert_eq!(ContactCard::parse(&card_msg("<msg username=")), None);
+        let text = WxMsg { r#type: 1, ..card_msg("<msg username=\"wxid_a\" />") };
+        assert_eq!(ContactCard::parse(&text), None);
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+use super::ContactInfo;
+
+/// 联系人分类，由 wxid 的格式和 Contact 表的 Type、VerifyFlag 判断
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ContactKind {
+    Friend,
+    /// 公众号、服务号
+    OfficialAccount,
+    ChatRoom,
+    /// 文件传输助手、微信团队等系统账号
+    System,
+    /// 群里的陌生人、已删除的好友等
+    Unknown,
+}
+
+// built-in accounts of the PC client, none of them is a real contact
+const SYSTEM_WXIDS: [&str; 24] = [
+    "filehelper",
+    "fmessage",
+    "floatbottle",
+    "medianote",
+    "newsapp",
+    "weixin",
+    "qqmail",
+    "qmessage",
+    "tmessage",
+    "qqsync",
+    "qqfriend",
+    "weibo",
+    "lbsapp",
+    "shakeapp",
+    "blogapp",
+    "masssendapp",
+    "feedsapp",
+    "voipapp",
+    "voicevoipapp",
+    "voiceinputapp",
+    "notifymessage",
+    "officialaccounts",
+    "helper_entry",
+    "mphelper",
+];
+
+// Type bit set for contacts in the address book
+const CONTACT_TYPE_FRIEND: i32 = 0x01;
+
+impl ContactKind {
+    /// 只根据 wxid 判断，无法区分好友和陌生人，此时返回 Unknown
+    pub fn from_wxid(wxid: &str) -> ContactKind {
+        if wxid.ends_with("@chatroom") {
+            ContactKind::ChatRoom
+        } else if wxid.starts_with("gh_") {
+            ContactKind::OfficialAccount
+        } else if SYSTEM_WXIDS.contains(&wxid) {
+            ContactKind::System
+        } else {
+            ContactKind::Unknown
+        }
+    }
+
+    pub fn of(contact: &ContactInfo) -> ContactKind {
+        match ContactKind::from_wxid(&contact.wxid) {
+            // official accounts without the gh_ prefix still carry a verify flag
+            ContactKind::Unknown if contact.verify_flag != 0 => ContactKind::OfficialAccount,
+            ContactKind::Unknown if contact.contact_type & CONTACT_TYPE_FRIEND != 0 && contact.del_flag == 0 => {
+                ContactKind::Friend
+            }
+            kind => kind,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact(wxid: &str, contact_type: i32, verify_flag: i32) -> ContactInfo {
+        ContactInfo { wxid: wxid.into(), contact_type, verify_flag, ..Default::default() }
+    }
+
+    #[test]
+    fn from_wxid() {
+        assert_eq!(ContactKind::from_wxid("12345678901@chatroom"), ContactKind::ChatRoom);
+        assert_eq!(ContactKind::from_wxid("gh_1234567890ab"), ContactKind::OfficialAccount);
+        assert_eq!(ContactKind::from_wxid("filehelper"), ContactKind::System);
+        assert_eq!(ContactKind::from_wxid("fmessage"), ContactKind::System);
+        // friends and strangers look the same, whatever the prefix
+        assert_eq!(ContactKind::from_wxid("wxid_abcdefghijkl12"), ContactKind::Unknown);
+        assert_eq!(ContactKind::from_wxid("zhangsan_88"), ContactKind::Unknown);
+        assert_eq!(ContactKind::from_wxid("1688850000000000@openim"), ContactKind::Unknown);
+        // only a suffix or prefix counts
+        assert_eq!(ContactKind::from_wxid("wxid_chatroom"), ContactKind::Unknown);
+        assert_eq!(ContactKind::from_wxid("wxid_gh_a"), ContactKind::Unknown);
+    }
+
+    #[test]
+    fn of_contact() {
+        assert_eq!(ContactKind::of(&contact("wxid_abcdefghijkl12", 3, 0)), ContactKind::Friend);
+        assert_eq!(ContactKind::of(&contact("wxid_abcdefghijkl12", 0, 0)), ContactKind::Unknown);
+        // enterprise wechat contacts added as friends
+        assert_eq!(ContactKind::of(&contact("1688850000000000@openim", 3, 0)), ContactKind::Friend);
+        assert_eq!(ContactKind::of(&contact("1688850000000000@openim", 0, 0)), ContactKind::Unknown);
+        let deleted = ContactInfo { del_flag: 1, ..contact("wxid_abcdefghijkl12", 3, 0) };
+        assert_eq!(ContactKind::of(&deleted), ContactKind::Unknown);
+        // verified accounts without the gh_ prefix
+        assert_eq!(ContactKind::of(&contact("cmbchina", 3, 24)), ContactKind::OfficialAccount);
+        assert_eq!(ContactKind::of(&contact("12345678901@chatroom", 2, 0)), ContactKind::ChatRoom);
+        assert_eq!(ContactKind::of(&contact("filehelper", 3, 0)), ContactKind::System);
+    }
+}
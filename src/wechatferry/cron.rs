@@ -0,0 +1,276 @@
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDateTime, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::{Result, WcfError};
+
+// how far next_after() looks ahead, enough for "29 2 *" style yearly expressions
+const MAX_SEARCH_DAYS: i64 = 366 * 8;
+
+// one field as a bit set, bit n set when value n matches
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Field {
+    bits: u64,
+    // false for "*" and "*/n", used by the day of month / day of week rule
+    restricted: bool,
+}
+
+impl Field {
+    fn parse(field: &str, min: u32, max: u32) -> Result<Field> {
+        let invalid = || WcfError::InvalidArgument(format!("invalid cron field: {}", field));
+        let mut bits = 0u64;
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+                None => (part, 1),
+            };
+            let (start, end) = match range {
+                "*" => (min, max),
+                _ => match range.split_once('-') {
+                    Some((start, end)) => (start.parse().map_err(|_| invalid())?, end.parse().map_err(|_| invalid())?),
+                    // "5/10" means from 5 to the end, every 10
+                    None => {
+                        let start = range.parse().map_err(|_| invalid())?;
+                        (start, if part.contains('/') { max } else { start })
+                    }
+                },
+            };
+            if step == 0 || start < min || end > max || start > end {
+                return Err(invalid());
+            }
+            for value in (start..=end).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        Ok(Field { bits, restricted: !field.starts_with('*') })
+    }
+
+    fn matches(&self, value: u32) -> bool {
+        self.bits & (1 << value) != 0
+    }
+}
+
+/// 标准的 5 段 cron 表达式：分 时 日 月 星期，支持 `*`、`,`、`-` 和 `/`，星期中 0 和 7 都表示周日，
+/// 例如 `30 9 * * 1-5` 为工作日 9:30。
+///
+/// 按 next_after() 参数所在的时区计算，通常为本地时间；夏令时跳过的时间在跳过后的第一分钟执行，重复的时间只执行一次
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronSchedule {
+    source: String,
+    minutes: Field,
+    hours: Field,
+    days: Field,
+    months: Field,
+    weekdays: Field,
+}
+
+impl FromStr for CronSchedule {
+    type Err = WcfError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields.as_slice() else {
+            return Err(WcfError::InvalidArgument(format!("cron expression needs 5 fields: {}", s)));
+        };
+        let mut weekdays = Field::parse(weekdays, 0, 7)?;
+        if weekdays.matches(7) {
+            weekdays.bits |= 1;
+        }
+        Ok(CronSchedule {
+            source: fields.join(" "),
+            minutes: Field::parse(minutes, 0, 59)?,
+            hours: Field::parse(hours, 0, 23)?,
+            days: Field::parse(days, 1, 31)?,
+            months: Field::parse(months, 1, 12)?,
+            weekdays,
+        })
+    }
+}
+
+impl TryFrom<String> for CronSchedule {
+    type Error = WcfError;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<CronSchedule> for String {
+    fn from(schedule: CronSchedule) -> Self {
+        schedule.source
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl CronSchedule {
+    // like cron, when both day fields are restricted either one matching is enough
+    fn matches_day(&self, time: &NaiveDateTime) -> bool {
+        let day = self.days.matches(time.day());
+        let weekday = self.weekdays.matches(time.weekday().num_days_from_sunday());
+        match (self.days.restricted, self.weekdays.restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// after 之后（不含）下一次执行的时间，表达式永远不会匹配时（例如 2 月 30 日）返回 None
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let timezone = after.timezone();
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let first_day = start.date();
+        for day_offset in 0..MAX_SEARCH_DAYS {
+            let midnight = (first_day + Duration::days(day_offset)).and_hms_opt(0, 0, 0)?;
+            if !self.months.matches(midnight.month()) || !self.matches_day(&midnight) {
+                continue;
+            }
+            for hour in (0..24).filter(|hour| self.hours.matches(*hour)) {
+                for minute in (0..60).filter(|minute| self.minutes.matches(*minute)) {
+                    let time = midnight + Duration::minutes((hour * 60 + minute) as i64);
+                    if time < start {
+                        continue;
+                    }
+                    if let Some(local) = to_local(&timezone, time) {
+                        // an ambiguous time maps to its first occurrence, so skip it if that is already past
+                        if local > *after {
+                            return Some(local);
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+// a time skipped by a DST change maps to the first minute after the gap
+fn to_local<Tz: TimeZone>(timezone: &Tz, time: NaiveDateTime) -> Option<DateTime<Tz>> {
+    let mut time = time;
+    for _ in 0..24 * 60 {
+        match timezone.from_local_datetime(&time) {
+            LocalResult::Single(local) => return Some(local),
+            LocalResult::Ambiguous(earliest, _) => return Some(earliest),
+            LocalResult::None => time += Duration::minutes(1),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, NaiveDate, Utc};
+
+    // central european time in 2024: +01:00, +02:00 from 03-31 01:00 UTC until 10-27 01:00 UTC
+    #[derive(Clone, Copy, Debug)]
+    struct Cet2024;
+
+    impl Cet2024 {
+        fn utc(month: u32, day: u32, hour: u32) -> NaiveDateTime {
+            NaiveDate::from_ymd_opt(2024, month, day).unwrap().and_hms_opt(hour, 0, 0).unwrap()
+        }
+
+        fn offset(summer: bool) -> FixedOffset {
+            FixedOffset::east_opt(if summer { 2 * 3600 } else { 3600 }).unwrap()
+        }
+    }
+
+    impl TimeZone for Cet2024 {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Cet2024
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(12, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let candidates: Vec<FixedOffset> = [false, true]
+                .into_iter()
+                .map(Cet2024::offset)
+                .filter(|offset| self.offset_from_utc_datetime(&(*local - *offset)) == *offset)
+                .collect();
+            match candidates.as_slice() {
+                [] => LocalResult::None,
+                [offset] => LocalResult::Single(*offset),
+                [standard, summer] => LocalResult::Ambiguous(*summer, *standard),
+                _ => unreachable!(),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            Cet2024::offset(*utc >= Cet2024::utc(3, 31, 1) && *utc < Cet2024::utc(10, 27, 1))
+        }
+    }
+
+    fn at(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Cet2024> {
+        Cet2024.with_ymd_and_hms(2024, month, day, hour, minute, 0).earliest().unwrap()
+    }
+
+    fn next(expression: &str, after: DateTime<Cet2024>) -> DateTime<Utc> {
+        let schedule: CronSchedule = expression.parse().unwrap();
+        schedule.next_after(&after).unwrap().with_timezone(&Utc)
+    }
+
+    fn utc(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, month, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn parse_fields() {
+        let schedule: CronSchedule = "30 9 * * 1-5".parse().unwrap();
+        assert_eq!(schedule.to_string(), "30 9 * * 1-5");
+        assert!(schedule.weekdays.matches(1) && schedule.weekdays.matches(5) && !schedule.weekdays.matches(0));
+        let sunday: CronSchedule = "0 0 * * 7".parse().unwrap();
+        assert!(sunday.weekdays.matches(0));
+        for invalid in ["* * * *", "60 * * * *", "*/0 * * * *", "5-1 * * * *", "a * * * *"] {
+            assert!(invalid.parse::<CronSchedule>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn day_of_month_or_weekday() {
+        // the 1st or any monday, 2024-07-01 is a monday
+        assert_eq!(next("0 9 1 * 1", at(7, 2, 0, 0)), next("0 9 * * 1", at(7, 2, 0, 0)));
+        assert_eq!(next("0 9 1 * 1", at(7, 27, 0, 0)), utc(7, 29, 7, 0));
+        assert_eq!(next("0 9 1 * 1", at(7, 30, 0, 0)), utc(8, 1, 7, 0));
+        assert!("0 0 30 2 *".parse::<CronSchedule>().unwrap().next_after(&at(1, 1, 0, 0)).is_none());
+    }
+
+    #[test]
+    fn daily_across_spring_forward() {
+        // 9:00 is +01:00 before the change and +02:00 after it
+        assert_eq!(next("0 9 * * *", at(3, 30, 10, 0)), utc(3, 31, 7, 0));
+        assert_eq!(next("0 9 * * *", at(3, 31, 9, 0)), utc(4, 1, 7, 0));
+        assert_eq!(next("0 9 * * *", at(3, 30, 8, 0)), utc(3, 30, 8, 0));
+    }
+
+    #[test]
+    fn skipped_time_runs_after_the_gap() {
+        // 02:30 does not exist on 03-31, clocks jump from 02:00 to 03:00
+        assert_eq!(next("30 2 * * *", at(3, 30, 12, 0)), utc(3, 31, 1, 0));
+        assert_eq!(next("30 2 * * *", at(3, 31, 3, 0)), utc(4, 1, 0, 30));
+    }
+
+    #[test]
+    fn repeated_time_runs_once() {
+        // 02:30 happens twice on 10-27, at 00:30 and 01:30 UTC
+        assert_eq!(next("30 2 * * *", at(10, 26, 12, 0)), utc(10, 27, 0, 30));
+        let first = "30 2 * * *".parse::<CronSchedule>().unwrap().next_after(&at(10, 26, 12, 0)).unwrap();
+        assert_eq!(next("30 2 * * *", first), utc(10, 28, 1, 30));
+        // hourly jobs skip the repeated 02:00 too, the next run is 03:00 +01:00
+        assert_eq!(next("0 * * * *", at(10, 27, 2, 0)), utc(10, 27, 2, 0));
+    }
+}
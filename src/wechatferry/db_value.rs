@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{sql, DbField, DbRow};
+
+/// 查询结果中一个字段的值，按 DbField.type（SQLite 的类型）解码
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DbValue {
+    Text(String),
+    Integer(i64),
+    Real(f64),
+    Blob(Vec<u8>),
+    Null,
+}
+
+/// exec_db_query_typed() 返回的一行，以列名为键
+pub type TypedDbRow = HashMap<String, DbValue>;
+
+// DbField.type, same as SQLITE_INTEGER ... SQLITE_NULL
+const SQLITE_INTEGER: i32 = 1;
+const SQLITE_FLOAT: i32 = 2;
+const SQLITE_TEXT: i32 = 3;
+const SQLITE_BLOB: i32 = 4;
+const SQLITE_NULL: i32 = 5;
+
+impl DbValue {
+    /// 按 SQLite 类型解码，整数为小端序的原始字节，浮点数为文本
+    pub fn decode(field_type: i32, content: Vec<u8>) -> DbValue {
+        match field_type {
+            SQLITE_INTEGER => DbValue::Integer(sql::decode_int(&content)),
+            SQLITE_FLOAT => match std::str::from_utf8(&content).ok().and_then(|s| s.trim().parse().ok()) {
+                Some(value) => DbValue::Real(value),
+                None if content.len() == 8 => DbValue::Real(f64::from_le_bytes(content.try_into().unwrap())),
+                None => DbValue::Blob(content),
+            },
+            SQLITE_TEXT => match String::from_utf8(content) {
+                Ok(text) => DbValue::Text(text),
+                Err(e) => DbValue::Blob(e.into_bytes()),
+            },
+            SQLITE_BLOB => DbValue::Blob(content),
+            SQLITE_NULL => DbValue::Null,
+            // unknown types are kept as raw bytes
+            _ => DbValue::Blob(content),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, DbValue::Null)
+    }
+
+    /// 整数值，文本会尝试解析
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            DbValue::Integer(value) => Some(*value),
+            DbValue::Real(value) => Some(*value as i64),
+            DbValue::Text(text) => text.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            DbValue::Integer(value) => Some(*value as f64),
+            DbValue::Real(value) => Some(*value),
+            DbValue::Text(text) => text.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            DbValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// 文本或 blob 的原始字节，例如 protobuf 编码的 RoomData
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            DbValue::Text(text) => Some(text.as_bytes()),
+            DbValue::Blob(blob) => Some(blob),
+            _ => None,
+        }
+    }
+
+    /// 转为字符串，数字会格式化，blob 不是 utf8 或值为 NULL 时返回 None
+    pub fn into_string(self) -> Option<String> {
+        match self {
+            DbValue::Text(text) => Some(text),
+            DbValue::Integer(value) => Some(value.to_string()),
+            DbValue::Real(value) => Some(value.to_string()),
+            DbValue::Blob(blob) => String::from_utf8(blob).ok(),
+            DbValue::Null => None,
+        }
+    }
+
+    // the usual shape of optional text columns, where '' means unset
+    pub(crate) fn into_non_empty(self) -> Option<String> {
+        self.into_string().filter(|s| !s.is_empty())
+    }
+}
+
+impl From<DbField> for DbValue {
+    fn from(field: DbField) -> Self {
+        DbValue::decode(field.r#type, field.content)
+    }
+}
+
+/// 逐个解码一行中的字段，保留列的顺序
+pub(crate) fn typed_fields(row: DbRow) -> impl Iterator<Item = (String, DbValue)> {
+    row.fields.into_iter().map(|field| (field.column, DbValue::decode(field.r#type, field.content)))
+}
+
+pub(crate) fn typed_row(row: DbRow) -> TypedDbRow {
+    typed_fields(row).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_each_type() {
+        let int = DbValue::decode(SQLITE_INTEGER, (-42i64).to_le_bytes().to_vec());
+        assert_eq!(int, DbValue::Integer(-42));
+        assert_eq!(DbValue::decode(SQLITE_FLOAT, b"3.5".to_vec()), DbValue::Real(3.5));
+        assert_eq!(DbValue::decode(SQLITE_FLOAT, 2.25f64.to_le_bytes().to_vec()), DbValue::Real(2.25));
+        assert_eq!(DbValue::decode(SQLITE_TEXT, "张三".as_bytes().to_vec()), DbValue::Text("张三".into()));
+        assert_eq!(DbValue::decode(SQLITE_BLOB, vec![0, 1, 2]), DbValue::Blob(vec![0, 1, 2]));
+        assert_eq!(DbValue::decode(SQLITE_NULL, vec![]), DbValue::Null);
+    }
+
+    #[test]
+    fn undecodable_values_are_blobs() {
+        assert_eq!(DbValue::decode(SQLITE_TEXT, vec![0xff, 0xfe]), DbValue::Blob(vec![0xff, 0xfe]));
+        assert_eq!(DbValue::decode(SQLITE_FLOAT, b"abc".to_vec()), DbValue::Blob(b"abc".to_vec()));
+        assert_eq!(DbValue::decode(99, vec![7]), DbValue::Blob(vec![7]));
+    }
+
+    #[test]
+    fn short_and_oversized_ints() {
+        // missing high bytes are zero
+        assert_eq!(sql::decode_int(&[]), 0);
+        assert_eq!(sql::decode_int(&[0x34, 0x12]), 0x1234);
+        assert_eq!(DbValue::decode(SQLITE_INTEGER, vec![0xff]), DbValue::Integer(0xff));
+        // bytes after the eighth are ignored
+        let mut bytes = 1_700_000_000_000i64.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[0xff, 0xff]);
+        assert_eq!(sql::decode_int(&bytes), 1_700_000_000_000);
+        assert_eq!(sql::decode_int(&i64::MIN.to_le_bytes()), i64::MIN);
+    }
+
+    #[test]
+    fn typed_row_by_column() {
+        let field =
+            |r#type, column: &str, content: &[u8]| DbField { r#type, column: column.into(), content: content.into() };
+        let row = DbRow {
+            fields: vec![field(SQLITE_TEXT, "UserName", b"wxid_a"), field(SQLITE_INTEGER, "Type", &3i64.to_le_bytes())],
+        };
+        let row = typed_row(row);
+        assert_eq!(row["UserName"].as_str(), Some("wxid_a"));
+        assert_eq!(row["Type"].as_i64(), Some(3));
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::{HashSet, VecDeque};
+
+/// 最近收到的消息 id，超过容量时丢弃最早的，用于过滤重连后重复推送的消息
+pub(crate) struct RecentIds {
+    capacity: usize,
+    order: VecDeque<u64>,
+    seen: HashSet<u64>,
+}
+
+impl RecentIds {
+    pub(crate) fn new(capacity: usize) -> Self {
+        RecentIds { capacity, order: VecDeque::with_capacity(capacity), seen: HashSet::with_capacity(capacity) }
+    }
+
+    /// 记录 id，已经见过时返回 false
+    pub(crate) fn insert(&mut self, id: u64) -> bool {
+        if !self.seen.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicates_are_rejected() {
+        let mut recent = RecentIds::new(3);
+        assert!(recent.insert(1));
+        assert!(recent.insert(2));
+        assert!(!recent.insert(1));
+        assert!(!recent.insert(2));
+    }
+
+    #[test]
+    fn oldest_is_evicted() {
+        let mut recent = RecentIds::new(3);
+        for id in 1..=4 {
+            assert!(recent.insert(id));
+        }
+        // 1 fell out of the window, 2..=4 are still remembered
+        assert!(recent.insert(1));
+        assert!(!recent.insert(3));
+        assert!(!recent.insert(4));
+        assert!(recent.insert(2));
+        assert_eq!(recent.order.len(), 3);
+        assert_eq!(recent.seen.len(), 3);
+    }
+}
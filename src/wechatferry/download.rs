@@ -0,0 +1,247 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::error::{Result, WcfError};
+use super::{MsgType, WxMsg};
+
+// how often the file system is checked while waiting for a download
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// 根据文件头判断图片格式，无法识别时返回 None
+pub(crate) fn image_extension(header: &[u8]) -> Option<&'static str> {
+    if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("jpg")
+    } else if header.starts_with(&[0x89, b'P', b'N', b'G']) {
+        Some("png")
+    } else if header.starts_with(b"GIF8") {
+        Some("gif")
+    } else if header.starts_with(b"BM") {
+        Some("bmp")
+    } else if header.len() >= 12 && header.starts_with(b"RIFF") && &header[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+/// 检查消息类型，不符合时返回 `WcfError::UnexpectedMsgType`
+pub(crate) fn check_msg_type(msg: &WxMsg, expected: MsgType) -> Result<()> {
+    let actual = MsgType::from(msg.r#type as i32);
+    if actual != expected {
+        return Err(WcfError::UnexpectedMsgType { expected, actual });
+    }
+    Ok(())
+}
+
+/// 视频消息 content 中 `<videomsg length="..">` 的文件大小，解析不到时返回 None
+pub(crate) fn video_size(content: &str) -> Option<u64> {
+    let doc = roxmltree::Document::parse(content.trim()).ok()?;
+    let video = doc.descendants().find(|node| node.has_tag_name("videomsg"))?;
+    video.attribute("length")?.parse().ok().filter(|&size| size > 0)
+}
+
+/// 文件存在且非空
+pub(crate) fn is_ready(path: &Path) -> bool {
+    path.metadata().is_ok_and(|metadata| metadata.is_file() && metadata.len() > 0)
+}
+
+/// 等待 path 出现并且非空，超过 deadline 时返回 `WcfError::DownloadTimeout`
+pub(crate) fn wait_for_file(path: &Path, deadline: Instant) -> Result<()> {
+    while !is_ready(path) {
+        if Instant::now() >= deadline {
+            return Err(WcfError::DownloadTimeout(path.to_path_buf()));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+/// 等待 path 下载完成：大小等于 expected_size，不知道大小时为连续两次检查大小不变且非空，
+/// 每次检查时用当前大小调用 progress
+pub(crate) fn wait_for_complete<F>(
+    path: &Path,
+    expected_size: Option<u64>,
+    deadline: Instant,
+    mut progress: F,
+) -> Result<()>
+where
+    F: FnMut(u64),
+{
+    let mut last_size = None;
+    loop {
+        let size = path.metadata().ok().filter(|metadata| metadata.is_file()).map(|metadata| metadata.len());
+        if let Some(size) = size {
+            progress(size);
+            let complete = match expected_size {
+                Some(expected) if expected > 0 => size >= expected,
+                _ => size > 0 && last_size == Some(size),
+            };
+            if complete {
+                return Ok(());
+            }
+        }
+        last_size = size;
+        if Instant::now() >= deadline {
+            return Err(WcfError::DownloadTimeout(path.to_path_buf()));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// 复制 src 到 dest_dir/file_name，返回目标路径，src 已在该位置时不复制
+pub(crate) fn copy_into(src: &Path, dest_dir: &Path, file_name: &str) -> Result<PathBuf> {
+    // never let a name from a message escape dest_dir
+    let file_name = Path::new(file_name)
+        .file_name()
+        .ok_or_else(|| WcfError::DownloadFailed(format!("invalid attachment file name: {:?}", file_name)))?;
+    fs::create_dir_all(dest_dir)?;
+    let dest = dest_dir.join(file_name);
+    if dest != src {
+        fs::copy(src, &dest)?;
+    }
+    Ok(dest)
+}
+
+/// 将解密后的图片按格式改名为 `{id}.{ext}` 放到 dest_dir，空文件视为失败
+pub(crate) fn finish_image(decrypted: &Path, dest_dir: &Path, id: u64) -> Result<PathBuf> {
+    let content = fs::read(decrypted)?;
+    if content.is_empty() {
+        let _ = fs::remove_file(decrypted);
+        return Err(WcfError::DownloadFailed(format!("decrypted image is empty: {:?}", decrypted)));
+    }
+    let extension = image_extension(&content).unwrap_or("dat");
+    let dest = dest_dir.join(format!("{}.{}", id, extension));
+    if decrypted != dest {
+        fs::rename(decrypted, &dest)
+            .or_else(|_| fs::write(&dest, &content).and_then(|_| fs::remove_file(decrypted)))?;
+    }
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    const VIDEO_XML: &str = r#"<?xml version="1.0"?>
+<msg>
+	<videomsg aeskey="8e1c0d2f4a6b3c5d7e9f0a1b2c3d4e5f" cdnvideourl="3057020100044b30490201000204" cdnthumbaeskey="8e1c0d2f4a6b3c5d7e9f0a1b2c3d4e5f" cdnthumburl="3057020100044b30490201000204" length="2462515" playlength="12" cdnthumblength="6023" cdnthumbwidth="224" cdnthumbheight="398" fromusername="wxid_a" md5="f1e2d3c4b5a697887766554433221100" newmd5="00112233445566778899aabbccddeeff" isplaceholder="0" rawmd5="" rawlength="0" cdnrawvideourl="" cdnrawvideoaeskey="" overwritenewmsgid="0" originsourcemd5="" isad="0" />
+</msg>
+"#;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wcf-download-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn is_timeout(result: Result<()>, path: &Path) -> bool {
+        matches!(result, Err(WcfError::DownloadTimeout(p)) if p == path)
+    }
+
+    #[test]
+    fn video_size_from_fixture() {
+        assert_eq!(video_size(VIDEO_XML), Some(2462515));
+        assert_eq!(video_size(&VIDEO_XML.replace(r#"length="2462515""#, r#"length="0""#)), None);
+        assert_eq!(video_size(&VIDEO_XML.replace(r#"length="2462515""#, "")), None);
+        assert_eq!(video_size("<msg><img length=\"10\" /></msg>"), None);
+        assert_eq!(video_size("not xml"), None);
+    }
+
+    #[test]
+    fn wait_for_file_times_out() {
+        let dir = temp_dir("missing");
+        let path = dir.join("never.mp4");
+        let start = Instant::now();
+        assert!(is_timeout(wait_for_file(&path, start + Duration::from_millis(300)), &path));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        // an empty file is not ready either
+        fs::write(&path, b"").unwrap();
+        assert!(is_timeout(wait_for_file(&path, Instant::now() + Duration::from_millis(300)), &path));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn wait_for_file_appears() {
+        let dir = temp_dir("appears");
+        let path = dir.join("a.mp4");
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(300));
+                fs::write(path, b"data").unwrap();
+            })
+        };
+        assert!(wait_for_file(&path, Instant::now() + Duration::from_secs(10)).is_ok());
+        writer.join().unwrap();
+        // already there
+        assert!(wait_for_file(&path, Instant::now()).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn wait_for_complete_growing_file() {
+        let dir = temp_dir("growing");
+        let path = dir.join("b.mp4");
+        fs::write(&path, [0u8; 100]).unwrap();
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                for _ in 0..4 {
+                    thread::sleep(Duration::from_millis(150));
+                    OpenOptions::new().append(true).open(&path).unwrap().write_all(&[0u8; 100]).unwrap();
+                }
+            })
+        };
+        let mut sizes = Vec::new();
+        let result =
+            wait_for_complete(&path, Some(500), Instant::now() + Duration::from_secs(10), |size| sizes.push(size));
+        writer.join().unwrap();
+        assert!(result.is_ok());
+        assert_eq!(sizes.first(), Some(&100));
+        assert_eq!(sizes.last(), Some(&500));
+        assert!(sizes.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        // a partial file which never reaches the expected size
+        let deadline = Instant::now() + Duration::from_millis(300);
+        assert!(is_timeout(wait_for_complete(&path, Some(1000), deadline, |_| {}), &path));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn wait_for_complete_already_complete() {
+        let dir = temp_dir("complete");
+        let path = dir.join("c.mp4");
+        fs::write(&path, [0u8; 64]).unwrap();
+        let mut sizes = Vec::new();
+        assert!(wait_for_complete(&path, Some(64), Instant::now(), |size| sizes.push(size)).is_ok());
+        assert_eq!(sizes, [64]);
+
+        // without the size, the file has to stay the same for two checks
+        let mut sizes = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        assert!(wait_for_complete(&path, None, deadline, |size| sizes.push(size)).is_ok());
+        assert_eq!(sizes, [64, 64]);
+
+        let missing = dir.join("missing.mp4");
+        let deadline = Instant::now() + Duration::from_millis(300);
+        assert!(is_timeout(wait_for_complete(&missing, None, deadline, |_| panic!("no size to report")), &missing));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn copy_into_keeps_the_file_name_only() {
+        let dir = temp_dir("copy");
+        let src = dir.join("src.txt");
+        fs::write(&src, b"x").unwrap();
+        let dest = copy_into(&src, &dir.join("out"), "../../escape.txt").unwrap();
+        assert_eq!(dest, dir.join("out").join("escape.txt"));
+        assert!(copy_into(&src, &dir, "..").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+
+use super::MsgType;
+
+pub type Result<T> = std::result::Result<T, WcfError>;
+
+/// wechatferry 模块的错误类型
+#[derive(Debug, Error)]
+pub enum WcfError {
+    #[error("wcf not inited")]
+    NotInited,
+    #[error("wcf already inited")]
+    AlreadyInited,
+    #[error("cmd_socket disconnected")]
+    SocketDisconnected,
+    #[error("cmd_socket already connected")]
+    SocketAlreadyConnected,
+    /// 收发超时，可以重试
+    #[error("socket timed out")]
+    Timeout,
+    #[error("socket error: {0}")]
+    Socket(nng::Error),
+    /// enable_listen() 开启了远端的消息推送，但在等待时间内连不上 msg socket，收不到任何消息
+    #[error("failed to connect msg socket on port {port}: {source}")]
+    MsgSocketConnect { port: u16, source: nng::Error },
+    /// 收到的响应无法解码
+    #[error("failed to decode message: {0}")]
+    DecodeError(#[from] prost::DecodeError),
+    /// 响应解码成功，但远端没有返回 msg，携带函数名，例如 "FUNC_IS_LOGIN"，见 exec_raw()
+    #[error("remote returned no msg for {0}")]
+    EmptyResponse(String),
+    #[error("failed to encode message: {0}")]
+    EncodeError(#[from] prost::EncodeError),
+    /// 加载 sdk.dll 或查找其中的函数失败，path 为尝试加载的路径
+    #[error("failed to load sdk dll {path:?}: {source}")]
+    DllLoad { path: PathBuf, source: libloading::Error },
+    #[error("sdk dll not loaded")]
+    DllNotLoaded,
+    /// 非 windows 平台，或未开启 real-sdk feature
+    #[error("sdk not available on this platform, it requires windows and the real-sdk feature")]
+    SdkUnavailable,
+    /// sdk.dll 旁边没有 init() 的 debug 参数对应的 spy.dll 或 spy_debug.dll，携带期望的路径
+    #[error("spy dll not found at {0:?}")]
+    SpyDllNotFound(PathBuf),
+    #[error("wcf init sdk failed, result={0}")]
+    SdkInitFailed(i32),
+    /// 已安装的微信版本不被附带的 wcf 支持，可以通过 InitOptions.force 跳过检查，见 check_compatibility()
+    #[error("wechat {installed} is not supported, wcf {wcf} requires wechat {supported}")]
+    IncompatibleWeChat { installed: String, supported: String, wcf: String },
+    /// init() 前检查到端口已被占用，可能是其他程序或之前崩溃后残留的 wcf，端口为 cmd 或 msg socket 的端口
+    #[error("port {0} is already in use")]
+    PortInUse(u16),
+    /// init_auto_port() 在给定范围内没有找到连续两个空闲的端口
+    #[error("no free port pair in {start}..={end}")]
+    NoFreePort { start: u16, end: u16 },
+    /// 远端没有按预期返回结果
+    #[error("remote side rejected the request: {0}")]
+    RemoteRejected(String),
+    #[error("gave up reconnecting cmd_socket after {0} attempt(s)")]
+    ReconnectFailed(u32),
+    /// 超过发送速率限制，retry_after 后可以重试
+    #[error("rate limited, retry after {retry_after:?}")]
+    RateLimited { retry_after: Duration },
+    /// 等待发送时被 HumanizeOptions.cancel 取消
+    #[error("cancelled")]
+    Cancelled,
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    /// 要发送的文件不存在、不可读或格式不对
+    #[error("invalid path {path:?}: {reason}")]
+    InvalidPath { path: PathBuf, reason: String },
+    /// 接收者不像是 wxid 或群 id
+    #[error("invalid receiver: {0:?}")]
+    InvalidReceiver(String),
+    #[error("failed to parse xml: {0}")]
+    Xml(#[from] roxmltree::Error),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    /// 消息类型不符合接口的要求，例如对文本消息调用 download_image()
+    #[error("expected a {expected} message, got {actual}")]
+    UnexpectedMsgType { expected: MsgType, actual: MsgType },
+    /// 等待附件下载超时，path 为等待的文件
+    #[error("timed out waiting for download of {0:?}")]
+    DownloadTimeout(PathBuf),
+    #[error("download failed: {0}")]
+    DownloadFailed(String),
+    /// 要查找的对象不存在，例如没有聊天记录的会话
+    #[error("not found: {0}")]
+    NotFound(String),
+    /// MessageStore 读写本地数据库失败
+    #[cfg(feature = "store")]
+    #[error("message store error: {0}")]
+    Store(#[from] rusqlite::Error),
+    /// 调用 HTTP 接口失败，例如 OpenAiResponder 请求超时
+    #[cfg(any(feature = "openai", feature = "http"))]
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+    /// install_shutdown_handler() 失败，例如已经安装过
+    #[cfg(feature = "ctrlc")]
+    #[error("failed to install shutdown handler: {0}")]
+    ShutdownHandler(#[from] ctrlc::Error),
+    /// InitOptions.launch_wechat 为 true 时没有找到 WeChat.exe，携带尝试过的路径
+    #[error("WeChat.exe not found, tried {0:?}")]
+    WeChatNotFound(Vec<PathBuf>),
+    /// InitOptions.launch_wechat 为 true 时启动微信失败
+    #[error("failed to launch wechat: {0}")]
+    WeChatLaunchFailed(String),
+    /// 读取或解析配置文件失败，reason 中有出错的行号
+    #[error("failed to load config {path:?}: {reason}")]
+    ConfigLoad { path: PathBuf, reason: String },
+    /// 配置的取值不合法，key 为字段名，例如 "wcf.port"，或覆盖它的环境变量名
+    #[error("invalid config {path:?}: {key} {reason}")]
+    InvalidConfig { path: PathBuf, key: String, reason: String },
+    /// wait_for_login() 超时，用户仍未登录
+    #[error("timed out waiting for login")]
+    LoginTimeout,
+}
+
+impl From<nng::Error> for WcfError {
+    fn from(e: nng::Error) -> Self {
+        match e {
+            nng::Error::TimedOut => WcfError::Timeout,
+            nng::Error::Closed => WcfError::SocketDisconnected,
+            e => WcfError::Socket(e),
+        }
+    }
+}
@@ -0,0 +1,706 @@
+use parking_lot::{Condvar, Mutex};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+use tracing::{error, trace, trace_span, warn, Span};
+
+use super::{metrics, Event, WxMsg};
+
+pub type CallbackFn = Arc<Mutex<dyn FnMut(Event) + Send + 'static>>;
+type MessageHandlerFn = Arc<Mutex<dyn FnMut(&WxMsg) + Send + 'static>>;
+type ConnectionHandlerFn = Arc<Mutex<dyn FnMut(ConnectionChange) + Send + 'static>>;
+
+/// socket 连接状态的变化，对应 Event 中的 CmdSocket* / MsgSocket* 事件
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionChange {
+    CmdSocketConnected,
+    CmdSocketDisconnected,
+    MsgSocketConnected,
+    MsgSocketDisconnected,
+}
+
+impl ConnectionChange {
+    fn from_event(event: &Event) -> Option<Self> {
+        match event {
+            Event::CmdSocketConnected => Some(ConnectionChange::CmdSocketConnected),
+            Event::CmdSocketDisconnected => Some(ConnectionChange::CmdSocketDisconnected),
+            Event::MsgSocketConnected => Some(ConnectionChange::MsgSocketConnected),
+            Event::MsgSocketDisconnected => Some(ConnectionChange::MsgSocketDisconnected),
+            _ => None,
+        }
+    }
+}
+
+/// on_message() 等注册函数返回的 id，用于注销对应的处理函数
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HandlerId(u64);
+
+#[derive(Clone)]
+enum Handler {
+    Message(MessageHandlerFn),
+    Connection(ConnectionHandlerFn),
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(s), _) => s.to_string(),
+        (_, Some(s)) => s.clone(),
+        _ => "unknown panic".into(),
+    }
+}
+
+// run a user callback, a panic inside is caught and returned as its message
+fn call_guarded(f: impl FnOnce()) -> Option<String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).err().map(|payload| {
+        let message = panic_message(payload.as_ref());
+        error!("event callback panicked: {}", message);
+        message
+    })
+}
+
+// the variant name, e.g. "MsgReceived"
+fn event_name(event: &Event) -> String {
+    let debug = format!("{:?}", event);
+    debug.split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default().to_string()
+}
+
+/// `subscribe()` 默认的队列长度
+pub const DEFAULT_SUBSCRIBER_CAPACITY: usize = 1024;
+
+// delivers every event to the registered callback, handlers and all live subscribers
+#[derive(Default)]
+struct Listeners {
+    callback: Mutex<Option<CallbackFn>>,
+    subscribers: Mutex<Vec<SyncSender<Event>>>,
+    #[cfg(feature = "async")]
+    async_subscribers: Mutex<Vec<tokio::sync::mpsc::Sender<Event>>>,
+    // kept in registration order
+    handlers: Mutex<Vec<(HandlerId, Handler)>>,
+    next_handler_id: AtomicU64,
+}
+
+impl Listeners {
+    fn add_handler(&self, handler: Handler) -> HandlerId {
+        let id = HandlerId(self.next_handler_id.fetch_add(1, Ordering::Relaxed));
+        self.handlers.lock().push((id, handler));
+        id
+    }
+
+    fn dispatch_to_handlers(&self, event: &Event, panics: &mut Vec<String>) {
+        let connection_change = ConnectionChange::from_event(event);
+        if connection_change.is_none() && !matches!(event, Event::MsgReceived(_)) {
+            return;
+        }
+        // call outside the lock, so handlers could add or remove handlers
+        let handlers: Vec<Handler> = self.handlers.lock().iter().map(|(_, handler)| handler.clone()).collect();
+        for handler in handlers {
+            let panicked = match (&handler, event, connection_change) {
+                (Handler::Message(f), Event::MsgReceived(msg), _) => call_guarded(|| f.lock()(msg)),
+                (Handler::Connection(f), _, Some(change)) => call_guarded(|| f.lock()(change)),
+                _ => None,
+            };
+            panics.extend(panicked);
+        }
+    }
+
+    fn deliver(&self, event: Event) {
+        let _span = trace_span!("dispatch_event", event = %event_name(&event)).entered();
+        let mut panics = vec![];
+        self.dispatch_to_handlers(&event, &mut panics);
+
+        // never block here, a slow subscriber loses new events instead of stalling the receive thread
+        self.subscribers.lock().retain(|sender| match sender.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(event)) => {
+                warn!("subscriber queue full, dropped event {:?}", event);
+                metrics::subscriber_event_dropped();
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+        #[cfg(feature = "async")]
+        self.async_subscribers.lock().retain(|sender| match sender.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(tokio::sync::mpsc::error::TrySendError::Full(event)) => {
+                warn!("async subscriber queue full, dropped event {:?}", event);
+                metrics::subscriber_event_dropped();
+                true
+            }
+            Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => false,
+        });
+
+        let arc_callback = self.callback.lock().clone();
+        if let Some(arc_callback) = arc_callback {
+            // report panics of CallbackPanicked itself only by log, or it may never end
+            let is_panic_event = matches!(event, Event::CallbackPanicked(_));
+            panics.extend(call_guarded(|| arc_callback.lock()(event)).filter(|_| !is_panic_event));
+        }
+        for message in panics {
+            self.deliver(Event::CallbackPanicked(message));
+        }
+    }
+}
+
+/// 事件队列满时的处理方式，见 `EventQueueConfig`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    /// 阻塞产生事件的线程（例如消息接收线程）直到队列有空位，事件不会丢失
+    #[default]
+    Block,
+    /// 丢弃队列中最早的事件
+    DropOldest,
+    /// 丢弃新产生的事件
+    DropNewest,
+}
+
+/// `EventQueueConfig` 默认的队列长度
+pub const DEFAULT_EVENT_QUEUE_CAPACITY: usize = 10_000;
+
+/// 等待 wcf-dispatch 线程分发的事件队列，见 `WcfClient::set_event_queue()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventQueueConfig {
+    /// 队列长度，至少为 1
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for EventQueueConfig {
+    fn default() -> Self {
+        EventQueueConfig { capacity: DEFAULT_EVENT_QUEUE_CAPACITY, overflow: OverflowPolicy::Block }
+    }
+}
+
+impl EventQueueConfig {
+    // QueueHighWatermark is sent when the depth reaches this, and again only after it fell below half the capacity
+    fn high_watermark(&self) -> usize {
+        (self.capacity.max(1) * 4).div_ceil(5)
+    }
+}
+
+// nearly everything queued is an event, boxing it would only add an allocation
+#[allow(clippy::large_enum_variant)]
+enum Queued {
+    // with the span it was queued in, e.g. the recv_msg span of a received message
+    Event(Event, Span),
+    // acked by the dispatcher thread once all events queued before it are delivered
+    Flush(SyncSender<()>),
+}
+
+#[derive(Default)]
+struct QueueState {
+    items: VecDeque<Queued>,
+    config: EventQueueConfig,
+    // events in items, flush markers are not counted
+    depth: usize,
+    // the dispatcher thread, started on the first queued event
+    thread: Option<ThreadId>,
+    // the dispatcher thread is gone after a panic, events are delivered on the calling thread
+    stopped: bool,
+    // the EventHub is dropped, the dispatcher ends once items are empty
+    closed: bool,
+    // QueueHighWatermark was sent and the depth has not fallen below half the capacity since
+    above_watermark: bool,
+    // dropped but not reported by EventsDropped yet
+    unreported_drops: u64,
+    // for stats(), reset by reset_dropped()
+    dropped_total: u64,
+}
+
+impl QueueState {
+    fn push_event(&mut self, event: Event, span: Span) {
+        self.items.push_back(Queued::Event(event, span));
+        self.depth += 1;
+        metrics::dispatch_queue_changed(1);
+    }
+
+    fn pop(&mut self) -> Option<Queued> {
+        let queued = self.items.pop_front()?;
+        if matches!(queued, Queued::Event(..)) {
+            self.depth -= 1;
+            metrics::dispatch_queue_changed(-1);
+            if self.depth < self.config.capacity / 2 {
+                self.above_watermark = false;
+            }
+        }
+        Some(queued)
+    }
+
+    // the oldest event, flush markers and the QueueHighWatermark warning stay in place
+    fn drop_oldest(&mut self) -> bool {
+        let droppable = |queued: &Queued| match queued {
+            Queued::Event(event, _) => !matches!(event, Event::QueueHighWatermark(_)),
+            Queued::Flush(_) => false,
+        };
+        match self.items.iter().position(droppable) {
+            Some(index) => {
+                self.items.remove(index);
+                self.depth -= 1;
+                metrics::dispatch_queue_changed(-1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn record_drop(&mut self) {
+        self.unreported_drops += 1;
+        self.dropped_total += 1;
+        metrics::dispatch_event_dropped();
+    }
+}
+
+#[derive(Default)]
+struct EventQueue {
+    state: Mutex<QueueState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+// callback panics are caught in deliver(), this only guards the loop itself,
+// afterwards dispatch() finds the queue stopped and delivers on the calling thread
+fn dispatcher_thread(listeners: Arc<Listeners>, queue: Arc<EventQueue>) {
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| dispatch_queued(&listeners, &queue))) {
+        error!("thread wcf-dispatch panicked: {}", panic_message(payload.as_ref()));
+        let mut state = queue.state.lock();
+        state.stopped = true;
+        // lost like before the panic, pending flushes are released by dropping their acks
+        while state.pop().is_some() {}
+        drop(state);
+        queue.not_full.notify_all();
+        listeners.deliver(Event::InternalThreadPanicked("wcf-dispatch"));
+    }
+}
+
+fn dispatch_queued(listeners: &Listeners, queue: &EventQueue) {
+    trace!("dispatcher_thread()");
+    loop {
+        let mut state = queue.state.lock();
+        let queued = loop {
+            match state.pop() {
+                Some(queued) => break queued,
+                // ends when the EventHub is dropped and everything queued is delivered
+                None if state.closed => return,
+                None => queue.not_empty.wait(&mut state),
+            }
+        };
+        let dropped = std::mem::take(&mut state.unreported_drops);
+        drop(state);
+        queue.not_full.notify_one();
+
+        if dropped > 0 {
+            listeners.deliver(Event::EventsDropped(dropped));
+        }
+        match queued {
+            Queued::Event(event, span) => span.in_scope(|| listeners.deliver(event)),
+            Queued::Flush(ack) => {
+                let _ = ack.send(());
+            }
+        }
+    }
+}
+
+// queues events and delivers them on a dedicated thread, so callbacks can safely call wcf functions
+#[derive(Default)]
+pub(crate) struct EventHub {
+    listeners: Arc<Listeners>,
+    queue: Arc<EventQueue>,
+    sync_dispatch: AtomicBool,
+}
+
+impl Drop for EventHub {
+    fn drop(&mut self) {
+        self.queue.state.lock().closed = true;
+        self.queue.not_empty.notify_all();
+    }
+}
+
+impl EventHub {
+    pub fn set_callback(&self, callback: Option<CallbackFn>) {
+        *self.listeners.callback.lock() = callback;
+    }
+
+    pub fn subscribe(&self, capacity: usize) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        self.listeners.subscribers.lock().push(sender);
+        receiver
+    }
+
+    #[cfg(feature = "async")]
+    pub fn subscribe_async(&self, capacity: usize) -> tokio::sync::mpsc::Receiver<Event> {
+        let (sender, receiver) = tokio::sync::mpsc::channel(capacity.max(1));
+        self.listeners.async_subscribers.lock().push(sender);
+        receiver
+    }
+
+    pub fn on_message<F>(&self, handler: F) -> HandlerId
+    where
+        F: FnMut(&WxMsg) + Send + 'static,
+    {
+        self.listeners.add_handler(Handler::Message(Arc::new(Mutex::new(handler))))
+    }
+
+    pub fn on_connection_change<F>(&self, handler: F) -> HandlerId
+    where
+        F: FnMut(ConnectionChange) + Send + 'static,
+    {
+        self.listeners.add_handler(Handler::Connection(Arc::new(Mutex::new(handler))))
+    }
+
+    pub fn remove_handler(&self, id: HandlerId) -> bool {
+        let mut handlers = self.listeners.handlers.lock();
+        let len = handlers.len();
+        handlers.retain(|(handler_id, _)| *handler_id != id);
+        handlers.len() != len
+    }
+
+    pub fn set_sync_dispatch(&self, sync_dispatch: bool) {
+        if sync_dispatch {
+            self.flush(); // keep events in order when switching
+        }
+        self.sync_dispatch.store(sync_dispatch, Ordering::SeqCst);
+    }
+
+    pub fn set_queue_config(&self, config: EventQueueConfig) {
+        let config = EventQueueConfig { capacity: config.capacity.max(1), ..config };
+        self.queue.state.lock().config = config;
+        // a larger capacity or a dropping policy may unblock producers
+        self.queue.not_full.notify_all();
+    }
+
+    pub fn queue_config(&self) -> EventQueueConfig {
+        self.queue.state.lock().config
+    }
+
+    // spawns the dispatcher thread if needed, false if it's not running
+    fn ensure_dispatcher(&self, state: &mut QueueState) -> bool {
+        if state.stopped {
+            return false;
+        }
+        if state.thread.is_none() {
+            let listeners = self.listeners.clone();
+            let queue = self.queue.clone();
+            let builder = thread::Builder::new().name("wcf-dispatch".into());
+            match builder.spawn(move || dispatcher_thread(listeners, queue)) {
+                Ok(handle) => state.thread = Some(handle.thread().id()),
+                Err(e) => {
+                    error!("failed to spawn dispatcher thread, deliver events synchronously, error={}", e);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    pub fn dispatch(&self, event: Event) {
+        if self.sync_dispatch.load(Ordering::SeqCst) {
+            return self.listeners.deliver(event);
+        }
+        let span = Span::current();
+        let mut state = self.queue.state.lock();
+        if !self.ensure_dispatcher(&mut state) {
+            drop(state);
+            return self.listeners.deliver(event);
+        }
+        // events sent by callbacks can't wait for the dispatcher thread, which is running the callback
+        let in_dispatcher = state.thread == Some(thread::current().id());
+        while state.depth >= state.config.capacity {
+            match state.config.overflow {
+                OverflowPolicy::Block if in_dispatcher => break,
+                OverflowPolicy::Block => {
+                    self.queue.not_full.wait(&mut state);
+                    if state.stopped {
+                        drop(state);
+                        return self.listeners.deliver(event);
+                    }
+                }
+                OverflowPolicy::DropOldest => {
+                    if !state.drop_oldest() {
+                        break;
+                    }
+                    state.record_drop();
+                }
+                OverflowPolicy::DropNewest => {
+                    trace!("event queue full, dropped event {}", event_name(&event));
+                    state.record_drop();
+                    return;
+                }
+            }
+        }
+        state.push_event(event, span);
+        if !state.above_watermark && state.depth >= state.config.high_watermark() {
+            state.above_watermark = true;
+            let depth = state.depth;
+            warn!("event queue reached {} of {} events", depth, state.config.capacity);
+            // ahead of the backlog, so it's seen before the queue drains
+            state.items.push_front(Queued::Event(Event::QueueHighWatermark(depth), Span::none()));
+            state.depth += 1;
+            metrics::dispatch_queue_changed(1);
+        }
+        drop(state);
+        self.queue.not_empty.notify_one();
+    }
+
+    pub fn queue_depth(&self) -> usize {
+        self.queue.state.lock().depth
+    }
+
+    pub fn events_dropped(&self) -> u64 {
+        self.queue.state.lock().dropped_total
+    }
+
+    pub fn reset_dropped(&self) {
+        self.queue.state.lock().dropped_total = 0;
+    }
+
+    /// wait until all queued events are delivered, returns at once when called in the dispatcher thread
+    pub fn flush(&self) {
+        self.flush_timeout(None);
+    }
+
+    /// same as flush(), but gives up after timeout, returns false if some events are still queued
+    pub fn flush_timeout(&self, timeout: Option<Duration>) -> bool {
+        let (ack_sender, ack_receiver) = mpsc::sync_channel(1);
+        {
+            let mut state = self.queue.state.lock();
+            match state.thread {
+                _ if state.stopped => return true,
+                Some(id) if id == thread::current().id() => return true,
+                Some(_) => state.items.push_back(Queued::Flush(ack_sender)),
+                None => return true,
+            }
+        }
+        self.queue.not_empty.notify_one();
+        match timeout {
+            Some(timeout) => !matches!(ack_receiver.recv_timeout(timeout), Err(mpsc::RecvTimeoutError::Timeout)),
+            None => {
+                let _ = ack_receiver.recv();
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn msg(id: u64) -> Event {
+        Event::MsgReceived(Arc::new(WxMsg { id, ..Default::default() }))
+    }
+
+    fn received_ids(receiver: &Receiver<Event>) -> Vec<u64> {
+        receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::MsgReceived(msg) => Some(msg.id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn panicking_handler_does_not_stop_dispatch() {
+        let hub = EventHub::default();
+        let events = hub.subscribe(16);
+        hub.on_message(|msg| {
+            if msg.id == 1 {
+                panic!("handler failed on {}", msg.id);
+            }
+        });
+        let handled = Arc::new(Mutex::new(vec![]));
+        let handled_clone = handled.clone();
+        hub.on_message(move |msg| handled_clone.lock().push(msg.id));
+
+        hub.dispatch(msg(1));
+        hub.dispatch(msg(2));
+        hub.flush();
+
+        assert_eq!(*handled.lock(), vec![1, 2]);
+        let events: Vec<Event> = events.try_iter().collect();
+        assert!(events.iter().any(|event| matches!(event, Event::CallbackPanicked(m) if m == "handler failed on 1")));
+        assert_eq!(events.iter().filter(|event| matches!(event, Event::MsgReceived(_))).count(), 2);
+        // still delivered by the dispatcher thread, not on the calling thread after a crash
+        let state = hub.queue.state.lock();
+        assert!(!state.stopped);
+        assert!(state.thread.is_some_and(|id| id != thread::current().id()));
+    }
+
+    #[test]
+    fn panicking_callback_keeps_subscribers() {
+        let hub = EventHub::default();
+        let events = hub.subscribe(16);
+        hub.set_callback(Some(Arc::new(Mutex::new(|_event: Event| panic!("callback failed")))));
+
+        hub.dispatch(msg(1));
+        hub.dispatch(msg(2));
+        hub.flush();
+
+        assert_eq!(received_ids(&events), vec![1, 2]);
+        assert!(!hub.queue.state.lock().stopped);
+    }
+
+    #[test]
+    fn subscribers_change_while_dispatching() {
+        let hub = Arc::new(EventHub::default());
+        let events = hub.subscribe(10_000);
+        let handled = Arc::new(AtomicU64::new(0));
+        let handled_clone = handled.clone();
+        hub.on_message(move |_| {
+            handled_clone.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let dispatcher = {
+            let hub = hub.clone();
+            thread::spawn(move || (0..2000).for_each(|id| hub.dispatch(msg(id))))
+        };
+        let churn: Vec<_> = (0..4)
+            .map(|_| {
+                let hub = hub.clone();
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        let id = hub.on_message(|_| {});
+                        let receiver = hub.subscribe(1);
+                        let connection = hub.on_connection_change(|_| {});
+                        assert!(hub.remove_handler(id));
+                        assert!(hub.remove_handler(connection));
+                        drop(receiver);
+                    }
+                })
+            })
+            .collect();
+        dispatcher.join().unwrap();
+        churn.into_iter().for_each(|handle| handle.join().unwrap());
+        hub.flush();
+
+        // the long-lived listeners saw every event in order
+        assert_eq!(handled.load(Ordering::SeqCst), 2000);
+        assert_eq!(received_ids(&events), (0..2000).collect::<Vec<_>>());
+        // only the long-lived handler is left, closed subscribers were removed on delivery
+        assert_eq!(hub.listeners.handlers.lock().len(), 1);
+        hub.dispatch(msg(2000));
+        hub.flush();
+        assert_eq!(hub.listeners.subscribers.lock().len(), 1);
+    }
+
+    #[test]
+    fn removed_handler_is_not_called() {
+        let hub = EventHub::default();
+        let calls = Arc::new(AtomicU64::new(0));
+        let calls_clone = calls.clone();
+        let id = hub.on_message(move |_| {
+            calls_clone.fetch_add(1, Ordering::SeqCst);
+        });
+        hub.dispatch(msg(1));
+        hub.flush();
+        assert!(hub.remove_handler(id));
+        assert!(!hub.remove_handler(id));
+        hub.dispatch(msg(2));
+        hub.flush();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    // a hub whose dispatcher is stuck in the handler of message 0 until the returned sender is used
+    fn paused_hub(config: EventQueueConfig) -> (Arc<EventHub>, Receiver<Event>, SyncSender<()>) {
+        let hub = Arc::new(EventHub::default());
+        hub.set_queue_config(config);
+        let events = hub.subscribe(100);
+        let (started_sender, started) = mpsc::sync_channel(1);
+        let (resume, resumed) = mpsc::sync_channel(1);
+        let resumed = Mutex::new(resumed);
+        hub.on_message(move |msg| {
+            if msg.id == 0 {
+                started_sender.send(()).unwrap();
+                resumed.lock().recv().unwrap();
+            }
+        });
+        hub.dispatch(msg(0));
+        started.recv_timeout(Duration::from_secs(5)).unwrap();
+        (hub, events, resume)
+    }
+
+    fn dropped_reports(events: &[Event]) -> Vec<u64> {
+        events.iter().filter_map(|event| if let Event::EventsDropped(n) = event { Some(*n) } else { None }).collect()
+    }
+
+    fn watermarks(events: &[Event]) -> Vec<usize> {
+        events
+            .iter()
+            .filter_map(|event| if let Event::QueueHighWatermark(n) = event { Some(*n) } else { None })
+            .collect()
+    }
+
+    fn msg_ids(events: &[Event]) -> Vec<u64> {
+        events
+            .iter()
+            .filter_map(|event| if let Event::MsgReceived(msg) = event { Some(msg.id) } else { None })
+            .collect()
+    }
+
+    #[test]
+    fn full_queue_drops_newest() {
+        let (hub, events, resume) = paused_hub(EventQueueConfig { capacity: 5, overflow: OverflowPolicy::DropNewest });
+        (1..=10).for_each(|id| hub.dispatch(msg(id)));
+        // four messages and the watermark warning fill the queue
+        assert_eq!(hub.queue_depth(), 5);
+        assert_eq!(hub.events_dropped(), 6);
+
+        resume.send(()).unwrap();
+        hub.flush();
+        let events: Vec<Event> = events.try_iter().collect();
+        assert_eq!(msg_ids(&events), vec![0, 1, 2, 3, 4]);
+        assert_eq!(watermarks(&events), vec![4]);
+        assert_eq!(dropped_reports(&events), vec![6]);
+        assert_eq!(hub.queue_depth(), 0);
+    }
+
+    #[test]
+    fn full_queue_drops_oldest() {
+        let (hub, events, resume) = paused_hub(EventQueueConfig { capacity: 5, overflow: OverflowPolicy::DropOldest });
+        (1..=10).for_each(|id| hub.dispatch(msg(id)));
+        assert_eq!(hub.queue_depth(), 5);
+        assert_eq!(hub.events_dropped(), 6);
+
+        resume.send(()).unwrap();
+        hub.flush();
+        let events: Vec<Event> = events.try_iter().collect();
+        assert_eq!(msg_ids(&events), vec![0, 7, 8, 9, 10]);
+        // the warning itself is never dropped
+        assert_eq!(watermarks(&events), vec![4]);
+        assert_eq!(dropped_reports(&events), vec![6]);
+        hub.reset_dropped();
+        assert_eq!(hub.events_dropped(), 0);
+    }
+
+    #[test]
+    fn full_queue_blocks_producer() {
+        let (hub, events, resume) = paused_hub(EventQueueConfig { capacity: 3, overflow: OverflowPolicy::Block });
+        let producer = {
+            let hub = hub.clone();
+            thread::spawn(move || (1..=5).for_each(|id| hub.dispatch(msg(id))))
+        };
+        // three messages and the warning, message 4 waits for room
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while hub.queue_depth() < 4 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(hub.queue_depth(), 4);
+        assert!(!producer.is_finished());
+
+        resume.send(()).unwrap();
+        producer.join().unwrap();
+        hub.flush();
+        let events: Vec<Event> = events.try_iter().collect();
+        assert_eq!(msg_ids(&events), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(watermarks(&events), vec![3]);
+        assert!(dropped_reports(&events).is_empty());
+        assert_eq!(hub.events_dropped(), 0);
+    }
+}
@@ -0,0 +1,289 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::error::Result;
+use super::{sql, DbMessage, DbRow, MsgType};
+
+// the columns of DbMessage, and BytesExtra for the sender of group messages and image paths
+pub(crate) const EXPORT_COLUMNS: &str =
+    "localId, MsgSvrID, Type, SubType, IsSender, CreateTime, StrTalker, StrContent, BytesExtra";
+// rows read from a shard at a time
+pub(crate) const EXPORT_PAGE_SIZE: usize = 500;
+
+/// `export_contacts_csv()`、`export_rooms_csv()` 每写入多少行调用一次 progress
+pub const CSV_PROGRESS_INTERVAL: u64 = 500;
+
+/// 导出的时间范围，None 表示不限制
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeRange {
+    /// 不早于该时间
+    pub since: Option<SystemTime>,
+    /// 早于该时间
+    pub until: Option<SystemTime>,
+}
+
+/// `export_chat()` 的输出格式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportFormat {
+    /// 一个 json 数组，每条消息一个对象
+    Json,
+    /// 带表头的 CSV，按 RFC 4180 转义
+    Csv,
+    /// 可以直接用浏览器打开的单个页面
+    Html,
+}
+
+/// `export_chat()` 的结果
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportSummary {
+    /// 写入文件的消息数
+    pub exported: u64,
+    /// 跳过的语音、视频、表情等内容无法导出的媒体消息数，找不到本地路径的图片也会被跳过
+    pub skipped: u64,
+}
+
+// BytesExtra of the MSG table, a list of (kind, value) pairs
+#[derive(Clone, PartialEq, prost::Message)]
+struct BytesExtra {
+    #[prost(message, repeated, tag = "3")]
+    entries: Vec<BytesExtraEntry>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct BytesExtraEntry {
+    #[prost(int32, tag = "1")]
+    kind: i32,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+// kinds of BytesExtraEntry
+const EXTRA_SENDER: i32 = 1;
+const EXTRA_IMAGE: i32 = 4;
+
+/// 导出的一条消息
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct ExportedMsg {
+    pub id: u64,
+    /// 本地时间，例如 "2024-08-01 12:00:00"
+    pub time: String,
+    pub sender: String,
+    pub sender_name: String,
+    pub is_self: bool,
+    pub msg_type: i32,
+    pub content: String,
+    /// 图片在微信文件目录（WeChat Files）下的相对路径，为加密的 .dat 文件
+    pub image: Option<String>,
+}
+
+/// 一行消息记录，以及 BytesExtra 中群消息的发送者和图片路径
+pub(crate) struct ExportRow {
+    pub msg: DbMessage,
+    pub sender: Option<String>,
+    pub image: Option<String>,
+}
+
+impl From<DbRow> for ExportRow {
+    fn from(mut row: DbRow) -> Self {
+        let extra = row.fields.iter().position(|field| field.column == "BytesExtra").map(|i| row.fields.remove(i));
+        let entries = extra.and_then(|field| prost::Message::decode(field.content.as_slice()).ok());
+        let entries = entries.map_or(vec![], |extra: BytesExtra| extra.entries);
+        let value = |kind: i32| {
+            entries.iter().find(|entry| entry.kind == kind && !entry.value.is_empty()).map(|entry| entry.value.clone())
+        };
+        ExportRow { sender: value(EXTRA_SENDER), image: value(EXTRA_IMAGE), msg: DbMessage::from(row) }
+    }
+}
+
+impl ExportRow {
+    /// 发送者 wxid，自己发送的为 self_wxid，私聊中对方发送的为会话 id
+    pub fn sender_wxid(&self, self_wxid: &str) -> String {
+        match &self.sender {
+            _ if self.msg.is_sender => self_wxid.to_string(),
+            Some(sender) => sender.clone(),
+            None => self.msg.talker.clone(),
+        }
+    }
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+/// 单个分库的查询语句，按时间从旧到新排列
+pub(crate) fn export_sql(talker: &str, range: Option<TimeRange>) -> Result<String> {
+    let mut sql = format!("SELECT {} FROM MSG WHERE {}", EXPORT_COLUMNS, sql::bind_params("StrTalker = ?", &[talker])?);
+    let range = range.unwrap_or_default();
+    if let Some(since) = range.since {
+        sql += &format!(" AND CreateTime >= {}", unix_secs(since));
+    }
+    if let Some(until) = range.until {
+        sql += &format!(" AND CreateTime < {}", unix_secs(until));
+    }
+    Ok(sql + " ORDER BY CreateTime, localId")
+}
+
+pub(crate) fn format_time(create_time: i64) -> String {
+    DateTime::from_timestamp(create_time, 0)
+        .map(|time| time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+// quoted when it contains a separator, a quote or a line break, quotes are doubled
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_line<S: AsRef<str>>(fields: &[S]) -> String {
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field.as_ref())).collect();
+    fields.join(",") + "\r\n"
+}
+
+/// 以 UTF-8 BOM 开头的 CSV 文件，Excel 打开时中文不会乱码
+pub(crate) struct CsvWriter {
+    out: BufWriter<File>,
+    rows: u64,
+}
+
+impl CsvWriter {
+    /// 创建或覆盖 path，写入 BOM 和表头
+    pub fn create(path: &Path, headers: &[&str]) -> Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all("\u{feff}".as_bytes())?;
+        out.write_all(csv_line(headers).as_bytes())?;
+        Ok(CsvWriter { out, rows: 0 })
+    }
+
+    /// 写入一行，每 CSV_PROGRESS_INTERVAL 行以已写入的行数调用 progress
+    pub fn write_row<S: AsRef<str>>(&mut self, fields: &[S], progress: &mut impl FnMut(u64)) -> Result<()> {
+        self.out.write_all(csv_line(fields).as_bytes())?;
+        self.rows += 1;
+        if self.rows % CSV_PROGRESS_INTERVAL == 0 {
+            progress(self.rows);
+        }
+        Ok(())
+    }
+
+    /// 写入文件，返回总行数，最后不足 CSV_PROGRESS_INTERVAL 的部分也会调用一次 progress
+    pub fn finish(mut self, progress: &mut impl FnMut(u64)) -> Result<u64> {
+        self.out.flush()?;
+        if self.rows % CSV_PROGRESS_INTERVAL != 0 {
+            progress(self.rows);
+        }
+        Ok(self.rows)
+    }
+}
+
+fn html_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const HTML_HEAD: &str = r#"<!DOCTYPE html>
+<html lang="zh-CN">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body { font-family: sans-serif; max-width: 800px; margin: 2em auto; background: #f5f5f5; }
+.msg { background: #fff; border-radius: 6px; margin: 8px 0; padding: 8px 12px; }
+.msg.self { background: #dcf8c6; }
+.meta { color: #888; font-size: 12px; }
+.content { white-space: pre-wrap; word-break: break-all; margin-top: 4px; }
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+"#;
+
+/// 按格式逐条写入消息，不在内存中保留已写入的消息
+pub(crate) struct ExportWriter {
+    format: ExportFormat,
+    out: BufWriter<File>,
+    written: u64,
+}
+
+impl ExportWriter {
+    /// 创建或覆盖 path，title 为 html 的标题
+    pub fn create(path: &Path, format: ExportFormat, title: &str) -> Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        match format {
+            ExportFormat::Json => out.write_all(b"[")?,
+            ExportFormat::Csv => out.write_all(b"id,time,sender,sender_name,is_self,msg_type,content,image\r\n")?,
+            ExportFormat::Html => out.write_all(HTML_HEAD.replace("{title}", &html_escape(title)).as_bytes())?,
+        }
+        Ok(ExportWriter { format, out, written: 0 })
+    }
+
+    pub fn write(&mut self, msg: &ExportedMsg) -> Result<()> {
+        match self.format {
+            ExportFormat::Json => {
+                self.out.write_all(if self.written == 0 { b"\n" } else { b",\n" })?;
+                serde_json::to_writer(&mut self.out, msg)?;
+            }
+            ExportFormat::Csv => {
+                let fields = [
+                    &msg.id.to_string(),
+                    &msg.time,
+                    &msg.sender,
+                    &msg.sender_name,
+                    &msg.is_self.to_string(),
+                    &msg.msg_type.to_string(),
+                    &msg.content,
+                    msg.image.as_deref().unwrap_or_default(),
+                ];
+                self.out.write_all(csv_line(&fields).as_bytes())?;
+            }
+            ExportFormat::Html => {
+                let class = if msg.is_self { "msg self" } else { "msg" };
+                let content = match &msg.image {
+                    // relative to the WeChat Files directory, the link works when the page is saved there
+                    Some(image) => {
+                        let href = image.replace('\\', "/");
+                        format!("<a href=\"{}\">[图片]</a>", html_escape(&href))
+                    }
+                    None if MsgType::from(msg.msg_type) == MsgType::Text => html_escape(&msg.content),
+                    None => format!("[{}] {}", MsgType::from(msg.msg_type), html_escape(&msg.content)),
+                };
+                writeln!(
+                    self.out,
+                    "<div class=\"{}\"><div class=\"meta\">{} {}</div><div class=\"content\">{}</div></div>",
+                    class,
+                    html_escape(&msg.time),
+                    html_escape(&msg.sender_name),
+                    content
+                )?;
+            }
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        match self.format {
+            ExportFormat::Json => self.out.write_all(b"\n]\n")?,
+            ExportFormat::Csv => {}
+            ExportFormat::Html => self.out.write_all(b"</body>\n</html>\n")?,
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
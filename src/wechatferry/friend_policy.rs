@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::{FriendRequest, MsgType, WxMsg};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+/// 自动通过好友申请的策略，见 `set_friend_policy()`，默认不自动通过
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FriendPolicy {
+    /// 是否自动通过，为 false 时不处理任何好友申请
+    pub auto_accept: bool,
+    /// 只通过打招呼内容中包含该关键词的申请，None 表示全部通过
+    pub keyword: Option<String>,
+    /// 成为好友后发送的文本，None 表示不发送
+    pub greeting: Option<String>,
+    /// 每小时最多通过的申请数，0 表示不限制
+    pub max_per_hour: u32,
+}
+
+impl FriendPolicy {
+    /// 检查打招呼内容是否符合关键词，不符合时返回原因
+    pub fn check(&self, request: &FriendRequest) -> std::result::Result<(), String> {
+        match &self.keyword {
+            Some(keyword) if !request.greeting.contains(keyword.as_str()) => {
+                Err(format!("greeting does not contain {:?}", keyword))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 是否为通过好友申请后的 "你已添加了xxx，现在可以开始聊天了。" 系统消息
+pub(crate) fn is_friend_added(msg: &WxMsg) -> bool {
+    if msg.is_group || MsgType::from(msg.r#type as i32) != MsgType::System {
+        return false;
+    }
+    let content = msg.content.trim();
+    (content.starts_with("你已添加了") && content.contains("现在可以开始聊天了"))
+        || (content.starts_with("You have added") && content.contains("Start chatting"))
+}
+
+// what auto accept remembers between messages
+#[derive(Default)]
+pub(crate) struct FriendAutomation {
+    // v3 of every request already accepted
+    accepted: HashSet<String>,
+    // accept times within the last hour
+    recent: VecDeque<Instant>,
+    // wxid -> greeting, sent once the "now friends" system message arrives
+    pending_greetings: HashMap<String, String>,
+}
+
+impl FriendAutomation {
+    pub(crate) fn is_accepted(&self, v3: &str) -> bool {
+        self.accepted.contains(v3)
+    }
+
+    /// 一小时内通过的申请数未达到 max_per_hour 时记录本次通过并返回 true
+    pub(crate) fn try_accept(&mut self, v3: &str, max_per_hour: u32) -> bool {
+        let now = Instant::now();
+        while self.recent.front().is_some_and(|&at| now.duration_since(at) >= HOUR) {
+            self.recent.pop_front();
+        }
+        if max_per_hour > 0 && self.recent.len() >= max_per_hour as usize {
+            return false;
+        }
+        self.recent.push_back(now);
+        self.accepted.insert(v3.to_string());
+        true
+    }
+
+    /// 通过失败时撤销记录，之后重新推送的申请还可以再次通过
+    pub(crate) fn undo_accept(&mut self, v3: &str) {
+        self.accepted.remove(v3);
+        self.recent.pop_back();
+    }
+
+    pub(crate) fn add_pending_greeting(&mut self, wxid: String, greeting: String) {
+        self.pending_greetings.insert(wxid, greeting);
+    }
+
+    pub(crate) fn take_pending_greeting(&mut self, wxid: &str) -> Option<String> {
+        self.pending_greetings.remove(wxid)
+    }
+}
@@ -0,0 +1,111 @@
+use roxmltree::Document;
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use super::error::Result;
+use super::{MsgType, WcfClient, WxMsg};
+
+/// 好友申请（type 37 消息）
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FriendRequest {
+    /// 申请人的 wxid
+    pub wxid: String,
+    /// encryptusername，accept_new_friend() 的 v3
+    pub v3: String,
+    /// ticket，accept_new_friend() 的 v4
+    pub v4: String,
+    /// 添加方式，例如 30 为扫码，14 为群聊
+    pub scene: i32,
+    pub nickname: String,
+    /// 申请时的打招呼内容
+    pub greeting: String,
+    /// 通过群聊或名片添加时，来源群或推荐人的名字
+    pub source: String,
+}
+
+impl FriendRequest {
+    /// 解析好友申请，不是 type 37 或 xml 无法解析时返回 None
+    pub fn parse(msg: &WxMsg) -> Option<FriendRequest> {
+        if MsgType::from(msg.r#type as i32) != MsgType::FriendConfirm {
+            return None;
+        }
+        let doc = match Document::parse(msg.content.trim()) {
+            Ok(doc) => doc,
+            Err(e) => {
+                trace!("invalid friend request xml, error={}, content={}", e, msg.content);
+                return None;
+            }
+        };
+        let root = doc.root_element();
+        let attr = |name| root.attribute(name).unwrap_or_default().to_string();
+        let request = FriendRequest {
+            wxid: attr("fromusername"),
+            v3: attr("encryptusername"),
+            v4: attr("ticket"),
+            scene: root.attribute("scene").and_then(|scene| scene.parse().ok()).unwrap_or_default(),
+            nickname: attr("fromnickname"),
+            greeting: attr("content"),
+            source: attr("sourcenickname"),
+        };
+        if request.v3.is_empty() || request.v4.is_empty() {
+            trace!("friend request without v3 or v4, content={}", msg.content);
+            return None;
+        }
+        Some(request)
+    }
+
+    /// 通过默认客户端同意好友申请
+    pub fn accept(&self) -> Result<bool> {
+        self.accept_with(super::default_client())
+    }
+
+    pub fn accept_with(&self, client: &WcfClient) -> Result<bool> {
+        client.accept_new_friend(self.v3.clone(), self.v4.clone(), self.scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUEST_XML: &str = r#"<msg fromusername="wxid_zhangsan123" encryptusername="v3_020b3826fd03010000000000a1f20b2c6b2d2e000000501ea9a3dba12f95f6b60a0536a1adb6c8fb9d2b5ef07c1b0fe0c1a7e0ec3d2f5e1a6c2@stranger" fromnickname="张三" content="我是群聊&quot;周末爬山&quot;的张三" fullpy="zhangsan" shortpy="ZS" imagestatus="3" scene="14" country="CN" province="Guangdong" city="Shenzhen" sign="" percard="1" sex="1" alias="" weibo="" albumflag="0" albumstyle="0" albumbgimgid="" snsflag="273" snsbgimgid="" snsbgobjectid="0" mhash="7c3e2a1b9d0f4e5a6b8c" mfullhash="7c3e2a1b9d0f4e5a6b8c" bigheadimgurl="http://wx.qlogo.cn/mmhead/ver_1/abc/0" smallheadimgurl="http://wx.qlogo.cn/mmhead/ver_1/abc/96" ticket="v4_000b708f0b040000010000000000d2ab5e1e2f3a4b5c6d7e8f90a1b2c3d4e5f6@stranger" opcode="2" googlecontact="" qrticket="" chatroomusername="34567890123@chatroom" sourceusername="" sourcenickname="周末爬山" sharecardusername="" sharecardnickname="" cardversion="" extflag="0"><brandlist count="0" ver="745281003"></brandlist></msg>"#;
+
+    fn msg(r#type: u32, content: &str) -> WxMsg {
+        WxMsg { r#type, sender: "fmessage".into(), content: content.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn parse_request() {
+        let request = FriendRequest::parse(&msg(37, REQUEST_XML)).unwrap();
+        assert_eq!(
+            request,
+            FriendRequest {
+                wxid: "wxid_zhangsan123".into(),
+                v3: "v3_020b3826fd03010000000000a1f20b2c6b2d2e000000501ea9a3dba12f95f6b60a0536a1adb6c8fb9d2b5ef07c1b0fe0c1a7e0ec3d2f5e1a6c2@stranger".into(),
+                v4: "v4_000b708f0b040000010000000000d2ab5e1e2f3a4b5c6d7e8f90a1b2c3d4e5f6@stranger".into(),
+                scene: 14,
+                nickname: "张三".into(),
+                greeting: r#"我是群聊"周末爬山"的张三"#.into(),
+                source: "周末爬山".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_rejects_malformed() {
+        // wrong type
+        assert_eq!(FriendRequest::parse(&msg(1, REQUEST_XML)), None);
+        // truncated xml
+        assert_eq!(FriendRequest::parse(&msg(37, &REQUEST_XML[..200])), None);
+        // not xml
+        assert_eq!(FriendRequest::parse(&msg(37, "加个好友")), None);
+        // no ticket
+        assert_eq!(FriendRequest::parse(&msg(37, &REQUEST_XML.replace("ticket=", "noticket="))), None);
+    }
+
+    #[test]
+    fn parse_defaults_bad_scene() {
+        let request = FriendRequest::parse(&msg(37, &REQUEST_XML.replace(r#"scene="14""#, r#"scene="x""#))).unwrap();
+        assert_eq!(request.scene, 0);
+    }
+}
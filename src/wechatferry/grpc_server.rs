@@ -0,0 +1,251 @@
+use parking_lot::Mutex;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+use tracing::{error, info, trace, warn};
+
+use super::error::{Result, WcfError};
+use super::proto::rpc::wcf_server::{Wcf, WcfServer};
+use super::proto::{self, DbQuery, DbRows, Empty, Functions, RpcContacts, TextMsg};
+use super::{Event, WcfClient, WxMsg};
+
+// how often the blocking tasks check for stop and uninit()
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// GrpcServer 的配置
+#[derive(Clone, Debug)]
+pub struct GrpcServerConfig {
+    /// 监听地址，默认只监听本机
+    pub addr: String,
+    /// 每个 EnableRecvTxt 调用缓存的消息数，客户端读取太慢时丢弃新消息
+    pub stream_capacity: usize,
+}
+
+impl Default for GrpcServerConfig {
+    fn default() -> Self {
+        GrpcServerConfig { addr: "127.0.0.1:50051".into(), stream_capacity: 256 }
+    }
+}
+
+/// 通过 gRPC 提供 wcf 的接口，服务定义见 `proto/wcf_service.proto`，生成的代码在 [`proto::rpc`] 中。
+///
+/// 除 `Call` 直接转发原始请求外，其他 RPC 都调用 WcfClient 上对应的函数，例如 `SendTxt` 受速率限制；
+/// `EnableRecvTxt` 会开启消息接收，并持续推送收到的消息，直到调用被取消。
+/// 在独立的 wcf-grpc 线程中运行，stop() 或客户端 uninit() 后关闭
+pub struct GrpcServer {
+    addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl GrpcServer {
+    /// 监听 config.addr 并开始处理请求，客户端未 init() 时返回 `WcfError::NotInited`
+    pub fn start(client: WcfClient, config: GrpcServerConfig) -> Result<GrpcServer> {
+        if !client.state().sdk_inited {
+            return Err(WcfError::NotInited);
+        }
+        let listener = TcpListener::bind(&config.addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let events = client.subscribe();
+        let service = Service { client, stopped: stopped.clone(), stream_capacity: config.stream_capacity };
+        let thread_stopped = stopped.clone();
+        let thread = thread::Builder::new()
+            .name("wcf-grpc".into())
+            .spawn(move || Self::serve_thread(runtime, listener, service, events, thread_stopped))?;
+        info!("grpc server listening on {}", addr);
+        Ok(GrpcServer { addr, stopped, thread: Mutex::new(Some(thread)) })
+    }
+
+    /// 实际监听的地址，例如 addr 的端口为 0 时
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 停止服务并等待线程退出，正在推送的消息流会被关闭
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.thread.lock().take() {
+            let _ = handle.join();
+        }
+    }
+
+    fn serve_thread(
+        runtime: Runtime,
+        listener: TcpListener,
+        service: Service,
+        events: Receiver<Event>,
+        stopped: Arc<AtomicBool>,
+    ) {
+        trace!("serve_thread()");
+        runtime.block_on(async move {
+            let listener = match tokio::net::TcpListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("failed to register grpc listener, error={}", e);
+                    return;
+                }
+            };
+            let shutdown = async move {
+                let _ = tokio::task::spawn_blocking(move || Self::wait_for_stop(events, &stopped)).await;
+            };
+            let result = tonic::transport::Server::builder()
+                .add_service(WcfServer::new(service))
+                .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+                .await;
+            if let Err(e) = result {
+                error!("grpc server failed, error={}", e);
+            }
+        });
+        info!("grpc server stopped");
+    }
+
+    // returns after stop() or uninit(), which also ends the message streams
+    fn wait_for_stop(events: Receiver<Event>, stopped: &AtomicBool) {
+        while !stopped.load(Ordering::SeqCst) {
+            match events.recv_timeout(POLL_INTERVAL) {
+                Ok(Event::SdkDestroyed | Event::SdkDetached) | Err(RecvTimeoutError::Disconnected) => {
+                    info!("client uninited, stopping grpc server");
+                    stopped.store(true, Ordering::SeqCst);
+                }
+                Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+    }
+}
+
+impl Drop for GrpcServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn to_status(e: WcfError) -> Status {
+    let message = e.to_string();
+    match e {
+        WcfError::InvalidArgument(_) | WcfError::InvalidPath { .. } | WcfError::InvalidReceiver(_) => {
+            Status::invalid_argument(message)
+        }
+        WcfError::NotFound(_) => Status::not_found(message),
+        WcfError::RateLimited { .. } => Status::resource_exhausted(message),
+        WcfError::NotInited | WcfError::SocketDisconnected => Status::unavailable(message),
+        WcfError::Timeout => Status::deadline_exceeded(message),
+        _ => Status::internal(message),
+    }
+}
+
+fn status_response(func: Functions, status: i32) -> proto::Response {
+    proto::Response { func: func.into(), msg: Some(proto::response::Msg::Status(status)) }
+}
+
+#[derive(Clone)]
+struct Service {
+    client: WcfClient,
+    stopped: Arc<AtomicBool>,
+    stream_capacity: usize,
+}
+
+impl Service {
+    // client calls block on the cmd socket, keep them off the runtime thread
+    async fn blocking<T, F>(&self, f: F) -> std::result::Result<Response<T>, Status>
+    where
+        F: FnOnce(&WcfClient) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let client = self.client.clone();
+        match tokio::task::spawn_blocking(move || f(&client)).await {
+            Ok(result) => result.map(Response::new).map_err(to_status),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    fn forward_msgs(
+        events: Receiver<Event>,
+        tx: mpsc::Sender<std::result::Result<WxMsg, Status>>,
+        stopped: &AtomicBool,
+    ) {
+        while !stopped.load(Ordering::SeqCst) && !tx.is_closed() {
+            match events.recv_timeout(POLL_INTERVAL) {
+                Ok(Event::MsgReceived(msg)) => match tx.try_send(Ok(Arc::unwrap_or_clone(msg))) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => warn!("grpc client is too slow, message dropped"),
+                    Err(TrySendError::Closed(_)) => break,
+                },
+                Ok(Event::SdkDestroyed | Event::SdkDetached) => {
+                    let _ = tx.try_send(Err(Status::unavailable(WcfError::NotInited.to_string())));
+                    break;
+                }
+                Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        trace!("grpc message stream closed");
+    }
+}
+
+#[tonic::async_trait]
+impl Wcf for Service {
+    type EnableRecvTxtStream = ReceiverStream<std::result::Result<WxMsg, Status>>;
+
+    async fn call(&self, request: Request<proto::Request>) -> std::result::Result<Response<proto::Response>, Status> {
+        let request = request.into_inner();
+        // the msg socket belongs to the local client, remote callers use EnableRecvTxt instead
+        let func = Functions::try_from(request.func).unwrap_or(Functions::FuncReserved);
+        if matches!(func, Functions::FuncEnableRecvTxt | Functions::FuncDisableRecvTxt) {
+            return Err(Status::invalid_argument("use EnableRecvTxt to receive messages"));
+        }
+        self.blocking(move |client| client.run_cmd(request.func, request.msg)).await
+    }
+
+    async fn is_login(&self, _: Request<Empty>) -> std::result::Result<Response<proto::Response>, Status> {
+        self.blocking(|client| Ok(status_response(Functions::FuncIsLogin, client.is_login()? as i32))).await
+    }
+
+    async fn get_self_wxid(&self, _: Request<Empty>) -> std::result::Result<Response<proto::Response>, Status> {
+        self.blocking(|client| {
+            let msg = client.get_self_wx_id()?.map(proto::response::Msg::Str);
+            Ok(proto::Response { func: Functions::FuncGetSelfWxid.into(), msg })
+        })
+        .await
+    }
+
+    async fn get_contacts(&self, _: Request<Empty>) -> std::result::Result<Response<RpcContacts>, Status> {
+        self.blocking(|client| Ok(client.get_contacts()?.unwrap_or_default())).await
+    }
+
+    async fn send_txt(&self, request: Request<TextMsg>) -> std::result::Result<Response<proto::Response>, Status> {
+        let TextMsg { msg, receiver, aters } = request.into_inner();
+        self.blocking(move |client| {
+            let result = client.send_text(msg, receiver, aters)?;
+            Ok(status_response(Functions::FuncSendTxt, result.status))
+        })
+        .await
+    }
+
+    async fn exec_db_query(&self, request: Request<DbQuery>) -> std::result::Result<Response<DbRows>, Status> {
+        let DbQuery { db, sql } = request.into_inner();
+        self.blocking(move |client| Ok(DbRows { rows: client.exec_db_query(db, sql)? })).await
+    }
+
+    async fn enable_recv_txt(
+        &self,
+        _: Request<Empty>,
+    ) -> std::result::Result<Response<Self::EnableRecvTxtStream>, Status> {
+        // subscribe first, so no message between enable_listen() and the stream is lost
+        let events = self.client.subscribe();
+        self.blocking(|client| client.enable_listen()).await?;
+        let (tx, rx) = mpsc::channel(self.stream_capacity.max(1));
+        let stopped = self.stopped.clone();
+        tokio::task::spawn_blocking(move || Self::forward_msgs(events, tx, &stopped));
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
@@ -0,0 +1,261 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::error::Result;
+use super::{db_value, sql, DbRow, MsgType};
+
+// columns read into DbMessage
+pub(crate) const MSG_COLUMNS: &str = "localId, MsgSvrID, Type, SubType, IsSender, CreateTime, StrTalker, StrContent";
+
+/// MSG*.db 中 MSG 表的一行消息记录
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DbMessage {
+    pub local_id: i64,
+    /// 即 WxMsg.id
+    pub msg_svr_id: u64,
+    pub msg_type: i32,
+    pub sub_type: i32,
+    /// 是否自己发送的
+    pub is_sender: bool,
+    /// 发送时间，unix 时间戳（秒）
+    pub create_time: i64,
+    /// 会话，私聊为 wxid，群聊为群 id
+    pub talker: String,
+    pub content: String,
+}
+
+impl DbMessage {
+    pub fn msg_type(&self) -> MsgType {
+        MsgType::from(self.msg_type)
+    }
+
+    /// 发送时间
+    pub fn created_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.create_time.max(0) as u64)
+    }
+
+    /// 内容是否为文本或 xml，图片、语音、视频等媒体消息的内容无法直接使用
+    pub fn is_textual(&self) -> bool {
+        let media = [MsgType::Image, MsgType::Voice, MsgType::Video, MsgType::Sticker, MsgType::ShortVideo];
+        !self.content.is_empty() && !media.contains(&self.msg_type())
+    }
+}
+
+impl From<DbRow> for DbMessage {
+    fn from(row: DbRow) -> Self {
+        let mut msg = DbMessage::default();
+        for (column, value) in db_value::typed_fields(row) {
+            match column.as_str() {
+                "localId" => msg.local_id = value.as_i64().unwrap_or_default(),
+                "MsgSvrID" => msg.msg_svr_id = value.as_i64().unwrap_or_default() as u64,
+                "Type" => msg.msg_type = value.as_i64().unwrap_or_default() as i32,
+                "SubType" => msg.sub_type = value.as_i64().unwrap_or_default() as i32,
+                "IsSender" => msg.is_sender = value.as_i64().unwrap_or_default() != 0,
+                "CreateTime" => msg.create_time = value.as_i64().unwrap_or_default(),
+                "StrTalker" => msg.talker = value.into_string().unwrap_or_default(),
+                "StrContent" => msg.content = value.into_string().unwrap_or_default(),
+                _ => {}
+            }
+        }
+        msg
+    }
+}
+
+/// `query_messages()` 的查询条件，None 表示不限制
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MessageFilter {
+    /// 会话，私聊为 wxid，群聊为群 id
+    pub talker: Option<String>,
+    /// 不早于该时间
+    pub since: Option<SystemTime>,
+    /// 早于该时间
+    pub until: Option<SystemTime>,
+    pub msg_type: Option<MsgType>,
+    /// 最多返回的条数
+    pub limit: Option<usize>,
+    /// 跳过最新的 offset 条，用于分页
+    pub offset: usize,
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+impl MessageFilter {
+    /// 单个分库的查询语句，每个分库最多取 limit + offset 条，合并后再分页
+    pub(crate) fn to_sql(&self) -> Result<String> {
+        let mut conditions = Vec::new();
+        if let Some(talker) = &self.talker {
+            conditions.push(sql::bind_params("StrTalker = ?", &[talker])?);
+        }
+        if let Some(since) = self.since {
+            conditions.push(format!("CreateTime >= {}", unix_secs(since)));
+        }
+        if let Some(until) = self.until {
+            conditions.push(format!("CreateTime < {}", unix_secs(until)));
+        }
+        if let Some(msg_type) = self.msg_type {
+            conditions.push(format!("Type = {}", i32::from(msg_type)));
+        }
+        let mut sql = format!("SELECT {} FROM MSG", MSG_COLUMNS);
+        if !conditions.is_empty() {
+            sql += &format!(" WHERE {}", conditions.join(" AND "));
+        }
+        sql += " ORDER BY CreateTime DESC, localId DESC";
+        if let Some(limit) = self.limit {
+            sql += &format!(" LIMIT {}", limit + self.offset);
+        }
+        Ok(sql)
+    }
+
+    /// 合并各分库的结果，按时间从新到旧排列后分页
+    pub(crate) fn merge(&self, mut messages: Vec<DbMessage>) -> Vec<DbMessage> {
+        messages.sort_by_key(|msg| Reverse((msg.create_time, msg.local_id)));
+        let messages = messages.into_iter().skip(self.offset);
+        match self.limit {
+            Some(limit) => messages.take(limit).collect(),
+            None => messages.collect(),
+        }
+    }
+}
+
+/// 会话到消息分库的映射缓存，分库列表变化（出现新的分库）时整体失效
+#[derive(Default)]
+pub(crate) struct MsgDbMap {
+    db_names: Vec<String>,
+    // talker -> shards holding its messages, newest first, may be empty
+    talkers: HashMap<String, Vec<String>>,
+}
+
+impl MsgDbMap {
+    /// 缓存的分库，`db_names` 和缓存时不同时先清空
+    pub(crate) fn get(&mut self, db_names: &[String], talker: &str) -> Option<Vec<String>> {
+        if self.db_names != db_names {
+            self.db_names = db_names.to_vec();
+            self.talkers.clear();
+        }
+        self.talkers.get(talker).cloned()
+    }
+
+    pub(crate) fn insert(&mut self, db_names: &[String], talker: &str, shards: Vec<String>) {
+        // the shard list changed while probing, the result may be incomplete
+        if self.db_names == db_names {
+            self.talkers.insert(talker.to_string(), shards);
+        }
+    }
+}
+
+/// 从数据库名中挑出消息分库 MSG0.db、MSG1.db ……，按编号从新到旧排列
+pub(crate) fn msg_db_names(db_names: Vec<String>) -> Vec<String> {
+    let shard = |name: &str| name.strip_prefix("MSG")?.strip_suffix(".db")?.parse::<u32>().ok();
+    let mut names: Vec<(u32, String)> =
+        db_names.into_iter().filter_map(|name| shard(&name).map(|index| (index, name))).collect();
+    names.sort_by_key(|(index, _)| Reverse(*index));
+    names.into_iter().map(|(_, name)| name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wechatferry::DbField;
+
+    fn int(column: &str, value: i64) -> DbField {
+        DbField { r#type: 1, column: column.into(), content: value.to_le_bytes().into() }
+    }
+
+    fn text(column: &str, value: &str) -> DbField {
+        DbField { r#type: 3, column: column.into(), content: value.as_bytes().into() }
+    }
+
+    fn msg(local_id: i64, create_time: i64) -> DbMessage {
+        DbMessage { local_id, create_time, ..Default::default() }
+    }
+
+    fn ids(messages: &[DbMessage]) -> Vec<(i64, i64)> {
+        messages.iter().map(|msg| (msg.create_time, msg.local_id)).collect()
+    }
+
+    #[test]
+    fn db_message_from_row() {
+        let row = DbRow {
+            fields: vec![
+                int("localId", 1024),
+                int("MsgSvrID", 8_123_456_789_012_345_678),
+                int("Type", 49),
+                int("SubType", 57),
+                int("IsSender", 1),
+                int("CreateTime", 1_717_171_717),
+                text("StrTalker", "123@chatroom"),
+                text("StrContent", "<msg><appmsg><title>回复</title></appmsg></msg>"),
+                DbField { r#type: 4, column: "BytesExtra".into(), content: vec![0x0a, 0xff] },
+            ],
+        };
+        let msg = DbMessage::from(row);
+        assert_eq!(msg.local_id, 1024);
+        assert_eq!(msg.msg_svr_id, 8_123_456_789_012_345_678);
+        assert_eq!(msg.msg_type(), MsgType::App);
+        assert_eq!(msg.sub_type, 57);
+        assert!(msg.is_sender);
+        assert_eq!(msg.created_at(), UNIX_EPOCH + Duration::from_secs(1_717_171_717));
+        assert_eq!(msg.talker, "123@chatroom");
+        assert!(msg.is_textual());
+
+        let row =
+            DbRow { fields: vec![int("IsSender", 0), int("Type", 3), DbField { r#type: 5, ..text("CreateTime", "") }] };
+        let msg = DbMessage::from(row);
+        assert!(!msg.is_sender);
+        assert_eq!(msg.created_at(), UNIX_EPOCH);
+        assert!(!msg.is_textual());
+    }
+
+    #[test]
+    fn filter_sql() {
+        let filter = MessageFilter::default();
+        assert_eq!(
+            filter.to_sql().unwrap(),
+            format!("SELECT {} FROM MSG ORDER BY CreateTime DESC, localId DESC", MSG_COLUMNS)
+        );
+
+        let filter = MessageFilter {
+            talker: Some("wxid_a'b".into()),
+            since: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            until: Some(UNIX_EPOCH + Duration::from_secs(1_700_086_400)),
+            msg_type: Some(MsgType::Image),
+            limit: Some(20),
+            offset: 40,
+        };
+        assert_eq!(
+            filter.to_sql().unwrap(),
+            format!(
+                "SELECT {} FROM MSG WHERE StrTalker = 'wxid_a''b' AND CreateTime >= 1700000000 \
+                 AND CreateTime < 1700086400 AND Type = 3 ORDER BY CreateTime DESC, localId DESC LIMIT 60",
+                MSG_COLUMNS
+            )
+        );
+    }
+
+    #[test]
+    fn merge_shards_by_create_time() {
+        // each shard is already newest first, MSG1.db holds the newer messages
+        let msg1 = [msg(7, 300), msg(6, 200), msg(5, 200)];
+        let msg0 = [msg(9, 250), msg(8, 100), msg(3, 50)];
+        let messages = || msg1.iter().chain(&msg0).cloned().collect::<Vec<_>>();
+
+        let all = MessageFilter::default().merge(messages());
+        assert_eq!(ids(&all), [(300, 7), (250, 9), (200, 6), (200, 5), (100, 8), (50, 3)]);
+
+        let page = MessageFilter { limit: Some(2), offset: 1, ..Default::default() }.merge(messages());
+        assert_eq!(ids(&page), [(250, 9), (200, 6)]);
+
+        let past_end = MessageFilter { limit: Some(2), offset: 6, ..Default::default() }.merge(messages());
+        assert!(past_end.is_empty());
+    }
+
+    #[test]
+    fn shard_names() {
+        let names = ["MicroMsg.db", "MSG0.db", "MSG10.db", "MSG2.db", "MediaMSG0.db", "MSG.db"];
+        assert_eq!(msg_db_names(names.map(String::from).to_vec()), ["MSG10.db", "MSG2.db", "MSG0.db"]);
+    }
+}
@@ -0,0 +1,214 @@
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{error, info, trace};
+
+use super::error::{Result, WcfError};
+use super::{Event, WcfClient};
+
+// how often the server thread checks for stop and uninit()
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// HttpServer 的配置
+#[derive(Clone, Debug)]
+pub struct HttpServerConfig {
+    /// 监听地址，默认只监听本机
+    pub addr: String,
+    /// 设置时请求需带上 `X-Api-Key` 头，否则返回 401
+    pub secret: Option<String>,
+    /// 请求体的最大字节数
+    pub max_body: usize,
+}
+
+impl Default for HttpServerConfig {
+    fn default() -> Self {
+        HttpServerConfig { addr: "127.0.0.1:8080".into(), secret: None, max_body: 1 << 20 }
+    }
+}
+
+#[derive(Deserialize)]
+struct SendTextBody {
+    receiver: String,
+    text: String,
+    #[serde(default)]
+    aters: String,
+}
+
+#[derive(Deserialize)]
+struct SendPathBody {
+    receiver: String,
+    path: PathBuf,
+}
+
+/// 返回给调用方的错误，error 为 WcfError 的变体名，例如 "InvalidReceiver"
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+    message: String,
+}
+
+// (status, body) of a failed request
+type HttpError = (u16, ErrorBody);
+
+fn http_error(status: u16, error: &str, message: impl Into<String>) -> HttpError {
+    (status, ErrorBody { error: error.into(), message: message.into() })
+}
+
+impl From<WcfError> for HttpError {
+    fn from(e: WcfError) -> Self {
+        let status = match e {
+            WcfError::InvalidArgument(_) | WcfError::InvalidPath { .. } | WcfError::InvalidReceiver(_) => 400,
+            WcfError::NotFound(_) => 404,
+            WcfError::RateLimited { .. } => 429,
+            WcfError::NotInited | WcfError::SocketDisconnected | WcfError::Timeout => 503,
+            _ => 500,
+        };
+        // the variant name is the Debug output up to its fields
+        let debug = format!("{:?}", e);
+        let variant = debug.split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default().to_string();
+        (status, ErrorBody { error: variant, message: e.to_string() })
+    }
+}
+
+/// 内嵌的 HTTP 接口，其他服务可以通过它发送消息、查询联系人：
+///
+/// - `POST /send/text`，body 为 `{"receiver": "...", "text": "...", "aters": "..."}`
+/// - `POST /send/image`、`POST /send/file`，body 为 `{"receiver": "...", "path": "..."}`，path 为本机上的文件
+/// - `GET /contacts`、`GET /rooms/{room_id}/members`、`GET /stats`、`GET /health`
+///
+/// 发送受速率限制；出错时返回 json `{"error": "WcfError 变体名", "message": "..."}`。
+/// 在独立的 wcf-http 线程中运行，stop() 或客户端 uninit() 后关闭
+pub struct HttpServer {
+    addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl HttpServer {
+    /// 监听 config.addr 并开始处理请求
+    pub fn start(client: WcfClient, config: HttpServerConfig) -> Result<HttpServer> {
+        let server = Server::http(&config.addr).map_err(|e| WcfError::Io(std::io::Error::other(e.to_string())))?;
+        let addr = server.server_addr().to_ip().ok_or_else(|| WcfError::InvalidArgument("not an ip address".into()))?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+        let events = client.subscribe();
+        let thread = thread::Builder::new()
+            .name("wcf-http".into())
+            .spawn(move || Self::serve_thread(server, client, config, events, thread_stopped))?;
+        info!("http server listening on {}", addr);
+        Ok(HttpServer { addr, stopped, thread: Mutex::new(Some(thread)) })
+    }
+
+    /// 实际监听的地址，例如 addr 的端口为 0 时
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 停止服务并等待线程退出，正在处理的请求会先完成
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.thread.lock().take() {
+            let _ = handle.join();
+        }
+    }
+
+    fn serve_thread(
+        server: Server,
+        client: WcfClient,
+        config: HttpServerConfig,
+        events: Receiver<Event>,
+        stopped: Arc<AtomicBool>,
+    ) {
+        trace!("serve_thread()");
+        while !stopped.load(Ordering::SeqCst) {
+            if events.try_iter().any(|event| matches!(event, Event::SdkDestroyed | Event::SdkDetached)) {
+                info!("client uninited, stopping http server");
+                break;
+            }
+            match server.recv_timeout(POLL_INTERVAL) {
+                Ok(Some(request)) => Self::respond(&client, &config, request),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("http server failed, error={}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn respond(client: &WcfClient, config: &HttpServerConfig, mut request: Request) {
+        let (status, body) = match Self::route(client, config, &mut request) {
+            Ok(body) => (200, body),
+            Err((status, body)) => (status, json!(body)),
+        };
+        trace!("http {} {} -> {}", request.method(), request.url(), status);
+        let header = Header::from_bytes("Content-Type", "application/json").expect("static header is valid");
+        let response = Response::from_string(body.to_string()).with_status_code(status).with_header(header);
+        if let Err(e) = request.respond(response) {
+            error!("failed to send http response, error={}", e);
+        }
+    }
+
+    fn route(
+        client: &WcfClient,
+        config: &HttpServerConfig,
+        request: &mut Request,
+    ) -> std::result::Result<serde_json::Value, HttpError> {
+        if let Some(secret) = &config.secret {
+            let key = request.headers().iter().find(|header| header.field.equiv("X-Api-Key"));
+            if key.is_none_or(|key| key.value.as_str() != secret) {
+                return Err(http_error(401, "Unauthorized", "missing or wrong X-Api-Key"));
+            }
+        }
+        let url = request.url().split('?').next().unwrap_or_default().to_string();
+        let segments: Vec<&str> = url.trim_matches('/').split('/').collect();
+        match (request.method(), segments.as_slice()) {
+            (Method::Get, ["health"]) => Ok(json!({ "status": "ok", "logged_in": client.is_login().unwrap_or(false) })),
+            (Method::Get, ["stats"]) => Ok(json!(client.stats())),
+            (Method::Get, ["contacts"]) => Ok(json!(client.query_all_contact_info()?)),
+            (Method::Get, ["rooms", room_id, "members"]) => Ok(json!(client.get_room_members(room_id.to_string())?)),
+            (Method::Post, ["send", "text"]) => {
+                let body: SendTextBody = Self::read_json(config, request)?;
+                Ok(json!(client.send_text(body.text, body.receiver, body.aters)?))
+            }
+            (Method::Post, ["send", "image"]) => {
+                let body: SendPathBody = Self::read_json(config, request)?;
+                Ok(json!(client.send_image(body.path, body.receiver)?))
+            }
+            (Method::Post, ["send", "file"]) => {
+                let body: SendPathBody = Self::read_json(config, request)?;
+                Ok(json!(client.send_file(body.path, body.receiver)?))
+            }
+            _ => Err(http_error(404, "NotFound", format!("no route for {} {}", request.method(), url))),
+        }
+    }
+
+    fn read_json<T: DeserializeOwned>(
+        config: &HttpServerConfig,
+        request: &mut Request,
+    ) -> std::result::Result<T, HttpError> {
+        let mut body = vec![];
+        let limit = config.max_body as u64 + 1;
+        request.as_reader().take(limit).read_to_end(&mut body).map_err(WcfError::from)?;
+        if body.len() > config.max_body {
+            return Err(http_error(413, "PayloadTooLarge", format!("body exceeds {} bytes", config.max_body)));
+        }
+        serde_json::from_slice(&body).map_err(|e| http_error(400, "InvalidArgument", e.to_string()))
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::error::{Result, WcfError};
+
+// separator after an @name in a mention, see send_text_with_mentions()
+const MENTION_END: char = '\u{2005}';
+// cancel flag is checked this often while waiting
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 模拟人工发送的参数，见 `send_text_humanized()` 和 `set_humanize()`
+#[derive(Clone, Debug)]
+pub struct HumanizeOptions {
+    /// 每条消息发送前随机等待 min_delay 到 max_delay
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// 文本超过这么多字符时拆分为多条发送，None 表示不拆分，只对 send_text_humanized() 有效
+    pub max_part_chars: Option<usize>,
+    /// 拆分后各条之间额外等待的时间
+    pub part_interval: Duration,
+    /// 置为 true 后，正在等待的发送立即返回 `WcfError::Cancelled`，例如退出时
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Default for HumanizeOptions {
+    fn default() -> Self {
+        HumanizeOptions {
+            min_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(2),
+            max_part_chars: None,
+            part_interval: Duration::from_secs(3),
+            cancel: None,
+        }
+    }
+}
+
+impl HumanizeOptions {
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    /// 等待 duration，期间被取消时返回 Err
+    pub(crate) fn sleep(&self, duration: Duration) -> Result<()> {
+        let deadline = Instant::now() + duration;
+        loop {
+            if self.is_cancelled() {
+                return Err(WcfError::Cancelled);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            thread::sleep((deadline - now).min(CANCEL_CHECK_INTERVAL));
+        }
+    }
+
+    /// min_delay 到 max_delay 之间的随机时间
+    pub(crate) fn jitter(&self) -> Duration {
+        let (min, max) = (self.min_delay, self.max_delay.max(self.min_delay));
+        let random = RandomState::new().build_hasher().finish();
+        min + (max - min).mul_f64(random as f64 / u64::MAX as f64)
+    }
+}
+
+// byte ranges of "@name\u{2005}" tokens, which must stay in one part
+fn mention_ranges(text: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    for (start, _) in text.match_indices('@') {
+        let rest = &text[start..];
+        if let Some(end) = rest.find(MENTION_END).filter(|&end| !rest[..end].contains('\n')) {
+            ranges.push((start, start + end + MENTION_END.len_utf8()));
+        }
+    }
+    ranges
+}
+
+/// 将 text 拆分为每段最多 max_chars 个字符，尽量在换行或空白处断开，不会拆开 @mention
+pub(crate) fn split_text(text: &str, max_chars: usize) -> Vec<&str> {
+    let max_chars = max_chars.max(1);
+    let mentions = mention_ranges(text);
+    let inside_mention = |pos: usize| mentions.iter().any(|&(start, end)| start < pos && pos < end);
+    let mut parts = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let rest = &text[start..];
+        let limit = match rest.char_indices().nth(max_chars) {
+            Some((offset, _)) => start + offset,
+            None => {
+                parts.push(rest);
+                break;
+            }
+        };
+        // candidate cuts are char boundaries in (start, limit], latest first
+        let candidates = text[start..limit].char_indices().skip(1).map(|(offset, _)| start + offset).chain([limit]);
+        let candidates: Vec<usize> = candidates.filter(|&pos| !inside_mention(pos)).collect();
+        let after_space = candidates.iter().rev().find(|&&pos| text[..pos].ends_with(char::is_whitespace));
+        let cut = match after_space.or(candidates.last()) {
+            Some(&cut) => cut,
+            // a mention longer than max_chars, keep it whole
+            None => mentions.iter().find(|&&(s, e)| s < limit && limit < e).map_or(limit, |&(_, end)| end),
+        };
+        parts.push(&text[start..cut]);
+        start = cut;
+    }
+    parts
+}
+
+/// 包含 @mention 的部分需要带上 aters
+pub(crate) fn has_mention(part: &str) -> bool {
+    !mention_ranges(part).is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn char_counts(parts: &[&str]) -> Vec<usize> {
+        parts.iter().map(|part| part.chars().count()).collect()
+    }
+
+    #[test]
+    fn split_cjk_at_max_chars() {
+        let text = "床前明月光疑是地上霜举头望明月低头思故乡";
+        let parts = split_text(text, 5);
+        assert_eq!(parts, ["床前明月光", "疑是地上霜", "举头望明月", "低头思故乡"]);
+        assert_eq!(split_text(text, 6).concat(), text);
+        assert_eq!(char_counts(&split_text(text, 6)), [6, 6, 6, 2]);
+        assert_eq!(split_text(text, 100), [text]);
+        assert!(split_text("", 5).is_empty());
+    }
+
+    #[test]
+    fn split_prefers_whitespace() {
+        assert_eq!(split_text("你好 世界 再见", 4), ["你好 ", "世界 ", "再见"]);
+        assert_eq!(split_text("第一行\n第二行", 6), ["第一行\n", "第二行"]);
+        // no whitespace in range, cut at max_chars
+        assert_eq!(split_text("ab cdefgh", 4), ["ab ", "cdef", "gh"]);
+    }
+
+    #[test]
+    fn split_keeps_mention_whole() {
+        // a plain cut at 4 chars would fall inside "@张三\u{2005}"
+        let text = "大家好@张三\u{2005}请看";
+        let parts = split_text(text, 4);
+        assert_eq!(parts, ["大家好", "@张三\u{2005}", "请看"]);
+        assert!(!has_mention(parts[0]));
+        assert!(has_mention(parts[1]));
+        assert!(!has_mention(parts[2]));
+
+        // "@" without the separator is not a mention and may be cut
+        assert_eq!(split_text("a@bcdef", 3), ["a@b", "cde", "f"]);
+    }
+
+    #[test]
+    fn split_mention_longer_than_max_chars() {
+        let text = "@很长很长的群昵称\u{2005}你好";
+        let parts = split_text(text, 3);
+        assert_eq!(parts, ["@很长很长的群昵称\u{2005}", "你好"]);
+        assert_eq!(split_text("嗨@很长的名字\u{2005}", 2), ["嗨", "@很长的名字\u{2005}"]);
+    }
+
+    #[test]
+    fn sleep_returns_when_cancelled() {
+        let cancel = Arc::new(AtomicBool::new(true));
+        let options = HumanizeOptions { cancel: Some(cancel.clone()), ..Default::default() };
+        assert!(matches!(options.sleep(Duration::from_secs(60)), Err(WcfError::Cancelled)));
+        cancel.store(false, Ordering::Relaxed);
+        assert!(options.sleep(Duration::from_millis(1)).is_ok());
+        let jitter = options.jitter();
+        assert!(options.min_delay <= jitter && jitter <= options.max_delay);
+    }
+}
@@ -0,0 +1,126 @@
+use super::error::{Result, WcfError};
+use super::{RichText, SendResult, WcfClient};
+
+// longer text is cut off by the client anyway
+const MAX_TITLE_CHARS: usize = 64;
+const MAX_DIGEST_CHARS: usize = 120;
+
+/// 链接卡片，通过 send_rich_text() 发送，例如：
+/// `LinkCard::new("标题", "https://example.com").description("摘要").send_to("wxid_xxx")?`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LinkCard {
+    title: String,
+    url: String,
+    description: String,
+    thumb_url: String,
+    name: String,
+    account: String,
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    text.chars().take(max_chars).collect()
+}
+
+fn check_url(url: &str) -> Result<()> {
+    if url.starts_with("https://") || url.starts_with("http://") {
+        Ok(())
+    } else {
+        Err(WcfError::InvalidArgument(format!("url should start with http:// or https://: {}", url)))
+    }
+}
+
+impl LinkCard {
+    pub fn new<T: Into<String>, U: Into<String>>(title: T, url: U) -> Self {
+        LinkCard { title: title.into(), url: url.into(), ..Default::default() }
+    }
+
+    /// 标题下方的摘要
+    pub fn description<T: Into<String>>(mut self, description: T) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    /// 右侧的缩略图
+    pub fn thumb_url<T: Into<String>>(mut self, thumb_url: T) -> Self {
+        self.thumb_url = thumb_url.into();
+        self
+    }
+
+    /// 卡片底部显示的来源名字，和 account 一起使用，可以显示为公众号
+    pub fn name<T: Into<String>>(mut self, name: T) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// 来源公众号的 id，例如 gh_xxx
+    pub fn account<T: Into<String>>(mut self, account: T) -> Self {
+        self.account = account.into();
+        self
+    }
+
+    /// 生成发给 receiver 的 RichText，url 不是 http(s) 时返回 `WcfError::InvalidArgument`，过长的标题和摘要会被截断
+    pub fn to_rich_text(&self, receiver: &str) -> Result<RichText> {
+        check_url(&self.url)?;
+        if !self.thumb_url.is_empty() {
+            check_url(&self.thumb_url)?;
+        }
+        Ok(RichText {
+            name: self.name.clone(),
+            account: self.account.clone(),
+            title: truncate(&self.title, MAX_TITLE_CHARS),
+            digest: truncate(&self.description, MAX_DIGEST_CHARS),
+            url: self.url.clone(),
+            thumburl: self.thumb_url.clone(),
+            receiver: receiver.to_string(),
+        })
+    }
+
+    pub fn send_to_with(&self, client: &WcfClient, receiver: &str) -> Result<SendResult> {
+        client.send_rich_text(self.to_rich_text(receiver)?)
+    }
+
+    /// 同 send_to_with()，使用默认客户端
+    pub fn send_to(&self, receiver: &str) -> Result<SendResult> {
+        self.send_to_with(super::default_client(), receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rich_text() {
+        let card = LinkCard::new("标题", "https://example.com/a?b=1&c=<2>")
+            .description("摘要")
+            .thumb_url("https://example.com/thumb.jpg")
+            .name("公众号")
+            .account("gh_1234567890ab");
+        let expected = RichText {
+            name: "公众号".into(),
+            account: "gh_1234567890ab".into(),
+            title: "标题".into(),
+            digest: "摘要".into(),
+            url: "https://example.com/a?b=1&c=<2>".into(),
+            thumburl: "https://example.com/thumb.jpg".into(),
+            receiver: "wxid_a".into(),
+        };
+        assert_eq!(card.to_rich_text("wxid_a").unwrap(), expected);
+    }
+
+    #[test]
+    fn long_text_is_truncated() {
+        let rich_text = LinkCard::new("标".repeat(100), "http://example.com").description("摘".repeat(200));
+        let rich_text = rich_text.to_rich_text("wxid_a").unwrap();
+        assert_eq!(rich_text.title, "标".repeat(MAX_TITLE_CHARS));
+        assert_eq!(rich_text.digest, "摘".repeat(MAX_DIGEST_CHARS));
+    }
+
+    #[test]
+    fn urls_must_be_http() {
+        let card = LinkCard::new("标题", "javascript:alert(1)");
+        assert!(matches!(card.to_rich_text("wxid_a"), Err(WcfError::InvalidArgument(_))));
+        let card = LinkCard::new("标题", "https://example.com").thumb_url("file:///C:/a.jpg");
+        assert!(matches!(card.to_rich_text("wxid_a"), Err(WcfError::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+
+use super::error::Result;
+use super::json_file;
+use super::{MsgType, WxMsg};
+
+/// 接收消息的过滤条件，见 `set_listen_filter()`，默认不过滤任何消息
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ListenFilter {
+    /// 忽略自己发送的消息，包括手机上发送的和机器人发送的，避免自己回复自己
+    pub ignore_self: bool,
+    /// 只接收这些群的群消息，None 表示不限制，不影响私聊消息
+    pub allowed_rooms: Option<HashSet<String>>,
+    /// 忽略这些 wxid 发送的消息，包括私聊和群聊
+    pub blocked_senders: HashSet<String>,
+    /// 只接收这些类型的消息，None 表示不限制
+    pub allowed_types: Option<HashSet<MsgType>>,
+    /// 被过滤的消息以 `Event::MsgFiltered` 发出，便于调试
+    pub report_filtered: bool,
+}
+
+impl ListenFilter {
+    /// 消息是否可以通过
+    pub fn accepts(&self, msg: &WxMsg) -> bool {
+        if self.ignore_self && msg.is_self {
+            return false;
+        }
+        if self.blocked_senders.contains(&msg.sender) {
+            return false;
+        }
+        if let Some(rooms) = &self.allowed_rooms {
+            if msg.is_group && !rooms.contains(&msg.roomid) {
+                return false;
+            }
+        }
+        if let Some(types) = &self.allowed_types {
+            if !types.contains(&MsgType::from(msg.r#type as i32)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// ListenFilter 中可以持久化的黑名单和群白名单，见 `persist_access_lists()`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct AccessLists {
+    pub(crate) blocked_senders: HashSet<String>,
+    pub(crate) allowed_rooms: Option<HashSet<String>>,
+}
+
+impl AccessLists {
+    pub(crate) fn of(filter: &ListenFilter) -> Self {
+        AccessLists { blocked_senders: filter.blocked_senders.clone(), allowed_rooms: filter.allowed_rooms.clone() }
+    }
+
+    pub(crate) fn apply_to(self, filter: &mut ListenFilter) {
+        filter.blocked_senders = self.blocked_senders;
+        filter.allowed_rooms = self.allowed_rooms;
+    }
+
+    /// 文件不存在时返回 None
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>> {
+        json_file::load(path)
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        json_file::save(self, path)
+    }
+}
@@ -1,45 +1,264 @@
-use anyhow::{anyhow, Result};
-use libloading::{Library, Symbol};
-use once_cell::sync::{Lazy, OnceCell};
-use parking_lot::Mutex;
-
-// check sdk API definition from wcf/include/sdk.h
-const SDK_DLL: &str = "sdk.dll";
-const WX_INIT_SDK: &str = "WxInitSDK";
-const WX_DESTROY_SDK: &str = "WxDestroySDK";
-type FnWxInitSDK = unsafe extern "C" fn(bool, i32) -> i32;
-type FnWxDestroySDK = unsafe extern "C" fn() -> i32;
-
-static DLL_LOADED: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
-static SDK_LIB: OnceCell<Library> = OnceCell::new();
-static SDK_FN_INIT_SDK: OnceCell<Symbol<FnWxInitSDK>> = OnceCell::new();
-static SDK_FN_DESTROY_SDK: OnceCell<Symbol<FnWxDestroySDK>> = OnceCell::new();
-
-pub fn load_sdk_dll() -> Result<bool> {
-    let mut loaded = DLL_LOADED.lock();
-    if *loaded {
-        return Ok(false); // load only once
-    }
-    unsafe {
-        SDK_LIB.set(Library::new(SDK_DLL)?).map_err(|_| anyhow!("failed to save {}", SDK_DLL))?;
-        let lib = SDK_LIB.get().unwrap();
-        let fn_wx_init_sdk = lib.get(WX_INIT_SDK.as_bytes())?;
-        let fn_wx_destroy_sdk = lib.get(WX_DESTROY_SDK.as_bytes())?;
-        SDK_FN_INIT_SDK.set(fn_wx_init_sdk).map_err(|_| anyhow!("failed to set {}", WX_INIT_SDK))?;
-        SDK_FN_DESTROY_SDK.set(fn_wx_destroy_sdk).map_err(|_| anyhow!("failed to set {}", WX_DESTROY_SDK))?;
-    }
-    *loaded = true;
-    Ok(true)
-}
-
-pub fn wx_init_sdk(debug: bool, port: i32) -> Result<i32> {
-    let target_fn = SDK_FN_INIT_SDK.get().ok_or_else(|| anyhow!("no WxInitSDK fn, sdk dll not load"))?;
-    let result = unsafe { target_fn(debug, port) };
-    Ok(result)
-}
-
-pub fn wx_destroy_sdk() -> Result<i32> {
-    let target_fn = SDK_FN_DESTROY_SDK.get().ok_or_else(|| anyhow!("no WxDestroySDK fn, sdk dll not load"))?;
-    let result = unsafe { target_fn() };
-    Ok(result)
+use super::error::{Result, WcfError};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "mock-sdk")]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(feature = "mock-sdk")]
+use std::sync::Arc;
+
+// sdk.dll is only available on windows, and can be left out by disabling the real-sdk feature
+#[cfg(all(windows, feature = "real-sdk"))]
+mod dll {
+    use super::{Result, WcfError};
+    use libloading::Library;
+    use once_cell::sync::Lazy;
+    use parking_lot::Mutex;
+    use std::path::{Path, PathBuf};
+    use tracing::{trace, warn};
+
+    // check sdk API definition from wcf/include/sdk.h
+    const SDK_DLL: &str = "sdk.dll";
+    // overrides the default search path when no path is given to init
+    const SDK_PATH_ENV: &str = "WCF_SDK_PATH";
+    const WX_INIT_SDK: &str = "WxInitSDK";
+    const WX_DESTROY_SDK: &str = "WxDestroySDK";
+    type FnWxInitSDK = unsafe extern "C" fn(bool, i32) -> i32;
+    type FnWxDestroySDK = unsafe extern "C" fn() -> i32;
+
+    // the fn pointers are copied out of their symbols, they are valid only while `_lib` is loaded
+    struct SdkLib {
+        fn_init_sdk: FnWxInitSDK,
+        fn_destroy_sdk: FnWxDestroySDK,
+        // where sdk.dll was found, spy.dll and the logs are next to it
+        dir: Option<PathBuf>,
+        _lib: Library,
+    }
+
+    // held while calling into the dll, so it cannot be unloaded during a call
+    static SDK_LIB: Lazy<Mutex<Option<SdkLib>>> = Lazy::new(|| Mutex::new(None));
+
+    // a directory means sdk.dll inside it
+    fn resolve_sdk_path(sdk_path: Option<&Path>) -> PathBuf {
+        let path = match sdk_path {
+            Some(path) => path.to_path_buf(),
+            None => match std::env::var_os(SDK_PATH_ENV) {
+                Some(path) => PathBuf::from(path),
+                None => return PathBuf::from(SDK_DLL),
+            },
+        };
+        if path.is_dir() {
+            path.join(SDK_DLL)
+        } else {
+            path
+        }
+    }
+
+    // a bare name is searched in the folder of the exe first, then the current directory
+    fn sdk_dir_of(path: &Path) -> Option<PathBuf> {
+        if path.parent().is_none_or(|dir| dir.as_os_str().is_empty()) {
+            let exe_dir = std::env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf));
+            let cwd = std::env::current_dir().ok();
+            return exe_dir.into_iter().chain(cwd).find(|dir| dir.join(path).is_file());
+        }
+        let path = std::env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.to_path_buf());
+        path.parent().map(Path::to_path_buf)
+    }
+
+    unsafe fn open_library(path: &Path) -> std::result::Result<Library, libloading::Error> {
+        use libloading::os::windows;
+        if path.parent().is_none_or(|dir| dir.as_os_str().is_empty()) {
+            return Library::new(path); // a bare name, use the default search order
+        }
+        // resolve dependencies from the folder of sdk.dll, sdk.dll looks for spy.dll next to itself as well
+        let path = std::env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.to_path_buf());
+        Ok(windows::Library::load_with_flags(path, windows::LOAD_WITH_ALTERED_SEARCH_PATH)?.into())
+    }
+
+    pub fn load_sdk_dll(sdk_path: Option<&Path>) -> Result<bool> {
+        let mut sdk_lib = SDK_LIB.lock();
+        if sdk_lib.is_some() {
+            if sdk_path.is_some() {
+                warn!("sdk dll already loaded, ignored sdk_path={:?}", sdk_path);
+            }
+            return Ok(false); // load only once until unloaded
+        }
+        let path = resolve_sdk_path(sdk_path);
+        trace!("loading sdk dll from {:?}", path);
+        let dll_load_error = |source| WcfError::DllLoad { path: path.clone(), source };
+        unsafe {
+            let lib = open_library(&path).map_err(dll_load_error)?;
+            let fn_init_sdk = *lib.get::<FnWxInitSDK>(WX_INIT_SDK.as_bytes()).map_err(dll_load_error)?;
+            let fn_destroy_sdk = *lib.get::<FnWxDestroySDK>(WX_DESTROY_SDK.as_bytes()).map_err(dll_load_error)?;
+            *sdk_lib = Some(SdkLib { fn_init_sdk, fn_destroy_sdk, dir: sdk_dir_of(&path), _lib: lib });
+        }
+        Ok(true)
+    }
+
+    /// unload the dll, the next load_sdk_dll() loads it again, returns false if not loaded
+    pub fn unload_sdk_dll() -> bool {
+        SDK_LIB.lock().take().is_some()
+    }
+
+    pub fn sdk_dir() -> Option<PathBuf> {
+        SDK_LIB.lock().as_ref().and_then(|sdk_lib| sdk_lib.dir.clone())
+    }
+
+    pub fn wx_init_sdk(debug: bool, port: i32) -> Result<i32> {
+        let sdk_lib = SDK_LIB.lock();
+        let sdk_lib = sdk_lib.as_ref().ok_or(WcfError::DllNotLoaded)?;
+        let result = unsafe { (sdk_lib.fn_init_sdk)(debug, port) };
+        Ok(result)
+    }
+
+    pub fn wx_destroy_sdk() -> Result<i32> {
+        let sdk_lib = SDK_LIB.lock();
+        let sdk_lib = sdk_lib.as_ref().ok_or(WcfError::DllNotLoaded)?;
+        let result = unsafe { (sdk_lib.fn_destroy_sdk)() };
+        Ok(result)
+    }
+}
+
+// same API as above, loading always fails so init() returns a clear error
+#[cfg(not(all(windows, feature = "real-sdk")))]
+mod dll {
+    use super::{Result, WcfError};
+    use std::path::{Path, PathBuf};
+
+    pub fn load_sdk_dll(_sdk_path: Option<&Path>) -> Result<bool> {
+        Err(WcfError::SdkUnavailable)
+    }
+
+    pub fn unload_sdk_dll() -> bool {
+        false
+    }
+
+    pub fn sdk_dir() -> Option<PathBuf> {
+        None
+    }
+
+    pub fn wx_init_sdk(_debug: bool, _port: i32) -> Result<i32> {
+        Err(WcfError::SdkUnavailable)
+    }
+
+    pub fn wx_destroy_sdk() -> Result<i32> {
+        Err(WcfError::SdkUnavailable)
+    }
+}
+
+/// 注入微信的 spy dll，由 init() 的 debug 参数决定
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpyVariant {
+    /// spy.dll
+    Release,
+    /// spy_debug.dll，输出更详细的日志
+    Debug,
+}
+
+impl SpyVariant {
+    pub fn from_debug(debug: bool) -> Self {
+        if debug {
+            SpyVariant::Debug
+        } else {
+            SpyVariant::Release
+        }
+    }
+
+    /// dll 的文件名，与 sdk.dll 在同一目录下
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            SpyVariant::Release => "spy.dll",
+            SpyVariant::Debug => "spy_debug.dll",
+        }
+    }
+}
+
+/// sdk.dll 的加载和调用，通过 `WcfClient::with_loader()` 替换，例如在没有微信的环境中测试
+pub trait SdkLoader: Send + Sync {
+    /// 加载 sdk，sdk_path 说明见 `InitOptions::sdk_path`，已加载时返回 Ok(false)
+    fn load(&self, sdk_path: Option<&Path>) -> Result<bool>;
+    /// 卸载 sdk，未加载时返回 false
+    fn unload(&self) -> bool;
+    /// 对应 WxInitSDK，返回 0 表示成功
+    fn init_sdk(&self, debug: bool, port: i32) -> Result<i32>;
+    /// 对应 WxDestroySDK，返回 0 表示成功
+    fn destroy_sdk(&self) -> Result<i32>;
+    /// 已加载的 sdk.dll 所在的目录，spy dll 和日志都在这里，不知道时返回 None，此时 init() 不检查 spy dll
+    fn sdk_dir(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// 默认的 loader，通过 libloading 加载 sdk.dll，dll 在进程内共享。
+///
+/// 只在 windows 上且开启 real-sdk feature（默认开启）时可用，否则加载时返回 `WcfError::SdkUnavailable`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DllSdkLoader;
+
+impl SdkLoader for DllSdkLoader {
+    fn load(&self, sdk_path: Option<&Path>) -> Result<bool> {
+        dll::load_sdk_dll(sdk_path)
+    }
+
+    fn unload(&self) -> bool {
+        dll::unload_sdk_dll()
+    }
+
+    fn init_sdk(&self, debug: bool, port: i32) -> Result<i32> {
+        dll::wx_init_sdk(debug, port)
+    }
+
+    fn destroy_sdk(&self) -> Result<i32> {
+        dll::wx_destroy_sdk()
+    }
+
+    fn sdk_dir(&self) -> Option<PathBuf> {
+        dll::sdk_dir()
+    }
+}
+
+/// 不加载任何 dll，假装 sdk 初始化成功，cmd socket 等需要另行提供，例如本地的 nng Pair1 服务。
+///
+/// clone 之间共享加载状态，交给 `WcfClient::with_loader()` 后仍可以通过保留的 clone 查询 `loads()`
+#[cfg(feature = "mock-sdk")]
+#[derive(Clone, Debug, Default)]
+pub struct MockSdkLoader {
+    loaded: Arc<AtomicBool>,
+    loads: Arc<AtomicUsize>,
+}
+
+#[cfg(feature = "mock-sdk")]
+impl MockSdkLoader {
+    /// load() 实际加载的次数，已加载时再次 load() 不计入
+    pub fn loads(&self) -> usize {
+        self.loads.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(feature = "mock-sdk")]
+impl SdkLoader for MockSdkLoader {
+    fn load(&self, _sdk_path: Option<&Path>) -> Result<bool> {
+        let loaded = !self.loaded.swap(true, Ordering::SeqCst);
+        if loaded {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(loaded)
+    }
+
+    fn unload(&self) -> bool {
+        self.loaded.swap(false, Ordering::SeqCst)
+    }
+
+    fn init_sdk(&self, _debug: bool, _port: i32) -> Result<i32> {
+        match self.loaded.load(Ordering::SeqCst) {
+            true => Ok(0),
+            false => Err(WcfError::DllNotLoaded),
+        }
+    }
+
+    fn destroy_sdk(&self) -> Result<i32> {
+        match self.loaded.load(Ordering::SeqCst) {
+            true => Ok(0),
+            false => Err(WcfError::DllNotLoaded),
+        }
+    }
 }
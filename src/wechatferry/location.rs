@@ -0,0 +1,113 @@
+use roxmltree::Document;
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use super::{MsgType, WxMsg};
+
+/// 位置消息（type 48）
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LocationMsg {
+    /// 纬度
+    pub lat: f64,
+    /// 经度
+    pub lng: f64,
+    /// 地址
+    pub label: String,
+    /// 地点名称
+    pub poi_name: String,
+    /// 地图缩放级别
+    pub scale: i32,
+}
+
+// some locales send "39,908860" instead of "39.908860"
+fn parse_coordinate(value: &str) -> Option<f64> {
+    value.trim().replace(',', ".").parse().ok().filter(|v: &f64| v.is_finite())
+}
+
+impl LocationMsg {
+    /// 解析位置消息，不是 type 48 或缺少经纬度时返回 None
+    pub fn parse(msg: &WxMsg) -> Option<LocationMsg> {
+        if MsgType::from(msg.r#type as i32) != MsgType::Location {
+            return None;
+        }
+        // group messages may be prefixed with "roomid:\n"
+        let xml = &msg.content[msg.content.find('<')?..];
+        let doc = match Document::parse(xml) {
+            Ok(doc) => doc,
+            Err(e) => {
+                trace!("invalid location xml, error={}, content={}", e, msg.content);
+                return None;
+            }
+        };
+        let location = doc.descendants().find(|node| node.has_tag_name("location"))?;
+        let attr = |name| location.attribute(name).unwrap_or_default().to_string();
+        Some(LocationMsg {
+            lat: parse_coordinate(location.attribute("x")?)?,
+            lng: parse_coordinate(location.attribute("y")?)?,
+            label: attr("label"),
+            poi_name: attr("poiname"),
+            scale: location.attribute("scale").and_then(|scale| scale.trim().parse().ok()).unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIVATE_XML: &str = r#"<?xml version="1.0"?>
+<msg>
+	<location x="39.908860" y="116.397390" scale="15" label="北京市东城区东长安街" maptype="roadmap" poiname="天安门广场" poiid="qqmap_2199027867430" buildingId="" floorName="" poiCategoryTips="旅游景点:广场" poiBusinessHour="" poiPhone="" poiPriceTips="" isFromPoiList="true" adcode="110101" cityname="北京市" fromusername="wxid_a" />
+</msg>
+"#;
+
+    const GROUP_CONTENT: &str = "wxid_b:\n<?xml version=\"1.0\"?>\n<msg>\n\t<location x=\"31,230416\" y=\"121,473701\" \
+        scale=\"16\" label=\"上海市黄浦区中山东一路\" maptype=\"0\" poiname=\"[位置]外滩\" poiid=\"\" fromusername=\"wxid_b\" />\n</msg>\n";
+
+    fn msg(r#type: u32, content: &str) -> WxMsg {
+        WxMsg { r#type, content: content.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn parse_private() {
+        assert_eq!(
+            LocationMsg::parse(&msg(48, PRIVATE_XML)),
+            Some(LocationMsg {
+                lat: 39.90886,
+                lng: 116.39739,
+                label: "北京市东城区东长安街".into(),
+                poi_name: "天安门广场".into(),
+                scale: 15,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_group_with_decimal_comma() {
+        let location = LocationMsg::parse(&WxMsg { is_group: true, ..msg(48, GROUP_CONTENT) }).unwrap();
+        assert_eq!(location.lat, 31.230416);
+        assert_eq!(location.lng, 121.473701);
+        assert_eq!(location.label, "上海市黄浦区中山东一路");
+        assert_eq!(location.poi_name, "[位置]外滩");
+        assert_eq!(location.scale, 16);
+    }
+
+    #[test]
+    fn parse_rejects_bad_coordinates() {
+        assert_eq!(LocationMsg::parse(&msg(1, PRIVATE_XML)), None);
+        assert_eq!(LocationMsg::parse(&msg(48, &PRIVATE_XML.replace(r#"x="39.908860" "#, ""))), None);
+        assert_eq!(LocationMsg::parse(&msg(48, &PRIVATE_XML.replace("116.397390", "116.3x7390"))), None);
+        assert_eq!(LocationMsg::parse(&msg(48, "[位置]")), None);
+    }
+
+    #[test]
+    fn coordinate() {
+        assert_eq!(parse_coordinate(" 39.90886 "), Some(39.90886));
+        assert_eq!(parse_coordinate("39,90886"), Some(39.90886));
+        assert_eq!(parse_coordinate(""), None);
+        assert_eq!(parse_coordinate("39,908,860"), None);
+        assert_eq!(parse_coordinate("abc"), None);
+        assert_eq!(parse_coordinate("NaN"), None);
+        assert_eq!(parse_coordinate("inf"), None);
+    }
+}
@@ -0,0 +1,10 @@
+use tracing_subscriber::EnvFilter;
+
+/// 初始化 tracing 的输出，日志级别通过 RUST_LOG 环境变量设置（和 env_logger 相同），未设置时为 info。
+///
+/// 使用 env_logger 时，将 `env_logger::init()` 替换为 `wechatferry::init_tracing()` 即可，依赖中使用 `log` 输出的日志也会一起输出。
+/// 不调用时，本模块的日志仍会通过 `log` 输出，原有的 env_logger 等不受影响。已经设置过全局 subscriber 时不做任何事
+pub fn init_tracing() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let _ = tracing_subscriber::fmt().with_env_filter(filter).with_thread_names(true).try_init();
+}
@@ -0,0 +1,305 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::error::Result;
+use super::{AppMsg, ContactCard, FriendRequest, LocationMsg, PatNotice, RevokeNotice, RoomEvent, WxMsg};
+
+/// 消息类型，对应 WxMsg.type，取值见 get_msg_types()
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MsgType {
+    Text,
+    Image,
+    Voice,
+    FriendConfirm,
+    PossibleFriend,
+    Card,
+    Video,
+    /// 石头剪刀布、表情图片
+    Sticker,
+    Location,
+    /// 共享实时位置、文件、转账、链接等
+    App,
+    VoipMsg,
+    WechatInit,
+    VoipNotify,
+    VoipInvite,
+    ShortVideo,
+    SysNotice,
+    /// 红包、系统消息
+    System,
+    Revoke,
+    Unknown(i32),
+}
+
+// (type id, variant, name returned by get_msg_types())
+const MSG_TYPES: [(i32, MsgType, &str); 18] = [
+    (1, MsgType::Text, "文字"),
+    (3, MsgType::Image, "图片"),
+    (34, MsgType::Voice, "语音"),
+    (37, MsgType::FriendConfirm, "好友确认"),
+    (40, MsgType::PossibleFriend, "POSSIBLEFRIEND_MSG"),
+    (42, MsgType::Card, "名片"),
+    (43, MsgType::Video, "视频"),
+    (47, MsgType::Sticker, "石头剪刀布 | 表情图片"),
+    (48, MsgType::Location, "位置"),
+    (49, MsgType::App, "共享实时位置、文件、转账、链接"),
+    (50, MsgType::VoipMsg, "VOIPMSG"),
+    (51, MsgType::WechatInit, "微信初始化"),
+    (52, MsgType::VoipNotify, "VOIPNOTIFY"),
+    (53, MsgType::VoipInvite, "VOIPINVITE"),
+    (62, MsgType::ShortVideo, "小视频"),
+    (9999, MsgType::SysNotice, "SYSNOTICE"),
+    (10000, MsgType::System, "红包、系统消息"),
+    (10002, MsgType::Revoke, "撤回消息"),
+];
+
+impl From<i32> for MsgType {
+    fn from(value: i32) -> Self {
+        MSG_TYPES.iter().find(|(id, _, _)| *id == value).map_or(MsgType::Unknown(value), |(_, t, _)| *t)
+    }
+}
+
+impl From<MsgType> for i32 {
+    fn from(msg_type: MsgType) -> Self {
+        match msg_type {
+            MsgType::Unknown(id) => id,
+            known => MSG_TYPES.iter().find(|(_, t, _)| *t == known).map_or(0, |(id, _, _)| *id),
+        }
+    }
+}
+
+impl fmt::Display for MsgType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match MSG_TYPES.iter().find(|(_, t, _)| t == self) {
+            Some((_, _, name)) => f.write_str(name),
+            None => write!(f, "未知类型({})", i32::from(*self)),
+        }
+    }
+}
+
+/// 对 WxMsg 的封装，提供常用的判断和字段访问。
+///
+/// 以 `{}` 输出时为一行摘要，例如 `[group:123@chatroom] wxid_a: 你好 (type=Text id=1)`，`{:#}` 输出完整内容和 xml
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Message {
+    msg: WxMsg,
+}
+
+impl From<WxMsg> for Message {
+    fn from(msg: WxMsg) -> Self {
+        Message { msg }
+    }
+}
+
+// text between <tag> and </tag>, with CDATA unwrapped
+fn xml_tag_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    let text = xml[start..end].trim();
+    Some(text.strip_prefix("<![CDATA[").and_then(|s| s.strip_suffix("]]>")).unwrap_or(text))
+}
+
+impl Message {
+    pub fn id(&self) -> u64 {
+        self.msg.id
+    }
+
+    /// 发送者 wxid，群消息时为实际发言的群成员
+    pub fn sender(&self) -> &str {
+        &self.msg.sender
+    }
+
+    /// 群消息时返回群 id
+    pub fn room_id(&self) -> Option<&str> {
+        Some(self.msg.roomid.as_str()).filter(|roomid| self.msg.is_group && !roomid.is_empty())
+    }
+
+    pub fn is_group(&self) -> bool {
+        self.room_id().is_some()
+    }
+
+    /// 是否自己发送的
+    pub fn is_self(&self) -> bool {
+        self.msg.is_self
+    }
+
+    pub fn msg_type(&self) -> MsgType {
+        MsgType::from(self.msg.r#type as i32)
+    }
+
+    /// 文本消息的内容，其他类型返回 None
+    pub fn text(&self) -> Option<&str> {
+        Some(self.msg.content.as_str()).filter(|_| self.msg_type() == MsgType::Text)
+    }
+
+    /// 非文本消息中 xml 格式的内容，例如图片、链接、文件等
+    pub fn xml_content(&self) -> Option<&str> {
+        let content = self.msg.content.trim_start();
+        Some(content).filter(|content| self.msg_type() != MsgType::Text && content.starts_with('<'))
+    }
+
+    /// 解析 type 49 消息的内容，其他类型返回 Ok(None)，例如收到转账时：
+    /// `if let Some(AppMsg::Transfer { transferid, transcationid, .. }) = msg.app_msg()? {
+    /// recv_transfer(msg.sender().into(), transferid, transcationid)?; }`
+    pub fn app_msg(&self) -> Result<Option<AppMsg>> {
+        match self.msg_type() {
+            MsgType::App => Ok(Some(AppMsg::parse(&self.msg.content)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// 解析好友申请，不是 type 37 时返回 None
+    pub fn friend_request(&self) -> Option<FriendRequest> {
+        FriendRequest::parse(&self.msg)
+    }
+
+    /// 解析撤回通知，不是 type 10002 的撤回消息时返回 None
+    pub fn revoke_notice(&self) -> Option<RevokeNotice> {
+        RevokeNotice::parse(&self.msg)
+    }
+
+    /// 解析位置消息，不是 type 48 时返回 None
+    pub fn as_location(&self) -> Option<LocationMsg> {
+        LocationMsg::parse(&self.msg)
+    }
+
+    /// 解析名片消息，不是 type 42 时返回 None
+    pub fn contact_card(&self) -> Option<ContactCard> {
+        ContactCard::parse(&self.msg)
+    }
+
+    /// 解析拍一拍通知，其他消息返回 None
+    pub fn pat_notice(&self) -> Option<PatNotice> {
+        PatNotice::parse(&self.msg)
+    }
+
+    /// 解析群聊中入群、移出、改名等系统消息，不是 type 10000 的群消息时返回 None
+    pub fn room_event(&self) -> Option<RoomEvent> {
+        RoomEvent::parse(&self.msg)
+    }
+
+    /// 缩略图路径，例如图片、视频消息
+    pub fn thumb_path(&self) -> Option<&str> {
+        Some(self.msg.thumb.as_str()).filter(|path| !path.is_empty())
+    }
+
+    /// 附件路径，例如图片、文件消息
+    pub fn extra_path(&self) -> Option<&str> {
+        Some(self.msg.extra.as_str()).filter(|path| !path.is_empty())
+    }
+
+    /// 消息是否 @ 了 self_wxid，根据消息 xml 中的 atuserlist 判断，@所有人 也算
+    pub fn mentions_me(&self, self_wxid: &str) -> bool {
+        match xml_tag_text(&self.msg.xml, "atuserlist") {
+            Some(users) => users
+                .split(',')
+                .map(str::trim)
+                .filter(|wxid| !wxid.is_empty())
+                .any(|wxid| wxid == self_wxid || wxid == "notify@all"),
+            None => false,
+        }
+    }
+
+    pub fn as_wx_msg(&self) -> &WxMsg {
+        &self.msg
+    }
+
+    pub fn into_wx_msg(self) -> WxMsg {
+        self.msg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE_XML: &str = r#"<?xml version="1.0"?>
+<msg>
+	<img aeskey="5f2b1c9e0a7d4e3b8c6a1f0d2e4b7c9a" encryver="1" cdnthumbaeskey="5f2b1c9e0a7d4e3b8c6a1f0d2e4b7c9a" cdnthumburl="3057020100044b3049020100020468" cdnthumblength="4521" cdnthumbheight="120" cdnthumbwidth="90" length="88231" md5="0f8c2d4a6b1e3f5a7c9d0b2e4f6a8c1d" />
+</msg>
+"#;
+
+    #[test]
+    fn private_text() {
+        let msg = Message::from(WxMsg {
+            id: 1,
+            r#type: 1,
+            sender: "wxid_a".into(),
+            content: "你好".into(),
+            xml: "<msgsource>\n\t<signature>v1_abc</signature>\n</msgsource>\n".into(),
+            ..Default::default()
+        });
+        assert_eq!(msg.sender(), "wxid_a");
+        assert_eq!(msg.room_id(), None);
+        assert!(!msg.is_group());
+        assert_eq!(msg.msg_type(), MsgType::Text);
+        assert_eq!(msg.text(), Some("你好"));
+        assert_eq!(msg.xml_content(), None);
+        assert_eq!(msg.thumb_path(), None);
+        assert_eq!(msg.extra_path(), None);
+        assert!(!msg.mentions_me("wxid_self"));
+    }
+
+    #[test]
+    fn group_text_with_mention() {
+        let msg = Message::from(WxMsg {
+            id: 2,
+            r#type: 1,
+            is_group: true,
+            sender: "wxid_a".into(),
+            roomid: "123@chatroom".into(),
+            content: "@机器人\u{2005}在吗".into(),
+            xml: "<msgsource>\n\t<atuserlist><![CDATA[,wxid_self,wxid_b]]></atuserlist>\n\t<silence>0</silence>\n\t\
+                  <membercount>3</membercount>\n</msgsource>\n"
+                .into(),
+            ..Default::default()
+        });
+        assert_eq!(msg.room_id(), Some("123@chatroom"));
+        assert!(msg.is_group());
+        assert_eq!(msg.text(), Some("@机器人\u{2005}在吗"));
+        assert!(msg.mentions_me("wxid_self"));
+        assert!(msg.mentions_me("wxid_b"));
+        assert!(!msg.mentions_me("wxid_c"));
+        assert!(!msg.mentions_me(""));
+
+        let all = Message::from(WxMsg {
+            xml: "<msgsource>\n\t<atuserlist>notify@all</atuserlist>\n</msgsource>".into(),
+            ..msg.into_wx_msg()
+        });
+        assert!(all.mentions_me("wxid_c"));
+    }
+
+    #[test]
+    fn group_flag_without_roomid_is_not_group() {
+        let msg = Message::from(WxMsg { r#type: 1, is_group: true, ..Default::default() });
+        assert_eq!(msg.room_id(), None);
+        assert!(!msg.is_group());
+    }
+
+    #[test]
+    fn image() {
+        let msg = Message::from(WxMsg {
+            id: 3,
+            r#type: 3,
+            sender: "wxid_a".into(),
+            content: IMAGE_XML.into(),
+            thumb: r"C:\WeChat Files\wxid_self\FileStorage\Image\Thumb\2024-05\a1b2.dat".into(),
+            extra: r"C:\WeChat Files\wxid_self\FileStorage\Image\2024-05\a1b2.dat".into(),
+            ..Default::default()
+        });
+        assert_eq!(msg.msg_type(), MsgType::Image);
+        assert_eq!(msg.text(), None);
+        assert_eq!(msg.xml_content(), Some(IMAGE_XML));
+        assert_eq!(msg.thumb_path(), Some(r"C:\WeChat Files\wxid_self\FileStorage\Image\Thumb\2024-05\a1b2.dat"));
+        assert_eq!(msg.extra_path(), Some(r"C:\WeChat Files\wxid_self\FileStorage\Image\2024-05\a1b2.dat"));
+        assert!(matches!(msg.app_msg(), Ok(None)));
+    }
+
+    #[test]
+    fn msg_type_round_trip() {
+        assert_eq!(MsgType::from(49), MsgType::App);
+        assert_eq!(i32::from(MsgType::Revoke), 10002);
+        assert_eq!(MsgType::from(12345), MsgType::Unknown(12345));
+        assert_eq!(MsgType::Unknown(12345).to_string(), "未知类型(12345)");
+    }
+}
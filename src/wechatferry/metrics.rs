@@ -0,0 +1,138 @@
+// Hooks called from the client, they compile to nothing unless the metrics feature is on.
+//
+// Metric names are part of the public interface, keep them stable:
+//
+// - wcf_cmd_duration_seconds{func}          histogram, round trip of each command, func is e.g. "FUNC_SEND_TXT"
+// - wcf_cmd_errors_total{func}              counter, commands which failed to send or receive
+// - wcf_messages_received_total{type}       counter, messages from the msg socket, type is the numeric WxMsg.type
+// - wcf_sends_total{result}                 counter, send_* and forward_msg results, "success" or "failure"
+// - wcf_socket_reconnects_total             counter, successful cmd socket reconnects
+// - wcf_dispatch_queue_depth                gauge, events waiting for the wcf-dispatch thread
+// - wcf_subscriber_events_dropped_total     counter, events dropped because a subscribe() queue was full
+// - wcf_dispatch_events_dropped_total       counter, events dropped because the event queue was full
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+
+use std::time::Duration;
+
+use super::error::Result;
+use super::proto;
+
+#[cfg(feature = "metrics")]
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+#[cfg(feature = "metrics")]
+struct Metrics {
+    registry: Registry,
+    cmd_duration: HistogramVec,
+    cmd_errors: IntCounterVec,
+    messages_received: IntCounterVec,
+    sends: IntCounterVec,
+    reconnects: IntCounter,
+    dispatch_queue: IntGauge,
+    events_dropped: IntCounter,
+    dispatch_dropped: IntCounter,
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    fn new() -> prometheus::Result<Metrics> {
+        let registry = Registry::new();
+        let cmd_duration = HistogramVec::new(
+            HistogramOpts::new("wcf_cmd_duration_seconds", "Round trip time of commands sent to wcf"),
+            &["func"],
+        )?;
+        let cmd_errors = IntCounterVec::new(
+            Opts::new("wcf_cmd_errors_total", "Commands which failed to send or receive"),
+            &["func"],
+        )?;
+        let messages_received = IntCounterVec::new(
+            Opts::new("wcf_messages_received_total", "Messages received from the msg socket by type"),
+            &["type"],
+        )?;
+        let sends = IntCounterVec::new(Opts::new("wcf_sends_total", "Results of send functions"), &["result"])?;
+        let reconnects = IntCounter::new("wcf_socket_reconnects_total", "Successful cmd socket reconnects")?;
+        let dispatch_queue = IntGauge::new("wcf_dispatch_queue_depth", "Events waiting to be dispatched")?;
+        let events_dropped =
+            IntCounter::new("wcf_subscriber_events_dropped_total", "Events dropped because a subscriber was full")?;
+        registry.register(Box::new(cmd_duration.clone()))?;
+        registry.register(Box::new(cmd_errors.clone()))?;
+        registry.register(Box::new(messages_received.clone()))?;
+        registry.register(Box::new(sends.clone()))?;
+        registry.register(Box::new(reconnects.clone()))?;
+        registry.register(Box::new(dispatch_queue.clone()))?;
+        let dispatch_dropped =
+            IntCounter::new("wcf_dispatch_events_dropped_total", "Events dropped because the event queue was full")?;
+        registry.register(Box::new(events_dropped.clone()))?;
+        registry.register(Box::new(dispatch_dropped.clone()))?;
+        Ok(Metrics {
+            registry,
+            cmd_duration,
+            cmd_errors,
+            messages_received,
+            sends,
+            reconnects,
+            dispatch_queue,
+            events_dropped,
+            dispatch_dropped,
+        })
+    }
+}
+
+#[cfg(feature = "metrics")]
+static METRICS: once_cell::sync::Lazy<Metrics> =
+    once_cell::sync::Lazy::new(|| Metrics::new().expect("metric names and labels are valid"));
+
+/// 以 Prometheus 文本格式返回所有指标，可以在自己的 HTTP 接口中返回给 Prometheus 抓取，指标名称见 metrics.rs
+#[cfg(feature = "metrics")]
+pub fn gather_metrics() -> String {
+    let mut buf = vec![];
+    if let Err(e) = TextEncoder::new().encode(&METRICS.registry.gather(), &mut buf) {
+        tracing::error!("failed to encode metrics, error={}", e);
+    }
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+// the response status of send functions is 1 on success, same as SendResult
+pub(crate) fn cmd_finished(func: i32, elapsed: Duration, result: &Result<proto::Response>) {
+    #[cfg(feature = "metrics")]
+    {
+        let label = super::client::function_name(func);
+        METRICS.cmd_duration.with_label_values(&[label]).observe(elapsed.as_secs_f64());
+        if result.is_err() {
+            METRICS.cmd_errors.with_label_values(&[label]).inc();
+        }
+        if super::client::is_send_function(func) {
+            let success =
+                result.as_ref().is_ok_and(|response| matches!(response.msg, Some(proto::response::Msg::Status(1))));
+            METRICS.sends.with_label_values(&[if success { "success" } else { "failure" }]).inc();
+        }
+    }
+}
+
+pub(crate) fn msg_received(msg_type: u32) {
+    #[cfg(feature = "metrics")]
+    METRICS.messages_received.with_label_values(&[&msg_type.to_string()]).inc();
+}
+
+pub(crate) fn socket_reconnected() {
+    #[cfg(feature = "metrics")]
+    METRICS.reconnects.inc();
+}
+
+// delta is +1 when an event is queued and -1 when it's taken by the dispatcher
+pub(crate) fn dispatch_queue_changed(delta: i64) {
+    #[cfg(feature = "metrics")]
+    METRICS.dispatch_queue.add(delta);
+}
+
+pub(crate) fn subscriber_event_dropped() {
+    #[cfg(feature = "metrics")]
+    METRICS.events_dropped.inc();
+}
+
+pub(crate) fn dispatch_event_dropped() {
+    #[cfg(feature = "metrics")]
+    METRICS.dispatch_dropped.inc();
+}
@@ -0,0 +1,185 @@
+use parking_lot::Mutex;
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use tracing::{debug, error};
+
+use super::dedup::RecentIds;
+use super::events::panic_message;
+use super::{Message, WcfClient};
+
+type HandlerFn = Arc<dyn Fn(Message, &mut Ctx) + Send + Sync>;
+
+/// 消息经过 Pipeline 的结果
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// 到达了最终的处理函数
+    Handled,
+    /// 被某一层拦截，携带原因
+    Dropped(String),
+    /// 某一层或处理函数 panic 了，携带 panic 信息
+    Panicked(String),
+}
+
+/// 在各层之间传递的上下文，按类型保存任意数据，每条消息一份
+pub struct Ctx {
+    client: WcfClient,
+    values: HashMap<TypeId, Box<dyn Any + Send>>,
+}
+
+impl Ctx {
+    pub fn new(client: WcfClient) -> Self {
+        Ctx { client, values: HashMap::new() }
+    }
+
+    pub fn client(&self) -> &WcfClient {
+        &self.client
+    }
+
+    /// 保存数据，返回同类型的旧数据
+    pub fn insert<T: Any + Send>(&mut self, value: T) -> Option<T> {
+        let old = self.values.insert(TypeId::of::<T>(), Box::new(value))?;
+        old.downcast().ok().map(|old| *old)
+    }
+
+    pub fn get<T: Any + Send>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    pub fn get_mut<T: Any + Send>(&mut self) -> Option<&mut T> {
+        self.values.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    pub fn remove<T: Any + Send>(&mut self) -> Option<T> {
+        self.values.remove(&TypeId::of::<T>())?.downcast().ok().map(|value| *value)
+    }
+}
+
+/// Pipeline 中的一层，调用 `next.run(msg, ctx)` 交给下一层，不调用时即拦截了这条消息
+pub trait Middleware: Send + Sync {
+    fn handle(&self, msg: Message, ctx: &mut Ctx, next: Next<'_>) -> Outcome;
+}
+
+/// 剩下的层和最终的处理函数
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn Middleware>],
+    handler: Option<&'a HandlerFn>,
+}
+
+impl Next<'_> {
+    pub fn run(self, msg: Message, ctx: &mut Ctx) -> Outcome {
+        let result = match self.layers.split_first() {
+            Some((layer, layers)) => {
+                let next = Next { layers, handler: self.handler };
+                panic::catch_unwind(AssertUnwindSafe(|| layer.handle(msg, ctx, next)))
+            }
+            None => match self.handler {
+                Some(handler) => panic::catch_unwind(AssertUnwindSafe(|| handler(msg, ctx))).map(|_| Outcome::Handled),
+                None => Ok(Outcome::Handled),
+            },
+        };
+        // an outer layer sees the panic as an outcome, so the rest of the pipeline keeps working
+        result.unwrap_or_else(|payload| {
+            let message = panic_message(payload.as_ref());
+            error!("middleware panicked: {}", message);
+            Outcome::Panicked(message)
+        })
+    }
+}
+
+/// 消息处理管道，按添加顺序依次经过各层，最后到达处理函数：
+/// `Pipeline::new().layer(SelfFilterLayer).layer(DedupLayer::new(1024)).handler(|msg, ctx| { ... })`。
+///
+/// 通过 `set_pipeline()` 设置后，收到的每条消息都会经过它
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    layers: Vec<Arc<dyn Middleware>>,
+    handler: Option<HandlerFn>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    /// 在最内层（最后）加入一层
+    pub fn layer(mut self, layer: impl Middleware + 'static) -> Self {
+        self.layers.push(Arc::new(layer));
+        self
+    }
+
+    /// 设置最终的处理函数，替换已有的
+    pub fn handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(Message, &mut Ctx) + Send + Sync + 'static,
+    {
+        self.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// 让一条消息经过管道
+    pub fn run(&self, msg: Message, ctx: &mut Ctx) -> Outcome {
+        Next { layers: &self.layers, handler: self.handler.as_ref() }.run(msg, ctx)
+    }
+}
+
+/// 拦截自己发送的消息
+pub struct SelfFilterLayer;
+
+impl Middleware for SelfFilterLayer {
+    fn handle(&self, msg: Message, ctx: &mut Ctx, next: Next<'_>) -> Outcome {
+        if msg.is_self() {
+            return Outcome::Dropped("sent by self".into());
+        }
+        next.run(msg, ctx)
+    }
+}
+
+/// 拦截最近 capacity 条消息中重复的消息 id
+pub struct DedupLayer {
+    recent: Mutex<RecentIds>,
+}
+
+impl DedupLayer {
+    pub fn new(capacity: usize) -> Self {
+        DedupLayer { recent: Mutex::new(RecentIds::new(capacity.max(1))) }
+    }
+}
+
+impl Middleware for DedupLayer {
+    fn handle(&self, msg: Message, ctx: &mut Ctx, next: Next<'_>) -> Outcome {
+        if !self.recent.lock().insert(msg.id()) {
+            return Outcome::Dropped(format!("duplicate msg {}", msg.id()));
+        }
+        next.run(msg, ctx)
+    }
+}
+
+/// 只放行这些群的群消息，不影响私聊消息
+pub struct AllowlistLayer {
+    pub rooms: HashSet<String>,
+}
+
+impl Middleware for AllowlistLayer {
+    fn handle(&self, msg: Message, ctx: &mut Ctx, next: Next<'_>) -> Outcome {
+        if let Some(room_id) = msg.room_id() {
+            if !self.rooms.contains(room_id) {
+                return Outcome::Dropped(format!("room {} is not allowed", room_id));
+            }
+        }
+        next.run(msg, ctx)
+    }
+}
+
+/// 以 debug 级别记录每条消息和它的结果
+pub struct LoggingLayer;
+
+impl Middleware for LoggingLayer {
+    fn handle(&self, msg: Message, ctx: &mut Ctx, next: Next<'_>) -> Outcome {
+        let (id, sender, msg_type) = (msg.id(), msg.sender().to_string(), msg.msg_type());
+        let outcome = next.run(msg, ctx);
+        debug!("msg id={}, sender={}, type={}, outcome={:?}", id, sender, msg_type, outcome);
+        outcome
+    }
+}
@@ -0,0 +1,142 @@
+use nng::Socket;
+use parking_lot::Mutex;
+use prost::Message;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use tracing::{error, trace};
+
+use super::error::Result;
+use super::{proto, WxMsg};
+
+enum MockReply {
+    Response(proto::Response),
+    Raw(Vec<u8>),
+    // no reply at all, as if the request was lost
+    Drop,
+}
+
+#[derive(Default)]
+struct MockState {
+    // keyed by function id, functions without a reply get status 0
+    replies: Mutex<HashMap<i32, MockReply>>,
+    // used up one by one before the replies above
+    once: Mutex<HashMap<i32, VecDeque<MockReply>>>,
+    requests: Mutex<Vec<proto::Request>>,
+}
+
+/// 本地模拟的 wcf 服务，配合 `MockSdkLoader` 在没有微信的环境中测试。
+///
+/// cmd 端口按功能号返回预设的 Response，并记录收到的请求；msg 端口（cmd 端口 + 1）可以主动推送消息。
+pub struct MockWcfServer {
+    port: u16,
+    cmd_socket: Socket,
+    msg_socket: Socket,
+    state: Arc<MockState>,
+    handle: Option<JoinHandle<()>>,
+}
+
+fn listen(port: u16) -> Result<Socket> {
+    let socket = Socket::new(nng::Protocol::Pair1)?;
+    socket.listen(&format!("tcp://127.0.0.1:{}", port))?;
+    Ok(socket)
+}
+
+fn serve_thread(socket: Socket, state: Arc<MockState>) {
+    trace!("serve_thread()");
+    loop {
+        let msg = match socket.recv() {
+            Ok(msg) => msg,
+            Err(nng::Error::Closed) => break,
+            Err(e) => {
+                error!("mock server recv error, e={}", e);
+                break;
+            }
+        };
+        let request = match proto::Request::decode(msg.as_slice()) {
+            Ok(request) => request,
+            Err(e) => {
+                error!("mock server received invalid request, error={}", e);
+                continue;
+            }
+        };
+        let func = request.func;
+        state.requests.lock().push(request);
+        let once = state.once.lock().get_mut(&func).and_then(VecDeque::pop_front);
+        let buf = match once.as_ref().or(state.replies.lock().get(&func)) {
+            Some(MockReply::Drop) => {
+                trace!("mock server dropped a request of {}", func);
+                continue;
+            }
+            Some(MockReply::Raw(buf)) => buf.clone(),
+            Some(MockReply::Response(response)) => response.encode_to_vec(),
+            None => proto::Response { func, msg: Some(proto::response::Msg::Status(0)) }.encode_to_vec(),
+        };
+        if let Err((_, e)) = socket.send(nng::Message::from(buf.as_slice())) {
+            error!("mock server send error, e={}", e);
+        }
+    }
+}
+
+impl MockWcfServer {
+    /// 在 port 和 port + 1 上监听，之后对同一端口 init() 即可连接
+    pub fn start(port: u16) -> Result<Self> {
+        let cmd_socket = listen(port)?;
+        let msg_socket = listen(port + 1)?;
+        let state = Arc::new(MockState::default());
+        let (socket, thread_state) = (cmd_socket.clone(), state.clone());
+        let handle = thread::spawn(move || serve_thread(socket, thread_state));
+        Ok(MockWcfServer { port, cmd_socket, msg_socket, state, handle: Some(handle) })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// 设置 func 的返回值，response.func 会被改为 func
+    pub fn respond(&self, func: proto::Functions, mut response: proto::Response) {
+        response.func = func.into();
+        self.state.replies.lock().insert(func.into(), MockReply::Response(response));
+    }
+
+    /// 只对 func 的下一次请求返回 response，多次调用时按顺序使用，用完后回到 respond() 设置的返回值
+    pub fn respond_once(&self, func: proto::Functions, mut response: proto::Response) {
+        response.func = func.into();
+        self.state.once.lock().entry(func.into()).or_default().push_back(MockReply::Response(response));
+    }
+
+    /// 不回复 func 的下一次请求，用于测试超时和重试，与 respond_once() 共用同一个顺序
+    pub fn drop_next(&self, func: proto::Functions) {
+        self.state.once.lock().entry(func.into()).or_default().push_back(MockReply::Drop);
+    }
+
+    /// 设置 func 的原始返回字节，用于测试解码失败等情况
+    pub fn respond_raw(&self, func: proto::Functions, buf: Vec<u8>) {
+        self.state.replies.lock().insert(func.into(), MockReply::Raw(buf));
+    }
+
+    /// 按顺序返回已收到的请求
+    pub fn requests(&self) -> Vec<proto::Request> {
+        self.state.requests.lock().clone()
+    }
+
+    /// 通过 msg 端口推送一条消息，需要客户端已 enable_listen()
+    pub fn push_msg(&self, msg: WxMsg) -> Result<()> {
+        let response = proto::Response {
+            func: proto::Functions::FuncEnableRecvTxt.into(),
+            msg: Some(proto::response::Msg::Wxmsg(msg)),
+        };
+        self.msg_socket.send(nng::Message::from(response.encode_to_vec().as_slice())).map_err(|(_, e)| e)?;
+        Ok(())
+    }
+}
+
+impl Drop for MockWcfServer {
+    fn drop(&mut self) {
+        self.cmd_socket.close();
+        self.msg_socket.close();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
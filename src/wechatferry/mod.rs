@@ -1,60 +1,267 @@
 #![allow(dead_code)]
 
-use anyhow::{anyhow, Result};
-use log::{error, trace, warn};
-use nng::options::{Options, RecvTimeout, SendTimeout};
-use nng::Socket;
 use once_cell::sync::Lazy;
-use parking_lot::Mutex;
-use prost::Message;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use prost::Message as _;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
 use std::sync::Arc;
+use std::thread::JoinHandle;
 use std::time::Duration;
 
+#[cfg(feature = "async")]
+pub mod aio;
+mod anti_revoke;
+mod app_msg;
+mod auto_reply;
+mod client;
+mod command;
+mod compat;
+#[cfg(feature = "config")]
+mod config;
+mod contact_cache;
+mod contact_card;
+mod contact_kind;
+mod cron;
+mod db_value;
+mod dedup;
+mod download;
+mod error;
+mod events;
+mod export;
+mod friend_policy;
+mod friend_request;
+#[cfg(feature = "grpc-server")]
+mod grpc_server;
+mod history;
+#[cfg(feature = "http-server")]
+mod http_server;
+mod humanize;
+mod json_file;
+mod link_card;
+mod listen_filter;
 mod loader;
+mod location;
+#[cfg(feature = "logging")]
+mod logging;
+mod message;
+mod metrics;
+mod middleware;
+#[cfg(feature = "mock-sdk")]
+mod mock;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "openai")]
+mod openai;
+mod pat;
+mod permissions;
+mod pretty;
+#[cfg(feature = "process")]
+mod process;
+mod rate_limit;
+mod responder;
+mod revoke;
+mod room_event;
+mod scheduler;
+mod search;
+mod session;
+mod sql;
+mod stats;
+#[cfg(feature = "store")]
+mod store;
+mod subscription;
+mod transfer_policy;
+mod validate;
+#[cfg(feature = "process")]
+mod watchdog;
+#[cfg(feature = "http")]
+mod webhook;
+mod welcome;
+#[cfg(feature = "websocket")]
+mod ws_server;
+mod xml_template;
 pub mod proto {
-    tonic::include_proto!("wcf");
-    tonic::include_proto!("roomdata");
+    include!(concat!(env!("OUT_DIR"), "/wcf.rs"));
+    include!(concat!(env!("OUT_DIR"), "/roomdata.rs"));
+
+    /// GrpcServer 提供的 gRPC 服务，包含生成的 server 和 client
+    #[cfg(feature = "grpc-server")]
+    pub mod rpc {
+        tonic::include_proto!("wcf.rpc");
+    }
 }
 
-// set in init(), and unset in uninit()
-static CMD_PORT: Lazy<Mutex<u16>> = Lazy::new(|| Mutex::new(0));
-// connect as caller requests, disconnect when uninit() or error happens
-static CMD_SOCKET: Lazy<Arc<Mutex<Option<Socket>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
-// set in enable_listen(), and unset in disable_listen()
-static MSG_PORT: Lazy<Mutex<u16>> = Lazy::new(|| Mutex::new(0));
-// lives in recv_msg_thread, and only one could live
-static MSG_RECEIVING: Lazy<Arc<Mutex<()>>> = Lazy::new(|| Arc::new(Mutex::new(())));
+// proto types that are part of the public API, use these paths instead of reaching into `proto::`
+pub use proto::request::Msg as RequestMsg;
+pub use proto::response::Msg as ResponseMsg;
+pub use proto::room_data::RoomMember;
+pub use proto::{DbField, DbRow, DbTable, Functions, OcrMsg, RichText, RoomData, RpcContact, RpcContacts, WxMsg};
 
-const RECV_TIMEOUT: Duration = Duration::from_millis(5000);
-const SEND_TIMEOUT: Duration = Duration::from_millis(5000);
+pub use anti_revoke::{AntiRevoke, AntiRevokeOptions, RevokeDestination};
+pub use app_msg::{AppMsg, TransferDirection};
+pub use auto_reply::{AutoReply, Matcher, Reply, ReplyRule, RuleId};
+pub use client::{
+    BroadcastOptions, CleanupHandler, CmdTimeouts, InitOptions, ListenStats, ListenStatus, ReconnectPolicy,
+    RetryPolicy, WcfClient, WcfState, DEFAULT_DEDUP_CAPACITY, DEFAULT_LISTEN_STOP_TIMEOUT, DEFAULT_SHUTDOWN_TIMEOUT,
+};
+pub use command::{split_args, CommandCtx, CommandOptions, CommandRouter, CommandScope, DEFAULT_COMMAND_PREFIX};
+pub use compat::{check_compatibility, CompatibilityReport, Verdict, SUPPORTED_WECHAT_VERSION, WCF_VERSION};
+#[cfg(feature = "config")]
+pub use config::{Config, ListenSection, RateLimitSection, RateSection, WcfSection, WebhookSection, WelcomeSection};
+pub use contact_cache::{ContactCache, DEFAULT_CONTACT_CACHE_TTL};
+pub use contact_card::ContactCard;
+pub use contact_kind::ContactKind;
+pub use cron::CronSchedule;
+pub use db_value::{DbValue, TypedDbRow};
+pub use error::{Result, WcfError};
+pub use events::{
+    CallbackFn, ConnectionChange, EventQueueConfig, HandlerId, OverflowPolicy, DEFAULT_EVENT_QUEUE_CAPACITY,
+    DEFAULT_SUBSCRIBER_CAPACITY,
+};
+pub use export::{ExportFormat, ExportSummary, TimeRange, CSV_PROGRESS_INTERVAL};
+pub use friend_policy::FriendPolicy;
+pub use friend_request::FriendRequest;
+#[cfg(feature = "grpc-server")]
+pub use grpc_server::{GrpcServer, GrpcServerConfig};
+pub use history::{DbMessage, MessageFilter};
+#[cfg(feature = "http-server")]
+pub use http_server::{HttpServer, HttpServerConfig};
+pub use humanize::HumanizeOptions;
+pub use link_card::LinkCard;
+pub use listen_filter::ListenFilter;
+#[cfg(feature = "mock-sdk")]
+pub use loader::MockSdkLoader;
+pub use loader::{DllSdkLoader, SdkLoader, SpyVariant};
+pub use location::LocationMsg;
+#[cfg(feature = "logging")]
+pub use logging::init_tracing;
+pub use message::{Message, MsgType};
+#[cfg(feature = "metrics")]
+pub use metrics::gather_metrics;
+pub use middleware::{
+    AllowlistLayer, Ctx, DedupLayer, LoggingLayer, Middleware, Next, Outcome, Pipeline, SelfFilterLayer,
+};
+#[cfg(feature = "mock-sdk")]
+pub use mock::MockWcfServer;
+#[cfg(feature = "mqtt")]
+pub use mqtt::{MqttBridge, MqttConfig};
+#[cfg(feature = "openai")]
+pub use openai::OpenAiResponder;
+pub use pat::PatNotice;
+pub use permissions::{PermissionLevel, Permissions};
+pub use pretty::to_pretty_json;
+#[cfg(feature = "process")]
+pub use process::{is_wechat_running, WECHAT_PROCESS_NAME};
+pub use rate_limit::{Rate, RateLimitConfig, RateLimitMode};
+pub use responder::{Responder, ResponderDriver, ResponderOptions};
+pub use revoke::RevokeNotice;
+pub use room_event::RoomEvent;
+pub use scheduler::{Clock, Job, JobId, JobInfo, Schedule, Scheduler, SystemClock};
+pub use search::{SearchHit, SearchOptions};
+pub use session::{SessionKey, SessionManager, DEFAULT_SESSION_CAPACITY};
+pub use stats::WcfStats;
+#[cfg(feature = "store")]
+pub use store::MessageStore;
+pub use subscription::{NotifyTarget, Scope, Subscription, SubscriptionId, Subscriptions};
+pub use transfer_policy::{TransferInfo, TransferPolicy};
+#[cfg(feature = "process")]
+pub use watchdog::{Watchdog, WatchdogConfig};
+#[cfg(feature = "http")]
+pub use webhook::{sign_body, WebhookConfig, WebhookForwarder};
+pub use welcome::WelcomeConfig;
+#[cfg(feature = "websocket")]
+pub use ws_server::{WsServer, WsServerConfig};
+pub use xml_template::XmlTemplate;
 
-pub struct CleanupHandler {
-    auto_clean: bool,
-}
+// the client behind the free functions below, kept for backwards compatibility
+static DEFAULT_CLIENT: Lazy<WcfClient> = Lazy::new(WcfClient::new);
+// used by display_name()
+static DEFAULT_CONTACT_CACHE: Lazy<ContactCache> = Lazy::new(|| ContactCache::new(DEFAULT_CLIENT.clone()));
 
-impl Drop for CleanupHandler {
-    fn drop(&mut self) {
-        if self.auto_clean {
-            uninit();
-        }
-    }
+/// 获取默认客户端，下列自由函数均通过它执行
+pub fn default_client() -> &'static WcfClient {
+    &DEFAULT_CLIENT
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub enum Event {
     SdkDllLoaded,
-    SdkInited(u16, bool),
+    /// init() 成功，携带 cmd 端口和注入的 spy dll
+    SdkInited(u16, SpyVariant),
     SdkDestroyed,
+    /// attach() 连接到了已注入的 wcf，携带 cmd 端口
+    SdkAttached(u16),
+    /// detach() 断开了 attach() 的连接，wcf 仍在微信中运行
+    SdkDetached,
+    /// uninit_and_unload() 卸载了 sdk.dll
+    SdkDllUnloaded,
     CmdSocketConnected,
     CmdSocketDisconnected,
     MsgSocketConnected,
     MsgSocketDisconnected,
-    MsgReceived(proto::WxMsg),
+    /// 收到的消息，可以通过 `Message::from(msg)` 转为封装后的消息
+    MsgReceived(Arc<WxMsg>),
+    /// msg socket 收到了无法解码的数据，可能是 wcf 的协议与本库附带的 proto 不一致。
+    /// 相同的错误每分钟最多发出一次，次数见 stats()
+    MsgDecodeError {
+        bytes_len: usize,
+        error: String,
+    },
+    /// msg socket 收到了不是 WxMsg 的响应，同一类响应每分钟最多发出一次，次数见 stats()
+    UnsupportedMsg(proto::response::Msg),
+    /// 被 ListenFilter 过滤掉的消息，只在 report_filtered 为 true 时发出
+    MsgFiltered(Arc<WxMsg>),
+    /// 回调函数 panic 了，携带 panic 信息，接收线程不受影响
+    CallbackPanicked(String),
+    /// 内部线程 panic 后退出了，携带线程名，例如 "wcf-msg-recv"，panic 信息见日志
+    InternalThreadPanicked(&'static str),
+    /// 事件队列的长度达到容量的 80%，携带当前长度，通常是回调处理太慢。长度降到一半以下后才会再次发出
+    QueueHighWatermark(usize),
+    /// 事件队列已满，按 OverflowPolicy 丢弃了事件，携带自上次发出后丢弃的数量
+    EventsDropped(u64),
+    /// 健康检查失败，每次失败都会发出，携带连续失败的次数
+    HealthCheckFailed {
+        consecutive_failures: u32,
+    },
+    /// 健康检查失败后恢复正常
+    HealthCheckRecovered,
+    /// wait_for_login() 检测到已登录
+    LoggedIn(UserInfo),
+    /// 按 TransferPolicy 自动收款成功
+    TransferAccepted(TransferInfo),
+    /// 开启自动收款时不符合 TransferPolicy 或收款失败的转账，reason 为原因
+    TransferIgnored {
+        transfer: TransferInfo,
+        reason: String,
+    },
+    /// 按 FriendPolicy 自动通过了好友申请
+    FriendRequestAccepted(FriendRequest),
+    /// 开启自动通过时不符合 FriendPolicy 或通过失败的好友申请，reason 为原因
+    FriendRequestRejected {
+        request: FriendRequest,
+        reason: String,
+    },
+    /// 一小时内通过的申请数已达到 FriendPolicy.max_per_hour，未处理
+    FriendRequestRateLimited(FriendRequest),
+    /// SessionManager 中的会话超时未完成
+    SessionExpired(SessionKey),
+    /// ResponderDriver 生成或发送回复失败，msg_id 为收到的消息
+    ResponderFailed {
+        msg_id: u64,
+        error: String,
+    },
+    /// WebhookForwarder 重试用尽或队列已满，msg_id 的消息没有送达
+    WebhookDeliveryFailed {
+        msg_id: u64,
+        error: String,
+    },
+    /// Watchdog 检测到微信进程已退出，可以调用 `WcfClient::reinitialize()` 恢复
+    WeChatProcessExited,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct UserInfo {
     pub wxid: String,
     pub name: String,
@@ -68,16 +275,50 @@ impl From<proto::UserInfo> for UserInfo {
     }
 }
 
-#[derive(Clone, Debug, Default)]
+/// 发送类接口的结果，status 为远端返回的状态码
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendResult {
+    /// status 为 1 时视为成功
+    pub success: bool,
+    /// 远端没有返回状态码时为 -1
+    pub status: i32,
+}
+
+impl From<&proto::Response> for SendResult {
+    fn from(response: &proto::Response) -> Self {
+        match response.msg {
+            Some(proto::response::Msg::Status(status)) => SendResult { success: status == 1, status },
+            _ => SendResult { success: false, status: -1 },
+        }
+    }
+}
+
+// keeps callers that only need a bool simple, e.g. `let sent: bool = send_text(...)?.into()`
+impl From<SendResult> for bool {
+    fn from(result: SendResult) -> Self {
+        result.success
+    }
+}
+
+/// send_text_with_mentions() 中要 @ 的对象
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mention {
+    /// 群成员的 wxid
+    Wxid(String),
+    /// @所有人，必须是群主或者管理员才有权限
+    All,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ContactInfo {
     /// 微信ID
     pub wxid: String,
     /// 微信号
     pub alias: Option<String>,
     /// 删除标记
-    pub del_flag: u8,
+    pub del_flag: i32,
     /// 类型
-    pub contact_type: u8,
+    pub contact_type: i32,
     /// 备注
     pub remark: Option<String>,
     /// 昵称
@@ -94,26 +335,39 @@ pub struct ContactInfo {
     pub small_head_url: Option<String>,
     /// 大头像
     pub big_head_url: Option<String>,
+    /// 认证标记，公众号不为 0
+    pub verify_flag: i32,
+}
+
+impl ContactInfo {
+    /// 显示的名字，依次使用备注、昵称、微信号、wxid
+    pub fn display_name(&self) -> &str {
+        self.remark.as_deref().or(self.nick_name.as_deref()).or(self.alias.as_deref()).unwrap_or(&self.wxid)
+    }
+
+    pub fn kind(&self) -> ContactKind {
+        ContactKind::of(self)
+    }
 }
 
-impl From<proto::DbRow> for ContactInfo {
-    fn from(row: proto::DbRow) -> Self {
+impl From<DbRow> for ContactInfo {
+    fn from(row: DbRow) -> Self {
         let mut ci = ContactInfo::default();
-        let from_utf8 = String::from_utf8; // to shorten code lines
-        for field in row.fields {
-            match field.column.as_str() {
-                "UserName" => ci.wxid = from_utf8(field.content).unwrap_or_default(),
-                "Alias" => ci.alias = from_utf8(field.content).ok().filter(|s| !s.is_empty()),
-                "DelFlag" => ci.del_flag = field.content.first().copied().unwrap_or(0),
-                "Type" => ci.contact_type = field.content.first().copied().unwrap_or(0),
-                "Remark" => ci.remark = from_utf8(field.content).ok().filter(|s| !s.is_empty()),
-                "NickName" => ci.nick_name = from_utf8(field.content).ok().filter(|s| !s.is_empty()),
-                "PYInitial" => ci.py_initial = from_utf8(field.content).ok().filter(|s| !s.is_empty()),
-                "QuanPin" => ci.quan_pin = from_utf8(field.content).ok().filter(|s| !s.is_empty()),
-                "RemarkPYInitial" => ci.remark_py_initial = from_utf8(field.content).ok().filter(|s| !s.is_empty()),
-                "RemarkQuanPin" => ci.remark_quan_pin = from_utf8(field.content).ok().filter(|s| !s.is_empty()),
-                "smallHeadImgUrl" => ci.small_head_url = from_utf8(field.content).ok().filter(|s| !s.is_empty()),
-                "bigHeadImgUrl" => ci.big_head_url = from_utf8(field.content).ok().filter(|s| !s.is_empty()),
+        for (column, value) in db_value::typed_fields(row) {
+            match column.as_str() {
+                "UserName" => ci.wxid = value.into_string().unwrap_or_default(),
+                "Alias" => ci.alias = value.into_non_empty(),
+                "DelFlag" => ci.del_flag = value.as_i64().unwrap_or(0) as i32,
+                "Type" => ci.contact_type = value.as_i64().unwrap_or(0) as i32,
+                "Remark" => ci.remark = value.into_non_empty(),
+                "NickName" => ci.nick_name = value.into_non_empty(),
+                "PYInitial" => ci.py_initial = value.into_non_empty(),
+                "QuanPin" => ci.quan_pin = value.into_non_empty(),
+                "RemarkPYInitial" => ci.remark_py_initial = value.into_non_empty(),
+                "RemarkQuanPin" => ci.remark_quan_pin = value.into_non_empty(),
+                "smallHeadImgUrl" => ci.small_head_url = value.into_non_empty(),
+                "bigHeadImgUrl" => ci.big_head_url = value.into_non_empty(),
+                "VerifyFlag" => ci.verify_flag = value.as_i64().unwrap_or(0) as i32,
                 _ => {}
             }
         }
@@ -121,28 +375,51 @@ impl From<proto::DbRow> for ContactInfo {
     }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ChatRoom {
     /// 群聊ID
     pub room_id: String,
     /// 群聊成员
-    pub room_data: proto::RoomData,
+    pub room_data: RoomData,
     /// 群聊头像
     pub room_head_img_url: Option<String>,
     /// 公告
     pub room_announcement: Option<String>,
+    /// 群主 wxid
+    pub room_owner: Option<String>,
 }
 
-impl From<proto::DbRow> for ChatRoom {
-    fn from(row: proto::DbRow) -> Self {
+/// 群成员，见 get_room_members()
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChatRoomMember {
+    pub wxid: String,
+    /// 群昵称
+    pub room_nickname: Option<String>,
+    /// 联系人备注，没有备注时为昵称
+    pub contact_name: Option<String>,
+    /// 是否群主，RoomData 中没有普通管理员的信息
+    pub is_owner: bool,
+}
+
+impl ChatRoomMember {
+    /// 显示的名字，依次使用群昵称、备注或昵称、wxid
+    pub fn display_name(&self) -> &str {
+        self.room_nickname.as_deref().or(self.contact_name.as_deref()).unwrap_or(&self.wxid)
+    }
+}
+
+impl From<DbRow> for ChatRoom {
+    fn from(row: DbRow) -> Self {
         let mut room = ChatRoom::default();
-        let from_utf8 = String::from_utf8; // to shorten code lines
-        for field in row.fields {
-            match field.column.as_str() {
-                "ChatRoomName" => room.room_id = from_utf8(field.content).unwrap_or_default(),
-                "RoomData" => room.room_data = proto::RoomData::decode(field.content.as_slice()).unwrap_or_default(),
-                "smallHeadImgUrl" => room.room_head_img_url = from_utf8(field.content).ok().filter(|s| !s.is_empty()),
-                "Announcement" => room.room_announcement = from_utf8(field.content).ok().filter(|s| !s.is_empty()),
+        for (column, value) in db_value::typed_fields(row) {
+            match column.as_str() {
+                "ChatRoomName" => room.room_id = value.into_string().unwrap_or_default(),
+                "RoomData" => {
+                    room.room_data = RoomData::decode(value.as_bytes().unwrap_or_default()).unwrap_or_default()
+                }
+                "smallHeadImgUrl" => room.room_head_img_url = value.into_non_empty(),
+                "Announcement" => room.room_announcement = value.into_non_empty(),
+                "Owner" => room.room_owner = value.into_non_empty(),
                 _ => {}
             }
         }
@@ -150,467 +427,684 @@ impl From<proto::DbRow> for ChatRoom {
     }
 }
 
-pub type CallbackFn = Arc<Mutex<dyn FnMut(Event) + Send + 'static>>;
-static EVENT_CALLBACK: Lazy<Mutex<Option<CallbackFn>>> = Lazy::new(|| Mutex::new(None));
-
-fn exchange_message(socket: &Socket, msg: nng::Message) -> Result<nng::Message> {
-    socket.send(msg).map_err(|e| anyhow!("send error, e: {:?}", e))?;
-    Ok(socket.recv()?)
+pub fn register_event_callback<F>(callback: F)
+where
+    F: FnMut(Event) + Send + 'static,
+{
+    DEFAULT_CLIENT.register_event_callback(callback)
 }
 
-fn exchange_message_via_cmd_socket(msg: nng::Message) -> Result<nng::Message> {
-    let mut cmd_socket_option = CMD_SOCKET.lock();
-    if cmd_socket_option.is_none() {
-        return Err(anyhow!("cmd_socket disconnected"));
-    }
-    let socket = cmd_socket_option.as_ref().unwrap();
-    let exchange_result = exchange_message(socket, msg);
-    if let Err(e) = exchange_result.as_ref() {
-        let error = format!("failed to send or receive, error={:?}", e);
-        error!("{}, disconnect cmd_socket", &error);
-        *cmd_socket_option = None;
-        send_event(Event::CmdSocketDisconnected);
-        return Err(anyhow!(error));
-    }
-    exchange_result
+pub fn unregister_event_callback() {
+    DEFAULT_CLIENT.unregister_event_callback()
 }
 
-fn run_cmd(func: i32, msg: Option<proto::request::Msg>) -> Result<proto::Response> {
-    let req = proto::Request { func, msg };
-    let mut buf = Vec::with_capacity(req.encoded_len());
-    req.encode(&mut buf)?;
-    let msg = nng::Message::from(&buf[..]);
-    let msg_recv = exchange_message_via_cmd_socket(msg)?;
-    Ok(proto::Response::decode(msg_recv.as_slice())?)
+/// 订阅事件，参考 [`WcfClient::subscribe`]
+pub fn subscribe() -> Receiver<Event> {
+    DEFAULT_CLIENT.subscribe()
 }
 
-fn get_response_status_as_bool(response: &proto::Response) -> bool {
-    match response.msg {
-        Some(proto::response::Msg::Status(status)) => 1 == status,
-        _ => false,
-    }
+pub fn subscribe_with_capacity(capacity: usize) -> Receiver<Event> {
+    DEFAULT_CLIENT.subscribe_with_capacity(capacity)
 }
 
-fn send_event(event: Event) {
-    let arc_callback = match EVENT_CALLBACK.lock().as_ref() {
-        Some(p) => p.clone(),
-        None => return,
-    };
-    arc_callback.lock()(event);
-}
-
-fn connect_socket(port: u16) -> Result<Socket> {
-    let socket = Socket::new(nng::Protocol::Pair1)?;
-    socket.set_opt::<RecvTimeout>(Some(RECV_TIMEOUT))?;
-    socket.set_opt::<SendTimeout>(Some(SEND_TIMEOUT))?;
-    let url = format!("tcp://127.0.0.1:{}", port);
-    socket.dial(&url)?;
-    Ok(socket)
-}
-
-fn recv_msg_thread(port: u16) {
-    trace!("recv_msg_thread()");
-    let _receiving = match MSG_RECEIVING.try_lock() {
-        Some(v) => v,
-        None => return, // cannot lock, which means there's another thread is still working
-    };
-    let socket = match connect_socket(port) {
-        Ok(s) => s,
-        Err(e) => {
-            error!("cannot connect to msg socket, port {}, error: {}", port, e);
-            return;
-        }
-    };
-    send_event(Event::MsgSocketConnected);
-
-    loop {
-        match socket.recv() {
-            Ok(mut msg) => {
-                let response = match proto::Response::decode(msg.as_slice()) {
-                    Ok(resp) => resp,
-                    Err(e) => {
-                        error!("received invalid msg, error={}", e);
-                        continue;
-                    }
-                };
-                msg.clear();
-                if let Some(proto::response::Msg::Wxmsg(msg)) = response.msg {
-                    send_event(Event::MsgReceived(msg));
-                } else {
-                    trace!("received unsupported msg, response.msg={:?}", response.msg);
-                }
-            }
-            Err(nng::Error::TimedOut) => {
-                let msg_port = *MSG_PORT.lock();
-                if msg_port == 0 {
-                    trace!("disabled receiving as user requested, now closing");
-                    break;
-                }
-            }
-            Err(e) => {
-                error!("recv error! now closing, e={}", e);
-                break;
-            }
-        }
-    }
-    socket.close();
-    send_event(Event::MsgSocketDisconnected);
+pub fn on_message<F>(handler: F) -> HandlerId
+where
+    F: FnMut(&WxMsg) + Send + 'static,
+{
+    DEFAULT_CLIENT.on_message(handler)
 }
 
-pub fn register_event_callback<F>(callback: F)
+pub fn on_connection_change<F>(handler: F) -> HandlerId
 where
-    F: FnMut(Event) + Send + 'static,
+    F: FnMut(ConnectionChange) + Send + 'static,
 {
-    *EVENT_CALLBACK.lock() = Some(Arc::new(Mutex::new(callback)));
+    DEFAULT_CLIENT.on_connection_change(handler)
 }
 
-pub fn unregister_event_callback() {
-    *EVENT_CALLBACK.lock() = None;
+pub fn remove_handler(id: HandlerId) -> bool {
+    DEFAULT_CLIENT.remove_handler(id)
+}
+
+/// 设置是否同步分发事件，参考 [`WcfClient::set_sync_dispatch`]
+pub fn set_sync_dispatch(sync_dispatch: bool) {
+    DEFAULT_CLIENT.set_sync_dispatch(sync_dispatch)
+}
+
+pub fn flush_events() {
+    DEFAULT_CLIENT.flush_events()
 }
 
 pub fn init(port: u16, debug: bool, auto_clean: bool) -> Result<CleanupHandler> {
-    trace!("init()");
-    if loader::load_sdk_dll()? {
-        send_event(Event::SdkDllLoaded);
-    }
-    let mut cmd_port = CMD_PORT.lock();
-    if *cmd_port != 0 {
-        return Err(anyhow!("wcf already inited"));
-    }
-    let init_sdk_result = loader::wx_init_sdk(debug, port as i32)?;
-    if init_sdk_result != 0 {
-        return Err(anyhow!("wcf init sdk failed, result={}", init_sdk_result));
-    }
-    *cmd_port = port;
-    send_event(Event::SdkInited(port, debug));
-    Ok(CleanupHandler { auto_clean })
+    DEFAULT_CLIENT.init(port, debug, auto_clean)
 }
 
-pub fn uninit() {
-    trace!("uninit()");
-    let mut cmd_port = CMD_PORT.lock();
-    if *cmd_port == 0 {
-        return; // no need to uninit
-    }
+pub fn init_with_options(port: u16, options: InitOptions) -> Result<CleanupHandler> {
+    DEFAULT_CLIENT.init_with_options(port, options)
+}
 
-    disconnect_cmd_socket();
-    let _ = disable_listen();
+/// 直接执行一个 wcf 函数，参考 [`WcfClient::exec_raw`]
+pub fn exec_raw(func: Functions, msg: Option<RequestMsg>) -> Result<proto::Response> {
+    DEFAULT_CLIENT.exec_raw(func, msg)
+}
 
-    match loader::wx_destroy_sdk() {
-        Ok(0) => {}
-        Ok(i) => warn!("wcf::uninit(), wx_destroy_sdk() returned result={}", i),
-        Err(e) => warn!("wcf::uninit(), wx_destroy_sdk() returned error={:?}", e),
-    }
-    *cmd_port = 0;
-    send_event(Event::SdkDestroyed);
+/// spy 的日志文件，参考 [`WcfClient::spy_log_path`]
+pub fn spy_log_path() -> Option<PathBuf> {
+    DEFAULT_CLIENT.spy_log_path()
+}
+
+/// 连接已注入的 wcf，参考 [`WcfClient::attach`]
+pub fn attach(port: u16) -> Result<CleanupHandler> {
+    DEFAULT_CLIENT.attach(port)
+}
+
+/// 断开 attach() 的连接，参考 [`WcfClient::detach`]
+pub fn detach() {
+    DEFAULT_CLIENT.detach()
+}
+
+/// 在 ports 范围内查找空闲端口并 init，参考 [`WcfClient::init_auto_port`]
+pub fn init_auto_port(ports: RangeInclusive<u16>, options: InitOptions) -> Result<CleanupHandler> {
+    DEFAULT_CLIENT.init_auto_port(ports, options)
+}
+
+pub fn uninit() {
+    DEFAULT_CLIENT.uninit()
+}
+
+/// uninit() 并卸载 sdk.dll，参考 [`WcfClient::uninit_and_unload`]
+pub fn uninit_and_unload() {
+    DEFAULT_CLIENT.uninit_and_unload()
 }
 
 pub fn connect_cmd_socket() -> Result<()> {
-    let cmd_port = *CMD_PORT.lock();
-    if cmd_port == 0 {
-        return Err(anyhow!("wcf not inited"));
-    }
+    DEFAULT_CLIENT.connect_cmd_socket()
+}
 
-    let mut cmd_socket = CMD_SOCKET.lock();
-    if cmd_socket.is_some() {
-        return Err(anyhow!("cmd_socket already connected"));
-    }
-    *cmd_socket = Some(connect_socket(cmd_port)?);
-    send_event(Event::CmdSocketConnected);
-    Ok(())
+pub fn set_cmd_reconnect(policy: Option<ReconnectPolicy>) {
+    DEFAULT_CLIENT.set_cmd_reconnect(policy)
+}
+
+/// 设置命令超时后的重试策略，参考 [`WcfClient::set_retry_policy`]
+pub fn set_retry_policy(policy: Option<RetryPolicy>) {
+    DEFAULT_CLIENT.set_retry_policy(policy)
+}
+
+pub fn set_cmd_timeouts(timeouts: CmdTimeouts) -> Result<()> {
+    DEFAULT_CLIENT.set_cmd_timeouts(timeouts)
+}
+
+/// 使用指定超时执行命令，参考 [`WcfClient::with_cmd_timeouts`]
+pub fn with_cmd_timeouts(timeouts: CmdTimeouts) -> WcfClient {
+    DEFAULT_CLIENT.with_cmd_timeouts(timeouts)
 }
 
 pub fn disconnect_cmd_socket() {
-    let cmd_socket_disconnect = CMD_SOCKET.lock().take().is_some();
-    if cmd_socket_disconnect {
-        send_event(Event::CmdSocketDisconnected);
-    }
+    DEFAULT_CLIENT.disconnect_cmd_socket()
 }
 
 pub fn is_login() -> Result<bool> {
-    let response = run_cmd(proto::Functions::FuncIsLogin.into(), None)?;
-    Ok(get_response_status_as_bool(&response))
+    DEFAULT_CLIENT.is_login()
+}
+
+/// 等待用户登录，参数说明见 [`WcfClient::wait_for_login`]
+pub fn wait_for_login(timeout: Duration, poll_interval: Duration) -> Result<UserInfo> {
+    DEFAULT_CLIENT.wait_for_login(timeout, poll_interval)
 }
 
 pub fn get_self_wx_id() -> Result<Option<String>> {
-    let response = run_cmd(proto::Functions::FuncGetSelfWxid.into(), None)?;
-    match response.msg {
-        Some(proto::response::Msg::Str(wx_id)) => Ok(Some(wx_id)),
-        _ => Ok(None),
-    }
+    DEFAULT_CLIENT.get_self_wx_id()
 }
 
 pub fn get_user_info() -> Result<Option<UserInfo>> {
-    let response = run_cmd(proto::Functions::FuncGetUserInfo.into(), None)?;
-    match response.msg {
-        Some(proto::response::Msg::Ui(user_info)) => Ok(Some(user_info.into())),
-        _ => Ok(None),
-    }
+    DEFAULT_CLIENT.get_user_info()
 }
 
-pub fn get_contacts() -> Result<Option<proto::RpcContacts>> {
-    let response = run_cmd(proto::Functions::FuncGetContacts.into(), None)?;
-    match response.msg {
-        Some(proto::response::Msg::Contacts(contacts)) => Ok(Some(contacts)),
-        _ => Ok(None),
-    }
+pub fn get_contacts() -> Result<Option<RpcContacts>> {
+    DEFAULT_CLIENT.get_contacts()
 }
 
 pub fn query_all_contact_info() -> Result<Vec<ContactInfo>> {
-    let sql = "SELECT * FROM Contact LEFT JOIN ContactHeadImgUrl ON Contact.UserName = ContactHeadImgUrl.usrName";
-    let rows = exec_db_query("MicroMsg.db".into(), sql.into())?;
-    Ok(rows.into_iter().map(|row| row.into()).collect())
+    DEFAULT_CLIENT.query_all_contact_info()
+}
+
+/// 获取好友，不包括群聊、公众号和系统账号
+pub fn list_friends() -> Result<Vec<ContactInfo>> {
+    DEFAULT_CLIENT.list_friends()
+}
+
+/// 获取通讯录中的群聊
+pub fn list_chatrooms() -> Result<Vec<ContactInfo>> {
+    DEFAULT_CLIENT.list_chatrooms()
+}
+
+/// 获取关注的公众号
+pub fn list_official_accounts() -> Result<Vec<ContactInfo>> {
+    DEFAULT_CLIENT.list_official_accounts()
 }
 
 pub fn query_contact_info(wxid: String) -> Result<Option<ContactInfo>> {
-    let sql = format!(
-        "SELECT * FROM Contact \
-        LEFT JOIN ContactHeadImgUrl \
-        ON Contact.UserName = ContactHeadImgUrl.usrName \
-        WHERE Contact.UserName = \"{}\"",
-        wxid
-    );
-    let rows = exec_db_query("MicroMsg.db".into(), sql)?;
-    Ok(rows.into_iter().next().map(|row| row.into()))
+    DEFAULT_CLIENT.query_contact_info(wxid)
 }
 
 pub fn query_chat_room_info(wxid: String) -> Result<Option<ChatRoom>> {
-    let sql = format!(
-        "SELECT ChatRoom.ChatRoomName AS ChatRoomName, \
-        ChatRoom.RoomData AS RoomData, \
-        ContactHeadImgUrl.smallHeadImgUrl AS smallHeadImgUrl, \
-        ChatRoomInfo.Announcement AS Announcement \
-        FROM ChatRoom \
-        LEFT JOIN ContactHeadImgUrl \
-        ON ChatRoom.ChatRoomName = ContactHeadImgUrl.usrName \
-        LEFT JOIN ChatRoomInfo \
-        ON ChatRoom.ChatRoomName = ChatRoomInfo.ChatRoomName \
-        WHERE ChatRoom.ChatRoomName = \"{}\"",
-        wxid
-    );
-    let rows = exec_db_query("MicroMsg.db".into(), sql)?;
-    Ok(rows.into_iter().next().map(|row| row.into()))
+    DEFAULT_CLIENT.query_chat_room_info(wxid)
+}
+
+/// 开启或关闭重复消息过滤，参考 [`WcfClient::set_dedup`]
+pub fn set_dedup(capacity: usize) {
+    DEFAULT_CLIENT.set_dedup(capacity)
+}
+
+/// 设置接收消息的过滤条件，参考 [`WcfClient::set_listen_filter`]
+pub fn set_listen_filter(filter: ListenFilter) {
+    DEFAULT_CLIENT.set_listen_filter(filter)
+}
+
+pub fn listen_filter() -> ListenFilter {
+    DEFAULT_CLIENT.listen_filter()
+}
+
+pub fn blocklist_add(wxid: &str) -> Result<bool> {
+    DEFAULT_CLIENT.blocklist_add(wxid)
+}
+
+pub fn blocklist_remove(wxid: &str) -> Result<bool> {
+    DEFAULT_CLIENT.blocklist_remove(wxid)
+}
+
+pub fn blocklist() -> HashSet<String> {
+    DEFAULT_CLIENT.blocklist()
+}
+
+pub fn allowlist_set_rooms(room_ids: Option<HashSet<String>>) -> Result<()> {
+    DEFAULT_CLIENT.allowlist_set_rooms(room_ids)
+}
+
+pub fn allowlist_rooms() -> Option<HashSet<String>> {
+    DEFAULT_CLIENT.allowlist_rooms()
+}
+
+/// 持久化黑名单和群白名单，参考 [`WcfClient::persist_access_lists`]
+pub fn persist_access_lists<P: Into<PathBuf>>(path: P) -> Result<()> {
+    DEFAULT_CLIENT.persist_access_lists(path)
+}
+
+/// 按配置设置默认客户端，参考 [`WcfClient::apply_config`]
+#[cfg(feature = "config")]
+pub fn apply_config(config: &Config) -> Result<()> {
+    DEFAULT_CLIENT.apply_config(config)
+}
+
+/// 设置事件队列的长度和队列满时的处理方式，参考 [`WcfClient::set_event_queue`]
+pub fn set_event_queue(config: EventQueueConfig) {
+    DEFAULT_CLIENT.set_event_queue(config)
+}
+
+pub fn event_queue() -> EventQueueConfig {
+    DEFAULT_CLIENT.event_queue()
+}
+
+/// 参考 [`WcfClient::stats`]
+pub fn stats() -> WcfStats {
+    DEFAULT_CLIENT.stats()
+}
+
+/// 参考 [`WcfClient::reset_stats`]
+pub fn reset_stats() {
+    DEFAULT_CLIENT.reset_stats()
+}
+
+pub fn listen_stats() -> ListenStats {
+    DEFAULT_CLIENT.listen_stats()
+}
+
+/// 设置发送类接口的速率限制，参考 [`WcfClient::set_rate_limit`]
+pub fn set_rate_limit(config: RateLimitConfig) {
+    DEFAULT_CLIENT.set_rate_limit(config)
+}
+
+pub fn rate_limit() -> RateLimitConfig {
+    DEFAULT_CLIENT.rate_limit()
+}
+
+/// 设置处理收到消息的管道，参考 [`WcfClient::set_pipeline`]
+pub fn set_pipeline(pipeline: Option<Pipeline>) {
+    DEFAULT_CLIENT.set_pipeline(pipeline)
+}
+
+/// 设置自动收款策略，参考 [`WcfClient::set_transfer_policy`]
+pub fn set_transfer_policy(policy: TransferPolicy) {
+    DEFAULT_CLIENT.set_transfer_policy(policy)
+}
+
+pub fn transfer_policy() -> TransferPolicy {
+    DEFAULT_CLIENT.transfer_policy()
+}
+
+/// 设置自动通过好友申请的策略，参考 [`WcfClient::set_friend_policy`]
+pub fn set_friend_policy(policy: FriendPolicy) {
+    DEFAULT_CLIENT.set_friend_policy(policy)
+}
+
+pub fn friend_policy() -> FriendPolicy {
+    DEFAULT_CLIENT.friend_policy()
+}
+
+/// 设置新成员入群的欢迎消息，参考 [`WcfClient::set_welcome`]
+pub fn set_welcome(room_id: Option<String>, config: WelcomeConfig) {
+    DEFAULT_CLIENT.set_welcome(room_id, config)
+}
+
+/// 取消欢迎消息，参考 [`WcfClient::remove_welcome`]
+pub fn remove_welcome(room_id: Option<String>) {
+    DEFAULT_CLIENT.remove_welcome(room_id)
+}
+
+pub fn welcome(room_id: &str) -> Option<WelcomeConfig> {
+    DEFAULT_CLIENT.welcome(room_id)
+}
+
+/// 所有发送类接口发送前随机等待，参考 [`WcfClient::set_humanize`]
+pub fn set_humanize(options: Option<HumanizeOptions>) {
+    DEFAULT_CLIENT.set_humanize(options)
+}
+
+/// 发送链接卡片，参考 [`LinkCard`]
+pub fn send_link(receiver: &str, title: &str, desc: &str, url: &str, thumb_url: &str) -> Result<SendResult> {
+    DEFAULT_CLIENT.send_link(receiver, title, desc, url, thumb_url)
+}
+
+/// 转发消息给多个接收者，参考 [`WcfClient::forward_msg_to_many`]
+pub fn forward_msg_to_many(id: u64, receivers: &[String]) -> Result<Vec<(String, Result<SendResult>)>> {
+    DEFAULT_CLIENT.forward_msg_to_many(id, receivers)
+}
+
+/// 转发会话中最新的一条消息
+pub fn forward_last_from(talker: &str, receiver: String) -> Result<SendResult> {
+    DEFAULT_CLIENT.forward_last_from(talker, receiver)
+}
+
+/// 下载并解密图片消息，参考 [`WcfClient::download_image`]
+pub fn download_image(msg: &WxMsg, dest_dir: &Path, timeout: Duration) -> Result<PathBuf> {
+    DEFAULT_CLIENT.download_image(msg, dest_dir, timeout)
+}
+
+/// 保存语音消息，参考 [`WcfClient::download_audio`]
+pub fn download_audio(msg: &WxMsg, dir: &Path, timeout: Duration) -> Result<PathBuf> {
+    DEFAULT_CLIENT.download_audio(msg, dir, timeout)
+}
+
+/// 下载文件、视频等附件，参考 [`WcfClient::download_attachment`]
+pub fn download_attachment<F>(msg: &WxMsg, dest_dir: Option<&Path>, timeout: Duration, progress: F) -> Result<PathBuf>
+where
+    F: FnMut(u64),
+{
+    DEFAULT_CLIENT.download_attachment(msg, dest_dir, timeout, progress)
+}
+
+/// 群发文本，参考 [`WcfClient::broadcast_text`]
+pub fn broadcast_text(
+    msg: &str,
+    receivers: &[String],
+    options: &BroadcastOptions,
+) -> Result<Vec<(String, Result<SendResult>)>> {
+    DEFAULT_CLIENT.broadcast_text(msg, receivers, options)
+}
+
+pub fn broadcast_image(
+    path: &Path,
+    receivers: &[String],
+    options: &BroadcastOptions,
+) -> Result<Vec<(String, Result<SendResult>)>> {
+    DEFAULT_CLIENT.broadcast_image(path, receivers, options)
+}
+
+pub fn broadcast_file(
+    path: &Path,
+    receivers: &[String],
+    options: &BroadcastOptions,
+) -> Result<Vec<(String, Result<SendResult>)>> {
+    DEFAULT_CLIENT.broadcast_file(path, receivers, options)
+}
+
+/// 模拟人工发送文本，参考 [`WcfClient::send_text_humanized`]
+pub fn send_text_humanized(
+    msg: String,
+    receiver: String,
+    aters: String,
+    options: &HumanizeOptions,
+) -> Result<Vec<SendResult>> {
+    DEFAULT_CLIENT.send_text_humanized(msg, receiver, aters, options)
+}
+
+/// 搜索联系人，参考 [`WcfClient::search_contacts`]
+pub fn search_contacts(query: &str) -> Result<Vec<ContactInfo>> {
+    DEFAULT_CLIENT.search_contacts(query)
+}
+
+/// 通过默认客户端的联系人缓存获取显示的名字，参考 [`ContactCache::display_name`]
+pub fn display_name(wxid: &str) -> Result<String> {
+    DEFAULT_CONTACT_CACHE.display_name(wxid)
+}
+
+/// 默认客户端使用的联系人缓存，可以用于 invalidate() 或 refresh_all()
+pub fn contact_cache() -> &'static ContactCache {
+    &DEFAULT_CONTACT_CACHE
+}
+
+/// 获取群成员，参考 [`WcfClient::get_room_members`]
+pub fn get_room_members(room_id: String) -> Result<Vec<ChatRoomMember>> {
+    DEFAULT_CLIENT.get_room_members(room_id)
+}
+
+/// 获取群主 wxid
+pub fn get_room_owner(room_id: String) -> Result<Option<String>> {
+    DEFAULT_CLIENT.get_room_owner(room_id)
 }
 
 pub fn get_db_names() -> Result<Vec<String>> {
-    let response = run_cmd(proto::Functions::FuncGetDbNames.into(), None)?;
-    match response.msg {
-        Some(proto::response::Msg::Dbs(dbs)) => Ok(dbs.names),
-        _ => Ok(vec![]),
-    }
+    DEFAULT_CLIENT.get_db_names()
 }
 
-pub fn get_db_tables(db: String) -> Result<Vec<proto::DbTable>> {
-    let msg = Some(proto::request::Msg::Str(db));
-    let response = run_cmd(proto::Functions::FuncGetDbTables.into(), msg)?;
-    match response.msg {
-        Some(proto::response::Msg::Tables(tables)) => Ok(tables.tables),
-        _ => Ok(vec![]),
-    }
+/// 查询聊天记录，参考 [`WcfClient::query_messages`]
+pub fn query_messages(filter: &MessageFilter) -> Result<Vec<DbMessage>> {
+    DEFAULT_CLIENT.query_messages(filter)
 }
 
-pub fn exec_db_query(db: String, sql: String) -> Result<Vec<proto::DbRow>> {
-    let db_query_msg = proto::DbQuery { db, sql };
-    let msg = Some(proto::request::Msg::Query(db_query_msg));
-    let response = run_cmd(proto::Functions::FuncExecDbQuery.into(), msg)?;
-    match response.msg {
-        Some(proto::response::Msg::Rows(rows)) => Ok(rows.rows),
-        _ => Ok(vec![]),
-    }
+/// 搜索聊天记录，参考 [`WcfClient::search_messages`]
+pub fn search_messages(query: &str, options: &SearchOptions) -> Result<Vec<SearchHit>> {
+    DEFAULT_CLIENT.search_messages(query, options)
 }
 
-/**
- * @param msg:      消息内容（如果是 @ 消息则需要有跟 @ 的人数量相同的 @）
- * @param receiver: 消息接收人，私聊为 wxid（wxid_xxxxxxxxxxxxxx），群聊为
- *                  roomid（xxxxxxxxxx@chatroom）
- * @param aters:    群聊时要 @ 的人（私聊时为空字符串），多个用逗号分隔。@所有人 用
- *                  notify@all（必须是群主或者管理员才有权限）
- * @return int
- * @Description 发送文本消息
- * @author Changhua
- * @example sendText(" Hello @ 某人1 @ 某人2 ", " xxxxxxxx @ chatroom ",
- * "wxid_xxxxxxxxxxxxx1,wxid_xxxxxxxxxxxxx2");
- */
-pub fn send_text(msg: String, receiver: String, aters: String) -> Result<bool> {
-    let text_msg = proto::TextMsg { msg, receiver, aters };
-    let msg = Some(proto::request::Msg::Txt(text_msg));
-    let response = run_cmd(proto::Functions::FuncSendTxt.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
-}
-
-pub fn send_image(path: PathBuf, receiver: String) -> Result<bool> {
-    let path_msg = proto::PathMsg { path: path.into_os_string().into_string().unwrap_or_default(), receiver };
-    let msg = Some(proto::request::Msg::File(path_msg));
-    let response = run_cmd(proto::Functions::FuncSendImg.into(), msg)?;
-    Ok(response.msg.is_some())
-}
-
-pub fn send_file(path: PathBuf, receiver: String) -> Result<bool> {
-    let path_msg = proto::PathMsg { path: path.into_os_string().into_string().unwrap_or_default(), receiver };
-    let msg = Some(proto::request::Msg::File(path_msg));
-    let response = run_cmd(proto::Functions::FuncSendFile.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
-}
-
-pub fn send_xml(xml: String, path: PathBuf, receiver: String, xml_type: i32) -> Result<bool> {
-    let xml_msg = proto::XmlMsg {
-        content: xml,
-        path: path.into_os_string().into_string().unwrap_or_default(),
-        receiver,
-        r#type: xml_type,
-    };
-    let msg = Some(proto::request::Msg::Xml(xml_msg));
-    let response = run_cmd(proto::Functions::FuncSendXml.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
-}
-
-pub fn send_emotion(path: PathBuf, receiver: String) -> Result<bool> {
-    let path_msg = proto::PathMsg { path: path.into_os_string().into_string().unwrap_or_default(), receiver };
-    let msg = Some(proto::request::Msg::File(path_msg));
-    let response = run_cmd(proto::Functions::FuncSendEmotion.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
-}
-
-pub fn enable_listen() -> Result<()> {
-    let msg_port = {
-        let mut msg_port = MSG_PORT.lock();
-        if *msg_port == 0 {
-            // only send command when msg_port not set
-            let cmd_port = *CMD_PORT.lock();
-            if cmd_port == 0 {
-                return Err(anyhow!("wcf not inited"));
-            }
-            let msg = Some(proto::request::Msg::Flag(true));
-            let response = run_cmd(proto::Functions::FuncEnableRecvTxt.into(), msg)?;
-            if response.msg.is_none() {
-                return Err(anyhow!("failed to enable remote listen service"));
-            }
-            *msg_port = cmd_port + 1;
-        }
-        *msg_port
-    };
-    // start recv msg thread
-    std::thread::spawn(move || recv_msg_thread(msg_port));
-    Ok(())
+/// 搜索结果前后的消息，参考 [`WcfClient::search_context`]
+pub fn search_context(hit: &SearchHit, n: usize) -> Result<Vec<DbMessage>> {
+    DEFAULT_CLIENT.search_context(hit, n)
+}
+
+/// 导出聊天记录，参考 [`WcfClient::export_chat`]
+pub fn export_chat(talker: &str, range: Option<TimeRange>, format: ExportFormat, out: &Path) -> Result<ExportSummary> {
+    DEFAULT_CLIENT.export_chat(talker, range, format, out)
+}
+
+/// 导出联系人，参考 [`WcfClient::export_contacts_csv`]
+pub fn export_contacts_csv<F>(path: &Path, progress: F) -> Result<u64>
+where
+    F: FnMut(u64),
+{
+    DEFAULT_CLIENT.export_contacts_csv(path, progress)
+}
+
+/// 导出群，参考 [`WcfClient::export_rooms_csv`]
+pub fn export_rooms_csv<F>(path: &Path, progress: F) -> Result<u64>
+where
+    F: FnMut(u64),
+{
+    DEFAULT_CLIENT.export_rooms_csv(path, progress)
+}
+
+/// 会话最新的消息所在的分库，参考 [`WcfClient::resolve_msg_db`]
+pub fn resolve_msg_db(talker: &str) -> Result<String> {
+    DEFAULT_CLIENT.resolve_msg_db(talker)
+}
+
+pub fn resolve_msg_dbs(talker: &str) -> Result<Vec<String>> {
+    DEFAULT_CLIENT.resolve_msg_dbs(talker)
+}
+
+/// 在消息库中查找被撤回的消息，参考 [`WcfClient::lookup_revoked_message`]
+pub fn lookup_revoked_message(msg_id: u64) -> Result<Option<DbMessage>> {
+    DEFAULT_CLIENT.lookup_revoked_message(msg_id)
+}
+
+pub fn get_db_tables(db: String) -> Result<Vec<DbTable>> {
+    DEFAULT_CLIENT.get_db_tables(db)
+}
+
+pub fn exec_db_query(db: String, sql: String) -> Result<Vec<DbRow>> {
+    DEFAULT_CLIENT.exec_db_query(db, sql)
+}
+
+/// 分页执行 sql，参考 [`WcfClient::exec_db_query_paged`]
+pub fn exec_db_query_paged(db: String, sql: String, page_size: usize) -> impl Iterator<Item = Result<Vec<DbRow>>> {
+    DEFAULT_CLIENT.exec_db_query_paged(db, sql, page_size)
+}
+
+/// 执行 sql 并按字段类型解码，参考 [`WcfClient::exec_db_query_typed`]
+pub fn exec_db_query_typed(db: String, sql: String) -> Result<Vec<TypedDbRow>> {
+    DEFAULT_CLIENT.exec_db_query_typed(db, sql)
+}
+
+pub fn exec_db_query_params(db: String, sql: &str, params: &[&str]) -> Result<Vec<DbRow>> {
+    DEFAULT_CLIENT.exec_db_query_params(db, sql, params)
+}
+
+/// 发送文本消息，参数说明见 [`WcfClient::send_text`]
+pub fn send_text(msg: String, receiver: String, aters: String) -> Result<SendResult> {
+    DEFAULT_CLIENT.send_text(msg, receiver, aters)
+}
+
+/// 发送群聊 @ 消息，参数说明见 [`WcfClient::send_text_with_mentions`]
+pub fn send_text_with_mentions(room_id: String, text: String, mentions: &[Mention]) -> Result<SendResult> {
+    DEFAULT_CLIENT.send_text_with_mentions(room_id, text, mentions)
+}
+
+pub fn send_image(path: PathBuf, receiver: String) -> Result<SendResult> {
+    DEFAULT_CLIENT.send_image(path, receiver)
+}
+
+pub fn send_image_unchecked(path: PathBuf, receiver: String) -> Result<SendResult> {
+    DEFAULT_CLIENT.send_image_unchecked(path, receiver)
+}
+
+pub fn send_file(path: PathBuf, receiver: String) -> Result<SendResult> {
+    DEFAULT_CLIENT.send_file(path, receiver)
+}
+
+pub fn send_file_unchecked(path: PathBuf, receiver: String) -> Result<SendResult> {
+    DEFAULT_CLIENT.send_file_unchecked(path, receiver)
+}
+
+pub fn send_xml(xml: String, path: PathBuf, receiver: String, xml_type: i32) -> Result<SendResult> {
+    DEFAULT_CLIENT.send_xml(xml, path, receiver, xml_type)
+}
+
+pub fn send_emotion(path: PathBuf, receiver: String) -> Result<SendResult> {
+    DEFAULT_CLIENT.send_emotion(path, receiver)
+}
+
+pub fn send_emotion_unchecked(path: PathBuf, receiver: String) -> Result<SendResult> {
+    DEFAULT_CLIENT.send_emotion_unchecked(path, receiver)
+}
+
+pub fn enable_listen() -> Result<ListenStatus> {
+    DEFAULT_CLIENT.enable_listen()
+}
+
+/// 连接指定的 msg 端口开启消息接收，参考 [`WcfClient::enable_listen_on`]
+pub fn enable_listen_on(port: u16) -> Result<ListenStatus> {
+    DEFAULT_CLIENT.enable_listen_on(port)
+}
+
+pub fn start_health_check(interval: Duration) {
+    DEFAULT_CLIENT.start_health_check(interval)
+}
+
+pub fn stop_health_check() -> bool {
+    DEFAULT_CLIENT.stop_health_check()
+}
+
+pub fn state() -> WcfState {
+    DEFAULT_CLIENT.state()
+}
+
+pub fn is_listening() -> bool {
+    DEFAULT_CLIENT.is_listening()
+}
+
+/// 微信退出后恢复默认客户端的连接，参考 [`WcfClient::reinitialize`]
+#[cfg(feature = "process")]
+pub fn reinitialize(wait: Duration) -> Result<CleanupHandler> {
+    DEFAULT_CLIENT.reinitialize(wait)
+}
+
+/// 按顺序关闭默认客户端，参考 [`WcfClient::shutdown`]
+pub fn shutdown() {
+    DEFAULT_CLIENT.shutdown()
+}
+
+/// 收到 Ctrl-C 等信号时关闭默认客户端并退出进程，参考 [`WcfClient::install_shutdown_handler`]
+#[cfg(feature = "ctrlc")]
+pub fn install_shutdown_handler() -> Result<()> {
+    DEFAULT_CLIENT.install_shutdown_handler()
 }
 
 pub fn disable_listen() -> Result<bool> {
-    let mut msg_port = MSG_PORT.lock();
-    if *msg_port == 0 {
-        return Ok(false); // no need to disable
-    }
+    DEFAULT_CLIENT.disable_listen()
+}
 
-    let response = run_cmd(proto::Functions::FuncDisableRecvTxt.into(), None)?;
-    match response.msg {
-        Some(_) => {
-            *msg_port = 0;
-            Ok(true)
-        }
-        None => Err(anyhow!("failed to disable recv, None returned from remote side")),
-    }
+pub fn disable_listen_with_timeout(timeout: Duration) -> Result<bool> {
+    DEFAULT_CLIENT.disable_listen_with_timeout(timeout)
 }
 
-/**
- * 获取消息类型
- * {"47": "石头剪刀布 | 表情图片", "62": "小视频", "43": "视频", "1": "文字", "10002": "撤回消息", "40": "POSSIBLEFRIEND_MSG", "10000": "红包、系统消息", "37": "好友确认", "48": "位置", "42": "名片", "49": "共享实时位置、文件、转账、链接", "3": "图片", "34": "语音", "9999": "SYSNOTICE", "52": "VOIPNOTIFY", "53": "VOIPINVITE", "51": "微信初始化", "50": "VOIPMSG"}
- */
+pub fn disable_listen_async() -> Result<JoinHandle<Result<bool>>> {
+    DEFAULT_CLIENT.disable_listen_async()
+}
+
+/// 获取消息类型，参考 [`WcfClient::get_msg_types`]
 pub fn get_msg_types() -> Result<HashMap<i32, String>> {
-    let response = run_cmd(proto::Functions::FuncGetMsgTypes.into(), None)?;
-    match response.msg {
-        Some(proto::response::Msg::Types(msg_types)) => Ok(msg_types.types),
-        _ => Ok(HashMap::default()),
-    }
+    DEFAULT_CLIENT.get_msg_types()
 }
 
 pub fn accept_new_friend(v3: String, v4: String, scene: i32) -> Result<bool> {
-    let msg = Some(proto::request::Msg::V(proto::Verification { v3, v4, scene }));
-    let response = run_cmd(proto::Functions::FuncAcceptFriend.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
+    DEFAULT_CLIENT.accept_new_friend(v3, v4, scene)
 }
 
 /* 添加群成员 */
 pub fn add_chatroom_members(roomid: String, wxids: String) -> Result<bool> {
-    let msg = Some(proto::request::Msg::M(proto::MemberMgmt { roomid, wxids }));
-    let response = run_cmd(proto::Functions::FuncAddRoomMembers.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
+    DEFAULT_CLIENT.add_chatroom_members(roomid, wxids)
 }
 
 /* 邀请群成员 */
 pub fn inv_chatroom_members(roomid: String, wxids: String) -> Result<bool> {
-    let msg = Some(proto::request::Msg::M(proto::MemberMgmt { roomid, wxids }));
-    let response = run_cmd(proto::Functions::FuncInvRoomMembers.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
+    DEFAULT_CLIENT.inv_chatroom_members(roomid, wxids)
 }
 
 /* 删除群成员 */
 pub fn del_chatroom_members(roomid: String, wxids: String) -> Result<bool> {
-    let msg = Some(proto::request::Msg::M(proto::MemberMgmt { roomid, wxids }));
-    let response = run_cmd(proto::Functions::FuncDelRoomMembers.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
+    DEFAULT_CLIENT.del_chatroom_members(roomid, wxids)
 }
 
 pub fn decrypt_image(src: String, dst: String) -> Result<bool> {
-    let msg = Some(proto::request::Msg::Dec(proto::DecPath { src, dst }));
-    let response = run_cmd(proto::Functions::FuncDecryptImage.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
+    DEFAULT_CLIENT.decrypt_image(src, dst)
 }
 
 pub fn recv_transfer(wxid: String, transferid: String, transcationid: String) -> Result<bool> {
-    let (tfid, taid) = (transferid, transcationid);
-    let msg = Some(proto::request::Msg::Tf(proto::Transfer { wxid, tfid, taid }));
-    let response = run_cmd(proto::Functions::FuncRecvTransfer.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
+    DEFAULT_CLIENT.recv_transfer(wxid, transferid, transcationid)
 }
 
 /** 刷新朋友圈 */
 pub fn refresh_pyq(id: u64) -> Result<bool> {
-    let msg = Some(proto::request::Msg::Ui64(id));
-    let response = run_cmd(proto::Functions::FuncRefreshPyq.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
+    DEFAULT_CLIENT.refresh_pyq(id)
 }
 
 /** 保存附件 */
 pub fn attach_msg(id: u64, thumb: String, extra: String) -> Result<bool> {
-    let msg = Some(proto::request::Msg::Att(proto::AttachMsg { id, thumb, extra }));
-    let response = run_cmd(proto::Functions::FuncDownloadAttach.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
+    DEFAULT_CLIENT.attach_msg(id, thumb, extra)
+}
+
+/** 通过 wxid 查询联系人信息 */
+pub fn get_contact_info(wxid: String) -> Result<Option<RpcContact>> {
+    DEFAULT_CLIENT.get_contact_info(wxid)
+}
+
+/** 撤回自己发送的消息 */
+pub fn revoke_msg(id: u64) -> Result<bool> {
+    DEFAULT_CLIENT.revoke_msg(id)
+}
+
+/** 刷新登录二维码 */
+pub fn refresh_qrcode() -> Result<Option<String>> {
+    DEFAULT_CLIENT.refresh_qrcode()
 }
 
 /** 获取语音 */
 pub fn get_audio_msg(id: u64, dir: String) -> Result<bool> {
-    let msg = Some(proto::request::Msg::Am(proto::AudioMsg { id, dir }));
-    let response = run_cmd(proto::Functions::FuncGetAudioMsg.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
+    DEFAULT_CLIENT.get_audio_msg(id, dir)
 }
 
 /** 发送富文本 */
-pub fn send_rich_text(richtext: proto::RichText) -> Result<bool> {
-    let msg = Some(proto::request::Msg::Rt(richtext));
-    let response = run_cmd(proto::Functions::FuncSendRichTxt.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
+pub fn send_rich_text(richtext: RichText) -> Result<SendResult> {
+    DEFAULT_CLIENT.send_rich_text(richtext)
 }
 
 /** 发送拍一拍 */
-pub fn send_pat_msg(roomid: String, wxid: String) -> Result<bool> {
-    let msg = Some(proto::request::Msg::Pm(proto::PatMsg { roomid, wxid }));
-    let response = run_cmd(proto::Functions::FuncSendPatMsg.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
+pub fn send_pat_msg(roomid: String, wxid: String) -> Result<SendResult> {
+    DEFAULT_CLIENT.send_pat_msg(roomid, wxid)
 }
 
 /** OCR */
-pub fn exec_ocr(path: PathBuf) -> Result<Option<proto::OcrMsg>> {
-    let path_str = path.into_os_string().into_string().map_err(|_| anyhow!("invalid path"))?;
-    let msg = Some(proto::request::Msg::Str(path_str));
-    let response = run_cmd(proto::Functions::FuncExecOcr.into(), msg)?;
-    match response.msg {
-        Some(proto::response::Msg::Ocr(msg)) => Ok(Some(msg)),
-        _ => Ok(None),
-    }
+pub fn exec_ocr(path: PathBuf) -> Result<Option<OcrMsg>> {
+    DEFAULT_CLIENT.exec_ocr(path)
+}
+
+/// 带重试的 OCR，参考 [`WcfClient::ocr_with_retry`]
+pub fn ocr_with_retry(path: &Path, attempts: u32, delay: Duration) -> Result<Option<String>> {
+    DEFAULT_CLIENT.ocr_with_retry(path, attempts, delay)
+}
+
+/// 下载并识别收到的图片，参考 [`WcfClient::ocr_received_image`]
+pub fn ocr_received_image(
+    msg: &WxMsg,
+    dest_dir: &Path,
+    timeout: Duration,
+    attempts: u32,
+    delay: Duration,
+) -> Result<Option<String>> {
+    DEFAULT_CLIENT.ocr_received_image(msg, dest_dir, timeout, attempts, delay)
 }
 
 /** 转发消息 */
-pub fn forward_msg(id: u64, receiver: String) -> Result<bool> {
-    let msg = Some(proto::request::Msg::Fm(proto::ForwardMsg { id, receiver }));
-    let response = run_cmd(proto::Functions::FuncForwardMsg.into(), msg)?;
-    Ok(get_response_status_as_bool(&response))
+pub fn forward_msg(id: u64, receiver: String) -> Result<SendResult> {
+    DEFAULT_CLIENT.forward_msg(id, receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wx_msg_json_round_trip() {
+        let msg = WxMsg {
+            is_self: false,
+            is_group: true,
+            id: u64::MAX - 1,
+            r#type: 1,
+            ts: 1_700_000_000,
+            roomid: "123@chatroom".into(),
+            content: "你好 \"world\" <&>\n".into(),
+            sender: "wxid_a".into(),
+            sign: "sign".into(),
+            thumb: String::new(),
+            extra: r"C:\WeChat Files\a.dat".into(),
+            xml: "<msgsource><atuserlist>wxid_b</atuserlist></msgsource>".into(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(serde_json::from_str::<WxMsg>(&json).unwrap(), msg);
+    }
+
+    #[test]
+    fn db_row_json_round_trip() {
+        // content is serde_bytes, including bytes that are not utf8
+        let field =
+            |r#type, column: &str, content: &[u8]| DbField { r#type, column: column.into(), content: content.into() };
+        let row = DbRow {
+            fields: vec![
+                field(3, "UserName", "张三".as_bytes()),
+                field(4, "RoomData", &[0x0a, 0xff, 0x00, 0x80]),
+                field(5, "Remark", &[]),
+            ],
+        };
+        let json = serde_json::to_string(&row).unwrap();
+        assert_eq!(serde_json::from_str::<DbRow>(&json).unwrap(), row);
+    }
 }
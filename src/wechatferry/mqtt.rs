@@ -0,0 +1,225 @@
+use parking_lot::Mutex;
+use rumqttc::{Client, Connection, Incoming, LastWill, MqttOptions, Outgoing, QoS};
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{error, info, trace, warn};
+
+use super::error::{Result, WcfError};
+use super::events::HandlerId;
+use super::{WcfClient, WxMsg};
+
+/// MqttBridge 的配置
+#[derive(Clone, Debug)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    /// 设置时使用用户名和密码登录 broker
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// 发布和订阅使用的 QoS，0、1 或 2
+    pub qos: u8,
+    /// 所有 topic 的前缀，例如默认的 `wechat/msg/...`
+    pub topic_prefix: String,
+    pub keep_alive: Duration,
+    /// 与 broker 断开后，每次重连前的等待时间
+    pub reconnect_delay: Duration,
+    /// 等待发布和等待发送的消息数，超过时丢弃新消息
+    pub queue_capacity: usize,
+}
+
+impl MqttConfig {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        MqttConfig {
+            host: host.into(),
+            port,
+            client_id: "wechat-bot".into(),
+            username: None,
+            password: None,
+            qos: 1,
+            topic_prefix: "wechat".into(),
+            keep_alive: Duration::from_secs(30),
+            reconnect_delay: Duration::from_secs(5),
+            queue_capacity: 1000,
+        }
+    }
+
+    fn topic(&self, suffix: &str) -> String {
+        format!("{}/{}", self.topic_prefix, suffix)
+    }
+}
+
+#[derive(Deserialize)]
+struct SendTextPayload {
+    receiver: String,
+    text: String,
+    #[serde(default)]
+    aters: String,
+}
+
+struct Running {
+    mqtt: Client,
+    stopped: Arc<AtomicBool>,
+    handler: HandlerId,
+    threads: Vec<JoinHandle<()>>,
+}
+
+/// 在 MQTT broker 和微信之间转发消息：
+///
+/// - 收到的消息以 json 发布到 `{prefix}/msg/{roomid 或 wxid}`
+/// - 订阅 `{prefix}/send/text`，payload 为 `{"receiver": "...", "text": "...", "aters": "..."}`，收到后发送文本消息
+/// - `{prefix}/status` 为保留消息，连接后为 `online`，断开后由 broker 通过遗嘱发布 `offline`
+///
+/// 连接和重连在独立的 wcf-mqtt 线程中进行，发送在 wcf-mqtt-send 线程中按顺序进行，都不会阻塞接收线程
+pub struct MqttBridge {
+    client: WcfClient,
+    config: MqttConfig,
+    malformed: Arc<AtomicU64>,
+    running: Mutex<Option<Running>>,
+}
+
+impl MqttBridge {
+    pub fn new(client: WcfClient, config: MqttConfig) -> Self {
+        MqttBridge { client, config, malformed: Arc::new(AtomicU64::new(0)), running: Mutex::new(None) }
+    }
+
+    /// 连接 broker 并开始转发，已经开始时不做任何事。连接失败不会返回错误，而是在后台不断重连
+    pub fn start(&self) -> Result<()> {
+        let mut running = self.running.lock();
+        if running.is_some() {
+            return Ok(());
+        }
+        let qos = rumqttc::qos(self.config.qos).map_err(|e| WcfError::InvalidArgument(e.to_string()))?;
+        let mut options = MqttOptions::new(&self.config.client_id, &self.config.host, self.config.port);
+        options.set_keep_alive(self.config.keep_alive);
+        options.set_last_will(LastWill::new(self.config.topic("status"), "offline", qos, true));
+        if let Some(username) = &self.config.username {
+            options.set_credentials(username, self.config.password.clone().unwrap_or_default());
+        }
+        let (mqtt, connection) = Client::new(options, self.config.queue_capacity.max(1));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::sync_channel::<SendTextPayload>(self.config.queue_capacity.max(1));
+
+        let client = self.client.clone();
+        let send_thread = thread::Builder::new().name("wcf-mqtt-send".into()).spawn(move || {
+            for payload in receiver {
+                if let Err(e) = client.send_text(payload.text, payload.receiver.clone(), payload.aters) {
+                    error!("failed to send text from mqtt, receiver={}, error={}", payload.receiver, e);
+                }
+            }
+        })?;
+        let (config, thread_mqtt, thread_stopped, malformed) =
+            (self.config.clone(), mqtt.clone(), stopped.clone(), self.malformed.clone());
+        let connection_thread = thread::Builder::new().name("wcf-mqtt".into()).spawn(move || {
+            Self::connection_thread(connection, thread_mqtt, config, qos, sender, thread_stopped, malformed)
+        })?;
+        let handler = self.on_message(mqtt.clone(), qos);
+        *running = Some(Running { mqtt, stopped, handler, threads: vec![connection_thread, send_thread] });
+        Ok(())
+    }
+
+    fn on_message(&self, mqtt: Client, qos: QoS) -> HandlerId {
+        let prefix = self.config.topic("msg");
+        self.client.on_message(move |msg: &WxMsg| {
+            let chat = if msg.is_group { &msg.roomid } else { &msg.sender };
+            let payload = match serde_json::to_vec(msg) {
+                Ok(payload) => payload,
+                Err(e) => return error!("failed to serialize msg {}, error={}", msg.id, e),
+            };
+            // never blocks, the event loop thread drains the queue
+            if let Err(e) = mqtt.try_publish(format!("{}/{}", prefix, chat), qos, false, payload) {
+                warn!("mqtt queue is full or closed, dropped msg {}, error={}", msg.id, e);
+            }
+        })
+    }
+
+    fn connection_thread(
+        mut connection: Connection,
+        mqtt: Client,
+        config: MqttConfig,
+        qos: QoS,
+        sender: SyncSender<SendTextPayload>,
+        stopped: Arc<AtomicBool>,
+        malformed: Arc<AtomicU64>,
+    ) {
+        trace!("connection_thread()");
+        let send_topic = config.topic("send/text");
+        // polling the connection again after an error reconnects
+        for event in connection.iter() {
+            match event {
+                // sent by stop(), after the offline status
+                Ok(rumqttc::Event::Outgoing(Outgoing::Disconnect)) => break,
+                Ok(rumqttc::Event::Incoming(Incoming::ConnAck(_))) => {
+                    info!("connected to mqtt broker {}:{}", config.host, config.port);
+                    // the session is clean, subscribe again after every reconnect
+                    let _ = mqtt.try_subscribe(&send_topic, qos);
+                    let _ = mqtt.try_publish(config.topic("status"), qos, true, "online");
+                }
+                Ok(rumqttc::Event::Incoming(Incoming::Publish(publish))) if publish.topic == send_topic => {
+                    match serde_json::from_slice::<SendTextPayload>(&publish.payload) {
+                        Ok(payload) => match sender.try_send(payload) {
+                            Ok(()) => {}
+                            Err(TrySendError::Full(payload)) => {
+                                warn!("mqtt send queue is full, dropped text to {}", payload.receiver)
+                            }
+                            Err(TrySendError::Disconnected(_)) => break,
+                        },
+                        Err(e) => {
+                            let count = malformed.fetch_add(1, Ordering::Relaxed) + 1;
+                            warn!("dropped malformed mqtt payload ({} so far), error={}", count, e);
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(_) if stopped.load(Ordering::SeqCst) => break,
+                Err(e) => {
+                    warn!("mqtt connection failed, retry in {:?}, error={}", config.reconnect_delay, e);
+                    Self::sleep_unless_stopped(&stopped, config.reconnect_delay);
+                }
+            }
+        }
+        trace!("mqtt connection thread stopped");
+    }
+
+    // sleep in short slices so stop() doesn't wait for a whole reconnect delay
+    fn sleep_unless_stopped(stopped: &AtomicBool, duration: Duration) {
+        let step = Duration::from_millis(100);
+        let mut slept = Duration::ZERO;
+        while slept < duration && !stopped.load(Ordering::SeqCst) {
+            thread::sleep(step.min(duration - slept));
+            slept += step;
+        }
+    }
+
+    /// 收到的格式不对、已被丢弃的 `send/text` payload 数
+    pub fn malformed_count(&self) -> u64 {
+        self.malformed.load(Ordering::Relaxed)
+    }
+
+    /// 发布 `offline` 后断开连接，等待已收到的发送请求处理完
+    pub fn stop(&self) {
+        let Some(running) = self.running.lock().take() else {
+            return;
+        };
+        self.client.remove_handler(running.handler);
+        running.stopped.store(true, Ordering::SeqCst);
+        if let Ok(qos) = rumqttc::qos(self.config.qos) {
+            let _ = running.mqtt.try_publish(self.config.topic("status"), qos, true, "offline");
+        }
+        let _ = running.mqtt.try_disconnect();
+        drop(running.mqtt);
+        for thread in running.threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for MqttBridge {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
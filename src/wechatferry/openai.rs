@@ -0,0 +1,88 @@
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::error::{Result, WcfError};
+use super::{Message, Responder};
+
+#[derive(Serialize)]
+struct ChatMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<ChatMessage<'a>>,
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChoiceMessage,
+}
+
+#[derive(Deserialize)]
+struct ChoiceMessage {
+    content: Option<String>,
+}
+
+/// 调用 OpenAI 兼容的 /chat/completions 接口生成回复，不使用流式输出
+pub struct OpenAiResponder {
+    http: Client,
+    base_url: String,
+    api_key: String,
+    model: String,
+    /// 作为 system 消息放在最前面，为空时不发送
+    pub system_prompt: String,
+}
+
+impl OpenAiResponder {
+    /// base_url 例如 `https://api.openai.com/v1`，timeout 为整个请求的超时时间
+    pub fn new(base_url: &str, api_key: &str, model: &str, timeout: Duration) -> Result<Self> {
+        let http = Client::builder().timeout(timeout).connect_timeout(timeout).build()?;
+        Ok(OpenAiResponder {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            model: model.to_string(),
+            system_prompt: String::new(),
+        })
+    }
+}
+
+impl Responder for OpenAiResponder {
+    fn respond(&self, incoming: &Message, history: &[Message]) -> Result<Option<String>> {
+        let mut messages = vec![];
+        if !self.system_prompt.is_empty() {
+            messages.push(ChatMessage { role: "system", content: &self.system_prompt });
+        }
+        for msg in history.iter().chain(std::iter::once(incoming)) {
+            let role = if msg.is_self() { "assistant" } else { "user" };
+            if let Some(text) = msg.text() {
+                messages.push(ChatMessage { role, content: text });
+            }
+        }
+        let request = ChatRequest { model: &self.model, messages, stream: false };
+        let response = self
+            .http
+            .post(format!("{}/chat/completions", self.base_url))
+            .bearer_auth(&self.api_key)
+            .json(&request)
+            .send()?
+            .error_for_status()?;
+        let response: ChatResponse = response.json()?;
+        let reply = response.choices.into_iter().next().and_then(|choice| choice.message.content);
+        match reply.map(|reply| reply.trim().to_string()) {
+            Some(reply) if !reply.is_empty() => Ok(Some(reply)),
+            Some(_) => Ok(None),
+            None => Err(WcfError::RemoteRejected("chat completion returned no choices".into())),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use roxmltree::Document;
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use super::{MsgType, WxMsg};
+
+/// 拍一拍通知，可以用 send_pat_msg() 拍回去
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PatNotice {
+    /// 拍人的 wxid
+    pub from_wxid: String,
+    /// 被拍的 wxid
+    pub patted_wxid: String,
+    /// 拍一拍的后缀，例如 "的脑袋"，没有设置时为空
+    pub suffix: String,
+    /// 在群里拍时为群 id
+    pub room_id: Option<String>,
+}
+
+impl PatNotice {
+    /// 解析拍一拍通知，红包、入群等其他系统消息返回 None
+    pub fn parse(msg: &WxMsg) -> Option<PatNotice> {
+        // sent as 10000 by older versions and 10002 by newer ones, both wrapped in <sysmsg type="pat">
+        if !matches!(MsgType::from(msg.r#type as i32), MsgType::System | MsgType::Revoke) {
+            return None;
+        }
+        // other system messages are plain text, group messages may be prefixed with "roomid:\n"
+        let xml = &msg.content[msg.content.find("<sysmsg")?..];
+        let doc = match Document::parse(xml) {
+            Ok(doc) => doc,
+            Err(e) => {
+                trace!("invalid system message xml, error={}, content={}", e, msg.content);
+                return None;
+            }
+        };
+        let root = doc.root_element();
+        if root.attribute("type") != Some("pat") {
+            return None;
+        }
+        let pat = root.children().find(|child| child.has_tag_name("pat"))?;
+        let text = |name| {
+            let node = pat.children().find(|child| child.has_tag_name(name));
+            node.and_then(|node| node.text()).unwrap_or_default().trim().to_string()
+        };
+        let (from_wxid, patted_wxid) = (text("fromusername"), text("pattedusername"));
+        if from_wxid.is_empty() || patted_wxid.is_empty() {
+            return None;
+        }
+        let chat = text("chatusername");
+        let room_id = match chat.ends_with("@chatroom") {
+            true => Some(chat),
+            false => Some(msg.roomid.clone()).filter(|roomid| msg.is_group && !roomid.is_empty()),
+        };
+        Some(PatNotice { from_wxid, patted_wxid, suffix: text("patsuffix"), room_id })
+    }
+}
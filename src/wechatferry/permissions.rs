@@ -0,0 +1,122 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::error::{Result, WcfError};
+use super::{json_file, Message, WcfClient};
+
+/// 权限等级，从低到高排列，可以直接比较大小
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum PermissionLevel {
+    /// 不能使用任何命令
+    Banned,
+    #[default]
+    User,
+    Admin,
+    Owner,
+}
+
+impl FromStr for PermissionLevel {
+    type Err = WcfError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "banned" => Ok(PermissionLevel::Banned),
+            "user" => Ok(PermissionLevel::User),
+            "admin" => Ok(PermissionLevel::Admin),
+            "owner" => Ok(PermissionLevel::Owner),
+            _ => Err(WcfError::InvalidArgument(format!("unknown permission level: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for PermissionLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PermissionLevel::Banned => "banned",
+            PermissionLevel::User => "user",
+            PermissionLevel::Admin => "admin",
+            PermissionLevel::Owner => "owner",
+        };
+        f.write_str(name)
+    }
+}
+
+/// 每个 wxid 的权限等级，没有设置的为 User，可以在多个线程中共享。
+///
+/// 通过 load() 创建时，每次修改都会写回 json 文件
+#[derive(Default)]
+pub struct Permissions {
+    levels: Mutex<HashMap<String, PermissionLevel>>,
+    path: Option<PathBuf>,
+}
+
+impl Permissions {
+    /// 只保存在内存中的权限
+    pub fn new() -> Self {
+        Permissions::default()
+    }
+
+    /// 从 json 文件加载，文件不存在时为空，之后的修改会写回该文件
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let levels = json_file::load(&path)?.unwrap_or_default();
+        Ok(Permissions { levels: Mutex::new(levels), path: Some(path) })
+    }
+
+    /// 设置过的等级，没有设置时为 User
+    pub fn level(&self, wxid: &str) -> PermissionLevel {
+        self.levels.lock().get(wxid).copied().unwrap_or_default()
+    }
+
+    pub fn set_level(&self, wxid: &str, level: PermissionLevel) -> Result<()> {
+        self.update(|levels| {
+            levels.insert(wxid.to_string(), level);
+        })
+    }
+
+    /// 删除设置，恢复为 User，没有设置过时返回 false
+    pub fn remove(&self, wxid: &str) -> Result<bool> {
+        self.update(|levels| levels.remove(wxid).is_some())
+    }
+
+    pub fn all(&self) -> HashMap<String, PermissionLevel> {
+        self.levels.lock().clone()
+    }
+
+    // the lock is held across the save, so concurrent updates are written in order
+    fn update<T>(&self, update: impl FnOnce(&mut HashMap<String, PermissionLevel>) -> T) -> Result<T> {
+        let mut levels = self.levels.lock();
+        let result = update(&mut levels);
+        if let Some(path) = &self.path {
+            json_file::save(&*levels, path)?;
+        }
+        Ok(result)
+    }
+
+    /// 消息的发送者是否至少为 min_level，Banned 总是不允许。
+    ///
+    /// 自己发送的消息视为 Owner；群聊中群主（RoomData 中没有普通管理员的信息）视为 Admin，只在需要时查询群成员
+    pub fn allows(&self, client: &WcfClient, msg: &Message, min_level: PermissionLevel) -> Result<bool> {
+        if msg.is_self() {
+            return Ok(true);
+        }
+        let level = self.level(msg.sender());
+        if level == PermissionLevel::Banned {
+            return Ok(false);
+        }
+        if level >= min_level {
+            return Ok(true);
+        }
+        match msg.room_id() {
+            Some(room_id) if min_level <= PermissionLevel::Admin => {
+                let members = client.get_room_members(room_id.to_string())?;
+                Ok(members.iter().any(|member| member.wxid == msg.sender() && member.is_owner))
+            }
+            _ => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+// One line summaries for printing, `{}` truncates long content and omits xml, `{:#}` shows everything
+use serde::Serialize;
+use std::fmt;
+
+use super::error::Result;
+use super::{ChatRoom, ContactInfo, Message, UserInfo};
+
+// characters of content kept in one line summaries
+const SUMMARY_CHARS: usize = 60;
+
+/// 以缩进的 json 格式输出全部字段，用于需要查看 xml 等完整内容的时候
+pub fn to_pretty_json<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    Ok(serde_json::to_string_pretty(value)?)
+}
+
+// the first SUMMARY_CHARS characters on one line, "…" marks the cut
+pub(crate) fn truncate(text: &str) -> String {
+    let line: String = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match line.char_indices().nth(SUMMARY_CHARS) {
+        Some((end, _)) => format!("{}…", line[..end].trim_end()),
+        None => line,
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.room_id() {
+            Some(room_id) => write!(f, "[group:{}] ", room_id)?,
+            None => f.write_str("[private] ")?,
+        }
+        let wx_msg = self.as_wx_msg();
+        let content = match self.xml_content() {
+            _ if f.alternate() => wx_msg.content.clone(),
+            Some(xml) => format!("<xml {} bytes>", xml.len()),
+            None => truncate(&wx_msg.content),
+        };
+        write!(f, "{}: {} (type={:?} id={})", self.sender(), content, self.msg_type(), self.id())?;
+        if f.alternate() && !wx_msg.xml.is_empty() {
+            write!(f, "\n{}", wx_msg.xml)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ContactInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.display_name(), self.wxid)?;
+        if f.alternate() {
+            let field = |value: &Option<String>| value.clone().unwrap_or_default();
+            write!(
+                f,
+                " alias={} nick_name={} remark={} kind={:?}",
+                field(&self.alias),
+                field(&self.nick_name),
+                field(&self.remark),
+                self.kind()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ChatRoom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} members={}", self.room_id, self.room_data.members.len())?;
+        if let Some(owner) = &self.room_owner {
+            write!(f, " owner={}", owner)?;
+        }
+        match &self.room_announcement {
+            Some(announcement) if f.alternate() => write!(f, "\n{}", announcement),
+            Some(announcement) if !announcement.is_empty() => write!(f, " announcement={}", truncate(announcement)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for UserInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.wxid)?;
+        if f.alternate() {
+            write!(f, " mobile={} home={}", self.mobile, self.home)?;
+        }
+        Ok(())
+    }
+}
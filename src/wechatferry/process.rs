@@ -0,0 +1,112 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+use sysinfo::{ProcessRefreshKind, System};
+use tracing::{info, warn};
+
+use super::error::{Result, WcfError};
+
+/// 微信主程序的进程名
+pub const WECHAT_PROCESS_NAME: &str = "WeChat.exe";
+
+/// 是否有正在运行的微信进程，非 windows 平台总是 false
+pub fn is_wechat_running() -> bool {
+    wechat_pid().is_some()
+}
+
+// only the process names are refreshed, which keeps each check cheap
+pub(crate) fn wechat_pid() -> Option<u32> {
+    let mut system = System::new();
+    system.refresh_processes_specifics(ProcessRefreshKind::new());
+    let mut processes = system.processes().values();
+    processes
+        .find(|process| process.name().eq_ignore_ascii_case(WECHAT_PROCESS_NAME))
+        .map(|process| process.pid().as_u32())
+}
+
+// the explicit path, then the install path in the registry, then the default install locations
+fn wechat_exe_candidates(path: Option<&Path>) -> Vec<PathBuf> {
+    let mut candidates = vec![];
+    if let Some(path) = path {
+        candidates.push(if path.is_dir() { path.join(WECHAT_PROCESS_NAME) } else { path.to_path_buf() });
+    }
+    #[cfg(windows)]
+    {
+        use winreg::enums::HKEY_CURRENT_USER;
+        use winreg::RegKey;
+        let key = RegKey::predef(HKEY_CURRENT_USER).open_subkey("Software\\Tencent\\WeChat");
+        if let Ok(install_path) = key.and_then(|key| key.get_value::<String, _>("InstallPath")) {
+            candidates.push(Path::new(&install_path).join(WECHAT_PROCESS_NAME));
+        }
+    }
+    for (var, default) in [("ProgramFiles(x86)", "C:\\Program Files (x86)"), ("ProgramFiles", "C:\\Program Files")] {
+        let program_files = env::var_os(var).map_or_else(|| PathBuf::from(default), PathBuf::from);
+        candidates.push(program_files.join("Tencent").join("WeChat").join(WECHAT_PROCESS_NAME));
+    }
+    candidates
+}
+
+// whether the process has a visible top level window, e.g. the login or main window
+#[cfg(windows)]
+fn has_window(pid: u32) -> bool {
+    use windows_sys::Win32::Foundation::{BOOL, HWND, LPARAM};
+    use windows_sys::Win32::UI::WindowsAndMessaging::{EnumWindows, GetWindowThreadProcessId, IsWindowVisible};
+
+    // lparam points to (pid, found)
+    unsafe extern "system" fn check_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let target = &mut *(lparam as *mut (u32, bool));
+        let mut pid = 0;
+        GetWindowThreadProcessId(hwnd, &mut pid);
+        if pid == target.0 && IsWindowVisible(hwnd) != 0 {
+            target.1 = true;
+            return 0; // found, stop enumerating
+        }
+        1
+    }
+
+    let mut target = (pid, false);
+    unsafe { EnumWindows(Some(check_window), &mut target as *mut (u32, bool) as LPARAM) };
+    target.1
+}
+
+// windows can't be checked on other platforms, the process alone is enough there
+#[cfg(not(windows))]
+fn has_window(_pid: u32) -> bool {
+    true
+}
+
+/// 没有正在运行的微信进程时启动微信，并等待微信的窗口出现，最多等待 wait，超时后不再等待，直接返回。
+///
+/// 微信已经在运行时返回 None，否则返回启动的进程，由调用方决定退出时是否结束它
+pub(crate) fn launch_wechat(path: Option<&Path>, wait: Duration) -> Result<Option<Child>> {
+    if wechat_pid().is_some() {
+        return Ok(None);
+    }
+    let candidates = wechat_exe_candidates(path);
+    let exe =
+        candidates.iter().find(|candidate| candidate.is_file()).ok_or(WcfError::WeChatNotFound(candidates.clone()))?;
+    info!("wechat is not running, launching {:?}", exe);
+    let mut child = Command::new(exe)
+        .spawn()
+        .map_err(|e| WcfError::WeChatLaunchFailed(format!("failed to start {:?}: {}", exe, e)))?;
+    let deadline = Instant::now() + wait;
+    loop {
+        // the started process may hand over to another WeChat.exe and exit, so look up the process by name
+        if wechat_pid().is_some_and(has_window) {
+            return Ok(Some(child));
+        }
+        if let Ok(Some(status)) = child.try_wait() {
+            if !status.success() {
+                return Err(WcfError::WeChatLaunchFailed(format!("{:?} exited with {}", exe, status)));
+            }
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            warn!("wechat window not found in {:?}, continue anyway", wait);
+            return Ok(Some(child));
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(500)));
+    }
+}
@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// 速率，per 时间内最多 count 次，允许一次性用完，count 为 0 时不限制
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rate {
+    pub count: u32,
+    pub per: Duration,
+}
+
+/// 超过速率限制时的处理方式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RateLimitMode {
+    /// 阻塞直到可以发送
+    Block,
+    /// 立即返回 `WcfError::RateLimited`
+    Reject,
+}
+
+/// 发送类接口的速率限制，见 `set_rate_limit()`，None 表示不限制，默认不限制
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// 对同一个接收者的限制
+    pub per_receiver: Option<Rate>,
+    /// 所有接收者合计的限制
+    pub global: Option<Rate>,
+    pub mode: RateLimitMode,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig::unlimited()
+    }
+}
+
+impl RateLimitConfig {
+    /// 不做任何限制
+    pub fn unlimited() -> Self {
+        RateLimitConfig { per_receiver: None, global: None, mode: RateLimitMode::Block }
+    }
+
+    /// 较保守的限制：同一接收者每秒 1 条、合计每分钟 20 条，超过时阻塞等待
+    pub fn conservative() -> Self {
+        RateLimitConfig {
+            per_receiver: Some(Rate { count: 1, per: Duration::from_secs(1) }),
+            global: Some(Rate { count: 20, per: Duration::from_secs(60) }),
+            mode: RateLimitMode::Block,
+        }
+    }
+}
+
+// a token bucket, refilled continuously at count/per
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(rate: &Rate, now: Instant) -> Self {
+        Bucket { tokens: rate.count as f64, updated: now }
+    }
+
+    fn refill(&mut self, rate: &Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        let per = rate.per.as_secs_f64().max(f64::EPSILON);
+        self.tokens = (self.tokens + elapsed * rate.count as f64 / per).min(rate.count as f64);
+        self.updated = now;
+    }
+
+    // zero when a token is available
+    fn wait_time(&self, rate: &Rate) -> Duration {
+        if self.tokens >= 1.0 || rate.count == 0 {
+            return Duration::ZERO;
+        }
+        rate.per.mul_f64((1.0 - self.tokens) / rate.count as f64)
+    }
+
+    fn is_full(&self, rate: &Rate) -> bool {
+        self.tokens >= rate.count as f64
+    }
+}
+
+// beyond this many receivers, buckets that are full again are dropped
+const MAX_IDLE_RECEIVERS: usize = 1024;
+
+/// 按配置对每次发送计数，时间由调用者传入，便于测试
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    global: Option<Bucket>,
+    receivers: HashMap<String, Bucket>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::new(RateLimitConfig::default())
+    }
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: RateLimitConfig) -> Self {
+        RateLimiter { config, global: None, receivers: HashMap::new() }
+    }
+
+    pub(crate) fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    /// 在 now 时发送给 receiver，可以发送时扣除次数，否则返回需要等待的时间
+    pub(crate) fn try_acquire(&mut self, receiver: &str, now: Instant) -> Result<(), Duration> {
+        let mut wait = Duration::ZERO;
+        if let Some(rate) = &self.config.global {
+            let bucket = self.global.get_or_insert_with(|| Bucket::full(rate, now));
+            bucket.refill(rate, now);
+            wait = wait.max(bucket.wait_time(rate));
+        }
+        if let Some(rate) = &self.config.per_receiver {
+            if self.receivers.len() > MAX_IDLE_RECEIVERS {
+                self.receivers.retain(|_, bucket| {
+                    bucket.refill(rate, now);
+                    !bucket.is_full(rate)
+                });
+            }
+            let bucket = self.receivers.entry(receiver.to_string()).or_insert_with(|| Bucket::full(rate, now));
+            bucket.refill(rate, now);
+            wait = wait.max(bucket.wait_time(rate));
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        if let Some(bucket) = self.global.as_mut() {
+            bucket.tokens -= 1.0;
+        }
+        if let Some(bucket) = self.receivers.get_mut(receiver) {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(per_receiver: Option<Rate>, global: Option<Rate>) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig { per_receiver, global, mode: RateLimitMode::Reject })
+    }
+
+    fn rate(count: u32, secs: u64) -> Rate {
+        Rate { count, per: Duration::from_secs(secs) }
+    }
+
+    #[test]
+    fn default_is_unlimited() {
+        assert_eq!(RateLimitConfig::default(), RateLimitConfig::unlimited());
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+        assert!((0..1000).all(|_| limiter.try_acquire("wxid_a", now).is_ok()));
+    }
+
+    #[test]
+    fn per_receiver_refills_over_time() {
+        let mut limiter = limiter(Some(rate(2, 10)), None);
+        let start = Instant::now();
+        assert_eq!(limiter.try_acquire("wxid_a", start), Ok(()));
+        assert_eq!(limiter.try_acquire("wxid_a", start), Ok(()));
+        // one token comes back every 5 seconds
+        assert_eq!(limiter.try_acquire("wxid_a", start), Err(Duration::from_secs(5)));
+        assert_eq!(limiter.try_acquire("wxid_a", start + Duration::from_secs(2)), Err(Duration::from_secs(3)));
+        assert_eq!(limiter.try_acquire("wxid_a", start + Duration::from_secs(5)), Ok(()));
+        assert!(limiter.try_acquire("wxid_a", start + Duration::from_secs(5)).is_err());
+        // other receivers have their own bucket
+        assert_eq!(limiter.try_acquire("wxid_b", start), Ok(()));
+        // never more than count after a long idle time
+        let later = start + Duration::from_secs(3600);
+        assert_eq!(limiter.try_acquire("wxid_a", later), Ok(()));
+        assert_eq!(limiter.try_acquire("wxid_a", later), Ok(()));
+        assert!(limiter.try_acquire("wxid_a", later).is_err());
+    }
+
+    #[test]
+    fn global_is_shared_by_receivers() {
+        let mut limiter = limiter(Some(rate(1, 1)), Some(rate(3, 60)));
+        let start = Instant::now();
+        for receiver in ["wxid_a", "wxid_b", "wxid_c"] {
+            assert_eq!(limiter.try_acquire(receiver, start), Ok(()));
+        }
+        assert_eq!(limiter.try_acquire("wxid_d", start), Err(Duration::from_secs(20)));
+        // the per-receiver wait is the longer one here
+        let now = start + Duration::from_millis(19_500);
+        assert_eq!(limiter.try_acquire("wxid_a", now), Err(Duration::from_millis(500)));
+        assert_eq!(limiter.try_acquire("wxid_a", start + Duration::from_secs(20)), Ok(()));
+    }
+
+    #[test]
+    fn rejected_attempt_uses_no_token() {
+        let mut limiter = limiter(Some(rate(1, 1)), Some(rate(2, 60)));
+        let start = Instant::now();
+        assert_eq!(limiter.try_acquire("wxid_a", start), Ok(()));
+        // rejected by the receiver limit, the global token is kept for wxid_b
+        assert!(limiter.try_acquire("wxid_a", start).is_err());
+        assert_eq!(limiter.try_acquire("wxid_b", start), Ok(()));
+        assert!(limiter.try_acquire("wxid_c", start).is_err());
+    }
+
+    #[test]
+    fn idle_receivers_are_dropped() {
+        let mut limiter = limiter(Some(rate(1, 1)), None);
+        let start = Instant::now();
+        for i in 0..=MAX_IDLE_RECEIVERS {
+            assert_eq!(limiter.try_acquire(&format!("wxid_{}", i), start), Ok(()));
+        }
+        assert_eq!(limiter.receivers.len(), MAX_IDLE_RECEIVERS + 1);
+        assert_eq!(limiter.try_acquire("wxid_new", start + Duration::from_secs(1)), Ok(()));
+        assert_eq!(limiter.receivers.len(), 1);
+    }
+}
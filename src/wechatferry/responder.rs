@@ -0,0 +1,152 @@
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use tracing::{error, trace, warn};
+
+use super::error::Result;
+use super::events::HandlerId;
+use super::{Event, Message, WcfClient};
+
+/// 根据收到的消息生成回复，例如调用大模型，返回 None 时不回复。
+///
+/// history 为同一会话中之前的消息，按时间顺序，不包括 incoming，自己发送的消息 is_self() 为 true
+pub trait Responder: Send + Sync {
+    fn respond(&self, incoming: &Message, history: &[Message]) -> Result<Option<String>>;
+}
+
+impl<F> Responder for F
+where
+    F: Fn(&Message, &[Message]) -> Result<Option<String>> + Send + Sync,
+{
+    fn respond(&self, incoming: &Message, history: &[Message]) -> Result<Option<String>> {
+        self(incoming, history)
+    }
+}
+
+/// ResponderDriver 的选项
+#[derive(Clone, Debug)]
+pub struct ResponderOptions {
+    /// 处理这些会话（私聊为 wxid，群聊为群 id）的文本消息，为空时处理所有会话
+    pub chats: HashSet<String>,
+    /// 每个会话保留的历史消息条数
+    pub history_len: usize,
+    /// 等待生成回复的消息数，超过时丢弃新消息
+    pub queue_capacity: usize,
+}
+
+impl Default for ResponderOptions {
+    fn default() -> Self {
+        ResponderOptions { chats: HashSet::new(), history_len: 10, queue_capacity: 64 }
+    }
+}
+
+// a rolling window of recent text messages per chat
+#[derive(Default)]
+struct Histories {
+    chats: HashMap<String, VecDeque<Message>>,
+}
+
+impl Histories {
+    // returns the history before msg
+    fn push(&mut self, chat: &str, msg: &Message, len: usize) -> Vec<Message> {
+        let history = self.chats.entry(chat.to_string()).or_default();
+        let before = history.iter().cloned().collect();
+        history.push_back(msg.clone());
+        while history.len() > len {
+            history.pop_front();
+        }
+        before
+    }
+}
+
+struct Running {
+    handler: HandlerId,
+    worker: JoinHandle<()>,
+}
+
+/// 把指定会话收到的文本消息交给 Responder，并把回复发送回该会话。
+///
+/// Responder 在独立的 wcf-responder 线程中依次调用，不会阻塞接收和事件分发；回复通过 send_text() 发送，
+/// 受发送速率限制。失败时发出 `Event::ResponderFailed`，不会发送到聊天中
+pub struct ResponderDriver {
+    client: WcfClient,
+    responder: Arc<dyn Responder>,
+    options: ResponderOptions,
+    running: Mutex<Option<Running>>,
+}
+
+impl ResponderDriver {
+    pub fn new(client: WcfClient, responder: impl Responder + 'static, options: ResponderOptions) -> Self {
+        ResponderDriver { client, responder: Arc::new(responder), options, running: Mutex::new(None) }
+    }
+
+    /// 开始处理收到的消息，已经开始时不做任何事
+    pub fn start(&self) -> Result<()> {
+        let mut running = self.running.lock();
+        if running.is_some() {
+            return Ok(());
+        }
+        let (sender, receiver) = mpsc::sync_channel::<(Message, Vec<Message>)>(self.options.queue_capacity.max(1));
+        let (client, responder) = (self.client.clone(), self.responder.clone());
+        let worker = thread::Builder::new().name("wcf-responder".into()).spawn(move || {
+            for (msg, history) in receiver {
+                Self::respond(&client, responder.as_ref(), &msg, &history);
+            }
+        })?;
+        let handler = self.on_message(sender);
+        *running = Some(Running { handler, worker });
+        Ok(())
+    }
+
+    fn on_message(&self, sender: SyncSender<(Message, Vec<Message>)>) -> HandlerId {
+        let options = self.options.clone();
+        let mut histories = Histories::default();
+        self.client.on_message(move |msg| {
+            let msg = Message::from(msg.clone());
+            let chat = msg.room_id().unwrap_or(msg.sender()).to_string();
+            if msg.text().is_none() || !(options.chats.is_empty() || options.chats.contains(&chat)) {
+                return;
+            }
+            // replies sent by the bot come back as self messages, they only go into the history
+            let history = histories.push(&chat, &msg, options.history_len);
+            if msg.is_self() {
+                return;
+            }
+            match sender.try_send((msg, history)) {
+                Ok(()) => {}
+                Err(TrySendError::Full((msg, _))) => warn!("responder queue is full, dropped msg {}", msg.id()),
+                Err(TrySendError::Disconnected(_)) => trace!("responder stopped"),
+            }
+        })
+    }
+
+    fn respond(client: &WcfClient, responder: &dyn Responder, msg: &Message, history: &[Message]) {
+        let receiver = msg.room_id().unwrap_or(msg.sender()).to_string();
+        let result = match responder.respond(msg, history) {
+            Ok(Some(reply)) => client.send_text(reply, receiver, String::new()).map(|_| ()),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("responder failed, msg_id={}, error={}", msg.id(), e);
+            client.send_event(Event::ResponderFailed { msg_id: msg.id(), error: e.to_string() });
+        }
+    }
+
+    /// 停止处理，等待队列中已有的消息处理完
+    pub fn stop(&self) {
+        if let Some(running) = self.running.lock().take() {
+            // removing the handler drops the sender, which ends the worker loop
+            self.client.remove_handler(running.handler);
+            let _ = running.worker.join();
+        }
+    }
+}
+
+impl Drop for ResponderDriver {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
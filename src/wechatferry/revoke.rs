@@ -0,0 +1,41 @@
+use roxmltree::Document;
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use super::{MsgType, WxMsg};
+
+/// 撤回通知（type 10002 消息）
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevokeNotice {
+    /// 撤回消息所在的会话，私聊为 wxid，群聊为群 id
+    pub session: String,
+    /// 被撤回消息的 id，即 WxMsg.id，可以用于 lookup_revoked_message()
+    pub revoked_msg_id: u64,
+    /// 替换显示的文字，例如 "xxx" 撤回了一条消息
+    pub replace_text: String,
+}
+
+impl RevokeNotice {
+    /// 解析撤回通知，不是 type 10002 的撤回消息或 xml 无法解析时返回 None
+    pub fn parse(msg: &WxMsg) -> Option<RevokeNotice> {
+        if MsgType::from(msg.r#type as i32) != MsgType::Revoke {
+            return None;
+        }
+        // group messages may be prefixed with "roomid:\n"
+        let xml = &msg.content[msg.content.find('<')?..];
+        let doc = match Document::parse(xml) {
+            Ok(doc) => doc,
+            Err(e) => {
+                trace!("invalid revoke xml, error={}, content={}", e, msg.content);
+                return None;
+            }
+        };
+        let revokemsg = doc.descendants().find(|node| node.has_tag_name("revokemsg"))?;
+        let text = |name| {
+            let node = revokemsg.children().find(|child| child.has_tag_name(name));
+            node.and_then(|node| node.text()).unwrap_or_default().trim().to_string()
+        };
+        let revoked_msg_id = text("newmsgid").parse().ok()?;
+        Some(RevokeNotice { session: text("session"), revoked_msg_id, replace_text: text("replacemsg") })
+    }
+}
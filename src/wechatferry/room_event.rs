@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+
+use super::{MsgType, WxMsg};
+
+/// 群聊系统消息（type 10000）中的成员变动等事件，成员为消息中显示的名字而不是 wxid，自己显示为 "你" / "You"
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoomEvent {
+    /// 邀请或扫码入群，inviter 为邀请人或二维码的分享人
+    MemberJoined {
+        inviter: String,
+        members: Vec<String>,
+    },
+    /// 被移出群聊
+    MemberLeft {
+        member: String,
+    },
+    NameChanged {
+        new_name: String,
+    },
+    AnnouncementChanged,
+    /// 其他系统消息，保留原文
+    Unknown(String),
+}
+
+// texts inside "..." or “...”, in order
+fn quoted(content: &str) -> Vec<String> {
+    let mut segments = vec![];
+    let mut current: Option<String> = None;
+    for c in content.chars() {
+        match (&mut current, c) {
+            (None, '"' | '“') => current = Some(String::new()),
+            (Some(_), '"' | '”') => segments.extend(current.take()),
+            (Some(segment), _) => segment.push(c),
+            _ => {}
+        }
+    }
+    segments
+}
+
+fn split_members(members: &str, separator: &str) -> Vec<String> {
+    members.split(separator).map(str::trim).filter(|m| !m.is_empty()).map(String::from).collect()
+}
+
+impl RoomEvent {
+    /// 解析群聊系统消息的内容，识别不了时返回 `RoomEvent::Unknown`
+    pub fn parse_content(content: &str) -> RoomEvent {
+        let content = content.trim();
+        let segments = quoted(content);
+        let first = segments.first().cloned().unwrap_or_default();
+        let last = segments.last().cloned().unwrap_or_default();
+        let by_self = content.starts_with('你') || content.starts_with("You ");
+        let self_name = if content.starts_with('你') { "你" } else { "You" };
+
+        if content.contains("通过扫描") && content.contains("二维码加入群聊") {
+            return RoomEvent::MemberJoined { inviter: last, members: vec![first] };
+        }
+        if content.contains("joined the group chat via the QR Code shared by") {
+            return RoomEvent::MemberJoined { inviter: last, members: vec![first] };
+        }
+        if content.contains("邀请") && content.contains("加入了群聊") {
+            let inviter = if by_self { self_name.to_string() } else { first };
+            return RoomEvent::MemberJoined { inviter, members: split_members(&last, "、") };
+        }
+        if content.contains(" invited ") && content.contains("to the group chat") {
+            let inviter = if by_self { self_name.to_string() } else { first };
+            return RoomEvent::MemberJoined { inviter, members: split_members(&last, ",") };
+        }
+        if content.contains("移出了群聊") || (content.contains(" removed ") && content.contains("from the group chat"))
+        {
+            return RoomEvent::MemberLeft { member: last };
+        }
+        if content.contains("修改群名为") || content.contains("changed the group name to") {
+            return RoomEvent::NameChanged { new_name: last };
+        }
+        if content.contains("群公告") || content.contains("group notice") || content.contains("group announcement") {
+            return RoomEvent::AnnouncementChanged;
+        }
+        RoomEvent::Unknown(content.to_string())
+    }
+
+    /// 解析群聊系统消息，不是 type 10000 的群消息时返回 None
+    pub fn parse(msg: &WxMsg) -> Option<RoomEvent> {
+        if MsgType::from(msg.r#type as i32) != MsgType::System || !msg.is_group {
+            return None;
+        }
+        Some(RoomEvent::parse_content(&msg.content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joined(inviter: &str, members: &[&str]) -> RoomEvent {
+        RoomEvent::MemberJoined { inviter: inviter.into(), members: members.iter().map(|m| m.to_string()).collect() }
+    }
+
+    #[test]
+    fn member_joined() {
+        assert_eq!(RoomEvent::parse_content(r#""张三"邀请"李四、王五"加入了群聊"#), joined("张三", &["李四", "王五"]));
+        assert_eq!(RoomEvent::parse_content(r#"你邀请"李四"加入了群聊"#), joined("你", &["李四"]));
+        assert_eq!(RoomEvent::parse_content(r#""李四"通过扫描"张三"分享的二维码加入群聊"#), joined("张三", &["李四"]));
+        assert_eq!(
+            RoomEvent::parse_content(r#""Alice" invited "Bob, Carol" to the group chat"#),
+            joined("Alice", &["Bob", "Carol"])
+        );
+        assert_eq!(RoomEvent::parse_content(r#"You invited "Bob" to the group chat"#), joined("You", &["Bob"]));
+        assert_eq!(
+            RoomEvent::parse_content(r#""Bob" joined the group chat via the QR Code shared by "Alice""#),
+            joined("Alice", &["Bob"])
+        );
+    }
+
+    #[test]
+    fn member_left() {
+        let left = RoomEvent::MemberLeft { member: "李四".into() };
+        assert_eq!(RoomEvent::parse_content(r#"你将"李四"移出了群聊"#), left);
+        let left = RoomEvent::MemberLeft { member: "Bob".into() };
+        assert_eq!(RoomEvent::parse_content(r#"You removed "Bob" from the group chat"#), left);
+    }
+
+    #[test]
+    fn name_changed() {
+        let renamed = RoomEvent::NameChanged { new_name: "周末爬山".into() };
+        assert_eq!(RoomEvent::parse_content("\"张三\"修改群名为“周末爬山”"), renamed);
+        let renamed = RoomEvent::NameChanged { new_name: "Hiking".into() };
+        assert_eq!(RoomEvent::parse_content(r#"You changed the group name to "Hiking""#), renamed);
+    }
+
+    #[test]
+    fn others() {
+        assert_eq!(RoomEvent::parse_content("\"张三\"修改了群公告"), RoomEvent::AnnouncementChanged);
+        assert_eq!(RoomEvent::parse_content(" 以上是打招呼的内容 "), RoomEvent::Unknown("以上是打招呼的内容".into()));
+    }
+
+    #[test]
+    fn only_group_system_messages() {
+        let content = r#""张三"邀请"李四"加入了群聊"#.to_string();
+        let msg = WxMsg { r#type: 10000, is_group: true, content, ..Default::default() };
+        assert_eq!(RoomEvent::parse(&msg), Some(joined("张三", &["李四"])));
+        assert_eq!(RoomEvent::parse(&WxMsg { is_group: false, ..msg.clone() }), None);
+        assert_eq!(RoomEvent::parse(&WxMsg { r#type: 1, ..msg }), None);
+    }
+}
@@ -0,0 +1,451 @@
+use chrono::{DateTime, Local};
+use parking_lot::{Condvar, Mutex};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{error, trace, warn};
+
+use super::cron::CronSchedule;
+use super::error::Result;
+use super::{json_file, WcfClient};
+
+type JobFn = Arc<dyn Fn(&WcfClient) -> Result<()> + Send + Sync>;
+
+// the thread wakes up at least this often, so a clock change is noticed
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Scheduler 取当前时间的时钟，测试时可以替换为手动推进的时钟
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Local>;
+}
+
+/// 系统时钟，即 `Local::now()`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+/// 任务执行的时间
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Schedule {
+    Cron(CronSchedule),
+    /// 添加后每隔一段时间执行一次
+    Every(Duration),
+    /// 只在该时间执行一次，执行后自动删除
+    Once(DateTime<Local>),
+}
+
+impl Schedule {
+    /// 解析 5 段 cron 表达式，见 `CronSchedule`
+    pub fn cron(expression: &str) -> Result<Schedule> {
+        Ok(Schedule::Cron(expression.parse()?))
+    }
+
+    /// after 之后下一次执行的时间，不会再执行时返回 None
+    pub fn next_after(&self, after: &DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Schedule::Cron(cron) => cron.next_after(after),
+            Schedule::Every(interval) => Some(*after + chrono::Duration::from_std(*interval).ok()?),
+            Schedule::Once(at) => Some(*at).filter(|at| at > after),
+        }
+    }
+}
+
+/// 定时执行的任务
+#[derive(Clone)]
+pub enum Job {
+    SendText {
+        receiver: String,
+        text: String,
+    },
+    /// 自定义任务，不会被持久化
+    Custom(JobFn),
+}
+
+impl Job {
+    pub fn custom<F>(f: F) -> Job
+    where
+        F: Fn(&WcfClient) -> Result<()> + Send + Sync + 'static,
+    {
+        Job::Custom(Arc::new(f))
+    }
+
+    fn run(&self, client: &WcfClient) -> Result<()> {
+        match self {
+            Job::SendText { receiver, text } => {
+                client.send_text(text.clone(), receiver.clone(), String::new())?;
+                Ok(())
+            }
+            Job::Custom(f) => f(client),
+        }
+    }
+}
+
+impl fmt::Debug for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Job::SendText { receiver, text } => {
+                f.debug_struct("SendText").field("receiver", receiver).field("text", text).finish()
+            }
+            Job::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// add() 返回的任务 id
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct JobId(u64);
+
+/// jobs() 返回的任务信息
+#[derive(Clone, Debug)]
+pub struct JobInfo {
+    pub id: JobId,
+    pub schedule: Schedule,
+    pub job: Job,
+    /// 下一次执行的时间
+    pub next_run: Option<DateTime<Local>>,
+}
+
+// what persist_to() writes, custom jobs are left out
+#[derive(Serialize, Deserialize)]
+struct SavedJob {
+    id: JobId,
+    schedule: Schedule,
+    receiver: String,
+    text: String,
+}
+
+struct JobEntry {
+    schedule: Schedule,
+    job: Job,
+    next_run: Option<DateTime<Local>>,
+}
+
+struct SchedulerState {
+    jobs: BTreeMap<JobId, JobEntry>,
+    next_id: u64,
+    // (attempts, delay) for a failed run
+    retry: (u32, Duration),
+    path: Option<PathBuf>,
+    running: bool,
+}
+
+impl Default for SchedulerState {
+    fn default() -> Self {
+        SchedulerState {
+            jobs: BTreeMap::new(),
+            next_id: 0,
+            retry: (3, Duration::from_secs(10)),
+            path: None,
+            running: false,
+        }
+    }
+}
+
+impl SchedulerState {
+    fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let saved: Vec<SavedJob> = self
+            .jobs
+            .iter()
+            .filter_map(|(id, entry)| match &entry.job {
+                Job::SendText { receiver, text } => Some(SavedJob {
+                    id: *id,
+                    schedule: entry.schedule.clone(),
+                    receiver: receiver.clone(),
+                    text: text.clone(),
+                }),
+                Job::Custom(_) => None,
+            })
+            .collect();
+        json_file::save(&saved, path)
+    }
+
+    // jobs due at `now`, with their next run moved on, or the job removed if it never runs again
+    fn take_due(&mut self, now: &DateTime<Local>) -> Vec<(JobId, Job)> {
+        let mut due = vec![];
+        for (id, entry) in self.jobs.iter_mut() {
+            if entry.next_run.is_some_and(|next_run| next_run <= *now) {
+                due.push((*id, entry.job.clone()));
+                entry.next_run = entry.schedule.next_after(now);
+            }
+        }
+        let len = self.jobs.len();
+        self.jobs.retain(|_, entry| entry.next_run.is_some());
+        if self.jobs.len() != len {
+            if let Err(e) = self.save() {
+                error!("failed to save scheduled jobs, error={}", e);
+            }
+        }
+        due
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<SchedulerState>,
+    // notified whenever jobs change or the scheduler stops
+    changed: Condvar,
+}
+
+/// 定时任务，start() 后在 wcf-scheduler 线程中按本地时间执行，例如工作日 9:30 发送提醒：
+/// `scheduler.add(Schedule::cron("30 9 * * 1-5")?, Job::SendText { receiver, text })`。
+///
+/// 每次执行在单独的线程中进行，失败时（例如 cmd socket 暂时断开）按 set_retry() 的设置重试
+pub struct Scheduler {
+    client: WcfClient,
+    clock: Arc<dyn Clock>,
+    shared: Arc<Shared>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Scheduler {
+    pub fn new(client: WcfClient) -> Self {
+        Self::with_clock(client, SystemClock)
+    }
+
+    /// 使用指定时钟的调度器，时钟的时间变化后需要等调度线程醒来（最多 1 分钟）才会执行到期的任务
+    pub fn with_clock(client: WcfClient, clock: impl Clock + 'static) -> Self {
+        Scheduler { client, clock: Arc::new(clock), shared: Arc::default(), thread: Mutex::new(None) }
+    }
+
+    /// 添加任务，返回任务 id，schedule 不会再执行时不添加并返回 None
+    pub fn add(&self, schedule: Schedule, job: Job) -> Result<Option<JobId>> {
+        let next_run = match schedule.next_after(&self.clock.now()) {
+            Some(next_run) => Some(next_run),
+            None => return Ok(None),
+        };
+        let mut state = self.shared.state.lock();
+        let id = JobId(state.next_id);
+        state.next_id += 1;
+        state.jobs.insert(id, JobEntry { schedule, job, next_run });
+        state.save()?;
+        self.shared.changed.notify_all();
+        Ok(Some(id))
+    }
+
+    /// 删除任务，不存在时返回 false
+    pub fn remove(&self, id: JobId) -> Result<bool> {
+        let mut state = self.shared.state.lock();
+        let removed = state.jobs.remove(&id).is_some();
+        if removed {
+            state.save()?;
+            self.shared.changed.notify_all();
+        }
+        Ok(removed)
+    }
+
+    /// 所有任务，按 id 排序
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        let state = self.shared.state.lock();
+        let jobs = state.jobs.iter();
+        jobs.map(|(id, entry)| JobInfo {
+            id: *id,
+            schedule: entry.schedule.clone(),
+            job: entry.job.clone(),
+            next_run: entry.next_run,
+        })
+        .collect()
+    }
+
+    /// 执行失败时最多尝试 attempts 次（包括第一次），每次间隔 delay，默认 3 次、10 秒
+    pub fn set_retry(&self, attempts: u32, delay: Duration) {
+        self.shared.state.lock().retry = (attempts.max(1), delay);
+    }
+
+    /// 把 SendText 任务保存到 json 文件，每次修改后写入，并加载文件中已有的任务（id 保持不变）。
+    ///
+    /// 已经过期的 Once 任务加载时会被丢弃，Custom 任务不会被保存
+    pub fn persist_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        let saved: Vec<SavedJob> = json_file::load(&path)?.unwrap_or_default();
+        let now = self.clock.now();
+        let mut state = self.shared.state.lock();
+        for saved in saved {
+            let next_run = saved.schedule.next_after(&now);
+            if next_run.is_none() {
+                continue;
+            }
+            let job = Job::SendText { receiver: saved.receiver, text: saved.text };
+            state.next_id = state.next_id.max(saved.id.0 + 1);
+            state.jobs.insert(saved.id, JobEntry { schedule: saved.schedule, job, next_run });
+        }
+        state.path = Some(path);
+        state.save()?;
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// 启动调度线程，已经启动时不做任何事
+    pub fn start(&self) {
+        let mut thread = self.thread.lock();
+        if thread.is_some() {
+            return;
+        }
+        self.shared.state.lock().running = true;
+        let (client, clock, shared) = (self.client.clone(), self.clock.clone(), self.shared.clone());
+        match self.client.spawn_thread("wcf-scheduler", move || Self::schedule_thread(client, clock, shared)) {
+            Ok(handle) => *thread = Some(handle),
+            Err(e) => error!("failed to spawn scheduler thread, error={}", e),
+        }
+    }
+
+    /// 停止调度线程，任务保留，正在执行的任务不受影响
+    pub fn stop(&self) {
+        let handle = self.thread.lock().take();
+        if let Some(handle) = handle {
+            self.shared.state.lock().running = false;
+            self.shared.changed.notify_all();
+            let _ = handle.join();
+        }
+    }
+
+    fn schedule_thread(client: WcfClient, clock: Arc<dyn Clock>, shared: Arc<Shared>) {
+        trace!("schedule_thread()");
+        let mut state = shared.state.lock();
+        while state.running {
+            let now = clock.now();
+            let due = state.take_due(&now);
+            if !due.is_empty() {
+                let retry = state.retry;
+                drop(state);
+                for (id, job) in due {
+                    Self::spawn_run(&client, id, job, retry);
+                }
+                state = shared.state.lock();
+                continue;
+            }
+            let next_run = state.jobs.values().filter_map(|entry| entry.next_run).min();
+            let sleep = next_run.and_then(|next_run| (next_run - now).to_std().ok()).unwrap_or(MAX_SLEEP);
+            shared.changed.wait_for(&mut state, sleep.min(MAX_SLEEP));
+        }
+    }
+
+    fn spawn_run(client: &WcfClient, id: JobId, job: Job, (attempts, delay): (u32, Duration)) {
+        let job_client = client.clone();
+        let spawned = client.spawn_thread("wcf-scheduled-job", move || {
+            for attempt in 1..=attempts {
+                match job.run(&job_client) {
+                    Ok(()) => return,
+                    Err(e) if attempt < attempts => {
+                        warn!("scheduled job failed, id={:?}, attempt={}, error={}", id, attempt, e);
+                        thread::sleep(delay);
+                    }
+                    Err(e) => error!("scheduled job failed, id={:?}, attempts={}, error={}", id, attempts, e),
+                }
+            }
+        });
+        if let Err(e) = spawned {
+            error!("failed to spawn scheduled job thread, id={:?}, error={}", id, e);
+        }
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::sync::mpsc;
+
+    // only moves when advanced
+    #[derive(Clone)]
+    struct ManualClock(Arc<Mutex<DateTime<Local>>>);
+
+    impl ManualClock {
+        fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> Self {
+            let now = Local.with_ymd_and_hms(year, month, day, hour, minute, 0).earliest().unwrap();
+            ManualClock(Arc::new(Mutex::new(now)))
+        }
+
+        fn advance(&self, duration: chrono::Duration) {
+            *self.0.lock() += duration;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> DateTime<Local> {
+            *self.0.lock()
+        }
+    }
+
+    fn text_job() -> Job {
+        Job::SendText { receiver: "filehelper".into(), text: "hi".into() }
+    }
+
+    #[test]
+    fn next_run_follows_the_clock() {
+        // 2024-07-01 is a monday
+        let clock = ManualClock::at(2024, 7, 1, 8, 0);
+        let scheduler = Scheduler::with_clock(WcfClient::new(), clock.clone());
+        let every = scheduler.add(Schedule::Every(Duration::from_secs(90)), text_job()).unwrap().unwrap();
+        let cron = scheduler.add(Schedule::cron("30 9 * * 1-5").unwrap(), text_job()).unwrap().unwrap();
+        let once_at = clock.now() + chrono::Duration::minutes(10);
+        let once = scheduler.add(Schedule::Once(once_at), text_job()).unwrap().unwrap();
+        assert_eq!(scheduler.add(Schedule::Once(clock.now()), text_job()).unwrap(), None);
+        let next_runs: Vec<_> = scheduler.jobs().into_iter().map(|job| (job.id, job.next_run.unwrap())).collect();
+        assert_eq!(
+            next_runs,
+            vec![
+                (every, clock.now() + chrono::Duration::seconds(90)),
+                (cron, Local.with_ymd_and_hms(2024, 7, 1, 9, 30, 0).unwrap()),
+                (once, once_at),
+            ]
+        );
+
+        // nothing due yet
+        assert!(scheduler.shared.state.lock().take_due(&clock.now()).is_empty());
+        clock.advance(chrono::Duration::minutes(10));
+        let due: Vec<JobId> =
+            scheduler.shared.state.lock().take_due(&clock.now()).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(due, vec![every, once]);
+        // the once job is gone, the interval restarts from now
+        let jobs = scheduler.jobs();
+        assert_eq!(jobs.iter().map(|job| job.id).collect::<Vec<_>>(), vec![every, cron]);
+        assert_eq!(jobs[0].next_run, Some(clock.now() + chrono::Duration::seconds(90)));
+
+        // friday 9:30 is followed by monday 9:30
+        clock.advance(chrono::Duration::days(4) + chrono::Duration::minutes(80));
+        let due = scheduler.shared.state.lock().take_due(&clock.now());
+        assert_eq!(due.into_iter().map(|(id, _)| id).collect::<Vec<_>>(), vec![every, cron]);
+        let next_run = scheduler.jobs().into_iter().find(|job| job.id == cron).unwrap().next_run;
+        assert_eq!(next_run, Some(Local.with_ymd_and_hms(2024, 7, 8, 9, 30, 0).unwrap()));
+    }
+
+    #[test]
+    fn thread_runs_jobs_when_the_clock_moves() {
+        let clock = ManualClock::at(2024, 7, 1, 8, 0);
+        let scheduler = Scheduler::with_clock(WcfClient::new(), clock.clone());
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let job = Job::custom(move |_| {
+            sender.lock().send(()).unwrap();
+            Ok(())
+        });
+        scheduler.add(Schedule::Once(clock.now() + chrono::Duration::hours(1)), job).unwrap();
+        scheduler.start();
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+
+        clock.advance(chrono::Duration::hours(1));
+        scheduler.shared.changed.notify_all();
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        scheduler.stop();
+        assert!(scheduler.jobs().is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::command::split_args;
+use super::error::{Result, WcfError};
+use super::export::EXPORT_COLUMNS;
+use super::{sql, MsgType};
+
+// characters of a snippet, and how many of them come before the first match
+const SNIPPET_CHARS: usize = 60;
+const SNIPPET_BEFORE: usize = 15;
+
+/// `search_messages()` 的选项，None 表示不限制
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SearchOptions {
+    /// 只搜索该会话，私聊为 wxid，群聊为群 id
+    pub talker: Option<String>,
+    /// 不早于该时间
+    pub since: Option<SystemTime>,
+    /// 早于该时间
+    pub until: Option<SystemTime>,
+    /// 最多返回的条数
+    pub limit: usize,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions { talker: None, since: None, until: None, limit: 20 }
+    }
+}
+
+/// 一条搜索结果
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SearchHit {
+    /// 即 WxMsg.id
+    pub msg_id: u64,
+    /// 会话，私聊为 wxid，群聊为群 id
+    pub talker: String,
+    /// 发送者 wxid，群消息时为实际发言的群成员
+    pub sender: String,
+    pub is_self: bool,
+    /// 发送时间，unix 时间戳（秒）
+    pub create_time: i64,
+    pub content: String,
+    /// 第一个匹配附近的一段内容，匹配的词以【】标出，例如 "…昨晚【服务器】又【宕机】了"
+    pub snippet: String,
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+/// 按空白分割搜索词，引号中的内容作为一个词，同 `split_args()`；没有词时返回 `WcfError::InvalidArgument`
+pub(crate) fn parse_terms(query: &str) -> Result<Vec<String>> {
+    let mut terms: Vec<String> = split_args(query).into_iter().filter(|term| !term.is_empty()).collect();
+    terms.dedup();
+    if terms.is_empty() {
+        return Err(WcfError::InvalidArgument("empty message search query".into()));
+    }
+    Ok(terms)
+}
+
+/// 单个分库的查询语句，只搜索文本消息，每个词都要出现（AND），按时间从新到旧排列
+pub(crate) fn msg_db_sql(terms: &[String], options: &SearchOptions) -> Result<String> {
+    let mut conditions = vec![format!("Type = {}", i32::from(MsgType::Text))];
+    for term in terms {
+        let pattern = format!("%{}%", sql::escape_like(term));
+        conditions.push(sql::bind_params("StrContent LIKE ? ESCAPE '\\'", &[&pattern])?);
+    }
+    if let Some(talker) = &options.talker {
+        conditions.push(sql::bind_params("StrTalker = ?", &[talker])?);
+    }
+    if let Some(since) = options.since {
+        conditions.push(format!("CreateTime >= {}", unix_secs(since)));
+    }
+    if let Some(until) = options.until {
+        conditions.push(format!("CreateTime < {}", unix_secs(until)));
+    }
+    Ok(format!(
+        "SELECT {} FROM MSG WHERE {} ORDER BY CreateTime DESC, localId DESC LIMIT {}",
+        EXPORT_COLUMNS,
+        conditions.join(" AND "),
+        options.limit
+    ))
+}
+
+/// 合并各分库的结果，按时间从新到旧排列后取前 limit 条
+pub(crate) fn merge(mut hits: Vec<SearchHit>, limit: usize) -> Vec<SearchHit> {
+    hits.sort_by_key(|hit| Reverse((hit.create_time, hit.msg_id)));
+    hits.truncate(limit);
+    hits
+}
+
+/// 第一个匹配前后的一段内容，英文不区分大小写，与 LIKE 一致
+pub(crate) fn snippet(content: &str, terms: &[String]) -> String {
+    let chars: Vec<char> = content.chars().map(|c| if c.is_whitespace() { ' ' } else { c }).collect();
+    let folded: Vec<char> = chars.iter().map(char::to_ascii_lowercase).collect();
+    let mut matched = vec![false; chars.len()];
+    for term in terms {
+        let term: Vec<char> = term.chars().map(|c| c.to_ascii_lowercase()).collect();
+        for start in 0..chars.len() {
+            if folded[start..].starts_with(&term) {
+                matched[start..start + term.len()].fill(true);
+            }
+        }
+    }
+    let start = matched.iter().position(|&m| m).unwrap_or_default().saturating_sub(SNIPPET_BEFORE);
+    let end = (start + SNIPPET_CHARS).min(chars.len());
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    for i in start..end {
+        if matched[i] && (i == start || !matched[i - 1]) {
+            snippet.push('【');
+        }
+        snippet.push(chars[i]);
+        if matched[i] && (i + 1 == end || !matched[i + 1]) {
+            snippet.push('】');
+        }
+    }
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
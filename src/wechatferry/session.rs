@@ -0,0 +1,205 @@
+use parking_lot::{Condvar, Mutex};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{error, trace};
+
+use super::{Event, Message, WcfClient};
+
+/// `SessionManager` 默认的最大会话数
+pub const DEFAULT_SESSION_CAPACITY: usize = 10000;
+
+// how often the sweeper looks for expired sessions
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 会话的 key，私聊时 room_id 为 None，同一个人在不同群中的会话互不影响
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SessionKey {
+    pub wxid: String,
+    pub room_id: Option<String>,
+}
+
+impl SessionKey {
+    /// 消息所在会话的 key
+    pub fn of(msg: &Message) -> Self {
+        SessionKey { wxid: msg.sender().to_string(), room_id: msg.room_id().map(String::from) }
+    }
+}
+
+struct Session {
+    state: Box<dyn Any + Send>,
+    expires_at: Instant,
+    // bumped on every access, the smallest one is evicted first
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Sessions {
+    sessions: HashMap<SessionKey, Session>,
+    clock: u64,
+    running: bool,
+}
+
+impl Sessions {
+    fn touch(&mut self, key: &SessionKey) -> Option<&mut Session> {
+        self.clock += 1;
+        let clock = self.clock;
+        let session = self.sessions.get_mut(key).filter(|session| session.expires_at > Instant::now())?;
+        session.last_used = clock;
+        Some(session)
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    sessions: Mutex<Sessions>,
+    // notified when the sweeper should stop
+    stopped: Condvar,
+    expired: AtomicU64,
+    evicted: AtomicU64,
+}
+
+/// 多轮对话的会话状态，按 (wxid, room_id) 保存任意类型的状态，超过 ttl 未完成时过期，可以在多个线程中共享。
+///
+/// start() 后在 wcf-sessions 线程中清理过期的会话并发出 `Event::SessionExpired`，便于发送超时提示；
+/// 会话数超过容量时丢弃最久未使用的会话，不发出事件
+pub struct SessionManager {
+    client: WcfClient,
+    capacity: usize,
+    shared: Arc<Shared>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl SessionManager {
+    pub fn new(client: WcfClient) -> Self {
+        Self::with_capacity(client, DEFAULT_SESSION_CAPACITY)
+    }
+
+    pub fn with_capacity(client: WcfClient, capacity: usize) -> Self {
+        SessionManager { client, capacity: capacity.max(1), shared: Arc::default(), thread: Mutex::new(None) }
+    }
+
+    /// 保存会话状态，替换已有的状态，ttl 后过期
+    pub fn set<T: Any + Send>(&self, key: SessionKey, state: T, ttl: Duration) {
+        let mut sessions = self.shared.sessions.lock();
+        sessions.clock += 1;
+        let session = Session { state: Box::new(state), expires_at: Instant::now() + ttl, last_used: sessions.clock };
+        sessions.sessions.insert(key, session);
+        while sessions.sessions.len() > self.capacity {
+            let oldest = sessions.sessions.iter().min_by_key(|(_, session)| session.last_used).map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                trace!("evicted session, key={:?}", oldest);
+                sessions.sessions.remove(&oldest);
+                self.shared.evicted.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// 未过期且类型为 T 的会话状态
+    pub fn get<T: Any + Clone>(&self, key: &SessionKey) -> Option<T> {
+        self.with(key, |state: &mut T| state.clone())
+    }
+
+    /// 修改未过期且类型为 T 的会话状态，返回 f 的结果
+    pub fn with<T: Any, R>(&self, key: &SessionKey, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let mut sessions = self.shared.sessions.lock();
+        let session = sessions.touch(key)?;
+        session.state.downcast_mut::<T>().map(f)
+    }
+
+    /// 延长会话的过期时间，会话不存在或已过期时返回 false
+    pub fn extend(&self, key: &SessionKey, ttl: Duration) -> bool {
+        let mut sessions = self.shared.sessions.lock();
+        sessions.touch(key).map(|session| session.expires_at = Instant::now() + ttl).is_some()
+    }
+
+    /// 结束会话并取出状态，类型不是 T 时会话保留
+    pub fn take<T: Any>(&self, key: &SessionKey) -> Option<T> {
+        let mut sessions = self.shared.sessions.lock();
+        sessions.touch(key)?.state.is::<T>().then_some(())?;
+        let session = sessions.sessions.remove(key)?;
+        session.state.downcast::<T>().ok().map(|state| *state)
+    }
+
+    /// 结束会话，会话不存在时返回 false
+    pub fn clear(&self, key: &SessionKey) -> bool {
+        self.shared.sessions.lock().sessions.remove(key).is_some()
+    }
+
+    /// 未过期的会话数
+    pub fn active_count(&self) -> usize {
+        let now = Instant::now();
+        self.shared.sessions.lock().sessions.values().filter(|session| session.expires_at > now).count()
+    }
+
+    /// 至今过期的会话数
+    pub fn expired_count(&self) -> u64 {
+        self.shared.expired.load(Ordering::Relaxed)
+    }
+
+    /// 至今因超过容量被丢弃的会话数
+    pub fn evicted_count(&self) -> u64 {
+        self.shared.evicted.load(Ordering::Relaxed)
+    }
+
+    /// 启动清理线程，已经启动时不做任何事
+    pub fn start(&self) {
+        let mut thread = self.thread.lock();
+        if thread.is_some() {
+            return;
+        }
+        self.shared.sessions.lock().running = true;
+        let (client, shared) = (self.client.clone(), self.shared.clone());
+        match self.client.spawn_thread("wcf-sessions", move || Self::sweep_thread(client, shared)) {
+            Ok(handle) => *thread = Some(handle),
+            Err(e) => error!("failed to spawn session sweeper thread, error={}", e),
+        }
+    }
+
+    /// 停止清理线程，会话保留，过期的会话仍然不会被 get() 返回
+    pub fn stop(&self) {
+        let handle = self.thread.lock().take();
+        if let Some(handle) = handle {
+            self.shared.sessions.lock().running = false;
+            self.shared.stopped.notify_all();
+            let _ = handle.join();
+        }
+    }
+
+    fn sweep_thread(client: WcfClient, shared: Arc<Shared>) {
+        trace!("sweep_thread()");
+        let mut sessions = shared.sessions.lock();
+        while sessions.running {
+            let now = Instant::now();
+            let expired: Vec<SessionKey> = sessions
+                .sessions
+                .iter()
+                .filter(|(_, session)| session.expires_at <= now)
+                .map(|(k, _)| k.clone())
+                .collect();
+            for key in &expired {
+                sessions.sessions.remove(key);
+            }
+            if !expired.is_empty() {
+                shared.expired.fetch_add(expired.len() as u64, Ordering::Relaxed);
+                drop(sessions);
+                for key in expired {
+                    client.send_event(Event::SessionExpired(key));
+                }
+                sessions = shared.sessions.lock();
+                continue;
+            }
+            shared.stopped.wait_for(&mut sessions, SWEEP_INTERVAL);
+        }
+    }
+}
+
+impl Drop for SessionManager {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
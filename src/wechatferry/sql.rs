@@ -0,0 +1,139 @@
+use super::error::{Result, WcfError};
+
+/// 将字符串转为 SQLite 字符串字面量，单引号按 SQL 标准双写转义
+///
+/// SQLite 中反斜杠和分号在字面量内没有特殊含义，无需额外处理
+pub(crate) fn quote_str(value: &str) -> Result<String> {
+    if value.contains('\0') {
+        return Err(WcfError::InvalidArgument("sql param contains NUL character".into()));
+    }
+    Ok(format!("'{}'", value.replace('\'', "''")))
+}
+
+/// 解码整数字段，远端按小端序返回原始字节
+pub(crate) fn decode_int(content: &[u8]) -> i64 {
+    let mut bytes = [0u8; 8];
+    let len = content.len().min(8);
+    bytes[..len].copy_from_slice(&content[..len]);
+    i64::from_le_bytes(bytes)
+}
+
+/// 转义 LIKE 模式中的 `%`、`_` 和 `\`，sql 中需要加上 `ESCAPE '\'`
+pub(crate) fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// 将 sql 中的 `?` 占位符依次替换为转义后的参数
+///
+/// 已有字面量（'...'）和标识符（"..."）中的 `?` 不会被替换，占位符数量必须和参数数量一致
+pub(crate) fn bind_params(sql: &str, params: &[&str]) -> Result<String> {
+    let mut bound = String::with_capacity(sql.len() + params.iter().map(|p| p.len() + 2).sum::<usize>());
+    let mut params_iter = params.iter();
+    let mut quote: Option<char> = None;
+    for c in sql.chars() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None, // a doubled quote just closes and reopens, still correct
+            (None, '?') => {
+                let param = params_iter
+                    .next()
+                    .ok_or_else(|| WcfError::InvalidArgument(format!("too few params for sql: {}", sql)))?;
+                bound.push_str(&quote_str(param)?);
+                continue;
+            }
+            _ => {}
+        }
+        bound.push(c);
+    }
+    if quote.is_some() {
+        return Err(WcfError::InvalidArgument(format!("unterminated quote in sql: {}", sql)));
+    }
+    if params_iter.next().is_some() {
+        return Err(WcfError::InvalidArgument(format!("too many params for sql: {}", sql)));
+    }
+    Ok(bound)
+}
+
+/// 字面量和标识符之外是否出现了 LIMIT 关键字，不区分大小写
+pub(crate) fn has_limit(sql: &str) -> bool {
+    let mut quote: Option<char> = None;
+    let mut word = String::new();
+    for c in sql.chars().chain(std::iter::once(' ')) {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, c) if c.is_ascii_alphanumeric() || c == '_' => {
+                word.push(c);
+                continue;
+            }
+            _ => {}
+        }
+        if word.eq_ignore_ascii_case("limit") {
+            return true;
+        }
+        word.clear();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_params_doubles_single_quotes() {
+        let sql = bind_params("SELECT * FROM Contact WHERE UserName = ?", &["foo'bar"]).unwrap();
+        assert_eq!(sql, "SELECT * FROM Contact WHERE UserName = 'foo''bar'");
+    }
+
+    #[test]
+    fn bind_params_keeps_other_characters_literal() {
+        let sql = "SELECT * FROM Contact WHERE UserName = ?";
+        // only single quotes end an sqlite string literal, double quotes and backslashes are plain characters
+        assert_eq!(bind_params(sql, &[r#"foo"bar"#]).unwrap(), r#"SELECT * FROM Contact WHERE UserName = 'foo"bar'"#);
+        assert_eq!(bind_params(sql, &[r"a\b"]).unwrap(), r"SELECT * FROM Contact WHERE UserName = 'a\b'");
+        // the whole value stays one literal, so the semicolon can't start a second statement
+        let injected = bind_params(sql, &["x'; DROP TABLE Contact; --"]).unwrap();
+        assert_eq!(injected, "SELECT * FROM Contact WHERE UserName = 'x''; DROP TABLE Contact; --'");
+    }
+
+    #[test]
+    fn bind_params_skips_quoted_placeholders() {
+        let sql = bind_params(r#"SELECT '?', "a?b" FROM t WHERE x = ? AND y = 'it''s ?'"#, &["1"]).unwrap();
+        assert_eq!(sql, r#"SELECT '?', "a?b" FROM t WHERE x = '1' AND y = 'it''s ?'"#);
+    }
+
+    #[test]
+    fn bind_params_checks_param_count() {
+        assert!(matches!(bind_params("x = ? AND y = ?", &["1"]), Err(WcfError::InvalidArgument(_))));
+        assert!(matches!(bind_params("x = ?", &["1", "2"]), Err(WcfError::InvalidArgument(_))));
+        assert!(matches!(bind_params("x = '?", &[]), Err(WcfError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn nul_is_rejected() {
+        assert!(matches!(quote_str("a\0b"), Err(WcfError::InvalidArgument(_))));
+        assert!(matches!(bind_params("x = ?", &["a\0b"]), Err(WcfError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn limit_inside_literals_is_ignored() {
+        assert!(has_limit("SELECT * FROM MSG LIMIT 10"));
+        assert!(has_limit("select * from MSG\nlimit 10"));
+        assert!(!has_limit("SELECT * FROM MSG WHERE StrContent = 'no limit here'"));
+        assert!(!has_limit(r#"SELECT "limit" FROM t"#));
+        assert!(!has_limit("SELECT * FROM MSG WHERE unlimited = 1 AND limit_x = 2"));
+    }
+
+    #[test]
+    fn escape_like_escapes_wildcards() {
+        assert_eq!(escape_like(r"50%_a\b"), r"50\%\_a\\b");
+    }
+}
@@ -0,0 +1,143 @@
+use chrono::{DateTime, Local};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use super::error::Result;
+use super::proto;
+
+/// 客户端的运行统计，见 `WcfClient::stats()`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WcfStats {
+    /// 从 msg socket 收到的消息数，包括之后被去重或过滤掉的
+    pub messages_received: u64,
+    /// 按 WxMsg.type 统计的收到的消息数
+    pub messages_by_type: BTreeMap<u32, u64>,
+    /// 发送成功的消息数，包括 send_* 和 forward_msg
+    pub messages_sent: u64,
+    /// 发送失败的次数，包括出错和远端返回失败
+    pub send_failures: u64,
+    /// 发送给 wcf 的命令数
+    pub commands: u64,
+    /// 命令的平均往返时间，单位为毫秒
+    pub avg_cmd_latency_ms: f64,
+    /// 等待分发的事件数
+    pub queue_depth: usize,
+    /// 事件队列满时按 OverflowPolicy 丢弃的事件数
+    pub events_dropped: u64,
+    /// 已分发的事件数
+    pub events: u64,
+    /// 最后一次收到消息的时间
+    pub last_message_at: Option<DateTime<Local>>,
+    /// init() 之后经过的秒数，未 init() 时为 None，reset_stats() 不影响
+    pub uptime_secs: Option<u64>,
+    /// cmd socket 自动重连成功的次数
+    pub reconnects: u64,
+    /// msg socket 收到的无法解码的数据数
+    pub decode_errors: u64,
+    /// msg socket 收到的不是 WxMsg 的响应数
+    pub unsupported_msgs: u64,
+}
+
+// updated at the choke points of the client, only atomics except for the per type map
+#[derive(Default)]
+pub(crate) struct StatsCounters {
+    messages_received: AtomicU64,
+    messages_by_type: Mutex<HashMap<u32, u64>>,
+    messages_sent: AtomicU64,
+    send_failures: AtomicU64,
+    commands: AtomicU64,
+    cmd_nanos: AtomicU64,
+    events: AtomicU64,
+    // unix time in milliseconds, 0 before the first message
+    last_message_ms: AtomicU64,
+    reconnects: AtomicU64,
+    decode_errors: AtomicU64,
+    unsupported_msgs: AtomicU64,
+    inited_at: Mutex<Option<Instant>>,
+}
+
+impl StatsCounters {
+    pub fn set_inited(&self, inited: bool) {
+        *self.inited_at.lock() = inited.then(Instant::now);
+    }
+
+    pub fn record_cmd(&self, func: i32, elapsed: Duration, result: &Result<proto::Response>) {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+        self.cmd_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        if super::client::is_send_function(func) {
+            // the response status of send functions is 1 on success, same as SendResult
+            let success =
+                result.as_ref().is_ok_and(|response| matches!(response.msg, Some(proto::response::Msg::Status(1))));
+            let counter = if success { &self.messages_sent } else { &self.send_failures };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_msg(&self, msg_type: u32) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        *self.messages_by_type.lock().entry(msg_type).or_default() += 1;
+        self.last_message_ms.store(Local::now().timestamp_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_event(&self) {
+        self.events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_decode_error(&self) {
+        self.decode_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_unsupported_msg(&self) {
+        self.unsupported_msgs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self, queue_depth: usize, events_dropped: u64) -> WcfStats {
+        let commands = self.commands.load(Ordering::Relaxed);
+        let cmd_nanos = self.cmd_nanos.load(Ordering::Relaxed);
+        let last_message_ms = self.last_message_ms.load(Ordering::Relaxed);
+        WcfStats {
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            messages_by_type: self.messages_by_type.lock().iter().map(|(&t, &count)| (t, count)).collect(),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            send_failures: self.send_failures.load(Ordering::Relaxed),
+            commands,
+            avg_cmd_latency_ms: if commands == 0 { 0.0 } else { cmd_nanos as f64 / commands as f64 / 1e6 },
+            queue_depth,
+            events_dropped,
+            events: self.events.load(Ordering::Relaxed),
+            last_message_at: Some(last_message_ms)
+                .filter(|&ms| ms > 0)
+                .and_then(|ms| DateTime::from_timestamp_millis(ms as i64))
+                .map(|time| time.with_timezone(&Local)),
+            uptime_secs: self.inited_at.lock().map(|inited_at| inited_at.elapsed().as_secs()),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            decode_errors: self.decode_errors.load(Ordering::Relaxed),
+            unsupported_msgs: self.unsupported_msgs.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.messages_received,
+            &self.messages_sent,
+            &self.send_failures,
+            &self.commands,
+            &self.cmd_nanos,
+            &self.events,
+            &self.last_message_ms,
+            &self.reconnects,
+            &self.decode_errors,
+            &self.unsupported_msgs,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.messages_by_type.lock().clear();
+    }
+}
@@ -0,0 +1,262 @@
+use parking_lot::Mutex;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+use super::error::{Result, WcfError};
+use super::{search, sql, HandlerId, MessageFilter, SearchHit, SearchOptions, WcfClient, WxMsg};
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER NOT NULL UNIQUE,
+    ts INTEGER NOT NULL,
+    sender TEXT NOT NULL,
+    roomid TEXT NOT NULL,
+    is_self INTEGER NOT NULL,
+    is_group INTEGER NOT NULL,
+    type INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    xml TEXT NOT NULL,
+    sign TEXT NOT NULL,
+    thumb TEXT NOT NULL,
+    extra TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS messages_roomid_ts ON messages (roomid, ts);
+CREATE INDEX IF NOT EXISTS messages_ts ON messages (ts);";
+
+// full-text index of text messages, the trigram tokenizer matches substrings of 3 or more characters, e.g. Chinese
+// words without spaces between them
+const FTS_SCHEMA: &str = "CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts
+    USING fts5(content, content='messages', tokenize='trigram');
+CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages WHEN new.type = 1 BEGIN
+    INSERT INTO messages_fts (rowid, content) VALUES (new.rowid, new.content);
+END;
+CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages WHEN old.type = 1 BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+END;";
+
+// the shortest term the trigram index can look up, shorter ones fall back to LIKE
+const FTS_MIN_CHARS: usize = 3;
+
+const COLUMNS: &str = "id, ts, sender, roomid, is_self, is_group, type, content, xml, sign, thumb, extra";
+
+/// 将收到的消息保存到本地 SQLite 文件，重启后仍可查询，需要开启 store feature。
+///
+/// 通过 attach() 接入客户端后自动保存每条 MsgReceived 消息，同一 id 的消息只保存一次（重连后可能重复推送）
+pub struct MessageStore {
+    conn: Arc<Mutex<Connection>>,
+    // the client and handler registered in attach()
+    attached: Mutex<Option<(WcfClient, HandlerId)>>,
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+fn insert(conn: &Connection, msg: &WxMsg) -> Result<bool> {
+    let sql = format!("INSERT OR IGNORE INTO messages ({}) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)", COLUMNS);
+    let inserted = conn.execute(
+        &sql,
+        params![
+            msg.id as i64,
+            msg.ts,
+            msg.sender,
+            msg.roomid,
+            msg.is_self,
+            msg.is_group,
+            msg.r#type,
+            msg.content,
+            msg.xml,
+            msg.sign,
+            msg.thumb,
+            msg.extra
+        ],
+    )?;
+    Ok(inserted > 0)
+}
+
+// terms as an FTS5 query, each quoted as a phrase, all of them must match
+fn fts_query(terms: &[&String]) -> String {
+    let phrases: Vec<String> = terms.iter().map(|term| format!("\"{}\"", term.replace('"', "\"\""))).collect();
+    phrases.join(" ")
+}
+
+fn read_msg(row: &Row) -> rusqlite::Result<WxMsg> {
+    Ok(WxMsg {
+        id: row.get::<_, i64>(0)? as u64,
+        ts: row.get(1)?,
+        sender: row.get(2)?,
+        roomid: row.get(3)?,
+        is_self: row.get(4)?,
+        is_group: row.get(5)?,
+        r#type: row.get(6)?,
+        content: row.get(7)?,
+        xml: row.get(8)?,
+        sign: row.get(9)?,
+        thumb: row.get(10)?,
+        extra: row.get(11)?,
+    })
+}
+
+impl MessageStore {
+    /// 打开或创建 path 处的数据库，使用 WAL 模式
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MessageStore> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        let has_fts = conn.query_row("SELECT 1 FROM sqlite_master WHERE name = 'messages_fts'", [], |_| Ok(()));
+        conn.execute_batch(FTS_SCHEMA)?;
+        // databases created before the index existed are indexed once
+        if has_fts.optional()?.is_none() {
+            conn.execute(
+                "INSERT INTO messages_fts (rowid, content) SELECT rowid, content FROM messages WHERE type = 1",
+                [],
+            )?;
+        }
+        Ok(MessageStore { conn: Arc::new(Mutex::new(conn)), attached: Mutex::new(None) })
+    }
+
+    /// 开始保存 client 收到的消息，已接入其他客户端时先断开
+    pub fn attach(&self, client: &WcfClient) {
+        self.detach();
+        let conn = self.conn.clone();
+        let id = client.on_message(move |msg| {
+            if let Err(e) = insert(&conn.lock(), msg) {
+                error!("failed to store message, id={}, error={}", msg.id, e);
+            }
+        });
+        *self.attached.lock() = Some((client.clone(), id));
+    }
+
+    /// 停止保存消息
+    pub fn detach(&self) {
+        if let Some((client, id)) = self.attached.lock().take() {
+            client.remove_handler(id);
+        }
+    }
+
+    /// 保存一条消息，id 已存在时忽略并返回 false
+    pub fn insert(&self, msg: &WxMsg) -> Result<bool> {
+        insert(&self.conn.lock(), msg)
+    }
+
+    /// 查询保存的消息，条件同 `query_messages()`，talker 匹配 WxMsg.roomid，按时间从新到旧排列
+    pub fn query(&self, filter: &MessageFilter) -> Result<Vec<WxMsg>> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+        if let Some(talker) = &filter.talker {
+            conditions.push("roomid = ?");
+            values.push(talker.clone().into());
+        }
+        if let Some(since) = filter.since {
+            conditions.push("ts >= ?");
+            values.push(unix_secs(since).into());
+        }
+        if let Some(until) = filter.until {
+            conditions.push("ts < ?");
+            values.push(unix_secs(until).into());
+        }
+        if let Some(msg_type) = filter.msg_type {
+            conditions.push("type = ?");
+            values.push(i64::from(i32::from(msg_type)).into());
+        }
+        let mut sql = format!("SELECT {} FROM messages", COLUMNS);
+        if !conditions.is_empty() {
+            sql += &format!(" WHERE {}", conditions.join(" AND "));
+        }
+        // SQLite needs a LIMIT before OFFSET, -1 means no limit
+        let limit = filter.limit.map_or(-1, |limit| limit as i64);
+        sql += &format!(" ORDER BY ts DESC, id DESC LIMIT {} OFFSET {}", limit, filter.offset);
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(&sql)?;
+        let messages = stmt.query_map(params_from_iter(values), read_msg)?.collect::<rusqlite::Result<_>>()?;
+        Ok(messages)
+    }
+
+    /// 搜索保存的文本消息，规则同 `search_messages()`，talker 匹配 WxMsg.roomid，按时间从新到旧排列。
+    ///
+    /// 3 个字符及以上的词通过全文索引查找，较短的词逐条比较
+    pub fn search(&self, query: &str, options: &SearchOptions) -> Result<Vec<SearchHit>> {
+        let terms = search::parse_terms(query)?;
+        let (indexed, short): (Vec<&String>, Vec<&String>) =
+            terms.iter().partition(|term| term.chars().count() >= FTS_MIN_CHARS);
+        let mut conditions = vec!["m.type = 1".to_string()];
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+        // messages_fts has a content column too
+        let columns: Vec<String> = COLUMNS.split(", ").map(|column| format!("m.{}", column)).collect();
+        let mut sql = format!("SELECT {} FROM messages m", columns.join(", "));
+        if !indexed.is_empty() {
+            sql += " JOIN messages_fts ON messages_fts.rowid = m.rowid";
+            conditions.push("messages_fts MATCH ?".into());
+            values.push(fts_query(&indexed).into());
+        }
+        for term in short {
+            conditions.push("m.content LIKE ? ESCAPE '\\'".into());
+            values.push(format!("%{}%", sql::escape_like(term)).into());
+        }
+        if let Some(talker) = &options.talker {
+            conditions.push("m.roomid = ?".into());
+            values.push(talker.clone().into());
+        }
+        if let Some(since) = options.since {
+            conditions.push("m.ts >= ?".into());
+            values.push(unix_secs(since).into());
+        }
+        if let Some(until) = options.until {
+            conditions.push("m.ts < ?".into());
+            values.push(unix_secs(until).into());
+        }
+        sql += &format!(" WHERE {} ORDER BY m.ts DESC, m.id DESC LIMIT {}", conditions.join(" AND "), options.limit);
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(&sql)?;
+        let messages: Vec<WxMsg> =
+            stmt.query_map(params_from_iter(values), read_msg)?.collect::<rusqlite::Result<_>>()?;
+        Ok(messages
+            .into_iter()
+            .map(|msg| SearchHit {
+                msg_id: msg.id,
+                snippet: search::snippet(&msg.content, &terms),
+                talker: msg.roomid,
+                sender: msg.sender,
+                is_self: msg.is_self,
+                create_time: i64::from(msg.ts),
+                content: msg.content,
+            })
+            .collect())
+    }
+
+    /// 搜索结果前后各 n 条同一会话中保存的消息，包括该消息本身，按保存的顺序排列，消息不存在时返回 `WcfError::NotFound`
+    pub fn context(&self, hit: &SearchHit, n: usize) -> Result<Vec<WxMsg>> {
+        let conn = self.conn.lock();
+        let rowid: i64 = conn
+            .query_row("SELECT rowid FROM messages WHERE id = ?", params![hit.msg_id as i64], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| WcfError::NotFound(format!("message {}", hit.msg_id)))?;
+        let before =
+            format!("SELECT {} FROM messages WHERE roomid = ? AND rowid < ? ORDER BY rowid DESC LIMIT ?", COLUMNS);
+        let after = format!("SELECT {} FROM messages WHERE roomid = ? AND rowid >= ? ORDER BY rowid LIMIT ?", COLUMNS);
+        let mut messages: Vec<WxMsg> = conn
+            .prepare(&before)?
+            .query_map(params![hit.talker, rowid, n as i64], read_msg)?
+            .collect::<rusqlite::Result<_>>()?;
+        messages.reverse();
+        let mut stmt = conn.prepare(&after)?;
+        for msg in stmt.query_map(params![hit.talker, rowid, n as i64 + 1], read_msg)? {
+            messages.push(msg?);
+        }
+        Ok(messages)
+    }
+
+    /// 删除早于 age 之前的消息，返回删除的条数
+    pub fn prune_older_than(&self, age: Duration) -> Result<usize> {
+        let before = unix_secs(SystemTime::now().checked_sub(age).unwrap_or(UNIX_EPOCH));
+        Ok(self.conn.lock().execute("DELETE FROM messages WHERE ts < ?", params![before])?)
+    }
+}
+
+impl Drop for MessageStore {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
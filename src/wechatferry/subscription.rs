@@ -0,0 +1,288 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{error, trace, warn};
+
+use super::error::{Result, WcfError};
+use super::events::HandlerId;
+use super::{json_file, pretty, ContactCache, Matcher, Message, MsgType, WcfClient};
+
+/// 订阅的群
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    /// 所有群，不包括私聊
+    AllGroups,
+    /// 指定的群 id
+    Rooms(HashSet<String>),
+}
+
+impl Scope {
+    fn contains(&self, room_id: &str) -> bool {
+        match self {
+            Scope::AllGroups => true,
+            Scope::Rooms(rooms) => rooms.contains(room_id),
+        }
+    }
+}
+
+/// 匹配到时通知谁
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NotifyTarget {
+    /// 私聊发给该 wxid，也可以是群 id
+    Wxid(String),
+}
+
+/// 一条关键词订阅
+#[derive(Clone)]
+pub struct Subscription {
+    pub matcher: Matcher,
+    pub scope: Scope,
+    pub notify: NotifyTarget,
+    /// 两次通知的最小间隔，冷却期间匹配到的消息不通知
+    pub cooldown: Duration,
+}
+
+impl Subscription {
+    /// 冷却时间为 60 秒的订阅
+    pub fn new(matcher: Matcher, scope: Scope, notify: NotifyTarget) -> Self {
+        Subscription { matcher, scope, notify, cooldown: Duration::from_secs(60) }
+    }
+}
+
+/// 订阅的 id，用于取消订阅
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SubscriptionId(u64);
+
+// a subscription in the json file, only Exact, Contains and Regex matchers can be saved, Regex needs the regex feature
+#[derive(Serialize, Deserialize)]
+struct SavedSubscription {
+    id: SubscriptionId,
+    kind: String,
+    pattern: String,
+    scope: Scope,
+    notify: NotifyTarget,
+    cooldown_secs: u64,
+}
+
+impl SavedSubscription {
+    fn new(id: SubscriptionId, subscription: &Subscription) -> Option<Self> {
+        let (kind, pattern) = match &subscription.matcher {
+            Matcher::Exact(exact) => ("exact", exact.clone()),
+            Matcher::Contains(keyword) => ("contains", keyword.clone()),
+            #[cfg(feature = "regex")]
+            Matcher::Regex(regex) => ("regex", regex.as_str().to_string()),
+            Matcher::Custom(_) => return None,
+        };
+        Some(SavedSubscription {
+            id,
+            kind: kind.into(),
+            pattern,
+            scope: subscription.scope.clone(),
+            notify: subscription.notify.clone(),
+            cooldown_secs: subscription.cooldown.as_secs(),
+        })
+    }
+
+    fn into_entry(self) -> Result<Entry> {
+        let matcher = match self.kind.as_str() {
+            "exact" => Matcher::Exact(self.pattern),
+            "contains" => Matcher::Contains(self.pattern),
+            #[cfg(feature = "regex")]
+            "regex" => Matcher::regex(&self.pattern)?,
+            kind => return Err(WcfError::InvalidArgument(format!("unknown subscription matcher: {}", kind))),
+        };
+        let subscription = Subscription {
+            matcher,
+            scope: self.scope,
+            notify: self.notify,
+            cooldown: Duration::from_secs(self.cooldown_secs),
+        };
+        Ok(Entry { id: self.id, subscription, last_notified: None })
+    }
+}
+
+struct Entry {
+    id: SubscriptionId,
+    subscription: Subscription,
+    last_notified: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Entries {
+    entries: Vec<Entry>,
+    next_id: u64,
+    path: Option<PathBuf>,
+}
+
+impl Entries {
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let saved: Vec<SavedSubscription> =
+            self.entries.iter().filter_map(|entry| SavedSubscription::new(entry.id, &entry.subscription)).collect();
+        json_file::save(&saved, path)
+    }
+}
+
+struct Running {
+    handler: HandlerId,
+    worker: JoinHandle<()>,
+}
+
+// messages waiting to be matched, newer ones are dropped when full
+const QUEUE_CAPACITY: usize = 256;
+
+/// 关键词订阅，例如任何群提到 "服务器宕机" 时私聊通知管理员。
+///
+/// start() 后在独立的 wcf-subscriptions 线程中匹配群里的文本消息，匹配到时把群名、发送者和内容摘要发给通知对象，
+/// 每条订阅有各自的冷却时间。订阅可以在运行中添加、删除；通过 load() 创建时每次修改都会写回 json 文件，
+/// 使用 `Matcher::Custom` 的订阅只保存在内存中
+pub struct Subscriptions {
+    client: WcfClient,
+    entries: Arc<Mutex<Entries>>,
+    running: Mutex<Option<Running>>,
+}
+
+impl Subscriptions {
+    /// 只保存在内存中的订阅
+    pub fn new(client: WcfClient) -> Self {
+        Subscriptions { client, entries: Arc::default(), running: Mutex::new(None) }
+    }
+
+    /// 从 json 文件加载，文件不存在时为空，之后的修改会写回该文件
+    pub fn load(client: WcfClient, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let saved: Vec<SavedSubscription> = json_file::load(&path)?.unwrap_or_default();
+        let entries = saved.into_iter().map(SavedSubscription::into_entry).collect::<Result<Vec<_>>>()?;
+        let next_id = entries.iter().map(|entry| entry.id.0 + 1).max().unwrap_or_default();
+        let entries = Entries { entries, next_id, path: Some(path) };
+        Ok(Subscriptions { client, entries: Arc::new(Mutex::new(entries)), running: Mutex::new(None) })
+    }
+
+    /// 添加冷却时间为 60 秒的订阅
+    pub fn subscribe_keyword(&self, pattern: Matcher, scope: Scope, notify: NotifyTarget) -> Result<SubscriptionId> {
+        self.subscribe(Subscription::new(pattern, scope, notify))
+    }
+
+    pub fn subscribe(&self, subscription: Subscription) -> Result<SubscriptionId> {
+        let mut entries = self.entries.lock();
+        let id = SubscriptionId(entries.next_id);
+        entries.next_id += 1;
+        entries.entries.push(Entry { id, subscription, last_notified: None });
+        entries.save()?;
+        Ok(id)
+    }
+
+    /// 取消订阅，订阅不存在时返回 false
+    pub fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        let mut entries = self.entries.lock();
+        let len = entries.entries.len();
+        entries.entries.retain(|entry| entry.id != id);
+        if entries.entries.len() == len {
+            return Ok(false);
+        }
+        entries.save()?;
+        Ok(true)
+    }
+
+    /// 按添加顺序返回所有订阅
+    pub fn list(&self) -> Vec<(SubscriptionId, Subscription)> {
+        self.entries.lock().entries.iter().map(|entry| (entry.id, entry.subscription.clone())).collect()
+    }
+
+    /// 开始匹配收到的消息，已经开始时不做任何事
+    pub fn start(&self) -> Result<()> {
+        let mut running = self.running.lock();
+        if running.is_some() {
+            return Ok(());
+        }
+        let (sender, receiver) = mpsc::sync_channel::<Message>(QUEUE_CAPACITY);
+        let (client, entries) = (self.client.clone(), self.entries.clone());
+        let worker = thread::Builder::new().name("wcf-subscriptions".into()).spawn(move || {
+            let contacts = ContactCache::new(client.clone());
+            for msg in receiver {
+                if let Err(e) = Self::notify(&client, &contacts, &entries, &msg) {
+                    error!("failed to notify subscribers, msg_id={}, error={}", msg.id(), e);
+                }
+            }
+        })?;
+        let handler = self.on_message(sender);
+        *running = Some(Running { handler, worker });
+        Ok(())
+    }
+
+    fn on_message(&self, sender: SyncSender<Message>) -> HandlerId {
+        self.client.on_message(move |msg| {
+            // only group texts from others are matched, check before copying the message
+            let group_text =
+                msg.is_group && !msg.roomid.is_empty() && MsgType::from(msg.r#type as i32) == MsgType::Text;
+            if msg.is_self || !group_text {
+                return;
+            }
+            match sender.try_send(Message::from(msg.clone())) {
+                Ok(()) => {}
+                Err(TrySendError::Full(msg)) => warn!("subscription queue is full, dropped msg {}", msg.id()),
+                Err(TrySendError::Disconnected(_)) => trace!("subscriptions stopped"),
+            }
+        })
+    }
+
+    fn notify(client: &WcfClient, contacts: &ContactCache, entries: &Mutex<Entries>, msg: &Message) -> Result<()> {
+        let room_id = msg.room_id().unwrap_or_default();
+        // targets are collected under the lock, sending happens after it is released
+        let targets: Vec<NotifyTarget> = {
+            let mut entries = entries.lock();
+            let now = Instant::now();
+            let mut targets = Vec::new();
+            for entry in entries.entries.iter_mut() {
+                let subscription = &entry.subscription;
+                if !subscription.scope.contains(room_id) || !subscription.matcher.matches(msg) {
+                    continue;
+                }
+                if entry.last_notified.is_some_and(|at| now.duration_since(at) < subscription.cooldown) {
+                    trace!("subscription cooling down, id={:?}, msg_id={}", entry.id, msg.id());
+                    continue;
+                }
+                entry.last_notified = Some(now);
+                if !targets.contains(&subscription.notify) {
+                    targets.push(subscription.notify.clone());
+                }
+            }
+            targets
+        };
+        if targets.is_empty() {
+            return Ok(());
+        }
+        let text = format!(
+            "[{}] {}: {}",
+            contacts.display_name(room_id)?,
+            contacts.display_name(msg.sender())?,
+            pretty::truncate(msg.text().unwrap_or_default())
+        );
+        for NotifyTarget::Wxid(wxid) in targets {
+            client.send_text(text.clone(), wxid, String::new())?;
+        }
+        Ok(())
+    }
+
+    /// 停止匹配，等待队列中已有的消息处理完，订阅保留，可以再次 start()
+    pub fn stop(&self) {
+        if let Some(running) = self.running.lock().take() {
+            // removing the handler drops the sender, which ends the worker loop
+            self.client.remove_handler(running.handler);
+            let _ = running.worker.join();
+        }
+    }
+}
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use super::{AppMsg, MsgType, TransferDirection, WxMsg};
+
+/// 自动收款的策略，见 `set_transfer_policy()`，默认不自动收款
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TransferPolicy {
+    /// 是否自动收款，为 false 时不处理任何转账
+    pub auto_accept: bool,
+    /// 只自动收取不超过该金额（元）的转账，None 表示不限制
+    pub max_amount: Option<f64>,
+    /// 只自动收取这些 wxid 的转账，None 表示不限制
+    pub allowed_senders: Option<HashSet<String>>,
+}
+
+/// 收到的待收款转账
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransferInfo {
+    /// 转账人的 wxid
+    pub wxid: String,
+    /// 金额描述，例如 "￥0.01"
+    pub amount: String,
+    pub transferid: String,
+    pub transcationid: String,
+}
+
+impl TransferInfo {
+    /// 解析对方发给自己的待收款转账，收款、退还通知和自己发出的转账返回 None
+    pub fn parse(msg: &WxMsg) -> Option<TransferInfo> {
+        if msg.is_self || MsgType::from(msg.r#type as i32) != MsgType::App {
+            return None;
+        }
+        match AppMsg::parse(&msg.content).ok()? {
+            AppMsg::Transfer { amount, transferid, transcationid, direction: TransferDirection::Incoming } => {
+                Some(TransferInfo { wxid: msg.sender.clone(), amount, transferid, transcationid })
+            }
+            _ => None,
+        }
+    }
+
+    /// 金额（元），从 amount 中去掉货币符号后解析，无法解析时返回 None
+    pub fn amount_value(&self) -> Option<f64> {
+        let digits: String = self.amount.chars().filter(|c| c.is_ascii_digit() || *c == '.').collect();
+        digits.parse().ok()
+    }
+}
+
+impl TransferPolicy {
+    /// 检查转账是否可以自动收取，不可以时返回原因
+    pub fn check(&self, transfer: &TransferInfo) -> std::result::Result<(), String> {
+        if !self.auto_accept {
+            return Err("auto accept is off".into());
+        }
+        if let Some(senders) = &self.allowed_senders {
+            if !senders.contains(&transfer.wxid) {
+                return Err(format!("sender {} is not allowed", transfer.wxid));
+            }
+        }
+        if let Some(max_amount) = self.max_amount {
+            match transfer.amount_value() {
+                Some(amount) if amount <= max_amount => {}
+                Some(amount) => return Err(format!("amount {} exceeds {}", amount, max_amount)),
+                None => return Err(format!("unknown amount {:?}", transfer.amount)),
+            }
+        }
+        Ok(())
+    }
+}
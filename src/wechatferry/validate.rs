@@ -0,0 +1,151 @@
+use std::fs::File;
+use std::path;
+use std::path::Path;
+
+use super::error::{Result, WcfError};
+
+/// send_image() 接受的图片扩展名
+pub(crate) const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "gif", "bmp", "webp"];
+/// send_emotion() 接受的表情扩展名
+pub(crate) const EMOTION_EXTENSIONS: [&str; 4] = ["gif", "png", "jpg", "jpeg"];
+
+fn invalid_path(path: &Path, reason: &str) -> WcfError {
+    WcfError::InvalidPath { path: path.to_path_buf(), reason: reason.to_string() }
+}
+
+/// 检查文件存在、可读且非空，extensions 不为空时还要求扩展名在其中（不区分大小写）
+pub(crate) fn check_file(path: &Path, extensions: &[&str]) -> Result<()> {
+    let metadata = path.metadata().map_err(|e| invalid_path(path, &e.to_string()))?;
+    if !metadata.is_file() {
+        return Err(invalid_path(path, "not a file"));
+    }
+    if metadata.len() == 0 {
+        return Err(invalid_path(path, "file is empty"));
+    }
+    File::open(path).map_err(|e| invalid_path(path, &format!("not readable, {}", e)))?;
+    if !extensions.is_empty() {
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+        if !extensions.iter().any(|allowed| allowed.eq_ignore_ascii_case(extension)) {
+            return Err(invalid_path(path, &format!("extension should be one of {:?}", extensions)));
+        }
+    }
+    Ok(())
+}
+
+/// 转为发给 spy 的路径字符串，相对路径按当前目录转为绝对路径（spy 按微信的工作目录解析相对路径），
+/// 不会添加或去掉 `\\?\` 前缀，空路径保持为空
+pub(crate) fn path_to_string(path: &Path) -> Result<String> {
+    if path.as_os_str().is_empty() {
+        return Ok(String::new());
+    }
+    let absolute = path::absolute(path).map_err(|e| invalid_path(path, &e.to_string()))?;
+    absolute.into_os_string().into_string().map_err(|_| invalid_path(path, "path is not valid UTF-8"))
+}
+
+/// 检查接收者像是 wxid、微信号或群 id，例如 wxid_xxx、filehelper、123@chatroom
+pub(crate) fn check_receiver(receiver: &str) -> Result<()> {
+    let (name, suffix) = match receiver.split_once('@') {
+        Some((name, suffix)) => (name, Some(suffix)),
+        None => (receiver, None),
+    };
+    let valid_name =
+        !name.is_empty() && name.len() <= 64 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    let valid_suffix = suffix.is_none_or(|suffix| suffix == "chatroom" || suffix == "openim");
+    if !valid_name || !valid_suffix {
+        return Err(WcfError::InvalidReceiver(receiver.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // a fresh directory under the system temp dir, named with a space and CJK characters
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("wcf validate 测试 {}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn reason(result: Result<()>) -> String {
+        match result {
+            Err(WcfError::InvalidPath { reason, .. }) => reason,
+            other => panic!("expected InvalidPath, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn check_file_accepts_spaces_and_cjk() {
+        let dir = temp_dir("ok");
+        let image = dir.join("截图 2024.PNG");
+        fs::write(&image, b"\x89PNG").unwrap();
+        assert!(check_file(&image, &IMAGE_EXTENSIONS).is_ok());
+        assert!(check_file(&image, &[]).is_ok());
+        assert!(reason(check_file(&image, &["gif"])).contains("extension"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn check_file_rejects_missing_empty_and_dirs() {
+        let dir = temp_dir("bad");
+        let empty = dir.join("空 文件.txt");
+        fs::write(&empty, b"").unwrap();
+        assert_eq!(reason(check_file(&empty, &[])), "file is empty");
+        assert_eq!(reason(check_file(&dir, &[])), "not a file");
+        assert!(check_file(&dir.join("不存在.png"), &IMAGE_EXTENSIONS).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn relative_path_becomes_absolute() {
+        let path = path_to_string(Path::new("图片/a b.png")).unwrap();
+        assert!(Path::new(&path).is_absolute());
+        assert_eq!(PathBuf::from(&path), env::current_dir().unwrap().join("图片").join("a b.png"));
+        assert_eq!(path_to_string(Path::new("")).unwrap(), "");
+    }
+
+    #[test]
+    fn absolute_path_is_kept() {
+        let dir = temp_dir("abs");
+        let file = dir.join("文件 1.txt");
+        assert_eq!(path_to_string(&file).unwrap(), file.to_str().unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn extended_length_path_is_kept() {
+        let dir = temp_dir("long");
+        let file = dir.join(format!("{}.png", "长".repeat(100)));
+        fs::write(&file, b"\x89PNG").unwrap();
+        let extended = PathBuf::from(format!(r"\\?\{}", file.display()));
+        assert!(check_file(&extended, &IMAGE_EXTENSIONS).is_ok());
+        assert_eq!(path_to_string(&extended).unwrap(), extended.to_str().unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_path_is_rejected() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"/tmp/\xff.png"));
+        assert_eq!(reason(path_to_string(path).map(|_| ())), "path is not valid UTF-8");
+    }
+
+    #[test]
+    fn receivers() {
+        for receiver in ["wxid_abc123", "filehelper", "123456@chatroom", "abc@openim", "zhang-san"] {
+            assert!(check_receiver(receiver).is_ok(), "{}", receiver);
+        }
+        for receiver in ["", "bad receiver", "a@b", "@chatroom", "张三", &"a".repeat(65)] {
+            assert!(check_receiver(receiver).is_err(), "{}", receiver);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use parking_lot::Mutex;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, trace, warn};
+
+use super::process;
+use super::{Event, WcfClient};
+
+/// Watchdog 的配置
+#[derive(Clone, Copy, Debug)]
+pub struct WatchdogConfig {
+    /// 两次检查之间的间隔
+    pub interval: Duration,
+    /// 微信进程不存在且连续多少次命令失败后，认为微信已退出
+    pub failure_threshold: u32,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig { interval: Duration::from_secs(5), failure_threshold: 2 }
+    }
+}
+
+/// 定期检查微信进程是否还在运行，进程不存在且 cmd socket 的命令连续失败时发出一次 `Event::WeChatProcessExited`，
+/// 之后可以调用 `WcfClient::reinitialize()` 等待微信重新启动并恢复连接。
+///
+/// 在独立的 wcf-watchdog 线程中运行，客户端未 init() 时只等待，不做检查
+pub struct Watchdog {
+    client: WcfClient,
+    config: WatchdogConfig,
+    running: Mutex<Option<(Sender<()>, JoinHandle<()>)>>,
+}
+
+impl Watchdog {
+    pub fn new(client: WcfClient, config: WatchdogConfig) -> Self {
+        Watchdog { client, config, running: Mutex::new(None) }
+    }
+
+    /// 开始检查，已经开始时不做任何事
+    pub fn start(&self) {
+        let mut running = self.running.lock();
+        if running.is_some() {
+            return;
+        }
+        let (stop, stop_receiver) = mpsc::channel();
+        let (client, config) = (self.client.clone(), self.config);
+        let spawned = thread::Builder::new()
+            .name("wcf-watchdog".into())
+            .spawn(move || Self::watchdog_thread(client, config, stop_receiver));
+        match spawned {
+            Ok(handle) => *running = Some((stop, handle)),
+            Err(e) => warn!("failed to spawn watchdog thread, error={}", e),
+        }
+    }
+
+    /// 停止检查，等待线程退出，正在进行的检查会先完成
+    pub fn stop(&self) {
+        if let Some((stop, handle)) = self.running.lock().take() {
+            drop(stop);
+            let _ = handle.join();
+        }
+    }
+
+    fn watchdog_thread(client: WcfClient, config: WatchdogConfig, stop: mpsc::Receiver<()>) {
+        trace!("watchdog_thread()");
+        let mut failures = 0;
+        let mut reported = false;
+        // the stop sender is dropped in stop()
+        while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(config.interval) {
+            if !client.state().sdk_inited {
+                failures = 0;
+                reported = false;
+                continue;
+            }
+            if process::is_wechat_running() {
+                if reported {
+                    info!("wechat process is running again");
+                }
+                failures = 0;
+                reported = false;
+                continue;
+            }
+            // the process list alone may be wrong, e.g. a renamed executable, so also require failing commands
+            match client.is_login() {
+                Ok(_) => failures = 0,
+                Err(e) => {
+                    failures += 1;
+                    trace!("wechat process not found and command failed {} time(s), error={}", failures, e);
+                }
+            }
+            if failures >= config.failure_threshold && !reported {
+                warn!("wechat process exited");
+                reported = true;
+                client.send_event(Event::WeChatProcessExited);
+            }
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,305 @@
+use hmac::{Hmac, Mac};
+use parking_lot::Mutex;
+use reqwest::blocking::Client;
+use sha2::Sha256;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{error, trace, warn};
+
+use super::error::Result;
+use super::events::HandlerId;
+use super::{Event, WcfClient, WxMsg};
+
+// delays between retries double up to this
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// WebhookForwarder 的配置
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    /// 以 POST 发送 json 格式的 WxMsg 的地址
+    pub url: String,
+    /// 设置时加上 `Authorization: Bearer ...`
+    pub bearer_token: Option<String>,
+    /// 设置时加上 `X-Signature-256: sha256=<hex>`，为以此为密钥对请求体计算的 HMAC-SHA256
+    pub secret: Option<String>,
+    /// 失败后最多重试的次数，4xx（429 除外）不重试
+    pub max_retries: u32,
+    /// 第一次重试前的等待时间，之后每次翻倍，最多 60 秒
+    pub retry_delay: Duration,
+    /// 等待发送的消息数，超过时丢弃新消息并发出 `Event::WebhookDeliveryFailed`
+    pub queue_capacity: usize,
+    /// 每个请求的超时时间
+    pub timeout: Duration,
+}
+
+impl WebhookConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        WebhookConfig {
+            url: url.into(),
+            bearer_token: None,
+            secret: None,
+            max_retries: 5,
+            retry_delay: Duration::from_secs(1),
+            queue_capacity: 1000,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// 对 body 计算 HMAC-SHA256，返回小写的十六进制字符串
+pub fn sign_body(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+    mac.update(body);
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// whether a failed delivery is worth retrying
+enum Failure {
+    Retry(String),
+    GiveUp(String),
+}
+
+struct Running {
+    handler: HandlerId,
+    worker: JoinHandle<()>,
+}
+
+/// 把收到的每条消息以 json POST 到指定地址，在独立的 wcf-webhook 线程中按顺序发送，失败时按配置重试。
+///
+/// 每个请求带有 `X-Wcf-Msg-Id` 头，重试时不变，接收方可以据此去重；重试用尽后发出 `Event::WebhookDeliveryFailed`
+pub struct WebhookForwarder {
+    client: WcfClient,
+    config: WebhookConfig,
+    running: Mutex<Option<Running>>,
+}
+
+impl WebhookForwarder {
+    pub fn new(client: WcfClient, config: WebhookConfig) -> Self {
+        WebhookForwarder { client, config, running: Mutex::new(None) }
+    }
+
+    /// 开始转发收到的消息，已经开始时不做任何事
+    pub fn start(&self) -> Result<()> {
+        let mut running = self.running.lock();
+        if running.is_some() {
+            return Ok(());
+        }
+        let http = Client::builder().timeout(self.config.timeout).build()?;
+        let (sender, receiver) = mpsc::sync_channel::<WxMsg>(self.config.queue_capacity.max(1));
+        let (client, config) = (self.client.clone(), self.config.clone());
+        let worker = thread::Builder::new()
+            .name("wcf-webhook".into())
+            .spawn(move || Self::forward(&client, &http, &config, receiver))?;
+        let handler = self.on_message(sender);
+        *running = Some(Running { handler, worker });
+        Ok(())
+    }
+
+    fn on_message(&self, sender: SyncSender<WxMsg>) -> HandlerId {
+        let client = self.client.clone();
+        self.client.on_message(move |msg| match sender.try_send(msg.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(msg)) => {
+                warn!("webhook queue is full, dropped msg {}", msg.id);
+                let error = "webhook queue is full".to_string();
+                client.send_event(Event::WebhookDeliveryFailed { msg_id: msg.id, error });
+            }
+            Err(TrySendError::Disconnected(_)) => trace!("webhook forwarder stopped"),
+        })
+    }
+
+    // delivers the queued messages in order until the sender is dropped
+    fn forward(client: &WcfClient, http: &Client, config: &WebhookConfig, receiver: Receiver<WxMsg>) {
+        for msg in receiver {
+            if let Err(error) = Self::deliver(http, config, &msg) {
+                error!("webhook delivery failed, msg_id={}, error={}", msg.id, error);
+                client.send_event(Event::WebhookDeliveryFailed { msg_id: msg.id, error });
+            }
+        }
+    }
+
+    // returns the last error once all retries are used up
+    fn deliver(http: &Client, config: &WebhookConfig, msg: &WxMsg) -> std::result::Result<(), String> {
+        let body = serde_json::to_vec(msg).map_err(|e| e.to_string())?;
+        let mut delay = config.retry_delay;
+        for attempt in 0..=config.max_retries {
+            let error = match Self::post(http, config, msg.id, &body) {
+                Ok(()) => return Ok(()),
+                Err(Failure::GiveUp(error)) => return Err(error),
+                Err(Failure::Retry(error)) => error,
+            };
+            if attempt == config.max_retries {
+                return Err(error);
+            }
+            warn!("webhook delivery failed, msg_id={}, attempt={}, error={}", msg.id, attempt + 1, error);
+            thread::sleep(delay);
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+        unreachable!("the last attempt always returns")
+    }
+
+    fn post(http: &Client, config: &WebhookConfig, msg_id: u64, body: &[u8]) -> std::result::Result<(), Failure> {
+        let mut request = http
+            .post(&config.url)
+            .header("Content-Type", "application/json")
+            .header("X-Wcf-Msg-Id", msg_id.to_string())
+            .body(body.to_vec());
+        if let Some(token) = &config.bearer_token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(secret) = &config.secret {
+            request = request.header("X-Signature-256", format!("sha256={}", sign_body(secret, body)));
+        }
+        let response = request.send().map_err(|e| Failure::Retry(e.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if status.is_client_error() && status.as_u16() != 429 {
+            Err(Failure::GiveUp(format!("webhook returned {}", status)))
+        } else {
+            Err(Failure::Retry(format!("webhook returned {}", status)))
+        }
+    }
+
+    /// 停止转发，等待队列中已有的消息发送完
+    pub fn stop(&self) {
+        if let Some(running) = self.running.lock().take() {
+            self.client.remove_handler(running.handler);
+            let _ = running.worker.join();
+        }
+    }
+}
+
+impl Drop for WebhookForwarder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_body_rfc4231() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign_body("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(sign_body("", b""), "b613679a0814d9ec772f95d778c35fc5ff1697c493715653c6c712144292c5ad");
+    }
+
+    #[cfg(feature = "http-server")]
+    mod delivery {
+        use super::*;
+        use std::collections::HashMap;
+        use std::sync::mpsc::RecvTimeoutError;
+        use tiny_http::{Response, Server};
+
+        // a received POST: headers by lowercase name, and the body
+        struct Received {
+            headers: HashMap<String, String>,
+            body: Vec<u8>,
+        }
+
+        // answers the first `failures` requests with `status` and the rest with 200, until `total` requests
+        fn start_server(failures: usize, status: u16, total: usize) -> (String, JoinHandle<Vec<Received>>) {
+            let server = Server::http("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/hook", server.server_addr().to_ip().unwrap());
+            let handle = thread::spawn(move || {
+                let mut received = Vec::new();
+                while received.len() < total {
+                    let mut request = match server.recv_timeout(Duration::from_secs(10)) {
+                        Ok(Some(request)) => request,
+                        _ => break,
+                    };
+                    let headers = request
+                        .headers()
+                        .iter()
+                        .map(|header| (header.field.as_str().as_str().to_lowercase(), header.value.to_string()))
+                        .collect();
+                    let mut body = Vec::new();
+                    request.as_reader().read_to_end(&mut body).unwrap();
+                    received.push(Received { headers, body });
+                    let status = if received.len() <= failures { status } else { 200 };
+                    request.respond(Response::empty(status)).unwrap();
+                }
+                received
+            });
+            (url, handle)
+        }
+
+        fn config(url: String, max_retries: u32) -> WebhookConfig {
+            WebhookConfig {
+                bearer_token: Some("token-1".into()),
+                secret: Some("密钥".into()),
+                max_retries,
+                retry_delay: Duration::from_millis(10),
+                ..WebhookConfig::new(url)
+            }
+        }
+
+        fn text_msg(id: u64) -> WxMsg {
+            WxMsg { id, r#type: 1, sender: "wxid_a".into(), content: "你好".into(), ..Default::default() }
+        }
+
+        #[test]
+        fn retries_until_accepted() {
+            let (url, server) = start_server(2, 503, 3);
+            let http = Client::builder().timeout(Duration::from_secs(10)).build().unwrap();
+            let msg = text_msg(42);
+            assert_eq!(WebhookForwarder::deliver(&http, &config(url, 3), &msg), Ok(()));
+
+            let received = server.join().unwrap();
+            assert_eq!(received.len(), 3);
+            let body = serde_json::to_vec(&msg).unwrap();
+            for request in &received {
+                assert_eq!(request.body, body);
+                assert_eq!(request.headers["content-type"], "application/json");
+                assert_eq!(request.headers["authorization"], "Bearer token-1");
+                assert_eq!(request.headers["x-wcf-msg-id"], "42");
+                assert_eq!(request.headers["x-signature-256"], format!("sha256={}", sign_body("密钥", &body)));
+            }
+            assert_eq!(serde_json::from_slice::<WxMsg>(&received[0].body).unwrap(), msg);
+        }
+
+        #[test]
+        fn client_errors_are_not_retried() {
+            let (url, server) = start_server(1, 400, 1);
+            let http = Client::new();
+            let result = WebhookForwarder::deliver(&http, &config(url, 3), &text_msg(1));
+            assert!(result.unwrap_err().contains("400"));
+            assert_eq!(server.join().unwrap().len(), 1);
+
+            // 429 is
+            let (url, server) = start_server(1, 429, 2);
+            assert_eq!(WebhookForwarder::deliver(&http, &config(url, 3), &text_msg(2)), Ok(()));
+            assert_eq!(server.join().unwrap().len(), 2);
+        }
+
+        #[test]
+        fn failed_event_after_retries() {
+            let (url, server) = start_server(3, 500, 3);
+            let client = WcfClient::new();
+            let events = client.subscribe();
+            let (sender, receiver) = mpsc::sync_channel(1);
+            sender.send(text_msg(7)).unwrap();
+            drop(sender);
+            let http = Client::new();
+            WebhookForwarder::forward(&client, &http, &config(url, 2), receiver);
+
+            assert_eq!(server.join().unwrap().len(), 3);
+            let failed = loop {
+                match events.recv_timeout(Duration::from_secs(5)) {
+                    Ok(Event::WebhookDeliveryFailed { msg_id, error }) => break (msg_id, error),
+                    Ok(_) => {}
+                    Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => panic!("no failed event"),
+                }
+            };
+            assert_eq!(failed.0, 7);
+            assert!(failed.1.contains("500"));
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::ChatRoomMember;
+
+/// 新成员入群时的欢迎消息，见 `set_welcome()`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WelcomeConfig {
+    /// 消息模板，{name} 替换为新成员的名字（多人时以 "、" 分隔），{room} 替换为群名，{inviter} 替换为邀请人
+    pub template: String,
+    /// 是否 @ 新成员，找不到对应群成员的名字不会 @
+    pub mention_new_member: bool,
+    /// 入群后等待多久再发送，期间入群的成员合并为一条消息
+    pub delay: Duration,
+}
+
+impl Default for WelcomeConfig {
+    fn default() -> Self {
+        WelcomeConfig {
+            template: "欢迎 {name} 加入 {room}".into(),
+            mention_new_member: false,
+            delay: Duration::from_secs(3),
+        }
+    }
+}
+
+impl WelcomeConfig {
+    /// 用新成员、群名和邀请人替换模板中的占位符
+    pub fn render(&self, names: &[String], room: &str, inviters: &[String]) -> String {
+        self.template
+            .replace("{name}", &names.join("、"))
+            .replace("{room}", room)
+            .replace("{inviter}", &inviters.join("、"))
+    }
+}
+
+/// 入群的显示名字对应的群成员 wxid，依次匹配群昵称、备注或昵称
+pub(crate) fn member_wxids(members: &[ChatRoomMember], names: &[String]) -> Vec<String> {
+    names
+        .iter()
+        .filter_map(|name| {
+            let matches = |member: &&ChatRoomMember| {
+                member.room_nickname.as_deref() == Some(name.as_str())
+                    || member.contact_name.as_deref() == Some(name.as_str())
+            };
+            members.iter().find(matches).map(|member| member.wxid.clone())
+        })
+        .collect()
+}
+
+// joins waiting for the delay to pass, per room
+#[derive(Default)]
+struct PendingJoins {
+    names: Vec<String>,
+    inviters: Vec<String>,
+}
+
+// configs by room, plus the one for rooms without their own
+#[derive(Default)]
+pub(crate) struct Welcomes {
+    rooms: HashMap<String, WelcomeConfig>,
+    all_rooms: Option<WelcomeConfig>,
+    pending: HashMap<String, PendingJoins>,
+}
+
+impl Welcomes {
+    pub(crate) fn set(&mut self, room_id: Option<String>, config: Option<WelcomeConfig>) {
+        match (room_id, config) {
+            (Some(room_id), Some(config)) => {
+                self.rooms.insert(room_id, config);
+            }
+            (Some(room_id), None) => {
+                self.rooms.remove(&room_id);
+            }
+            (None, config) => self.all_rooms = config,
+        }
+    }
+
+    pub(crate) fn get(&self, room_id: &str) -> Option<&WelcomeConfig> {
+        self.rooms.get(room_id).or(self.all_rooms.as_ref())
+    }
+
+    /// 记录入群的成员，是该群第一批等待发送的成员时返回 true
+    pub(crate) fn add_joined(&mut self, room_id: &str, inviter: String, names: Vec<String>) -> bool {
+        let first = !self.pending.contains_key(room_id);
+        let pending = self.pending.entry(room_id.to_string()).or_default();
+        for name in names {
+            if !pending.names.contains(&name) {
+                pending.names.push(name);
+            }
+        }
+        if !inviter.is_empty() && !pending.inviters.contains(&inviter) {
+            pending.inviters.push(inviter);
+        }
+        first
+    }
+
+    /// 取出等待发送的新成员和邀请人
+    pub(crate) fn take_joined(&mut self, room_id: &str) -> Option<(Vec<String>, Vec<String>)> {
+        self.pending.remove(room_id).map(|pending| (pending.names, pending.inviters))
+    }
+}
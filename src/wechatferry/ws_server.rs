@@ -0,0 +1,270 @@
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{error, info, trace, warn};
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::{Message as WsMessage, WebSocket};
+
+use super::error::{Result, WcfError};
+use super::{Event, WcfClient};
+
+// how often threads check for stop, also the read timeout of client sockets
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// WsServer 的配置
+#[derive(Clone, Debug)]
+pub struct WsServerConfig {
+    /// 监听地址，默认只监听本机
+    pub addr: String,
+    /// 设置时连接需带上 `?token=...` 或 `Authorization: Bearer ...`，否则返回 401
+    pub token: Option<String>,
+    /// 每个连接等待发送的事件数，超过时断开该连接，不会拖慢事件分发
+    pub client_queue: usize,
+}
+
+impl Default for WsServerConfig {
+    fn default() -> Self {
+        WsServerConfig { addr: "127.0.0.1:8081".into(), token: None, client_queue: 256 }
+    }
+}
+
+/// 客户端发送的订阅消息，字段为空时不过滤，例如 `{"kinds": ["MsgReceived"], "rooms": ["xxx@chatroom"]}`
+#[derive(Clone, Debug, Default, Deserialize)]
+struct Subscription {
+    /// 事件名，即 json 中事件的 key，例如 "MsgReceived"、"SdkDestroyed"
+    #[serde(default)]
+    kinds: HashSet<String>,
+    /// 只接收这些群的消息事件，不影响不带消息的事件
+    #[serde(default)]
+    rooms: HashSet<String>,
+}
+
+impl Subscription {
+    fn accepts(&self, kind: &str, event: &Event) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(kind) {
+            return false;
+        }
+        match event {
+            Event::MsgReceived(msg) | Event::MsgFiltered(msg) if !self.rooms.is_empty() => {
+                self.rooms.contains(&msg.roomid)
+            }
+            _ => true,
+        }
+    }
+}
+
+struct Connection {
+    sender: SyncSender<String>,
+    subscription: Arc<Mutex<Subscription>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    connections: Mutex<Vec<Connection>>,
+    connected: AtomicUsize,
+    stopped: AtomicBool,
+}
+
+// the name serde gives the variant, e.g. "MsgReceived" for {"MsgReceived": {...}}
+fn event_kind(json: &serde_json::Value) -> String {
+    match json {
+        serde_json::Value::String(kind) => kind.clone(),
+        serde_json::Value::Object(map) => map.keys().next().cloned().unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+fn ws_error(e: tungstenite::Error) -> WcfError {
+    WcfError::Io(std::io::Error::other(e))
+}
+
+fn token_matches(request: &Request, token: &str) -> bool {
+    let query_token =
+        request.uri().query().unwrap_or_default().split('&').any(|pair| pair == format!("token={}", token));
+    let header = request.headers().get("Authorization").and_then(|value| value.to_str().ok());
+    query_token || header == Some(format!("Bearer {}", token).as_str())
+}
+
+/// 把每个事件以 json 文本帧推送给所有连接的 WebSocket 客户端，便于在浏览器中实时查看消息。
+///
+/// 客户端可以随时发送订阅消息按事件名或群过滤，见 `Subscription`；处理不过来的客户端会被断开。
+/// 在独立的线程中运行，每个连接一个线程
+pub struct WsServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl WsServer {
+    pub fn start(client: WcfClient, config: WsServerConfig) -> Result<WsServer> {
+        let listener = TcpListener::bind(&config.addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared::default());
+        let events = client.subscribe();
+        let accept_shared = shared.clone();
+        let accept = thread::Builder::new()
+            .name("wcf-ws-accept".into())
+            .spawn(move || Self::accept_thread(listener, config, accept_shared))?;
+        let broadcast_shared = shared.clone();
+        let broadcast = thread::Builder::new()
+            .name("wcf-ws-broadcast".into())
+            .spawn(move || Self::broadcast_thread(events, broadcast_shared))?;
+        info!("websocket server listening on {}", addr);
+        Ok(WsServer { addr, shared, threads: Mutex::new(vec![accept, broadcast]) })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 当前连接的客户端数
+    pub fn client_count(&self) -> usize {
+        self.shared.connected.load(Ordering::Relaxed)
+    }
+
+    /// 停止服务，断开所有连接
+    pub fn stop(&self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        self.shared.connections.lock().clear();
+        for handle in self.threads.lock().drain(..) {
+            let _ = handle.join();
+        }
+    }
+
+    fn accept_thread(listener: TcpListener, config: WsServerConfig, shared: Arc<Shared>) {
+        trace!("accept_thread()");
+        while !shared.stopped.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    let (config, shared) = (config.clone(), shared.clone());
+                    let builder = thread::Builder::new().name("wcf-ws-client".into());
+                    if let Err(e) = builder.spawn(move || Self::client_thread(stream, peer, config, shared)) {
+                        error!("failed to spawn websocket client thread, error={}", e);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                Err(e) => warn!("websocket accept failed, error={}", e),
+            }
+        }
+    }
+
+    fn broadcast_thread(events: Receiver<Event>, shared: Arc<Shared>) {
+        trace!("broadcast_thread()");
+        while !shared.stopped.load(Ordering::SeqCst) {
+            let event = match events.recv_timeout(POLL_INTERVAL) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let json = match serde_json::to_value(&event) {
+                Ok(json) => json,
+                Err(e) => {
+                    error!("failed to serialize event, error={}", e);
+                    continue;
+                }
+            };
+            let (kind, text) = (event_kind(&json), json.to_string());
+            // a full queue drops the connection, its thread notices the closed channel and disconnects
+            shared.connections.lock().retain(|connection| {
+                if !connection.subscription.lock().accepts(&kind, &event) {
+                    return true;
+                }
+                match connection.sender.try_send(text.clone()) {
+                    Ok(()) => true,
+                    Err(TrySendError::Full(_)) => {
+                        warn!("websocket client is too slow, disconnecting");
+                        false
+                    }
+                    Err(TrySendError::Disconnected(_)) => false,
+                }
+            });
+        }
+    }
+
+    fn client_thread(stream: TcpStream, peer: SocketAddr, config: WsServerConfig, shared: Arc<Shared>) {
+        let mut socket = match Self::handshake(stream, &config) {
+            Ok(socket) => socket,
+            Err(e) => {
+                info!("websocket handshake with {} failed, error={}", peer, e);
+                return;
+            }
+        };
+        let (sender, receiver) = mpsc::sync_channel(config.client_queue.max(1));
+        let subscription = Arc::new(Mutex::new(Subscription::default()));
+        shared.connections.lock().push(Connection { sender, subscription: subscription.clone() });
+        let count = shared.connected.fetch_add(1, Ordering::Relaxed) + 1;
+        info!("websocket client {} connected, clients={}", peer, count);
+        if let Err(e) = Self::serve(&mut socket, &receiver, &subscription, &shared) {
+            trace!("websocket client {} closed, error={}", peer, e);
+        }
+        let _ = socket.close(None);
+        let _ = socket.flush();
+        let count = shared.connected.fetch_sub(1, Ordering::Relaxed) - 1;
+        info!("websocket client {} disconnected, clients={}", peer, count);
+    }
+
+    fn handshake(stream: TcpStream, config: &WsServerConfig) -> Result<WebSocket<TcpStream>> {
+        stream.set_nonblocking(false)?;
+        let token = config.token.clone();
+        // the error type is dictated by tungstenite
+        #[allow(clippy::result_large_err)]
+        let check_token = move |request: &Request, response: Response| match &token {
+            Some(token) if !token_matches(request, token) => {
+                let mut denied = ErrorResponse::new(Some("missing or wrong token".into()));
+                *denied.status_mut() = StatusCode::UNAUTHORIZED;
+                Err(denied)
+            }
+            _ => Ok(response),
+        };
+        let socket = tungstenite::accept_hdr(stream, check_token)
+            .map_err(|e| WcfError::Io(std::io::Error::other(e.to_string())))?;
+        // reads time out, so the same thread can also send queued events
+        socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(socket)
+    }
+
+    fn serve(
+        socket: &mut WebSocket<TcpStream>,
+        events: &Receiver<String>,
+        subscription: &Mutex<Subscription>,
+        shared: &Shared,
+    ) -> Result<()> {
+        while !shared.stopped.load(Ordering::SeqCst) {
+            loop {
+                match events.try_recv() {
+                    Ok(text) => socket.write(WsMessage::Text(text)).map_err(ws_error)?,
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    // dropped by the broadcaster, the client is too slow or the server stopped
+                    Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            socket.flush().map_err(ws_error)?;
+            match socket.read() {
+                Ok(WsMessage::Text(text)) => match serde_json::from_str(&text) {
+                    Ok(new_subscription) => *subscription.lock() = new_subscription,
+                    Err(e) => warn!("invalid websocket subscription, error={}, text={}", e, text),
+                },
+                Ok(WsMessage::Close(_)) => return Ok(()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => return Err(ws_error(e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WsServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
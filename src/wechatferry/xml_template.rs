@@ -0,0 +1,145 @@
+use std::path::PathBuf;
+
+use super::error::Result;
+use super::{Message, SendResult, WcfClient};
+
+// send_xml() type of appmsg xml, same as MsgType::App
+const XML_TYPE_APP: i32 = 49;
+
+/// 转义 xml 文本和属性中的特殊字符
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// an appmsg of `app_type` with the given inner elements, already escaped
+fn appmsg(app_type: i32, title: &str, body: &str) -> String {
+    format!(
+        "<msg><appmsg appid=\"\" sdkver=\"0\"><title>{}</title><type>{}</type>{}</appmsg></msg>",
+        escape(title),
+        app_type,
+        body
+    )
+}
+
+/// 常用的 send_xml() 消息，生成 xml 并使用对应的 xml_type，用户输入会被转义
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XmlTemplate {
+    xml: String,
+    xml_type: i32,
+}
+
+impl XmlTemplate {
+    /// 文件卡片，只有文件名和大小，对方无法下载，需要真实文件时使用 send_file()
+    pub fn file_card(name: &str, size: u64) -> Self {
+        let ext = name.rsplit_once('.').map_or("", |(_, ext)| ext);
+        let body = format!("<appattach><totallen>{}</totallen><fileext>{}</fileext></appattach>", size, escape(ext));
+        XmlTemplate { xml: appmsg(6, name, &body), xml_type: XML_TYPE_APP }
+    }
+
+    /// 音乐卡片，url 为播放的音频地址
+    pub fn music(title: &str, artist: &str, url: &str) -> Self {
+        let body = format!(
+            "<des>{}</des><url>{}</url><dataurl>{}</dataurl><lowurl>{}</lowurl><lowdataurl>{}</lowdataurl>",
+            escape(artist),
+            escape(url),
+            escape(url),
+            escape(url),
+            escape(url)
+        );
+        XmlTemplate { xml: appmsg(3, title, &body), xml_type: XML_TYPE_APP }
+    }
+
+    /// 引用回复 original，只支持引用文本消息的内容
+    pub fn quote_reply(original: &Message, reply_text: &str) -> Self {
+        let msg = original.as_wx_msg();
+        let chat = original.room_id().unwrap_or(original.sender());
+        let body = format!(
+            "<refermsg><type>{}</type><svrid>{}</svrid><fromusr>{}</fromusr><chatusr>{}</chatusr>\
+            <content>{}</content></refermsg>",
+            msg.r#type,
+            msg.id,
+            escape(chat),
+            escape(original.sender()),
+            escape(&msg.content)
+        );
+        XmlTemplate { xml: appmsg(57, reply_text, &body), xml_type: XML_TYPE_APP }
+    }
+
+    pub fn xml(&self) -> &str {
+        &self.xml
+    }
+
+    pub fn xml_type(&self) -> i32 {
+        self.xml_type
+    }
+
+    pub fn send_with(&self, client: &WcfClient, receiver: &str) -> Result<SendResult> {
+        client.send_xml(self.xml.clone(), PathBuf::new(), receiver.to_string(), self.xml_type)
+    }
+
+    /// 同 send_with()，使用默认客户端
+    pub fn send(&self, receiver: &str) -> Result<SendResult> {
+        self.send_with(super::default_client(), receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wechatferry::{AppMsg, WxMsg};
+
+    const SPECIAL: &str = r#"a<b>&c"d'e"#;
+
+    #[test]
+    fn file_card_round_trip() {
+        let name = format!("{}.pdf", SPECIAL);
+        let template = XmlTemplate::file_card(&name, 1024);
+        assert_eq!(template.xml_type(), 49);
+        let expected = AppMsg::File { name, size: 1024, md5: String::new() };
+        assert_eq!(AppMsg::parse(template.xml()).unwrap(), expected);
+    }
+
+    #[test]
+    fn music_round_trip() {
+        let url = "https://example.com/song.mp3?a=1&b='2'";
+        let template = XmlTemplate::music(SPECIAL, SPECIAL, url);
+        // there is no AppMsg variant for music, the xml is kept as is
+        assert_eq!(AppMsg::parse(template.xml()).unwrap(), AppMsg::Other(template.xml().to_string()));
+        let doc = roxmltree::Document::parse(template.xml()).unwrap();
+        let text = |tag: &str| doc.descendants().find(|node| node.has_tag_name(tag)).and_then(|node| node.text());
+        assert_eq!(text("title"), Some(SPECIAL));
+        assert_eq!(text("type"), Some("3"));
+        assert_eq!(text("des"), Some(SPECIAL));
+        for tag in ["url", "dataurl", "lowurl", "lowdataurl"] {
+            assert_eq!(text(tag), Some(url));
+        }
+    }
+
+    #[test]
+    fn quote_reply_round_trip() {
+        let original = WxMsg {
+            id: 1234567890123,
+            r#type: 1,
+            sender: "wxid_sender".into(),
+            roomid: "1@chatroom".into(),
+            is_group: true,
+            content: SPECIAL.into(),
+            ..Default::default()
+        };
+        let template = XmlTemplate::quote_reply(&Message::from(original), SPECIAL);
+        let expected =
+            AppMsg::QuotedReply { text: SPECIAL.into(), quoted_msg_id: 1234567890123, quoted_text: SPECIAL.into() };
+        assert_eq!(AppMsg::parse(template.xml()).unwrap(), expected);
+    }
+}
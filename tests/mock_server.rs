@@ -0,0 +1,316 @@
+//! 通过 MockWcfServer 测试命令的收发，每个测试使用不同的端口，可以并行运行
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use wechat_bot::wechatferry::proto::{self, response, Functions};
+use wechat_bot::wechatferry::{
+    CleanupHandler, ConnectionChange, Event, InitOptions, ListenStatus, MockSdkLoader, MockWcfServer, WcfClient,
+    WcfError, WcfState, WxMsg,
+};
+
+// skips the installed wechat version check, there is no wechat in tests
+fn init_options() -> InitOptions {
+    InitOptions { force: true, auto_clean: false, ..Default::default() }
+}
+
+// a client inited on the server's port with the cmd socket connected
+fn connect(server: &MockWcfServer) -> (WcfClient, CleanupHandler) {
+    let client = WcfClient::with_loader(MockSdkLoader::default());
+    let cleanup = client.init_with_options(server.port(), init_options()).unwrap();
+    client.connect_cmd_socket().unwrap();
+    (client, cleanup)
+}
+
+fn response(msg: response::Msg) -> proto::Response {
+    proto::Response { func: 0, msg: Some(msg) }
+}
+
+#[test]
+fn state_follows_connection() {
+    let server = MockWcfServer::start(19410).unwrap();
+    let client = WcfClient::with_loader(MockSdkLoader::default());
+    let changes = Arc::new(Mutex::new(vec![]));
+    let changes_clone = changes.clone();
+    client.on_connection_change(move |change| changes_clone.lock().push(change));
+
+    // disconnected
+    assert_eq!(client.state(), WcfState::default());
+
+    // connecting: the sdk is inited, the cmd socket is not connected yet
+    let _cleanup = client.init_with_options(server.port(), init_options()).unwrap();
+    let state = client.state();
+    assert!(state.sdk_inited);
+    assert_eq!(state.cmd_port, Some(19410));
+    assert!(!state.cmd_connected);
+    assert!(!state.listening);
+
+    // connected
+    client.connect_cmd_socket().unwrap();
+    assert!(client.state().cmd_connected);
+    assert_eq!(client.enable_listen().unwrap(), ListenStatus::Started);
+    let state = client.state();
+    assert!(state.listening);
+    assert_eq!(state.msg_port, Some(19411));
+
+    assert!(client.disable_listen().unwrap());
+    let state = client.state();
+    assert!(state.cmd_connected);
+    assert!(!state.listening);
+    assert_eq!(state.msg_port, None);
+
+    // disconnected again
+    client.uninit();
+    assert_eq!(client.state(), WcfState::default());
+    assert_eq!(
+        *changes.lock(),
+        vec![
+            ConnectionChange::CmdSocketConnected,
+            ConnectionChange::MsgSocketConnected,
+            ConnectionChange::MsgSocketDisconnected,
+            ConnectionChange::CmdSocketDisconnected,
+        ]
+    );
+}
+
+#[test]
+fn init_twice_loads_sdk_once() {
+    let loader = MockSdkLoader::default();
+    let client = WcfClient::with_loader(loader.clone());
+    let events = client.subscribe();
+
+    client.init_with_options(19420, init_options()).unwrap();
+    client.uninit();
+    client.init_with_options(19420, init_options()).unwrap();
+    assert!(client.state().sdk_inited);
+    client.uninit();
+    assert_eq!(loader.loads(), 1);
+    let loaded = events.try_iter().filter(|event| matches!(event, Event::SdkDllLoaded)).count();
+    assert_eq!(loaded, 1);
+
+    // loaded again after unloading
+    client.uninit_and_unload();
+    client.init_with_options(19420, init_options()).unwrap();
+    client.uninit();
+    assert_eq!(loader.loads(), 2);
+}
+
+#[test]
+fn is_login() {
+    let server = MockWcfServer::start(19430).unwrap();
+    let (client, _cleanup) = connect(&server);
+    assert!(!client.is_login().unwrap());
+    server.respond(Functions::FuncIsLogin, response(response::Msg::Status(1)));
+    assert!(client.is_login().unwrap());
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests.iter().all(|request| request.func == i32::from(Functions::FuncIsLogin) && request.msg.is_none()));
+}
+
+#[test]
+fn get_contacts() {
+    let server = MockWcfServer::start(19440).unwrap();
+    let (client, _cleanup) = connect(&server);
+    let contact = proto::RpcContact { wxid: "wxid_a".into(), name: "张三".into(), gender: 1, ..Default::default() };
+    let contacts = proto::RpcContacts { contacts: vec![contact.clone()] };
+    server.respond(Functions::FuncGetContacts, response(response::Msg::Contacts(contacts)));
+    let contacts = client.get_contacts().unwrap().unwrap();
+    assert_eq!(contacts.contacts, vec![contact]);
+}
+
+#[test]
+fn exec_db_query() {
+    let server = MockWcfServer::start(19450).unwrap();
+    let (client, _cleanup) = connect(&server);
+    let field = proto::DbField { r#type: 3, column: "UserName".into(), content: b"wxid_a".to_vec() };
+    let rows = proto::DbRows { rows: vec![proto::DbRow { fields: vec![field] }] };
+    server.respond(Functions::FuncExecDbQuery, response(response::Msg::Rows(rows.clone())));
+
+    let sql = "SELECT UserName FROM Contact LIMIT 1";
+    assert_eq!(client.exec_db_query("MicroMsg.db".into(), sql.into()).unwrap(), rows.rows);
+    let request = server.requests().pop().unwrap();
+    let expected = proto::DbQuery { db: "MicroMsg.db".into(), sql: sql.into() };
+    assert_eq!(request.msg, Some(proto::request::Msg::Query(expected)));
+}
+
+#[test]
+fn send_text() {
+    let server = MockWcfServer::start(19460).unwrap();
+    let (client, _cleanup) = connect(&server);
+    server.respond(Functions::FuncSendTxt, response(response::Msg::Status(1)));
+
+    let result = client.send_text("hi @张三".into(), "123@chatroom".into(), "wxid_a".into()).unwrap();
+    assert!(result.success);
+    let request = server.requests().pop().unwrap();
+    assert_eq!(request.func, i32::from(Functions::FuncSendTxt));
+    let Some(proto::request::Msg::Txt(text)) = request.msg else {
+        panic!("not a TextMsg: {:?}", request.msg);
+    };
+    assert_eq!(text.msg, "hi @张三");
+    assert_eq!(text.receiver, "123@chatroom");
+    assert_eq!(text.aters, "wxid_a");
+}
+
+#[test]
+fn garbage_response_is_decode_error() {
+    let server = MockWcfServer::start(19470).unwrap();
+    let (client, _cleanup) = connect(&server);
+    server.respond_raw(Functions::FuncIsLogin, vec![0xff, 0xff, 0xff]);
+    assert!(matches!(client.is_login(), Err(WcfError::DecodeError(_))));
+    // the socket is still usable afterwards
+    server.respond(Functions::FuncIsLogin, response(response::Msg::Status(1)));
+    assert!(client.is_login().unwrap());
+}
+
+// a video message saved by wechat at `saved`, the xml says it has `length` bytes
+fn video_msg(saved: &std::path::Path, length: u64) -> WxMsg {
+    WxMsg {
+        id: 42,
+        r#type: 43,
+        sender: "wxid_a".into(),
+        content: format!(r#"<msg><videomsg length="{}" playlength="3" md5="abc" /></msg>"#, length),
+        extra: saved.to_string_lossy().into_owned(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn download_attachment_skips_saved_video() {
+    let server = MockWcfServer::start(19480).unwrap();
+    let (client, _cleanup) = connect(&server);
+    // wechat files layout: <root>/FileStorage/Video/2024-08/<name>.mp4
+    let root = std::env::temp_dir().join(format!("wcf-download-{}", std::process::id()));
+    let video_dir = root.join("FileStorage").join("Video").join("2024-08");
+    std::fs::create_dir_all(&video_dir).unwrap();
+    let saved = video_dir.join("f00d.mp4");
+    std::fs::write(&saved, [7u8; 16]).unwrap();
+    let dest = root.join("dest");
+
+    // complete already, copied without asking the spy
+    let copied = client.download_attachment(&video_msg(&saved, 16), Some(&dest), Duration::ZERO, |_| {}).unwrap();
+    assert_eq!(copied, dest.join("f00d.mp4"));
+    assert_eq!(std::fs::read(&copied).unwrap(), [7u8; 16]);
+    assert!(server.requests().is_empty());
+
+    // only part of it, downloaded again
+    server.respond(Functions::FuncDownloadAttach, response(response::Msg::Status(1)));
+    let result = client.download_attachment(&video_msg(&saved, 32), Some(&dest), Duration::ZERO, |_| {});
+    assert!(matches!(result, Err(WcfError::DownloadTimeout(path)) if path == saved));
+    let request = server.requests().pop().unwrap();
+    assert_eq!(request.func, i32::from(Functions::FuncDownloadAttach));
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn ocr_retries_until_ready() {
+    let server = MockWcfServer::start(19490).unwrap();
+    let (client, _cleanup) = connect(&server);
+    let image = std::env::temp_dir().join(format!("wcf-ocr-{}.png", std::process::id()));
+    std::fs::write(&image, [0x89, b'P', b'N', b'G']).unwrap();
+    let ocr = |status, result: &str| response(response::Msg::Ocr(proto::OcrMsg { status, result: result.into() }));
+    // not ready, then a non-zero status, then the text
+    server.respond_once(Functions::FuncExecOcr, ocr(0, ""));
+    server.respond_once(Functions::FuncExecOcr, ocr(-1, "partial"));
+    server.respond(Functions::FuncExecOcr, ocr(0, " 识别结果\n"));
+
+    let text = client.ocr_with_retry(&image, 5, Duration::ZERO).unwrap();
+    assert_eq!(text.as_deref(), Some("识别结果"));
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    let path = std::path::absolute(&image).unwrap().to_string_lossy().into_owned();
+    assert!(requests.iter().all(|request| request.msg == Some(proto::request::Msg::Str(path.clone()))));
+
+    // gives up after the given attempts
+    server.respond(Functions::FuncExecOcr, ocr(1, "busy"));
+    assert_eq!(client.ocr_with_retry(&image, 2, Duration::ZERO).unwrap(), None);
+    assert_eq!(server.requests().len(), 5);
+    let _ = std::fs::remove_file(&image);
+}
+
+#[test]
+fn init_checks_port_pair() {
+    use std::net::{Ipv4Addr, TcpListener};
+
+    let client = WcfClient::with_loader(MockSdkLoader::default());
+    let checked = InitOptions { check_ports: true, ..init_options() };
+    // the msg port of 19510, then 19512 itself
+    let _msg_port = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 19511)).unwrap();
+    let _cmd_port = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 19512)).unwrap();
+    assert!(matches!(client.init_with_options(19510, checked.clone()), Err(WcfError::PortInUse(19511))));
+    assert!(matches!(client.init_with_options(19512, checked.clone()), Err(WcfError::PortInUse(19512))));
+    assert!(!client.state().sdk_inited);
+
+    // 19510, 19511 and 19512 all touch a taken port
+    let cleanup = client.init_auto_port(19510..=19515, init_options()).unwrap();
+    assert_eq!(cleanup.port(), 19513);
+    assert!(client.state().sdk_inited);
+    client.uninit();
+
+    let none_free = client.init_auto_port(19510..=19512, init_options());
+    assert!(matches!(none_free, Err(WcfError::NoFreePort { start: 19510, end: 19512 })));
+}
+
+#[test]
+fn get_contact_info() {
+    let server = MockWcfServer::start(19520).unwrap();
+    let (client, _cleanup) = connect(&server);
+    // no contact with that wxid
+    assert_eq!(client.get_contact_info("wxid_none".into()).unwrap(), None);
+
+    let contact =
+        proto::RpcContact {
+            wxid: "wxid_a".into(), name: "张三".into(), remark: "老张".into(), ..Default::default()
+        };
+    let contacts = proto::RpcContacts { contacts: vec![contact.clone()] };
+    server.respond(Functions::FuncGetContactInfo, response(response::Msg::Contacts(contacts)));
+    assert_eq!(client.get_contact_info("wxid_a".into()).unwrap(), Some(contact));
+    server.respond(Functions::FuncGetContactInfo, response(response::Msg::Contacts(Default::default())));
+    assert_eq!(client.get_contact_info("wxid_a".into()).unwrap(), None);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert!(requests.iter().all(|request| request.func == i32::from(Functions::FuncGetContactInfo)));
+    assert_eq!(requests[0].msg, Some(proto::request::Msg::Str("wxid_none".into())));
+    assert_eq!(requests[1].msg, Some(proto::request::Msg::Str("wxid_a".into())));
+}
+
+#[test]
+fn revoke_msg() {
+    let server = MockWcfServer::start(19530).unwrap();
+    let (client, _cleanup) = connect(&server);
+    server.respond_once(Functions::FuncRevokeMsg, response(response::Msg::Status(1)));
+    assert!(client.revoke_msg(8_123_456_789_012_345_678).unwrap());
+    // any other status, or no status at all, is a failure
+    server.respond_once(Functions::FuncRevokeMsg, response(response::Msg::Status(0)));
+    assert!(!client.revoke_msg(1).unwrap());
+    server.respond_once(Functions::FuncRevokeMsg, response(response::Msg::Status(-1)));
+    assert!(!client.revoke_msg(2).unwrap());
+    server.respond_once(Functions::FuncRevokeMsg, response(response::Msg::Str("1".into())));
+    assert!(!client.revoke_msg(3).unwrap());
+
+    let requests = server.requests();
+    assert!(requests.iter().all(|request| request.func == i32::from(Functions::FuncRevokeMsg)));
+    let ids: Vec<_> = requests.into_iter().map(|request| request.msg).collect();
+    let expected = [8_123_456_789_012_345_678, 1, 2, 3].map(|id| Some(proto::request::Msg::Ui64(id)));
+    assert_eq!(ids, expected);
+}
+
+#[test]
+fn refresh_qrcode() {
+    let server = MockWcfServer::start(19540).unwrap();
+    let (client, _cleanup) = connect(&server);
+    let qrcode = "http://weixin.qq.com/x/abcDEF123";
+    server.respond_once(Functions::FuncRefreshQrcode, response(response::Msg::Str(qrcode.into())));
+    assert_eq!(client.refresh_qrcode().unwrap().as_deref(), Some(qrcode));
+    // logged in already
+    server.respond_once(Functions::FuncRefreshQrcode, response(response::Msg::Str(String::new())));
+    assert_eq!(client.refresh_qrcode().unwrap(), None);
+    server.respond_once(Functions::FuncRefreshQrcode, response(response::Msg::Status(0)));
+    assert_eq!(client.refresh_qrcode().unwrap(), None);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert!(requests
+        .iter()
+        .all(|request| request.func == i32::from(Functions::FuncRefreshQrcode) && request.msg.is_none()));
+}